@@ -1,4 +1,5 @@
 //! Configuration for Quale Analyzer
+use crate::analyzer::report::AnalysisReport;
 use crate::ast::Qast;
 use crate::error::Result;
 
@@ -16,9 +17,9 @@ impl AnalyzerConfig {
         }
     }
 
-    pub fn analyze(&self, ast: &Qast) -> Result<()> {
-        for _fn in ast {}
-        Ok(())
+    /// Analyzes the program without compiling it, see `AnalysisReport`.
+    pub fn analyze(&self, ast: &Qast) -> Result<AnalysisReport> {
+        Ok(AnalysisReport::new(ast))
     }
 }
 
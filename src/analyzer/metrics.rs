@@ -0,0 +1,90 @@
+//! Circuit metrics for reporting the size of compiled functions.
+use crate::codegen::circuit::{Circuit, Op};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Metrics {
+    function: String,
+    gates: usize,
+    two_qubit_gates: usize,
+    depth: usize,
+    qubits: usize,
+    clbits: usize,
+}
+
+impl From<&Circuit> for Metrics {
+    fn from(circuit: &Circuit) -> Self {
+        let gates = circuit
+            .ops
+            .iter()
+            .filter(|op| matches!(op, Op::Gate { .. } | Op::Conditional { .. }))
+            .collect::<Vec<&Op>>();
+
+        Self {
+            function: circuit.name.clone(),
+            gates: gates.len(),
+            two_qubit_gates: gates.iter().filter(|op| op.qubits().len() == 2).count(),
+            depth: circuit.depth(),
+            qubits: circuit.qubits,
+            clbits: circuit.clbits,
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    /// Formats metrics as a JSON object.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"function\": \"{}\", \"gates\": {}, \"two_qubit_gates\": {}, \"depth\": {}, \"qubits\": {}, \"clbits\": {}}}",
+            self.function.escape_default(),
+            self.gates,
+            self.two_qubit_gates,
+            self.depth,
+            self.qubits,
+            self.clbits
+        )
+    }
+}
+
+/// Returns a JSON array of metrics for each circuit.
+pub(crate) fn to_json(circuits: &[Circuit]) -> String {
+    let metrics = circuits
+        .iter()
+        .map(|circuit| format!("  {}", Metrics::from(circuit)))
+        .collect::<Vec<String>>();
+
+    if metrics.is_empty() {
+        "[]".into()
+    } else {
+        format!("[\n{}\n]", metrics.join(",\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::circuit;
+    use crate::error::Result;
+    use crate::inference::infer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn check_metrics() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/bell.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let circuits = circuit::lower(ast)?;
+        assert_eq!(
+            to_json(&circuits),
+            "[
+  {\"function\": \"main\", \"gates\": 2, \"two_qubit_gates\": 1, \"depth\": 3, \"qubits\": 2, \"clbits\": 0}
+]"
+        );
+        assert_eq!(to_json(&[]), "[]");
+
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 //! Static analyzer for qcc
 pub mod config;
+pub mod metrics;
+pub mod report;
 
 #[cfg(test)]
 mod tests {
@@ -26,4 +28,84 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn check_analysis_report() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/test11.ql"])?.unwrap();
+        let config = parser.get_config();
+        let qast = parser.parse(&config.analyzer.src)?;
+        let report = config.analyzer.analyze(&qast)?;
+
+        for module in &qast {
+            for function in &*module {
+                assert!(report.function(function.get_name()).is_some());
+            }
+        }
+        let main = report.function("main").unwrap();
+        assert_eq!(
+            (main.params, main.instructions, main.quantum),
+            (0, 3, false)
+        );
+        assert!(report.warnings.is_empty());
+
+        let mut parser = Parser::new(vec!["tests/alloc.ql"])?.unwrap();
+        let config = parser.get_config();
+        let qast = parser.parse(&config.analyzer.src)?;
+        let report = config.analyzer.analyze(&qast)?;
+        // builtins like `alloc` aren't part of the call graph
+        assert_eq!(report.call_graph.callees("main"), ["flip"]);
+        assert_eq!(report.call_graph.callers("flip"), ["main"]);
+        assert!(report.function("flip").unwrap().quantum);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_empty_bodies() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/empty-body.ql"])?.unwrap();
+        let config = parser.get_config();
+        let qast = parser.parse(&config.analyzer.src)?;
+        let report = config.analyzer.analyze(&qast)?;
+
+        // `bar` is empty too, but it returns nothing
+        assert_eq!(
+            report.warnings,
+            [
+                "function `bar` is never called",
+                "function `foo` @empty-body.ql:2:4 returns qubit but its body is empty"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_recursion() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/recursion.ql"])?.unwrap();
+        let config = parser.get_config();
+        let qast = parser.parse(&config.analyzer.src)?;
+        let report = config.analyzer.analyze(&qast)?;
+
+        assert_eq!(report.call_graph.cycles(), [vec!["f", "g"], vec!["spin"]]);
+        assert_eq!(
+            report.warnings,
+            [
+                "functions `f` @recursion.ql:2:4, `g` @recursion.ql:6:4 call each other",
+                "function `spin` @recursion.ql:10:4 calls itself"
+            ]
+        );
+
+        // calls which never lead back to the caller aren't cycles
+        let mut parser = Parser::new(vec!["tests/alloc.ql"])?.unwrap();
+        let config = parser.get_config();
+        let qast = parser.parse(&config.analyzer.src)?;
+        assert!(config
+            .analyzer
+            .analyze(&qast)?
+            .call_graph
+            .cycles()
+            .is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,264 @@
+//! Results of static analysis, as returned by `AnalyzerConfig::analyze`.
+use crate::ast::{FunctionAST, Ident, Qast};
+use crate::builtins::Builtin;
+use crate::lexer::Location;
+use crate::optimizer::passes::gather_callees;
+use crate::types::Type;
+use std::fmt;
+
+/// What is known about a single function without compiling it.
+#[derive(Debug, PartialEq)]
+pub struct FunctionReport {
+    pub(crate) name: Ident,
+    pub(crate) params: usize,
+    pub(crate) instructions: usize,
+    /// takes, returns or binds qubits
+    pub(crate) quantum: bool,
+    pub(crate) location: Location,
+}
+
+impl From<&FunctionAST> for FunctionReport {
+    fn from(function: &FunctionAST) -> Self {
+        let binds_qubits = function
+            .into_iter()
+            .any(|expr| expr.as_ref().borrow().get_type().is_quantum());
+        Self {
+            name: function.get_name().clone(),
+            params: function.get_input_type().len(),
+            instructions: function.into_iter().count(),
+            quantum: binds_qubits
+                || function.get_output_type().is_quantum()
+                || function.get_input_type().iter().any(Type::is_quantum),
+            location: function.get_loc().clone(),
+        }
+    }
+}
+
+/// Functions each function calls, builtins excluded.
+#[derive(Debug, Default, PartialEq)]
+pub struct CallGraph {
+    edges: Vec<(Ident, Vec<Ident>)>,
+}
+
+impl CallGraph {
+    /// Functions called by `name`, in the order of their first call.
+    pub(crate) fn callees(&self, name: &str) -> &[Ident] {
+        self.edges
+            .iter()
+            .find(|(caller, _)| caller == name)
+            .map(|(_, callees)| callees.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Functions calling `name`.
+    pub(crate) fn callers(&self, name: &str) -> Vec<&Ident> {
+        self.edges
+            .iter()
+            .filter(|(_, callees)| callees.iter().any(|callee| callee == name))
+            .map(|(caller, _)| caller)
+            .collect()
+    }
+
+    /// Groups of functions which call each other, directly or not, found as
+    /// the strongly connected components of the graph with Tarjan's
+    /// algorithm. A function calling itself is a group of its own, functions
+    /// which aren't recursive aren't part of any group. Groups and their
+    /// functions are in the order functions are defined.
+    pub(crate) fn cycles(&self) -> Vec<Vec<&Ident>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: vec![None; self.edges.len()],
+            low: vec![0; self.edges.len()],
+            stack: vec![],
+            next: 0,
+            components: vec![],
+        };
+        for node in 0..self.edges.len() {
+            if tarjan.index[node].is_none() {
+                tarjan.connect(node);
+            }
+        }
+
+        let mut cycles = tarjan
+            .components
+            .into_iter()
+            .filter(|component| match component.as_slice() {
+                &[node] => self
+                    .callees(&self.edges[node].0)
+                    .contains(&self.edges[node].0),
+                _ => true,
+            })
+            .map(|mut component| {
+                component.sort();
+                component
+            })
+            .collect::<Vec<Vec<usize>>>();
+        cycles.sort();
+        cycles
+            .into_iter()
+            .map(|component| component.iter().map(|&node| &self.edges[node].0).collect())
+            .collect()
+    }
+
+    fn node(&self, name: &str) -> Option<usize> {
+        self.edges.iter().position(|(caller, _)| caller == name)
+    }
+}
+
+impl FromIterator<(Ident, Vec<Ident>)> for CallGraph {
+    /// Builds a graph from each caller along with its callees, e.g. of
+    /// circuits and the gates they apply rather than of functions.
+    fn from_iter<I: IntoIterator<Item = (Ident, Vec<Ident>)>>(edges: I) -> Self {
+        Self {
+            edges: edges.into_iter().collect(),
+        }
+    }
+}
+
+/// State of Tarjan's algorithm over the functions of a call graph, each
+/// identified by its position in the graph.
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    /// order in which functions are visited
+    index: Vec<Option<usize>>,
+    /// smallest index reachable from a function through the ones on the stack
+    low: Vec<usize>,
+    stack: Vec<usize>,
+    next: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn connect(&mut self, node: usize) {
+        self.index[node] = Some(self.next);
+        self.low[node] = self.next;
+        self.next += 1;
+        self.stack.push(node);
+
+        // callees which aren't in the graph, e.g. imported ones, are left out
+        let graph = self.graph;
+        for callee in graph.edges[node]
+            .1
+            .iter()
+            .filter_map(|callee| graph.node(callee))
+        {
+            match self.index[callee] {
+                None => {
+                    self.connect(callee);
+                    self.low[node] = self.low[node].min(self.low[callee]);
+                }
+                Some(index) if self.stack.contains(&callee) => {
+                    self.low[node] = self.low[node].min(index);
+                }
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low[node]) == self.index[node] {
+            let start = self.stack.iter().rposition(|&n| n == node).unwrap();
+            self.components.push(self.stack.split_off(start));
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AnalysisReport {
+    pub(crate) functions: Vec<FunctionReport>,
+    pub(crate) warnings: Vec<String>,
+    pub(crate) call_graph: CallGraph,
+}
+
+impl AnalysisReport {
+    pub(crate) fn new(ast: &Qast) -> Self {
+        let mut report = Self::default();
+        for module in ast {
+            for function in &*module {
+                report.functions.push((&*function).into());
+
+                let mut callees = vec![];
+                gather_callees(&function, &mut callees);
+                let mut seen = vec![];
+                callees.retain(|callee| {
+                    let first = Builtin::lookup(callee).is_none() && !seen.contains(callee);
+                    seen.push(callee.clone());
+                    first
+                });
+                report
+                    .call_graph
+                    .edges
+                    .push((function.get_name().clone(), callees));
+            }
+        }
+
+        // the entry point is called by whoever runs the program
+        for function in &report.functions {
+            if function.name != "main" && report.call_graph.callers(&function.name).is_empty() {
+                report
+                    .warnings
+                    .push(format!("function `{}` is never called", function.name));
+            }
+        }
+
+        // a function without instructions can't return what it declares
+        for module in ast {
+            for function in &*module {
+                let output = function.get_output_type();
+                if function.into_iter().next().is_none()
+                    && !matches!(output, Type::Bottom | Type::Unit)
+                {
+                    report.warnings.push(format!(
+                        "function `{}` {} returns {} but its body is empty",
+                        function.get_name(),
+                        function.get_loc(),
+                        output
+                    ));
+                }
+            }
+        }
+
+        // recursion can't be unrolled into a circuit
+        let location = |name: &Ident| match report.function(name) {
+            Some(function) => format!("`{}` {}", name, function.location),
+            None => format!("`{}`", name),
+        };
+        let mut recursive = vec![];
+        for cycle in report.call_graph.cycles() {
+            let functions = cycle.iter().map(|name| location(name)).collect::<Vec<_>>();
+            recursive.push(match functions.as_slice() {
+                [function] => format!("function {} calls itself", function),
+                _ => format!("functions {} call each other", functions.join(", ")),
+            });
+        }
+        report.warnings.extend(recursive);
+        report
+    }
+
+    pub(crate) fn function(&self, name: &str) -> Option<&FunctionReport> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+impl fmt::Display for AnalysisReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16}{:<8}{:<14}{:<9}{}",
+            "function", "params", "instructions", "quantum", "calls"
+        )?;
+        for function in &self.functions {
+            let row = format!(
+                "{:<16}{:<8}{:<14}{:<9}{}",
+                function.name,
+                function.params,
+                function.instructions,
+                if function.quantum { "yes" } else { "no" },
+                self.call_graph.callees(&function.name).join(", ")
+            );
+            writeln!(f, "{}", row.trim_end())?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
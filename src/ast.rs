@@ -1,9 +1,12 @@
 //! QAST is an abstract representation for quale language.
 use crate::attributes::Attributes;
+use crate::builtins::Builtin;
+use crate::complex::Complex;
 use crate::error::{QccError, QccErrorKind};
 use crate::lexer::Location;
-use crate::types::Type;
+use crate::types::{Signature, Type};
 use std::borrow::Borrow;
+use std::collections::HashMap;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub(crate) enum Token {
@@ -24,10 +27,16 @@ pub(crate) enum Token {
     Sub = '-' as isize,
     Mul = '*' as isize,
     Div = '/' as isize,
+    BitAnd = '&' as isize,
+    BitOr = '|' as isize,
+    BitXor = '^' as isize,
+    Tilde = '~' as isize,
+    Lt = '<' as isize,
+    Gt = '>' as isize,
 
     /* Eof is replaced by None, Option<Token> is used. */
     Identifier = -1,
-    Literal = -2,
+    Literal = -2, // string literal, quotes included
     Attribute = -3,
     Function = -4,
     Multi = -5, // token comprises of more than one chars
@@ -39,11 +48,40 @@ pub(crate) enum Token {
     Import = -11,
     Let = -12,
     Qbit = -13,
+    Measure = -14,
+    Arrow = -15, // ->
+    Alias = -16,
+    Gate = -17,
+    If = -18,
+    Else = -19,
+    Eq = -20,  // ==
+    Neq = -21, // !=
+    Le = -22,  // <=
+    Ge = -23,  // >=
+    Use = -24,
+    Pub = -25,
+    Operator = -26, // defined by a function, e.g. `**`
+    While = -27,
+    At = '@' as isize,
 }
 
 impl Token {
     pub(crate) fn all_binops() -> &'static [Self] {
-        &[Self::Add, Self::Sub, Self::Mul, Self::Div]
+        &[
+            Self::Add,
+            Self::Sub,
+            Self::Mul,
+            Self::Div,
+            Self::BitAnd,
+            Self::BitOr,
+            Self::BitXor,
+            Self::Eq,
+            Self::Neq,
+            Self::Lt,
+            Self::Gt,
+            Self::Le,
+            Self::Ge,
+        ]
     }
 }
 
@@ -64,6 +102,52 @@ impl Qast {
     pub(crate) fn append_module(&mut self, module: ModuleAST) {
         self.modules.push(std::rc::Rc::new(module.into()));
     }
+
+    /// Combines two independently parsed ASTs into one, keeping modules of
+    /// `self` before those of `other`.
+    ///
+    /// A module parsed more than once, e.g. the file of a module imported by
+    /// two others, is kept once. It is the same module if it is defined at the
+    /// same location, and its functions are told apart by name and signature:
+    /// a function carried twice is kept once, but another function of the
+    /// same name is `QccErrorKind::DuplicateFunction`. Gates of both are the
+    /// same, and re-exports of both are kept. Other modules of the same name
+    /// are `QccErrorKind::DuplicateModule`.
+    pub fn merge(mut self, other: Qast) -> Result<Qast, QccError> {
+        for module in other.modules {
+            let name = module.as_ref().borrow().get_name();
+            let Some(this) = self
+                .modules
+                .iter()
+                .find(|this| this.as_ref().borrow().get_name() == name)
+            else {
+                self.modules.push(module);
+                continue;
+            };
+
+            let mut this = this.as_ref().borrow_mut();
+            let module = module.as_ref().borrow();
+            if this.location != module.location {
+                Err(QccErrorKind::DuplicateModule)?
+            }
+            for function in &module.functions {
+                let signature = function.as_ref().borrow().signature();
+                let same_name = this.functions.iter().find(|this| {
+                    this.as_ref().borrow().get_name() == function.as_ref().borrow().get_name()
+                });
+                match same_name {
+                    Some(this) if this.as_ref().borrow().signature() == signature => {}
+                    Some(_) => Err(QccErrorKind::DuplicateFunction)?,
+                    None => this.functions.push(function.clone()),
+                }
+            }
+            for (fn_name, mod_name) in &module.reexports {
+                this.add_reexport(fn_name.clone(), mod_name.clone());
+            }
+        }
+
+        Ok(self)
+    }
 }
 
 impl<'a> IntoIterator for &'a Qast {
@@ -92,10 +176,16 @@ impl<'a> IntoIterator for &'a mut Qast {
     }
 }
 
+/// Prints the AST with the location of each module and function as a comment.
+/// The alternate form `{:#}` leaves locations out, see `--ast-locations`.
 impl std::fmt::Display for Qast {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for module in &self.modules {
-            writeln!(f, "{}", module.as_ref().borrow())?;
+            if f.alternate() {
+                writeln!(f, "{:#}", module.as_ref().borrow())?;
+            } else {
+                writeln!(f, "{}", module.as_ref().borrow())?;
+            }
         }
         Ok(())
     }
@@ -106,6 +196,10 @@ pub struct ModuleAST {
     name: Ident,
     location: Location,
     functions: Vec<QccCell<FunctionAST>>,
+    gates: Vec<GateAST>,
+    /// functions imported with `pub import`, along with the module defining
+    /// them, which modules importing this one see as well
+    reexports: Vec<(Ident, Ident)>,
 }
 
 impl ModuleAST {
@@ -118,13 +212,53 @@ impl ModuleAST {
             name,
             location,
             functions,
+            gates: Default::default(),
+            reexports: Default::default(),
         }
     }
 
+    /// Re-exports `fn_name` defined in module `mod_name`.
+    pub(crate) fn add_reexport(&mut self, fn_name: Ident, mod_name: Ident) {
+        if !self
+            .reexports
+            .contains(&(fn_name.clone(), mod_name.clone()))
+        {
+            self.reexports.push((fn_name, mod_name));
+        }
+    }
+
+    /// Module defining `fn_name` if this module re-exports it.
+    pub(crate) fn reexport(&self, fn_name: &Ident) -> Option<&Ident> {
+        self.reexports
+            .iter()
+            .find_map(|(name, module)| (name == fn_name).then_some(module))
+    }
+
+    pub(crate) fn append_gate(&mut self, gate: GateAST) {
+        self.gates.push(gate);
+    }
+
+    #[inline]
+    pub(crate) fn iter_gates(&self) -> impl Iterator<Item = &GateAST> + '_ {
+        self.gates.iter()
+    }
+
     pub(crate) fn append_function(&mut self, function: FunctionAST) {
         self.functions.push(std::rc::Rc::new(function.into()));
     }
 
+    /// Returns the functions of the module, shared with it.
+    #[inline]
+    pub(crate) fn iter_functions(&self) -> impl Iterator<Item = &QccCell<FunctionAST>> + '_ {
+        self.functions.iter()
+    }
+
+    /// Keeps only functions for which `keep` returns true.
+    pub(crate) fn retain_functions(&mut self, mut keep: impl FnMut(&FunctionAST) -> bool) {
+        self.functions
+            .retain(|function| keep(&function.as_ref().borrow()));
+    }
+
     #[inline]
     pub(crate) fn get_name(&self) -> Ident {
         self.name.clone()
@@ -159,10 +293,24 @@ impl<'a> IntoIterator for &'a mut ModuleAST {
 
 impl std::fmt::Display for ModuleAST {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "module {} {{  // {}", self.name, self.location)?;
+        if f.alternate() {
+            writeln!(f, "module {} {{", self.name)?;
+        } else {
+            writeln!(f, "module {} {{  // {}", self.name, self.location)?;
+        }
+        for (fn_name, mod_name) in &self.reexports {
+            writeln!(f, "pub import {}::{};", mod_name, fn_name)?;
+        }
+        for gate in &self.gates {
+            writeln!(f, "{}", gate)?;
+        }
         for function in &self.functions {
             // TODO: Add tab before each function line for pretty printing.
-            writeln!(f, "{}", function.as_ref().borrow())?;
+            if f.alternate() {
+                writeln!(f, "{:#}", function.as_ref().borrow())?;
+            } else {
+                writeln!(f, "{}", function.as_ref().borrow())?;
+            }
         }
         writeln!(f, "}}")?;
         Ok(())
@@ -245,7 +393,13 @@ impl VarAST {
     /// # NOTE: It does not check for untyped variables.
     #[inline]
     pub(crate) fn get_type(&self) -> Type {
-        self.type_
+        self.type_.clone()
+    }
+
+    /// Checks if the variable is negated, as in `-x`.
+    #[inline]
+    pub(crate) fn is_negative(&self) -> bool {
+        self.unary_negative
     }
 }
 
@@ -277,6 +431,7 @@ impl From<VarAST> for QccCell<Expr> {
 }
 
 /// Mathematical operators.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Opcode {
     Add,
     Sub,
@@ -284,6 +439,71 @@ pub(crate) enum Opcode {
     Div,
     Eq,
     Neq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+}
+
+impl Opcode {
+    /// Binding strength of the operator, operators with a higher precedence
+    /// are grouped first. Bitwise operators bind looser than arithmetic ones,
+    /// in the same order as in C.
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Self::BitOr => 1,
+            Self::BitXor => 2,
+            Self::BitAnd => 3,
+            Self::Eq | Self::Neq | Self::Lt | Self::Gt | Self::Le | Self::Ge => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div => 6,
+        }
+    }
+
+    /// Checks if the operator works on classical bits.
+    pub(crate) fn is_bitwise(&self) -> bool {
+        matches!(self, Self::BitAnd | Self::BitOr | Self::BitXor)
+    }
+
+    /// Checks if the operator compares its operands, which results in a bit
+    /// whatever they are.
+    pub(crate) fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Neq | Self::Lt | Self::Gt | Self::Le | Self::Ge
+        )
+    }
+
+    /// Applies the operator to operands known at compile time. Comparisons
+    /// are 1 if they hold and 0 otherwise.
+    pub(crate) fn eval(&self, lhs: f64, rhs: f64) -> Option<f64> {
+        let holds = |cond: bool| Some(if cond { 1.0 } else { 0.0 });
+        match self {
+            Self::Add => Some(lhs + rhs),
+            Self::Sub => Some(lhs - rhs),
+            Self::Mul => Some(lhs * rhs),
+            Self::Div => Some(lhs / rhs),
+            Self::Eq => holds(lhs == rhs),
+            Self::Neq => holds(lhs != rhs),
+            Self::Lt => holds(lhs < rhs),
+            Self::Gt => holds(lhs > rhs),
+            Self::Le => holds(lhs <= rhs),
+            Self::Ge => holds(lhs >= rhs),
+            _ => None,
+        }
+    }
+
+    /// Evaluates the operator like `eval` on operands of type `ty`, where
+    /// integer division truncates toward zero.
+    pub(crate) fn eval_as(&self, lhs: f64, rhs: f64, ty: &Type) -> Option<f64> {
+        match self {
+            Self::Div if ty.is_integer() => Some((lhs / rhs).trunc()),
+            _ => self.eval(lhs, rhs),
+        }
+    }
 }
 
 impl std::str::FromStr for Opcode {
@@ -296,6 +516,13 @@ impl std::str::FromStr for Opcode {
             "/" => Ok(Self::Div),
             "==" => Ok(Self::Eq),
             "!=" => Ok(Self::Neq),
+            "<" => Ok(Self::Lt),
+            ">" => Ok(Self::Gt),
+            "<=" => Ok(Self::Le),
+            ">=" => Ok(Self::Ge),
+            "&" => Ok(Self::BitAnd),
+            "|" => Ok(Self::BitOr),
+            "^" => Ok(Self::BitXor),
             _ => Err(QccErrorKind::UnknownOpcode.into()),
         }
     }
@@ -310,19 +537,57 @@ impl std::fmt::Display for Opcode {
             Self::Div => write!(f, "/")?,
             Self::Eq => write!(f, "==")?,
             Self::Neq => write!(f, "!=")?,
+            Self::Lt => write!(f, "<")?,
+            Self::Gt => write!(f, ">")?,
+            Self::Le => write!(f, "<=")?,
+            Self::Ge => write!(f, ">=")?,
+            Self::BitAnd => write!(f, "&")?,
+            Self::BitOr => write!(f, "|")?,
+            Self::BitXor => write!(f, "^")?,
         }
         Ok(())
     }
 }
 
+/// How far the squared magnitudes of the amplitudes of a qubit may sum from
+/// one, which allows amplitudes like `0.707` for 1/√2.
+pub(crate) const NORM_TOLERANCE: f64 = 1e-3;
+
 pub(crate) struct Qbit {
-    amp_0: f64,
-    amp_1: f64,
+    amp_0: QccCell<Expr>,
+    amp_1: QccCell<Expr>,
+}
+
+impl Qbit {
+    pub(crate) fn new(amp_0: QccCell<Expr>, amp_1: QccCell<Expr>) -> Self {
+        Self { amp_0, amp_1 }
+    }
+
+    /// Returns the pair of amplitudes if both are known at compile time.
+    pub(crate) fn amplitudes(&self) -> Option<(Complex, Complex)> {
+        let amp_0 = self.amp_0.as_ref().borrow().eval_complex()?;
+        let amp_1 = self.amp_1.as_ref().borrow().eval_complex()?;
+        Some((amp_0, amp_1))
+    }
+
+    /// Checks if the squared magnitudes of the amplitudes sum to one, see
+    /// `NORM_TOLERANCE`. Amplitudes only known at runtime are left to the
+    /// program.
+    pub(crate) fn is_normalized(&self) -> bool {
+        self.amplitudes().map_or(true, |(amp_0, amp_1)| {
+            (amp_0.norm_sqr() + amp_1.norm_sqr() - 1.0).abs() <= NORM_TOLERANCE
+        })
+    }
 }
 
 impl std::fmt::Display for Qbit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "0q{}_{}", self.amp_0, self.amp_1)
+        write!(
+            f,
+            "0q{}_{}",
+            self.amp_0.as_ref().borrow(),
+            self.amp_1.as_ref().borrow()
+        )
     }
 }
 
@@ -331,13 +596,20 @@ impl std::str::FromStr for Qbit {
 
     /// A quantum numeral should be of the form `0q(<amplitude>, amplitude)`
     /// where the pair of amplitudes are probability amplitudes for zero and one
-    /// basis vectors respectively.
+    /// basis vectors respectively, or `0q0` and `0q1` for the basis vectors. An amplitude is either real or complex as in
+    /// `0q(0.6, 0.8i)` or `0q(0.6+0.0i, 0-0.8i)`. Only literal amplitudes can
+    /// be read from a string, the parser handles amplitudes which are
+    /// expressions. Amplitudes must be normalized, see `Qbit::is_normalized`.
     fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         if !s.starts_with("0q") {
             Err(QccErrorKind::ExpectedQbit)?
         }
 
-        let s = s.trim_start_matches("0q");
+        let s = match s.trim_start_matches("0q") {
+            "0" => "(1, 0)",
+            "1" => "(0, 1)",
+            s => s,
+        };
 
         if !s.starts_with('(') || !s.ends_with(')') {
             Err(QccErrorKind::ExpectedParenth)?
@@ -348,42 +620,69 @@ impl std::str::FromStr for Qbit {
             .split_once(',')
             .ok_or(QccErrorKind::ExpectedComma)?;
 
-        let amp_0 = s1.trim().parse::<f64>();
-        if amp_0.is_err() {
-            Err(QccErrorKind::ExpectedAmpinQbit)?
-        }
-        let amp_0 = amp_0.unwrap();
-        let amp_1 = s2.trim().parse::<f64>();
-        if amp_1.is_err() {
-            Err(QccErrorKind::ExpectedAmpinQbit)?
+        let amplitude = |s: &str| -> core::result::Result<QccCell<Expr>, Self::Err> {
+            let amp = s
+                .trim()
+                .parse::<Complex>()
+                .map_err(|_| QccErrorKind::ExpectedAmpinQbit)?;
+
+            let re = Expr::Literal(LiteralAST::Lit_Digit(amp.re).into());
+            let im = Expr::Literal(LiteralAST::Lit_Imag(amp.im).into());
+            Ok(if amp.is_real() {
+                re.into()
+            } else if amp.re == 0.0 {
+                im.into()
+            } else {
+                Expr::BinaryExpr(re.into(), Opcode::Add, im.into()).into()
+            })
+        };
+
+        let qbit = Self::new(amplitude(s1)?, amplitude(s2)?);
+        if !qbit.is_normalized() {
+            Err(QccErrorKind::UnnormalizedQbit)?
         }
-        let amp_1 = amp_1.unwrap();
-
-        Ok(Self { amp_0, amp_1 })
+        Ok(qbit)
     }
 }
 
 pub(crate) enum LiteralAST {
     Lit_Qbit(Qbit),
     Lit_Digit(f64),
+    /// digit written without a fraction, a float unless an integer is
+    /// expected, see `Expr::get_type_as`
+    Lit_Int(i64),
+    /// imaginary number written with an `i` suffix, e.g. `0.5i`
+    Lit_Imag(f64),
     Lit_Str(Vec<u8>), // does not store the quotations around str
+    /// tensor of expressions written as `[e0, e1, ..]`, found where its `[` is
+    Lit_Tensor(Vec<QccCell<Expr>>, Location),
 }
 
 impl std::str::FromStr for LiteralAST {
     type Err = QccErrorKind;
     fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
-        if s.starts_with("\"") {
-            if s.ends_with("\"") {
-                return Err(QccErrorKind::UnexpectedStr)?;
-            }
+        if let Some(quoted) = s.strip_prefix('"') {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or(QccErrorKind::UnexpectedStr)?;
 
+            // escapes are `\n`, `\t`, `\"` and `\\`
             let mut v = vec![];
-            while let Some(c) = s.chars().next() {
-                if c != '\"' {
-                    v.push(c as u8);
-                }
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                v.push(match c {
+                    '\\' => match chars.next() {
+                        Some('n') => b'\n',
+                        Some('t') => b'\t',
+                        Some('"') => b'"',
+                        Some('\\') => b'\\',
+                        _ => Err(QccErrorKind::UnexpectedStr)?,
+                    },
+                    '"' => Err(QccErrorKind::UnexpectedStr)?,
+                    c => c as u8,
+                });
             }
-            return Ok(Self::Lit_Str((v)));
+            Ok(Self::Lit_Str(v))
         } else if s.starts_with("0q") {
             // quantum numeral
             let qn = s.parse::<Qbit>()?;
@@ -403,14 +702,28 @@ impl std::fmt::Display for LiteralAST {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             LiteralAST::Lit_Digit(d) => write!(f, "{}", d),
+            LiteralAST::Lit_Int(n) => write!(f, "{}", n),
+            LiteralAST::Lit_Imag(d) => write!(f, "{}i", d),
             LiteralAST::Lit_Str(s) => {
                 write!(f, "\"")?;
                 for &c in s {
-                    write!(f, "{}", c as char)?;
+                    match c {
+                        b'\n' => write!(f, "\\n")?,
+                        b'\t' => write!(f, "\\t")?,
+                        b'"' | b'\\' => write!(f, "\\{}", c as char)?,
+                        _ => write!(f, "{}", c as char)?,
+                    }
                 }
                 write!(f, "\"")
             }
             LiteralAST::Lit_Qbit(qn) => write!(f, "{}", qn),
+            LiteralAST::Lit_Tensor(elems, _) => {
+                let elems = elems
+                    .iter()
+                    .map(|elem| elem.as_ref().borrow().to_string())
+                    .collect::<Vec<String>>();
+                write!(f, "[{}]", elems.join(", "))
+            }
         }
     }
 }
@@ -457,43 +770,234 @@ pub enum Expr {
     FnCall(FunctionAST, Vec<QccCell<Expr>>),
     Let(VarAST, QccCell<Expr>),
     Literal(QccCell<LiteralAST>),
+    /// `if cond { truth } else { otherwise }`, where an `else if` is a
+    /// conditional nested in the else block.
+    Conditional(QccCell<Expr>, Vec<QccCell<Expr>>, Vec<QccCell<Expr>>),
+    /// `while (cond) { body }`, which is unrolled when lowered to a circuit
+    Loop(QccCell<Expr>, Vec<QccCell<Expr>>),
 }
 
 impl Expr {
+    /// Copies the expression without sharing any subexpression, replacing
+    /// each type by `subst` of it.
+    pub(crate) fn copy_with(&self, subst: &dyn Fn(&Type) -> Type) -> Expr {
+        let copy = |expr: &QccCell<Expr>| -> QccCell<Expr> {
+            expr.as_ref().borrow().copy_with(subst).into()
+        };
+        let var_copy = |var: &VarAST| {
+            let mut var = var.clone();
+            var.set_type(subst(&var.get_type()));
+            var
+        };
+
+        match self {
+            Self::Var(var) => Self::Var(var_copy(var)),
+            Self::BinaryExpr(lhs, op, rhs) => Self::BinaryExpr(copy(lhs), *op, copy(rhs)),
+            Self::FnCall(f, args) => {
+                Self::FnCall(f.copy_with(subst), args.iter().map(copy).collect())
+            }
+            Self::Let(var, val) => Self::Let(var_copy(var), copy(val)),
+            Self::Conditional(cond, truth, otherwise) => Self::Conditional(
+                copy(cond),
+                truth.iter().map(copy).collect(),
+                otherwise.iter().map(copy).collect(),
+            ),
+            Self::Loop(cond, body) => Self::Loop(copy(cond), body.iter().map(copy).collect()),
+            Self::Literal(lit) => Self::Literal(
+                match *lit.as_ref().borrow() {
+                    LiteralAST::Lit_Qbit(ref qbit) => {
+                        LiteralAST::Lit_Qbit(Qbit::new(copy(&qbit.amp_0), copy(&qbit.amp_1)))
+                    }
+                    LiteralAST::Lit_Digit(digit) => LiteralAST::Lit_Digit(digit),
+                    LiteralAST::Lit_Int(n) => LiteralAST::Lit_Int(n),
+                    LiteralAST::Lit_Imag(digit) => LiteralAST::Lit_Imag(digit),
+                    LiteralAST::Lit_Str(ref s) => LiteralAST::Lit_Str(s.clone()),
+                    LiteralAST::Lit_Tensor(ref elems, ref location) => {
+                        LiteralAST::Lit_Tensor(elems.iter().map(copy).collect(), location.clone())
+                    }
+                }
+                .into(),
+            ),
+        }
+    }
+
     pub(crate) fn get_location(&self) -> Location {
         match &self {
             Self::Var(v) => v.location().clone(),
-            Self::BinaryExpr(lhs, _, _) => lhs.as_ref().borrow().get_location(),
+            Self::BinaryExpr(lhs, _, rhs) => {
+                let lhs = lhs.as_ref().borrow().get_location();
+                lhs.merge(&rhs.as_ref().borrow().get_location())
+            }
             Self::FnCall(f, _) => f.get_loc().clone(),
             Self::Let(var, _) => var.location.clone(),
-            Self::Literal(lit) =>
-            /*TODO*/
-            {
-                Default::default()
+            Self::Conditional(cond, ..) | Self::Loop(cond, _) => {
+                cond.as_ref().borrow().get_location()
+            }
+            Self::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Tensor(_, ref location) => location.clone(),
+                /*TODO*/
+                _ => Default::default(),
+            },
+        }
+    }
+
+    /// Evaluates a classical expression whose value is known at compile time,
+    /// i.e. it is built only from digits, arithmetic and math builtins.
+    pub(crate) fn eval_const(&self) -> Option<f64> {
+        match self {
+            Self::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Digit(digit) => Some(digit),
+                LiteralAST::Lit_Int(n) => Some(n as f64),
+                _ => None,
+            },
+            Self::BinaryExpr(lhs, op, rhs) => {
+                let lhs = lhs.as_ref().borrow().eval_const()?;
+                let rhs = rhs.as_ref().borrow().eval_const()?;
+                op.eval(lhs, rhs)
+            }
+            Self::FnCall(f, args) => {
+                let builtin = Builtin::lookup(f.get_name())?;
+                let args = args
+                    .iter()
+                    .map(|arg| arg.as_ref().borrow().eval_const())
+                    .collect::<Option<Vec<f64>>>()?;
+                builtin.eval(&args)
             }
+            Self::Var(_) | Self::Let(..) | Self::Conditional(..) | Self::Loop(..) => None,
         }
     }
 
-    pub(crate) fn get_type(&self) -> Type {
-        match &self {
-            Self::Var(v) => v.get_type(),
+    /// Evaluates an expression built only from integers and `+`, `-`, `*`, `/`
+    /// in 64 bits, where division truncates toward zero. It is `None` if the
+    /// expression is not such an expression or divides by zero, and
+    /// `QccErrorKind::IntegerOverflow` if evaluating it overflows.
+    pub(crate) fn eval_int(&self) -> Result<Option<i64>, QccError> {
+        match self {
+            Self::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Int(n) => Ok(Some(n)),
+                _ => Ok(None),
+            },
             Self::BinaryExpr(lhs, op, rhs) => {
-                if lhs.as_ref().borrow().get_type() == rhs.as_ref().borrow().get_type() {
-                    return lhs.as_ref().borrow().get_type();
-                } else {
-                    // TODO
-                    return Type::Bottom;
+                let Some(lhs) = lhs.as_ref().borrow().eval_int()? else {
+                    return Ok(None);
+                };
+                let Some(rhs) = rhs.as_ref().borrow().eval_int()? else {
+                    return Ok(None);
+                };
+                let value = match op {
+                    Opcode::Add => lhs.checked_add(rhs),
+                    Opcode::Sub => lhs.checked_sub(rhs),
+                    Opcode::Mul => lhs.checked_mul(rhs),
+                    // dividing by zero is left for the program to do
+                    Opcode::Div if rhs == 0 => return Ok(None),
+                    Opcode::Div => lhs.checked_div(rhs),
+                    _ => return Ok(None),
+                };
+                Ok(Some(value.ok_or(QccErrorKind::IntegerOverflow)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluates an expression whose value is known at compile time like
+    /// `eval_const`, but also allows imaginary numbers.
+    pub(crate) fn eval_complex(&self) -> Option<Complex> {
+        match self {
+            Self::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Digit(digit) => Some(digit.into()),
+                LiteralAST::Lit_Int(n) => Some((n as f64).into()),
+                LiteralAST::Lit_Imag(digit) => Some(Complex::new(0.0, digit)),
+                _ => None,
+            },
+            Self::BinaryExpr(lhs, op, rhs) => {
+                let lhs = lhs.as_ref().borrow().eval_complex()?;
+                let rhs = rhs.as_ref().borrow().eval_complex()?;
+                match op {
+                    Opcode::Add => Some(lhs + rhs),
+                    Opcode::Sub => Some(lhs - rhs),
+                    Opcode::Mul => Some(lhs * rhs),
+                    Opcode::Div => Some(lhs / rhs),
+                    _ => None,
                 }
             }
-            Self::FnCall(f, args) => *f.get_output_type(),
+            _ => self.eval_const().map(Complex::from),
+        }
+    }
+
+    pub(crate) fn get_type(&self) -> Type {
+        match &self {
+            Self::Var(v) => v.get_type(),
+            Self::BinaryExpr(..) => self.get_type_as(&Type::Bottom),
+            Self::FnCall(f, args) => f.get_output_type().clone(),
             Self::Let(var, val) => var.get_type(),
+            Self::Conditional(..) | Self::Loop(..) => Type::Unit,
             Self::Literal(lit) => match *lit.as_ref().borrow() {
-                LiteralAST::Lit_Str(_) => Type::Bottom,
-                LiteralAST::Lit_Digit(_) => Type::F64,
+                LiteralAST::Lit_Str(_) => Type::Str,
+                LiteralAST::Lit_Digit(_) | LiteralAST::Lit_Int(_) => Type::F64,
+                LiteralAST::Lit_Imag(_) => Type::Complex,
                 LiteralAST::Lit_Qbit(_) => Type::Qbit,
+                LiteralAST::Lit_Tensor(ref elems, _) => Type::tensor(
+                    &elems
+                        .iter()
+                        .map(|elem| elem.as_ref().borrow().get_type())
+                        .collect::<Vec<Type>>(),
+                )
+                .unwrap_or_default(),
             },
         }
     }
+
+    /// Returns the type of an expression where a type is expected, e.g. the
+    /// declared type of a let binding. Digits written without a fraction are
+    /// integers where integers are expected, and so are they next to an
+    /// integer operand.
+    pub(crate) fn get_type_as(&self, expected: &Type) -> Type {
+        match self {
+            Self::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Int(n) if expected.represents(n as f64) => expected.clone(),
+                _ => self.get_type(),
+            },
+            Self::BinaryExpr(lhs, op, rhs) => {
+                Self::binary_type(&lhs.as_ref().borrow(), op, &rhs.as_ref().borrow(), expected)
+                    .unwrap_or_default()
+            }
+            _ => self.get_type(),
+        }
+    }
+
+    /// Returns the type of a binary expression, or `Bottom` if its operands
+    /// don't go together. A matrix times qubits is the qubits it transforms,
+    /// and it fails unless the matrix is of the size of their state.
+    pub(crate) fn binary_type(
+        lhs: &Expr,
+        op: &Opcode,
+        rhs: &Expr,
+        expected: &Type,
+    ) -> Result<Type, QccErrorKind> {
+        if *op == Opcode::Mul {
+            if let Some(transformed) = lhs.get_type().transform(&rhs.get_type()) {
+                return transformed;
+            }
+        }
+
+        // operands are compared as the type they are promoted to, which
+        // doesn't depend on the bit expected
+        if op.is_comparison() {
+            return Ok(match Self::promoted_type(lhs, rhs, &Type::Bottom) {
+                Type::Bottom => Type::Bottom,
+                _ => Type::Bit,
+            });
+        }
+        Ok(Self::promoted_type(lhs, rhs, expected))
+    }
+
+    /// Returns the type operands of a binary expression are promoted to, see
+    /// `Type::bigtype`, or `Bottom` if they don't go together.
+    pub(crate) fn promoted_type(lhs: &Expr, rhs: &Expr, expected: &Type) -> Type {
+        let lhs_type = lhs.get_type_as(&rhs.get_type_as(expected));
+        let rhs_type = rhs.get_type_as(&lhs_type);
+        lhs_type.bigtype(&rhs_type).unwrap_or_default()
+    }
 }
 
 impl From<Expr> for QccCell<Expr> {
@@ -536,34 +1040,90 @@ impl From<Expr> for QccCell<Expr> {
 
 impl std::fmt::Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Var(v) => write!(f, "{}", v),
-            Self::BinaryExpr(lhs, op, rhs) => {
-                write!(
-                    f,
-                    "({} {} {})",
-                    *lhs.as_ref().borrow(),
-                    op,
-                    *rhs.as_ref().borrow()
-                )
+        self.display_with(&|name| name.clone()).fmt(f)
+    }
+}
+
+impl Expr {
+    /// Displays the expression with each function it calls named by `callee`,
+    /// e.g. to name calls to imported functions as they are written.
+    pub(crate) fn display_with<'a>(
+        &'a self,
+        callee: &'a dyn Fn(&Ident) -> Ident,
+    ) -> ExprDisplay<'a> {
+        ExprDisplay { expr: self, callee }
+    }
+}
+
+/// An expression displayed with its callees renamed, see `Expr::display_with`.
+pub(crate) struct ExprDisplay<'a> {
+    expr: &'a Expr,
+    callee: &'a dyn Fn(&Ident) -> Ident,
+}
+
+impl std::fmt::Display for ExprDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show =
+            |expr: &QccCell<Expr>| expr.as_ref().borrow().display_with(self.callee).to_string();
+        match self.expr {
+            Expr::Var(v) => write!(f, "{}", v),
+            Expr::BinaryExpr(lhs, op, rhs) => write!(f, "({} {} {})", show(lhs), op, show(rhs)),
+            // `reg[i]` is a call to the builtin indexing a register
+            Expr::FnCall(function, args)
+                if Builtin::lookup(&function.name) == Some(Builtin::Index) && args.len() == 2 =>
+            {
+                write!(f, "{}[{}]", show(&args[0]), show(&args[1]))
             }
-            Self::FnCall(function, args) => {
+            Expr::FnCall(function, args) => {
+                let name = (self.callee)(&function.name);
                 if *function.get_output_type() != Type::Bottom {
-                    write!(f, "{}: {} (", function.name, function.output_type)?;
+                    write!(f, "{}: {} (", name, function.output_type)?;
                 } else {
-                    write!(f, "{}(", function.name)?;
+                    write!(f, "{}(", name)?;
                 }
-                let args_str = args
-                    .iter()
-                    .map(|p| p.as_ref().borrow().to_string())
-                    .collect::<Vec<String>>()
-                    .join(", ");
+                let args_str = args.iter().map(show).collect::<Vec<String>>().join(", ");
                 write!(f, "{args_str}")?;
                 write!(f, ")")?;
                 Ok(())
             }
-            Self::Let(var, val) => write!(f, "{} = {}", var, *val.as_ref().borrow()),
-            Self::Literal(lit) => write!(f, "{}", *lit.as_ref().borrow()),
+            Expr::Let(var, val) => write!(f, "{} = {}", var, show(val)),
+            Expr::Literal(lit) => match *lit.as_ref().borrow() {
+                // elements of a tensor may call functions too
+                LiteralAST::Lit_Tensor(ref elems, _) => {
+                    let elems = elems.iter().map(show).collect::<Vec<String>>();
+                    write!(f, "[{}]", elems.join(", "))
+                }
+                ref lit => write!(f, "{}", lit),
+            },
+            Expr::Conditional(cond, truth, otherwise) => {
+                match *cond.as_ref().borrow() {
+                    Expr::Let(..) => writeln!(f, "if let {} {{", show(cond))?,
+                    _ => writeln!(f, "if {} {{", show(cond))?,
+                }
+                for expr in truth {
+                    for line in show(expr).lines() {
+                        writeln!(f, "    {}", line)?;
+                    }
+                }
+                if !otherwise.is_empty() {
+                    writeln!(f, "}} else {{")?;
+                    for expr in otherwise {
+                        for line in show(expr).lines() {
+                            writeln!(f, "    {}", line)?;
+                        }
+                    }
+                }
+                write!(f, "}}")
+            }
+            Expr::Loop(cond, body) => {
+                writeln!(f, "while {} {{", show(cond))?;
+                for expr in body {
+                    for line in show(expr).lines() {
+                        writeln!(f, "    {}", line)?;
+                    }
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -577,6 +1137,9 @@ pub struct FunctionAST {
     output_type: Type,
     attrs: Attributes,
     body: Vec<QccCell<Expr>>,
+    nested: Vec<FunctionAST>, // functions defined inside the body
+    /// compile-time sizes of registers, as in `fn qft<n>(reg: qbit[n])`
+    generics: Vec<Ident>,
 }
 
 // impl Expr for FunctionAST {}
@@ -599,9 +1162,83 @@ impl FunctionAST {
             output_type,
             attrs,
             body,
+            nested: Default::default(),
+            generics: Default::default(),
         }
     }
 
+    pub(crate) fn set_generics(&mut self, generics: Vec<Ident>) {
+        self.generics = generics;
+    }
+
+    #[inline]
+    pub(crate) fn get_generics(&self) -> &[Ident] {
+        &self.generics
+    }
+
+    /// Returns a copy of a generic function named `name`, where each register
+    /// sized by a generic is given the size in `sizes`. Nothing is shared with
+    /// the generic function, so both can be inferred independently.
+    pub(crate) fn instantiate(&self, name: Ident, sizes: &HashMap<Ident, usize>) -> Self {
+        let mut instance = self.copy_with(&|ty| ty.substitute(sizes));
+        instance.name = name;
+        instance.generics.clear();
+        instance
+    }
+
+    fn copy_with(&self, subst: &dyn Fn(&Type) -> Type) -> Self {
+        let params = self
+            .params
+            .iter()
+            .map(|param| {
+                let mut param = param.clone();
+                param.set_type(subst(&param.get_type()));
+                param
+            })
+            .collect::<Vec<VarAST>>();
+        Self {
+            name: self.name.clone(),
+            location: self.location.clone(),
+            input_type: params.iter().map(VarAST::get_type).collect(),
+            params,
+            output_type: subst(&self.output_type),
+            attrs: self.attrs.clone(),
+            body: self
+                .body
+                .iter()
+                .map(|expr| expr.as_ref().borrow().copy_with(subst).into())
+                .collect(),
+            nested: self.nested.iter().map(|f| f.copy_with(subst)).collect(),
+            generics: self.generics.clone(),
+        }
+    }
+
+    /// Appends a function defined inside the body of this function. It can
+    /// refer to parameters of this function.
+    pub(crate) fn append_nested(&mut self, function: FunctionAST) {
+        self.nested.push(function);
+    }
+
+    /// Removes all instructions of the body and returns them.
+    pub(crate) fn take_body(&mut self) -> Vec<QccCell<Expr>> {
+        std::mem::take(&mut self.body)
+    }
+
+    pub(crate) fn set_body(&mut self, body: Vec<QccCell<Expr>>) {
+        self.body = body;
+    }
+
+    /// Removes all nested functions and returns them.
+    pub(crate) fn take_nested(&mut self) -> Vec<FunctionAST> {
+        std::mem::take(&mut self.nested)
+    }
+
+    /// Appends a parameter along with its type.
+    pub(crate) fn append_param(&mut self, param: VarAST) {
+        self.input_type.push(param.get_type());
+        self.params.push(param);
+    }
+
     /// Inserts the input type in function. This should be called successively
     /// for many-parametered functions to append types for each parameter into a
     /// vector.
@@ -630,6 +1267,14 @@ impl FunctionAST {
         &self.location
     }
 
+    /// Returns types of parameters and the return type.
+    pub(crate) fn signature(&self) -> Signature {
+        Signature {
+            params: self.params.iter().map(VarAST::get_type).collect(),
+            ret: self.output_type.clone(),
+        }
+    }
+
     #[inline]
     pub(crate) fn get_input_type(&self) -> &Vec<Type> {
         &self.input_type
@@ -655,6 +1300,12 @@ impl FunctionAST {
         self.body.last_mut()
     }
 
+    /// Removes the instruction at `index` of the body.
+    #[inline]
+    pub(crate) fn remove(&mut self, index: usize) -> QccCell<Expr> {
+        self.body.remove(index)
+    }
+
     // /// If a return expression exists in function, return its reference.
     // // TODO:
     // pub(crate) fn get_return_expr(&self) -> Option<&Expr> {
@@ -674,6 +1325,16 @@ impl FunctionAST {
     pub(crate) fn iter_params_mut(&mut self) -> impl Iterator<Item = &mut VarAST> + '_ {
         self.params.iter_mut()
     }
+
+    #[inline]
+    pub(crate) fn iter_nested(&self) -> impl Iterator<Item = &FunctionAST> + '_ {
+        self.nested.iter()
+    }
+
+    #[inline]
+    pub(crate) fn iter_nested_mut(&mut self) -> impl Iterator<Item = &mut FunctionAST> + '_ {
+        self.nested.iter_mut()
+    }
 }
 
 impl<'a> IntoIterator for &'a FunctionAST {
@@ -717,14 +1378,37 @@ impl std::fmt::Display for FunctionAST {
             .collect::<Vec<String>>()
             .join(", ");
 
-        writeln!(
+        let generics = if self.generics.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", self.generics.join(", "))
+        };
+        write!(
             f,
-            "{} ({}) : {} {{  // {}",
-            self.name, params, self.output_type, self.location
+            "{}{} ({}) : {} {{",
+            self.name, generics, params, self.output_type
         )?;
+        if f.alternate() {
+            writeln!(f)?;
+        } else {
+            writeln!(f, "  // {}", self.location)?;
+        }
+
+        for nested in &self.nested {
+            let nested = if f.alternate() {
+                format!("{:#}", nested)
+            } else {
+                nested.to_string()
+            };
+            for line in nested.lines() {
+                writeln!(f, "    {}", line)?;
+            }
+        }
 
         for expr in &self.body {
-            writeln!(f, "    {}", *expr.as_ref().borrow())?;
+            for line in expr.as_ref().borrow().to_string().lines() {
+                writeln!(f, "    {}", line)?;
+            }
         }
         writeln!(f, "}}")?;
 
@@ -732,6 +1416,101 @@ impl std::fmt::Display for FunctionAST {
     }
 }
 
+/// A gate declared as `gate name(params) qargs { body }`. Its parameters are
+/// angles and its qargs single qubits, and its body only applies other gates
+/// to them, so it maps directly onto an OpenQASM gate.
+pub struct GateAST {
+    name: Ident,
+    location: Location,
+    params: Vec<VarAST>,
+    qargs: Vec<VarAST>,
+    body: Vec<QccCell<Expr>>,
+}
+
+impl GateAST {
+    pub(crate) fn new(
+        name: Ident,
+        location: Location,
+        params: Vec<VarAST>,
+        qargs: Vec<VarAST>,
+        body: Vec<QccCell<Expr>>,
+    ) -> Self {
+        Self {
+            name,
+            location,
+            params,
+            qargs,
+            body,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_name(&self) -> &Ident {
+        &self.name
+    }
+
+    #[inline]
+    pub(crate) fn get_loc(&self) -> &Location {
+        &self.location
+    }
+
+    /// A gate is applied as `name(params.., qargs..)` and, like standard
+    /// gates, returns the first qubit it is applied on.
+    pub(crate) fn signature(&self) -> Signature {
+        Signature {
+            params: self
+                .params
+                .iter()
+                .chain(&self.qargs)
+                .map(VarAST::get_type)
+                .collect(),
+            ret: Type::Qbit,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn iter_params(&self) -> impl Iterator<Item = &VarAST> + '_ {
+        self.params.iter()
+    }
+
+    #[inline]
+    pub(crate) fn iter_qargs(&self) -> impl Iterator<Item = &VarAST> + '_ {
+        self.qargs.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a GateAST {
+    type Item = &'a QccCell<Expr>;
+    type IntoIter = std::slice::Iter<'a, QccCell<Expr>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.body.iter()
+    }
+}
+
+impl std::fmt::Display for GateAST {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = |vars: &[VarAST]| {
+            vars.iter()
+                .map(|var| var.name().clone())
+                .collect::<Vec<Ident>>()
+                .join(", ")
+        };
+        writeln!(
+            f,
+            "gate {} ({}) {} {{  // {}",
+            self.name,
+            names(&self.params),
+            names(&self.qargs),
+            self.location
+        )?;
+        for expr in &self.body {
+            writeln!(f, "    {}", *expr.as_ref().borrow())?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
 /// A type for representing identifiers of all kinds. It includes
 /// language-specific keywords and also variable names.
 pub(crate) type Ident = String;
@@ -742,6 +1521,118 @@ mod tests {
     use std::cell::RefCell;
     use std::rc::Rc;
 
+    #[test]
+    fn check_qast_merge() -> Result<(), QccError> {
+        let mut first = Qast::default();
+        first.append_module(ModuleAST::new("foo".into(), Location::default(), vec![]));
+        let mut second = Qast::default();
+        second.append_module(ModuleAST::new("bar".into(), Location::default(), vec![]));
+
+        let merged = first.merge(second)?;
+        let names = merged
+            .into_iter()
+            .map(|module| module.get_name())
+            .collect::<Vec<Ident>>();
+        assert_eq!(names, ["foo", "bar"]);
+
+        let mut conflict = Qast::default();
+        let elsewhere = Location::new("other.ql", 1, 1);
+        conflict.append_module(ModuleAST::new("foo".into(), elsewhere, vec![]));
+        assert_eq!(
+            merged.merge(conflict).err(),
+            Some(QccError(QccErrorKind::DuplicateModule))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qast_merge_same_module() -> Result<(), QccError> {
+        let function = |name: &str, output_type| {
+            let location = Location::new("lib.ql", 1, 4);
+            let function = FunctionAST::new(
+                name.into(),
+                location,
+                vec![],
+                vec![],
+                output_type,
+                Default::default(),
+                vec![],
+            );
+            Rc::new(RefCell::new(function))
+        };
+        let lib = |functions| {
+            let module = ModuleAST::new("lib".into(), Location::new("lib.ql", 1, 1), functions);
+            Qast::new(vec![Rc::new(RefCell::new(module))])
+        };
+
+        // the same file parsed twice, once with a function the other lacks
+        let first = lib(vec![function("flip", Type::Qbit)]);
+        let second = lib(vec![
+            function("flip", Type::Qbit),
+            function("mix", Type::Qbit),
+        ]);
+        let merged = first.merge(second)?;
+        let functions = merged
+            .into_iter()
+            .flat_map(|module| {
+                module
+                    .into_iter()
+                    .map(|function| function.get_name().clone())
+                    .collect::<Vec<Ident>>()
+            })
+            .collect::<Vec<Ident>>();
+        assert_eq!(functions, ["flip", "mix"]);
+
+        let conflict = lib(vec![function("flip", Type::F64)]);
+        assert_eq!(
+            merged.merge(conflict).err(),
+            Some(QccError(QccErrorKind::DuplicateFunction))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_dump_without_locations() -> Result<(), QccError> {
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/test11.ql", "--ast-locations=off"])?.unwrap();
+        let config = parser.get_config();
+        assert!(!config.ast_locations);
+        let ast = parser.parse(&config.analyzer.src)?;
+
+        assert!(format!("{ast}").contains("module test11 {  // @test11.ql:1:1\n"));
+        let dump = format!("{ast:#}");
+        assert!(!dump.contains('@'));
+        assert!(dump.starts_with("module test11 {\nfn main () : <bottom> {\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_binary_expr_location() {
+        let var =
+            |name: &str, col| Expr::Var(VarAST::new(name.into(), Location::new("a.ql", 2, col)));
+        let digit = Expr::Literal(Rc::new(RefCell::new(LiteralAST::Lit_Digit(1.0))));
+
+        // a ^ b + 1
+        let xor = Expr::BinaryExpr(var("a", 13).into(), Opcode::BitXor, var("b", 17).into());
+        let location = xor.get_location();
+        assert_eq!((location.row(), location.col()), (2, 13));
+        assert_eq!(location.end(), (2, 17));
+        let sum = Expr::BinaryExpr(xor.into(), Opcode::Add, digit.into());
+        assert_eq!(sum.get_location(), location);
+
+        // the other way around, a location in another file is left out
+        let other = Location::new("b.ql", 1, 1);
+        assert_eq!(
+            Location::new("a.ql", 2, 17).merge(&Location::new("a.ql", 2, 13)),
+            location
+        );
+        assert_eq!(location.merge(&other), location);
+    }
+
     #[test]
     fn check_var_ast() {
         let x = VarAST::new(String::from("x"), Location::default());
@@ -857,10 +1748,10 @@ fn foo (x, y) : <bottom> {  // @unknown:0:0
 
     #[test]
     fn check_qbit() {
-        let s0 = "0q(0.5, 0.5)";
+        let s0 = "0q(0.6, 0.8)";
         let qbit = s0.parse::<Qbit>();
         assert!(qbit.is_ok());
-        assert_eq!(format!("{}", qbit.unwrap()), "0q0.5_0.5");
+        assert_eq!(format!("{}", qbit.unwrap()), "0q0.6_0.8");
 
         let s1 = "0(1, 0)";
         let qbit1 = s1.parse::<Qbit>();
@@ -881,5 +1772,83 @@ fn foo (x, y) : <bottom> {  // @unknown:0:0
         let err_qbit = s4.parse::<Qbit>();
         assert!(err_qbit.is_err());
         assert_eq!(err_qbit.err().unwrap(), QccErrorKind::ExpectedComma);
+
+        let s5 = "0q(0.6, -0.8i)";
+        let qbit5 = s5.parse::<Qbit>().unwrap();
+        assert_eq!(
+            qbit5.amplitudes(),
+            Some((0.6.into(), Complex::new(0.0, -0.8)))
+        );
+        assert_eq!(format!("{}", qbit5), "0q0.6_-0.8i");
+
+        let s6 = "0q(0.6+0.8i, 0)";
+        let qbit6 = s6.parse::<Qbit>().unwrap();
+        assert_eq!(
+            qbit6.amplitudes(),
+            Some((Complex::new(0.6, 0.8), 0.0.into()))
+        );
+
+        let zero = "0q0".parse::<Qbit>().unwrap();
+        assert_eq!(zero.amplitudes(), Some((Complex::ONE, Complex::ZERO)));
+        let one = "0q1".parse::<Qbit>().unwrap();
+        assert_eq!(one.amplitudes(), Some((Complex::ZERO, Complex::ONE)));
+        assert_eq!(
+            "0q2".parse::<Qbit>().err(),
+            Some(QccErrorKind::ExpectedParenth)
+        );
+    }
+
+    #[test]
+    fn check_qbit_normalized() {
+        for normalized in ["0q(1, 0)", "0q(0, 1)", "0q(0.707, 0.707)", "0q(0.6i, 0.8)"] {
+            assert!(normalized.parse::<Qbit>().is_ok(), "{}", normalized);
+        }
+        for unnormalized in ["0q(5, 5)", "0q(0, 0)", "0q(0.5, 0.5)"] {
+            let err = unnormalized.parse::<Qbit>().err();
+            assert_eq!(
+                err,
+                Some(QccErrorKind::UnnormalizedQbit),
+                "{}",
+                unnormalized
+            );
+        }
+    }
+
+    #[test]
+    fn check_qbit_amplitudes() -> Result<(), QccError> {
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/qbit-amplitudes.ql"])?.unwrap();
+        let config = parser.get_config();
+        let ast = parser.parse(&config.analyzer.src)?;
+
+        let mut amplitudes = vec![];
+        for module in &ast {
+            for function in &*module {
+                for expr in &*function {
+                    if let Expr::Let(_, ref val) = *expr.as_ref().borrow() {
+                        if let Expr::Literal(ref lit) = *val.as_ref().borrow() {
+                            if let LiteralAST::Lit_Qbit(ref qbit) = *lit.as_ref().borrow() {
+                                amplitudes.push(qbit.amplitudes());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // amplitudes depending on a variable aren't known at compile time
+        assert_eq!(
+            amplitudes,
+            [
+                Some((0.6.into(), 0.8.into())),
+                Some((Complex::ONE, Complex::ZERO)),
+                None,
+                Some((Complex::new(0.6, 0.0), Complex::new(0.0, -0.8))),
+                Some((Complex::ZERO, Complex::ONE)),
+            ]
+        );
+
+        Ok(())
     }
 }
@@ -1,14 +1,25 @@
 //! Attributes: Function definitions can have certain attributes associated to
 //! them. What are these attributes and what they function isn't defined right
 //! now.
+use crate::ast::{Ident, Opcode};
 use crate::error::{QccErrorKind, QccErrorLoc};
 use crate::lexer::Location;
 
-#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub(crate) enum Attribute {
     Deter,
     #[default]
     NonDeter,
+    /// controlled by as many qubits, written as `controlled(n)`
+    Controlled(usize),
+    /// name of the standard or declared OpenQASM gate the function applies,
+    /// written as `qasm_name("h")`
+    QasmName(Ident),
+    /// an entry point of the program besides `main`, written as `entry`
+    Entry,
+    /// binary operator which is a call to the function, written as
+    /// `operator("**")`
+    Operator(String),
 }
 
 impl std::str::FromStr for Attribute {
@@ -16,10 +27,56 @@ impl std::str::FromStr for Attribute {
                              // error, location cannot be determined here, but
                              // can be tagged along down the call stack.
 
+    /// Parses an attribute, which is either a name or a name followed by
+    /// parenthesized arguments, e.g. `qasm_name("h")`.
     fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
-        Ok(match s {
-            "deter" => Self::Deter,
-            "nondeter" => Self::NonDeter,
+        let (name, args) = match s.split_once('(') {
+            Some((name, args)) => {
+                let args = args
+                    .strip_suffix(')')
+                    .ok_or(QccErrorKind::UnexpectedAttr)?
+                    .split(',')
+                    .map(str::trim)
+                    .collect::<Vec<&str>>();
+                (name.trim(), Some(args))
+            }
+            None => (s, None),
+        };
+
+        Ok(match (name, args.as_deref()) {
+            ("deter", None) => Self::Deter,
+            ("nondeter", None) => Self::NonDeter,
+            ("entry", None) => Self::Entry,
+            ("controlled", Some(&[controls])) => {
+                Self::Controlled(controls.parse().or(Err(QccErrorKind::UnexpectedAttr))?)
+            }
+            ("operator", Some(&[op])) => {
+                // the operator must be made of symbols and not be a builtin one
+                let op = op
+                    .strip_prefix('"')
+                    .and_then(|op| op.strip_suffix('"'))
+                    .filter(|op| {
+                        !op.is_empty()
+                            && op.chars().all(|c| "+-*/%&|^<>=!~".contains(c))
+                            && !op.contains("//")
+                            && op.parse::<Opcode>().is_err()
+                            && !matches!(*op, "=" | "!" | "~" | "->")
+                    })
+                    .ok_or(QccErrorKind::UnexpectedAttr)?;
+                Self::Operator(op.into())
+            }
+            ("qasm_name", Some(&[name])) => {
+                // the name must be an OpenQASM identifier
+                let name = name
+                    .strip_prefix('"')
+                    .and_then(|name| name.strip_suffix('"'))
+                    .filter(|name| {
+                        name.starts_with(|c: char| c.is_ascii_alphabetic())
+                            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    })
+                    .ok_or(QccErrorKind::UnexpectedAttr)?;
+                Self::QasmName(name.into())
+            }
             _ => Err(QccErrorKind::UnexpectedAttr)?,
         })
     }
@@ -30,6 +87,10 @@ impl std::fmt::Display for Attribute {
         match self {
             Attribute::Deter => write!(f, "deter"),
             Attribute::NonDeter => write!(f, "nondeter"),
+            Attribute::Controlled(controls) => write!(f, "controlled({controls})"),
+            Attribute::QasmName(name) => write!(f, "qasm_name(\"{name}\")"),
+            Attribute::Entry => write!(f, "entry"),
+            Attribute::Operator(op) => write!(f, "operator(\"{op}\")"),
         }
     }
 }
@@ -47,6 +108,27 @@ impl Attributes {
     pub(crate) fn push(&mut self, attr: Attribute) {
         self.0.push(attr);
     }
+
+    /// Check if the function is an entry point.
+    pub(crate) fn is_entry(&self) -> bool {
+        self.0.contains(&Attribute::Entry)
+    }
+
+    /// Name given by a `qasm_name` attribute, if any.
+    pub(crate) fn qasm_name(&self) -> Option<&Ident> {
+        self.0.iter().find_map(|attr| match attr {
+            Attribute::QasmName(name) => Some(name),
+            _ => None,
+        })
+    }
+
+    /// Operator given by an `operator` attribute, if any.
+    pub(crate) fn operator(&self) -> Option<&String> {
+        self.0.iter().find_map(|attr| match attr {
+            Attribute::Operator(op) => Some(op),
+            _ => None,
+        })
+    }
 }
 
 impl std::str::FromStr for Attributes {
@@ -63,7 +145,7 @@ impl std::str::FromStr for Attributes {
         col += 2; // for '#['
 
         // FIXME: This will loose information if separator has more whitespaces.
-        let attrs: Vec<&str> = s.split_terminator(',').map(|x| x.trim()).collect();
+        let attrs: Vec<&str> = split_attrs(s).into_iter().map(|x| x.trim()).collect();
 
         let mut parsed: Self = Default::default();
         let mut first = true;
@@ -93,6 +175,28 @@ impl std::str::FromStr for Attributes {
     }
 }
 
+/// Splits a list of attributes at commas which don't separate arguments of an
+/// attribute. A trailing comma is ignored.
+fn split_attrs(s: &str) -> Vec<&str> {
+    let mut attrs = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                attrs.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < s.len() {
+        attrs.push(&s[start..]);
+    }
+    attrs
+}
+
 impl std::fmt::Display for Attributes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let attrs = self
@@ -118,6 +222,42 @@ mod tests {
         let attrs = s.parse::<Attributes>().unwrap();
         assert_eq!(attrs, Attributes(vec![Deter, NonDeter]));
 
+        let s = "#[deter, controlled(2)]";
+        let attrs = s.parse::<Attributes>().unwrap();
+        assert_eq!(attrs, Attributes(vec![Deter, Controlled(2)]));
+        assert_eq!(attrs.to_string(), "deter, controlled(2)");
+
+        let s = "#[deter, qasm_name(\"h\")]";
+        let attrs = s.parse::<Attributes>().unwrap();
+        assert_eq!(attrs.qasm_name(), Some(&"h".to_string()));
+        assert_eq!(attrs.to_string(), "deter, qasm_name(\"h\")");
+        assert!(!attrs.is_entry());
+
+        let s = "#[entry]";
+        let attrs = s.parse::<Attributes>().unwrap();
+        assert!(attrs.is_entry());
+
+        let s = "#[operator(\"**\")]";
+        let attrs = s.parse::<Attributes>().unwrap();
+        assert_eq!(attrs.operator(), Some(&"**".to_string()));
+        assert_eq!(attrs.to_string(), "operator(\"**\")");
+
+        for s in [
+            "#[controlled]",
+            "#[controlled(x)]",
+            "#[deter(1)]",
+            "#[qasm_name]",
+            "#[qasm_name(h)]",
+            "#[qasm_name(\"1h\")]",
+            "#[deter(\"h\")]",
+            "#[operator(\"*\")]",
+            "#[operator(\"<=\")]",
+            "#[operator(\"a\")]",
+        ] {
+            let err = s.parse::<Attributes>().err().unwrap();
+            assert!(err.get_error().is(QccErrorKind::UnexpectedAttr));
+        }
+
         let s = "#[nondeter, unknown]";
         let err = s.parse::<Attributes>().err().unwrap();
         assert!(err == (QccErrorKind::UnexpectedAttr, Location::new("", 0, 12)).into());
@@ -0,0 +1,307 @@
+//! Builtin functions which are understood by qcc without being declared in the
+//! source.
+use crate::error::QccErrorKind;
+use crate::types::{Signature, Type};
+use std::f64::consts::FRAC_PI_2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Builtin {
+    /// `alloc()` returns a fresh qubit in |0〉 state.
+    Alloc,
+    /// `free(q)` releases a qubit so that its register can be reused.
+    Free,
+    /// `measure reg -> bits` measures every qubit of a register into a
+    /// classical register of the same size. `measure(reg, basis) -> bits`
+    /// measures in another basis, which is passed as the last argument.
+    Measure,
+    /// Standard gates, each applied as `gate(params.., qubits..)`.
+    H,
+    X,
+    Y,
+    Z,
+    S,
+    T,
+    Rx,
+    Ry,
+    Rz,
+    U,
+    Cx,
+    /// `swap(a, b)` exchanges the states of two qubits.
+    Swap,
+    /// Math functions on `float64`, usable in classical expressions.
+    Sin,
+    Cos,
+    Sqrt,
+    /// `~b` flips a classical bit.
+    BitNot,
+    /// `reg[i]` is the qubit of a register at index `i`.
+    Index,
+}
+
+impl Builtin {
+    /// Returns the builtin for a function name, if it is one.
+    pub(crate) fn lookup(name: &str) -> Option<Self> {
+        match name {
+            "alloc" => Some(Self::Alloc),
+            "free" => Some(Self::Free),
+            "measure" => Some(Self::Measure),
+            "h" => Some(Self::H),
+            "x" => Some(Self::X),
+            "y" => Some(Self::Y),
+            "z" => Some(Self::Z),
+            "s" => Some(Self::S),
+            "t" => Some(Self::T),
+            "rx" => Some(Self::Rx),
+            "ry" => Some(Self::Ry),
+            "rz" => Some(Self::Rz),
+            "U" => Some(Self::U),
+            "cx" => Some(Self::Cx),
+            "swap" => Some(Self::Swap),
+            "sin" => Some(Self::Sin),
+            "cos" => Some(Self::Cos),
+            "sqrt" => Some(Self::Sqrt),
+            "~" => Some(Self::BitNot),
+            "[]" => Some(Self::Index),
+            _ => None,
+        }
+    }
+
+    /// Checks if the builtin is a gate applied on qubits.
+    pub(crate) fn is_gate(&self) -> bool {
+        !matches!(
+            self,
+            Self::Alloc
+                | Self::Free
+                | Self::Measure
+                | Self::Sin
+                | Self::Cos
+                | Self::Sqrt
+                | Self::BitNot
+                | Self::Index
+        )
+    }
+
+    /// Checks if the builtin is a gate on a single qubit, which applied on a
+    /// register is applied on each of its qubits.
+    pub(crate) fn is_single_qubit_gate(&self) -> bool {
+        matches!(
+            self,
+            Self::H
+                | Self::X
+                | Self::Y
+                | Self::Z
+                | Self::S
+                | Self::T
+                | Self::Rx
+                | Self::Ry
+                | Self::Rz
+                | Self::U
+        )
+    }
+
+    /// Types of parameters a builtin expects.
+    pub(crate) fn input_type(&self) -> &'static [Type] {
+        match self {
+            Self::Alloc => &[],
+            // registers of any size, see `check_args`
+            Self::Measure | Self::Index => &[],
+            Self::Free => &[Type::Qbit],
+            Self::H | Self::X | Self::Y | Self::Z | Self::S | Self::T => &[Type::Qbit],
+            Self::Rx | Self::Ry | Self::Rz => &[Type::F64, Type::Qbit],
+            Self::U => &[Type::F64, Type::F64, Type::F64, Type::Qbit],
+            Self::Cx | Self::Swap => &[Type::Qbit, Type::Qbit],
+            Self::Sin | Self::Cos | Self::Sqrt => &[Type::F64],
+            Self::BitNot => &[Type::Bit],
+        }
+    }
+
+    /// Type of the value a builtin returns. Single-qubit gates return the
+    /// qubit they are applied on.
+    pub(crate) fn output_type(&self) -> Type {
+        match self {
+            Self::Free | Self::Measure | Self::Cx | Self::Swap => Type::Unit,
+            Self::Sin | Self::Cos | Self::Sqrt => Type::F64,
+            Self::BitNot => Type::Bit,
+            _ => Type::Qbit,
+        }
+    }
+
+    /// Type of the value a call with arguments of types `args` returns, which
+    /// differs from `output_type` for `measure(q)` returning the bit `q` is
+    /// measured into, and for a single-qubit gate returning the register it
+    /// is applied on.
+    pub(crate) fn output_type_of(&self, args: &[Type]) -> Type {
+        match (self, args) {
+            (Self::Measure, [_]) => Type::Bit,
+            (_, [.., Type::Qreg(len)]) if self.is_single_qubit_gate() => Type::Qreg(*len),
+            _ => self.output_type(),
+        }
+    }
+
+    pub(crate) fn signature(&self) -> Signature {
+        Signature {
+            params: self.input_type().to_vec(),
+            ret: self.output_type(),
+        }
+    }
+
+    /// Checks types of arguments a builtin is called with, where a fixed
+    /// signature isn't enough.
+    pub(crate) fn check_args(&self, args: &[Type]) -> Result<(), QccErrorKind> {
+        match (self, args) {
+            (Self::Measure, [Type::Qreg(qubits), Type::Creg(bits)]) if qubits == bits => Ok(()),
+            (Self::Measure, [Type::Qbit, Type::Bit]) => Ok(()),
+            (Self::Measure, [Type::Qbit]) => Ok(()),
+            (Self::Measure, _) => Err(QccErrorKind::TypeMismatch),
+            // a swap returns nothing, so its arguments aren't checked elsewhere
+            (Self::Swap, [Type::Qbit, Type::Qbit]) => Ok(()),
+            (Self::Swap, _) => Err(QccErrorKind::TypeMismatch),
+            (Self::Index, [Type::Qreg(_), index]) if index.is_integer() || *index == Type::F64 => {
+                Ok(())
+            }
+            (Self::Index, _) => Err(QccErrorKind::TypeMismatch),
+            // no builtin takes a complex number, it only goes in amplitudes
+            (_, args) if args.contains(&Type::Complex) => Err(QccErrorKind::TypeMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Splits the basis off the arguments of a measurement, if it is given
+    /// one.
+    pub(crate) fn split_basis<'a, T>(&self, args: &'a [T]) -> (&'a [T], Option<&'a T>) {
+        match (self, args) {
+            (Self::Measure, [_, _, basis]) => (&args[..2], Some(basis)),
+            _ => (args, None),
+        }
+    }
+
+    /// Evaluates a classical builtin on constant arguments.
+    pub(crate) fn eval(&self, args: &[f64]) -> Option<f64> {
+        match (self, args) {
+            (Self::Sin, &[x]) => Some(x.sin()),
+            (Self::Cos, &[x]) => Some(x.cos()),
+            (Self::Sqrt, &[x]) => Some(x.sqrt()),
+            _ => None,
+        }
+    }
+}
+
+/// Basis a qubit is measured in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Basis {
+    X,
+    Y,
+    Z,
+}
+
+impl Basis {
+    pub(crate) fn lookup(name: &str) -> Option<Self> {
+        match name {
+            "X" => Some(Self::X),
+            "Y" => Some(Self::Y),
+            "Z" => Some(Self::Z),
+            _ => None,
+        }
+    }
+
+    /// Rotation taking the +1 and -1 eigenstates of the basis to |0〉 and |1〉,
+    /// applied before measuring in the computational basis.
+    pub(crate) fn rotation(&self) -> Option<(Builtin, f64)> {
+        match self {
+            Self::X => Some((Builtin::Ry, -FRAC_PI_2)),
+            Self::Y => Some((Builtin::Rx, FRAC_PI_2)),
+            Self::Z => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Alloc => write!(f, "alloc"),
+            Self::Free => write!(f, "free"),
+            Self::Measure => write!(f, "measure"),
+            Self::H => write!(f, "h"),
+            Self::X => write!(f, "x"),
+            Self::Y => write!(f, "y"),
+            Self::Z => write!(f, "z"),
+            Self::S => write!(f, "s"),
+            Self::T => write!(f, "t"),
+            Self::Rx => write!(f, "rx"),
+            Self::Ry => write!(f, "ry"),
+            Self::Rz => write!(f, "rz"),
+            Self::U => write!(f, "U"),
+            Self::Cx => write!(f, "cx"),
+            Self::Swap => write!(f, "swap"),
+            Self::Sin => write!(f, "sin"),
+            Self::Cos => write!(f, "cos"),
+            Self::Sqrt => write!(f, "sqrt"),
+            Self::BitNot => write!(f, "~"),
+            Self::Index => write!(f, "[]"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_builtins() {
+        let alloc = Builtin::lookup("alloc").unwrap();
+        assert_eq!(alloc, Builtin::Alloc);
+        assert!(alloc.input_type().is_empty());
+        assert_eq!(alloc.output_type(), Type::Qbit);
+
+        let free = Builtin::lookup("free").unwrap();
+        assert_eq!(free.input_type(), &[Type::Qbit]);
+        assert_eq!(free.output_type(), Type::Unit);
+        assert_eq!(format!("{free}"), "free");
+
+        let rz = Builtin::lookup("rz").unwrap();
+        assert!(rz.is_gate());
+        assert_eq!(rz.input_type(), &[Type::F64, Type::Qbit]);
+        assert_eq!(rz.output_type(), Type::Qbit);
+        assert_eq!(
+            rz.output_type_of(&[Type::F64, Type::Qreg(3)]),
+            Type::Qreg(3)
+        );
+        assert!(!alloc.is_gate());
+
+        let cos = Builtin::lookup("cos").unwrap();
+        assert!(!cos.is_gate());
+        assert_eq!(cos.output_type(), Type::F64);
+        assert_eq!(cos.eval(&[0.0]), Some(1.0));
+        assert_eq!(rz.eval(&[0.0]), None);
+
+        let measure = Builtin::lookup("measure").unwrap();
+        assert!(!measure.is_gate());
+        assert_eq!(measure.check_args(&[Type::Qreg(3), Type::Creg(3)]), Ok(()));
+        assert_eq!(
+            measure.check_args(&[Type::Qreg(3), Type::Creg(2)]),
+            Err(QccErrorKind::TypeMismatch)
+        );
+
+        let swap = Builtin::lookup("swap").unwrap();
+        assert!(swap.is_gate());
+        assert_eq!(swap.input_type(), &[Type::Qbit, Type::Qbit]);
+        assert_eq!(swap.output_type(), Type::Unit);
+        assert_eq!(
+            swap.check_args(&[Type::Qbit, Type::F64]),
+            Err(QccErrorKind::TypeMismatch)
+        );
+
+        let index = Builtin::lookup("[]").unwrap();
+        assert_eq!(index.check_args(&[Type::Qreg(16), Type::F64]), Ok(()));
+        assert_eq!(
+            index.check_args(&[Type::Qbit, Type::F64]),
+            Err(QccErrorKind::TypeMismatch)
+        );
+
+        assert!(Builtin::lookup("foo").is_none());
+
+        assert_eq!(Basis::lookup("X"), Some(Basis::X));
+        assert_eq!(Basis::Z.rotation(), None);
+        assert!(Basis::lookup("x").is_none());
+    }
+}
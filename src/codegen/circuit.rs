@@ -0,0 +1,1781 @@
+//! Circuit representation of quantum functions.
+//!
+//! A `Circuit` is a flat list of operations on physical qubits, lowered from a
+//! function body after qubit allocation. Backends and analyses work on it
+//! instead of walking the AST again.
+use crate::analyzer::report::CallGraph;
+use crate::ast::{Expr, FunctionAST, Ident, LiteralAST, Opcode, Qast, Qbit, QccCell, VarAST};
+use crate::builtins::{Basis, Builtin};
+use crate::codegen::regalloc::QubitAllocator;
+use crate::complex::Complex;
+use crate::config::Tolerance;
+use crate::error::{QccErrorKind, QccErrorLoc, Result};
+use crate::simulator::simulate_unmeasured;
+use crate::types::Type;
+use crate::utils::{lift_nested, mangle};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An operation applied on physical qubits.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Op {
+    /// Resets a qubit to |0〉.
+    Reset(usize),
+    /// Measures a qubit into a classical bit.
+    Measure { qubit: usize, clbit: usize },
+    /// Applies a gate with classical parameters on qubits.
+    Gate {
+        name: Ident,
+        params: Vec<Param>,
+        qubits: Vec<usize>,
+    },
+    /// Applies an operation only if a classical bit has `value`, which is
+    /// false in the else block of a conditional. The operation may be
+    /// conditioned on another bit in turn, for nested conditionals.
+    Conditional {
+        clbit: usize,
+        value: bool,
+        op: Box<Op>,
+    },
+}
+
+/// A classical parameter of a gate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Param {
+    /// Value known at compile time.
+    Const(f64),
+    /// QASM arithmetic over `pi` and formal parameters of the enclosing gate,
+    /// left for the backend to evaluate.
+    Expr(String),
+}
+
+impl Param {
+    /// Returns the value of a parameter known at compile time.
+    pub(crate) fn value(&self) -> Option<f64> {
+        match self {
+            Self::Const(value) => Some(*value),
+            Self::Expr(_) => None,
+        }
+    }
+
+    /// Returns a scope binding `pi` and formal parameters of a gate to
+    /// themselves, see `lower`.
+    pub(crate) fn scope<'a>(params: impl Iterator<Item = &'a Ident>) -> HashMap<Ident, Self> {
+        std::iter::once("pi".to_string())
+            .chain(params.cloned())
+            .map(|name| (name.clone(), Self::Expr(name)))
+            .collect()
+    }
+
+    /// Lowers a classical expression, folding every constant subexpression.
+    /// Variables are looked up in `scope`, where classical bindings are kept
+    /// with their values. It is None if any other variable is used.
+    pub(crate) fn lower(expr: &Expr, scope: &HashMap<Ident, Self>) -> Option<Self> {
+        if let Some(value) = expr.eval_const() {
+            return Some(Self::Const(value));
+        }
+
+        match expr {
+            Expr::Var(var) => Some(match scope.get(var.name())?.clone() {
+                Self::Const(value) if var.is_negative() => Self::Const(-value),
+                Self::Expr(s) if var.is_negative() && is_name(&s) => Self::Expr(format!("-{}", s)),
+                Self::Expr(s) if var.is_negative() => Self::Expr(format!("-({})", s)),
+                param => param,
+            }),
+            Expr::BinaryExpr(lhs, op, rhs) => {
+                if !matches!(op, Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div) {
+                    return None;
+                }
+                // values of bindings are grouped like the expressions they are
+                // substituted for
+                let operand = |expr: &QccCell<Expr>| {
+                    let expr = expr.as_ref().borrow();
+                    let grouped = matches!(*expr, Expr::BinaryExpr(..) | Expr::Var(_));
+                    Some((grouped, Self::lower(&expr, scope)?))
+                };
+                let (lhs, rhs) = (operand(lhs)?, operand(rhs)?);
+                // operands bound to values known at compile time are folded
+                if let ((_, Self::Const(lhs)), (_, Self::Const(rhs))) = (&lhs, &rhs) {
+                    return op.eval_as(*lhs, *rhs, &expr.get_type()).map(Self::Const);
+                }
+                // operands which aren't folded keep their grouping
+                let operand = |(grouped, param): (bool, Self)| match param {
+                    Self::Expr(s) if grouped && !is_name(&s) => format!("({})", s),
+                    param => param.to_string(),
+                };
+                Some(Self::Expr(format!(
+                    "{}{}{}",
+                    operand(lhs),
+                    op,
+                    operand(rhs)
+                )))
+            }
+            // math builtins are part of QASM expressions as well
+            Expr::FnCall(f, args) => {
+                let builtin = Builtin::lookup(f.get_name())?;
+                let args = args
+                    .iter()
+                    .map(|arg| Self::lower(&arg.as_ref().borrow(), scope))
+                    .collect::<Option<Vec<Self>>>()?;
+                let values = args.iter().map(Self::value).collect::<Option<Vec<f64>>>();
+                if let Some(value) = values.and_then(|values| builtin.eval(&values)) {
+                    return Some(Self::Const(value));
+                }
+                let args = args.iter().map(Self::to_string).collect::<Vec<String>>();
+                Some(Self::Expr(format!("{}({})", f.get_name(), args.join(","))))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Checks if a QASM expression is only a name.
+fn is_name(expr: &str) -> bool {
+    expr.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Const(value) => write!(f, "{}", value),
+            Self::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl Op {
+    /// Physical qubits the operation acts on.
+    pub(crate) fn qubits(&self) -> &[usize] {
+        match self {
+            Self::Reset(qubit) | Self::Measure { qubit, .. } => std::slice::from_ref(qubit),
+            Self::Gate { qubits, .. } => qubits,
+            Self::Conditional { op, .. } => op.qubits(),
+        }
+    }
+
+    /// Splits an operation into the bits conditioning it with their values,
+    /// outermost first, and the operation applied once all of them hold.
+    pub(crate) fn conditions(&self) -> (Vec<(usize, bool)>, &Op) {
+        match self {
+            Self::Conditional { clbit, value, op } => {
+                let (mut clbits, applied) = op.conditions();
+                clbits.insert(0, (*clbit, *value));
+                (clbits, applied)
+            }
+            op => (vec![], op),
+        }
+    }
+
+    /// Conditions an operation on each of `clbits` having its value,
+    /// outermost first, unless it is conditioned on that already.
+    pub(crate) fn conditioned(self, clbits: &[(usize, bool)]) -> Self {
+        clbits.iter().rev().fold(self, |op, &(clbit, value)| {
+            if op.conditions().0.contains(&(clbit, value)) {
+                return op;
+            }
+            Self::Conditional {
+                clbit,
+                value,
+                op: Box::new(op),
+            }
+        })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reset(qubit) => write!(f, "reset q[{}];", qubit),
+            Self::Measure { qubit, clbit } => write!(f, "measure q[{}] -> c[{}];", qubit, clbit),
+            Self::Gate {
+                name,
+                params,
+                qubits,
+            } => {
+                write!(f, "{}", name)?;
+                if !params.is_empty() {
+                    let params = params
+                        .iter()
+                        .map(|param| param.to_string())
+                        .collect::<Vec<String>>();
+                    write!(f, "({})", params.join(","))?;
+                }
+                let qargs = qubits
+                    .iter()
+                    .map(|qubit| format!("q[{}]", qubit))
+                    .collect::<Vec<String>>();
+                write!(f, " {};", qargs.join(", "))
+            }
+            Self::Conditional { clbit, value, op } => {
+                write!(f, "if(c[{}]=={}) {}", clbit, u8::from(*value), op)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Circuit {
+    pub(crate) name: Ident,
+    /// module the function is defined in, which tells apart functions of
+    /// the same name
+    pub(crate) module: Ident,
+    /// classical formal parameters
+    pub(crate) params: Vec<Ident>,
+    /// qubit formal parameters, bound to the first physical qubits in order
+    pub(crate) qargs: Vec<Ident>,
+    /// bit formal parameters, bound to the first classical bits in order
+    pub(crate) cargs: Vec<Ident>,
+    /// whether the function is an entry point of the program, which runs
+    /// instead of being applied by another function
+    pub(crate) entry: bool,
+    pub(crate) ops: Vec<Op>,
+    /// number of physical qubits used
+    pub(crate) qubits: usize,
+    /// number of classical bits used
+    pub(crate) clbits: usize,
+    /// variable each physical qubit is bound to first, with its index if the
+    /// variable is a register
+    pub(crate) qubit_names: Vec<Option<(Ident, usize)>>,
+    /// variable each classical bit is bound to first, likewise
+    pub(crate) clbit_names: Vec<Option<(Ident, usize)>>,
+}
+
+/// Names `bit` after element `index` of variable `name`, unless it is named
+/// already.
+pub(crate) fn name_bit(
+    names: &mut Vec<Option<(Ident, usize)>>,
+    bit: usize,
+    name: &Ident,
+    index: usize,
+) {
+    if names.len() <= bit {
+        names.resize(bit + 1, None);
+    }
+    names[bit].get_or_insert_with(|| (name.clone(), index));
+}
+
+impl Circuit {
+    /// Name of the function as called from other modules, which applications
+    /// of the circuit are named by, see `mangle`.
+    pub(crate) fn mangled_name(&self) -> Ident {
+        mangle(&self.module, &self.name)
+    }
+
+    /// Length of the longest chain of operations depending on each other
+    /// through a shared qubit.
+    pub(crate) fn depth(&self) -> usize {
+        let mut levels = vec![0; self.qubits];
+        for op in &self.ops {
+            let level = op.qubits().iter().map(|&q| levels[q]).max().unwrap_or(0) + 1;
+            for &qubit in op.qubits() {
+                levels[qubit] = level;
+            }
+        }
+        levels.into_iter().max().unwrap_or(0)
+    }
+
+    /// Rounds parameters known at compile time to `digits` decimal places,
+    /// so that emitted angles don't depend on the last bits of a computation.
+    pub(crate) fn round_params(&mut self, digits: usize) {
+        fn round(op: &mut Op, digits: usize) {
+            match op {
+                Op::Gate { params, .. } => {
+                    for param in params {
+                        if let Param::Const(value) = param {
+                            // the shortest decimal printed for the rounded
+                            // value is the rounded decimal itself
+                            *value = format!("{:.*}", digits, value).parse().unwrap();
+                        }
+                    }
+                }
+                Op::Conditional { op, .. } => round(op, digits),
+                Op::Reset(_) | Op::Measure { .. } => {}
+            }
+        }
+        for op in &mut self.ops {
+            round(op, digits);
+        }
+    }
+
+    /// Lowers a function call into operations. Returns the qubits which the
+    /// result of call is bound to, in order, a qubit result being the first
+    /// of them.
+    fn lower_call(
+        &mut self,
+        allocator: &mut QubitAllocator,
+        cregs: &HashMap<Ident, Vec<usize>>,
+        scope: &HashMap<Ident, Param>,
+        callees: &mut Callees,
+        callee: &FunctionAST,
+        args: &[QccCell<Expr>],
+    ) -> core::result::Result<Vec<usize>, QccErrorLoc> {
+        let builtin = Builtin::lookup(callee.get_name());
+        match builtin {
+            Some(Builtin::Measure) => {
+                let (args, basis) = Builtin::Measure.split_basis(args);
+                // the basis was checked during inference
+                let rotation = basis
+                    .and_then(|basis| match *basis.as_ref().borrow() {
+                        Expr::Var(ref var) => Basis::lookup(var.name()),
+                        _ => None,
+                    })
+                    .and_then(|basis| basis.rotation());
+                let names = args
+                    .iter()
+                    .filter_map(|arg| match *arg.as_ref().borrow() {
+                        Expr::Var(ref var) => Some(var.name().clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<Ident>>();
+                if let [qreg, creg] = names.as_slice() {
+                    // inference made sure both registers have the same size,
+                    // a single qubit is measured into a single bit
+                    let qubit = allocator.lookup(qreg);
+                    let qubits = allocator.lookup_register(qreg).unwrap_or(qubit.as_slice());
+                    let clbits = cregs.get(creg).map(Vec::as_slice).unwrap_or_default();
+                    for (&qubit, &clbit) in qubits.iter().zip(clbits) {
+                        // other bases are measured by rotating them onto Z
+                        if let Some((gate, angle)) = rotation {
+                            self.ops.push(Op::Gate {
+                                name: gate.to_string(),
+                                params: vec![Param::Const(angle)],
+                                qubits: vec![qubit],
+                            });
+                        }
+                        self.ops.push(Op::Measure { qubit, clbit });
+                    }
+                }
+                return Ok(vec![]);
+            }
+            Some(Builtin::Alloc) => {
+                // a reused qubit may be in any state, so reset it
+                let qubit = allocator.alloc();
+                self.ops.push(Op::Reset(qubit));
+                return Ok(vec![qubit]);
+            }
+            Some(Builtin::Index) => {
+                return Ok(lower_index(allocator, scope, args)?.into_iter().collect())
+            }
+            Some(Builtin::Free) => {
+                for arg in args {
+                    if let Expr::Var(ref var) = *arg.as_ref().borrow() {
+                        allocator.release(var.name());
+                    }
+                }
+                return Ok(vec![]);
+            }
+            _ => {}
+        }
+
+        let input_type = builtin.map(|b| b.input_type()).unwrap_or_default();
+        let mut params = vec![];
+        let mut qubits = vec![];
+        for (i, arg) in args.iter().enumerate() {
+            let arg = arg.as_ref().borrow();
+            // angles given to declared gates are only known by their type
+            let is_angle = match builtin {
+                Some(_) => input_type.get(i) == Some(&Type::F64),
+                None => arg.get_type() == Type::F64,
+            };
+            if is_angle {
+                let param = Param::lower(&arg, scope).ok_or(QccErrorKind::TranslationError)?;
+                params.push(param);
+            } else {
+                qubits.extend(self.lower_qubits(allocator, cregs, scope, callees, &arg)?);
+            }
+        }
+
+        if qubits.is_empty() {
+            return match builtin {
+                // math builtins only compute angles
+                Some(_) => Ok(vec![]),
+                // while a gate is applied on at least one qubit
+                None => self.inline(allocator, callees, callee, params),
+            };
+        }
+
+        // a gate on a single qubit is applied on each qubit of a register
+        if builtin.is_some_and(|builtin| builtin.is_single_qubit_gate()) {
+            for &qubit in &qubits {
+                self.ops.push(Op::Gate {
+                    name: callee.get_name().clone(),
+                    params: params.clone(),
+                    qubits: vec![qubit],
+                });
+            }
+            return Ok(qubits);
+        }
+
+        // a gate returns the qubits it is applied on
+        self.ops.push(Op::Gate {
+            name: callee.get_name().clone(),
+            params,
+            qubits: qubits.clone(),
+        });
+        Ok(qubits)
+    }
+
+    /// Lowers an expression bound to qubits, e.g. an argument of a call, and
+    /// returns them in order. Calls are lowered before the qubits they return
+    /// are used.
+    fn lower_qubits(
+        &mut self,
+        allocator: &mut QubitAllocator,
+        cregs: &HashMap<Ident, Vec<usize>>,
+        scope: &HashMap<Ident, Param>,
+        callees: &mut Callees,
+        expr: &Expr,
+    ) -> core::result::Result<Vec<usize>, QccErrorLoc> {
+        match expr {
+            // a register is given qubit by qubit
+            Expr::Var(var) => match allocator.lookup(var.name()) {
+                Some(qubit) => Ok(vec![qubit]),
+                None => match allocator.lookup_register(var.name()) {
+                    Some(qubits) => Ok(qubits.to_vec()),
+                    None => Err(QccErrorKind::TranslationError)?,
+                },
+            },
+            Expr::FnCall(callee, args) => {
+                self.lower_call(allocator, cregs, scope, callees, callee, args)
+            }
+            // a qubit written in place is prepared on a new one
+            Expr::Literal(lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Qbit(ref qbit) => Ok(vec![self.prepare(allocator, qbit)]),
+                _ => Err((QccErrorKind::TranslationError, expr.get_location()))?,
+            },
+            _ => Err((QccErrorKind::TranslationError, expr.get_location()))?,
+        }
+    }
+
+    /// Prepares a new qubit in the state of a qubit literal.
+    fn prepare(&mut self, allocator: &mut QubitAllocator, qbit: &Qbit) -> usize {
+        let qubit = allocator.alloc();
+        self.ops.push(Op::Reset(qubit));
+        // amplitudes only known at runtime are left to the simulator
+        if let Some(params) = qbit.amplitudes().and_then(state_prep) {
+            self.ops.push(Op::Gate {
+                name: "U".into(),
+                params: params.into_iter().map(Param::Const).collect(),
+                qubits: vec![qubit],
+            });
+        }
+        qubit
+    }
+
+    /// Lowers the body of a function applied on no qubit in place of its
+    /// call, since a gate is applied on at least one. `params` are the
+    /// angles it is given. Returns the qubits its result is bound to.
+    fn inline(
+        &mut self,
+        allocator: &mut QubitAllocator,
+        callees: &mut Callees,
+        callee: &FunctionAST,
+        params: Vec<Param>,
+    ) -> core::result::Result<Vec<usize>, QccErrorLoc> {
+        let Some((name, module, f)) = callees.lookup(callee.get_name()) else {
+            // a call without qubits may be classical as well
+            if callee.get_output_type().is_quantum() {
+                Err(QccErrorKind::TranslationError)?
+            }
+            return Ok(vec![]);
+        };
+        let f = f.as_ref().borrow();
+        if !is_quantum(&f) {
+            return Ok(vec![]);
+        }
+        // a function inlined into itself would never end
+        if callees.inlining.contains(&name) {
+            Err((QccErrorKind::TranslationError, f.get_loc().clone()))?
+        }
+
+        let mut scope = Param::scope(std::iter::empty());
+        let angles = f
+            .iter_params()
+            .filter(|param| param.get_type() == Type::F64);
+        for (param, value) in angles.zip(params) {
+            scope.insert(param.name().clone(), value);
+        }
+        let outer = std::mem::replace(&mut callees.module, module);
+        callees.inlining.push(name);
+        let mut inner = allocator.nested();
+        let mut cregs = HashMap::new();
+
+        // the last instruction is the result of the function
+        let body = f.into_iter().collect::<Vec<&QccCell<Expr>>>();
+        let mut result = Ok(vec![]);
+        if let Some((last, body)) = body.split_last() {
+            for expr in body {
+                self.lower_expr(&mut inner, &mut cregs, &mut scope, callees, expr)?;
+            }
+            result = if f.get_output_type().is_quantum() {
+                let last = last.as_ref().borrow();
+                self.lower_qubits(&mut inner, &cregs, &scope, callees, &last)
+            } else {
+                self.lower_expr(&mut inner, &mut cregs, &mut scope, callees, last)
+                    .map(|_| vec![])
+            };
+        }
+
+        allocator.join(inner);
+        callees.inlining.pop();
+        callees.module = outer;
+        result
+    }
+
+    /// Lowers an instruction of a function body into operations. Classical
+    /// bindings are kept in `scope`, to be substituted into angles.
+    fn lower_expr(
+        &mut self,
+        allocator: &mut QubitAllocator,
+        cregs: &mut HashMap<Ident, Vec<usize>>,
+        scope: &mut HashMap<Ident, Param>,
+        callees: &mut Callees,
+        expr: &QccCell<Expr>,
+    ) -> core::result::Result<(), QccErrorLoc> {
+        match *expr.as_ref().borrow() {
+            Expr::Let(ref var, ref val) if var.get_type() == Type::Bit => {
+                // a qubit bound to a bit is measured into a new one
+                let qubit = match *val.as_ref().borrow() {
+                    ref val if val.get_type() != Type::Qbit => None,
+                    Expr::Var(ref other) => allocator.lookup(other.name()),
+                    Expr::FnCall(ref callee, ref args) => self
+                        .lower_call(allocator, cregs, scope, callees, callee, args)?
+                        .first()
+                        .copied(),
+                    _ => None,
+                };
+                if let Some(qubit) = qubit {
+                    let clbit = self.clbits;
+                    self.clbits += 1;
+                    self.ops.push(Op::Measure { qubit, clbit });
+                    name_bit(&mut self.clbit_names, clbit, var.name(), 0);
+                    cregs.insert(var.name().clone(), vec![clbit]);
+                }
+            }
+            Expr::Let(ref var, ref val) if !var.get_type().is_quantum() => {
+                // a call on qubits is applied all the same, e.g. the `cx` of
+                // `let c = cx(a, b);`, only its value isn't kept
+                if let Expr::FnCall(ref callee, ref args) = *val.as_ref().borrow() {
+                    if args
+                        .iter()
+                        .any(|arg| arg.as_ref().borrow().get_type().is_quantum())
+                    {
+                        self.lower_call(allocator, cregs, scope, callees, callee, args)?;
+                    }
+                }
+                // a value which isn't an angle shadows the variable all the
+                // same, and integers known at compile time are evaluated as
+                // such
+                let val = val.as_ref().borrow();
+                let param = match val.eval_int() {
+                    Ok(Some(n)) if var.get_type().is_integer() => Some(Param::Const(n as f64)),
+                    _ => Param::lower(&val, scope),
+                };
+                match param {
+                    Some(param) => scope.insert(var.name().clone(), param),
+                    None => scope.remove(var.name()),
+                };
+            }
+            Expr::Let(ref var, ref val) => {
+                if let Type::Qreg(_) = var.get_type() {
+                    return self.lower_register(
+                        allocator,
+                        cregs,
+                        scope,
+                        callees,
+                        var,
+                        &val.as_ref().borrow(),
+                    );
+                }
+                if var.get_type() != Type::Qbit {
+                    Err(QccErrorKind::TranslationError)?
+                }
+
+                let qubit = match *val.as_ref().borrow() {
+                    // a classical bit is prepared on a new qubit
+                    ref bit if bit.get_type() == Type::Bit => {
+                        let qubit = allocator.alloc();
+                        self.ops.push(Op::Reset(qubit));
+                        if !self.lower_bit(cregs, bit, qubit) {
+                            Err((QccErrorKind::TranslationError, bit.get_location()))?
+                        }
+                        Some(qubit)
+                    }
+                    // binding one qubit variable to another only moves the
+                    // qubit, it doesn't need a new one
+                    Expr::Var(ref other) => allocator.lookup(other.name()),
+                    Expr::FnCall(ref callee, ref args) => self
+                        .lower_call(allocator, cregs, scope, callees, callee, args)?
+                        .first()
+                        .copied(),
+                    Expr::Literal(ref lit) => {
+                        let LiteralAST::Lit_Qbit(ref qbit) = *lit.as_ref().borrow() else {
+                            Err(QccErrorKind::TranslationError)?
+                        };
+                        Some(self.prepare(allocator, qbit))
+                    }
+                    // a matrix applied on qubits isn't decomposed into gates
+                    _ => Err(QccErrorKind::TranslationError)?,
+                };
+
+                let qubit = match qubit {
+                    Some(qubit) => qubit,
+                    None => {
+                        let qubit = allocator.alloc();
+                        self.ops.push(Op::Reset(qubit));
+                        qubit
+                    }
+                };
+                name_bit(&mut self.qubit_names, qubit, var.name(), 0);
+                allocator.bind(var.name().clone(), qubit);
+            }
+            Expr::FnCall(ref callee, ref args) => {
+                self.lower_call(allocator, cregs, scope, callees, callee, args)?;
+            }
+            Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+                // conditions left by `unroll` are bits, either a variable
+                // or the one `if let c = measure(q)` measures `q` into
+                let (clbit, bound) = match *cond.as_ref().borrow() {
+                    Expr::Var(ref bit) => match cregs.get(bit.name()).map(Vec::as_slice) {
+                        Some(&[clbit]) => (clbit, None),
+                        _ => return Ok(()),
+                    },
+                    Expr::Let(ref var, ref val) => {
+                        let Expr::FnCall(_, ref args) = *val.as_ref().borrow() else {
+                            return Ok(());
+                        };
+                        let qubit = match args.as_slice() {
+                            [qubit] => match *qubit.as_ref().borrow() {
+                                Expr::Var(ref qubit) => allocator.lookup(qubit.name()),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        let Some(qubit) = qubit else {
+                            return Ok(());
+                        };
+                        let clbit = self.clbits;
+                        self.clbits += 1;
+                        self.ops.push(Op::Measure { qubit, clbit });
+                        name_bit(&mut self.clbit_names, clbit, var.name(), 0);
+                        let outer = cregs.insert(var.name().clone(), vec![clbit]);
+                        (clbit, Some((var.name().clone(), outer)))
+                    }
+                    _ => return Ok(()),
+                };
+
+                // bindings in either block may not happen, and the bit bound
+                // by `if let` is only in scope of the first
+                let mut inner = scope.clone();
+                let start = self.ops.len();
+                for expr in truth {
+                    self.lower_expr(allocator, cregs, &mut inner, callees, expr)?;
+                }
+                match bound {
+                    Some((name, Some(outer))) => cregs.insert(name, outer),
+                    Some((name, None)) => cregs.remove(&name),
+                    None => None,
+                };
+                self.condition(start, clbit, true);
+
+                let mut inner = scope.clone();
+                let start = self.ops.len();
+                for expr in otherwise {
+                    self.lower_expr(allocator, cregs, &mut inner, callees, expr)?;
+                }
+                self.condition(start, clbit, false);
+            }
+            ref product @ Expr::BinaryExpr(..) if product.get_type().is_quantum() => {
+                Err(QccErrorKind::TranslationError)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Conditions operations from `start` on `clbit` having `value`.
+    fn condition(&mut self, start: usize, clbit: usize, value: bool) {
+        for op in &mut self.ops[start..] {
+            // qubits allocated in the block are reset either way, while an
+            // operation of a nested block is conditioned on both bits
+            if matches!(op, Op::Reset(_)) {
+                continue;
+            }
+            let conditioned = std::mem::replace(op, Op::Reset(0));
+            *op = conditioned.conditioned(&[(clbit, value)]);
+        }
+    }
+
+    /// Binds a register variable to the qubits of `val`, which must be bound
+    /// already, as it only names them.
+    fn lower_register(
+        &mut self,
+        allocator: &mut QubitAllocator,
+        cregs: &HashMap<Ident, Vec<usize>>,
+        scope: &HashMap<Ident, Param>,
+        callees: &mut Callees,
+        var: &VarAST,
+        val: &Expr,
+    ) -> core::result::Result<(), QccErrorLoc> {
+        let Type::Qreg(len) = var.get_type() else {
+            Err(QccErrorKind::TranslationError)?
+        };
+        // a register binding only names qubits which are bound already
+        let qubits = match *val {
+            Expr::Var(ref other) => allocator
+                .lookup_register(other.name())
+                .map(<[usize]>::to_vec),
+            Expr::FnCall(ref callee, ref args) => {
+                Some(self.lower_call(allocator, cregs, scope, callees, callee, args)?)
+            }
+            Expr::Literal(ref lit) => match *lit.as_ref().borrow() {
+                LiteralAST::Lit_Tensor(ref elems, _) => {
+                    let mut qubits = vec![];
+                    for elem in elems {
+                        let elem = elem.as_ref().borrow();
+                        qubits.extend(self.lower_qubits(allocator, cregs, scope, callees, &elem)?);
+                    }
+                    Some(qubits)
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let qubits = match qubits {
+            Some(mut qubits) if qubits.len() >= len => {
+                qubits.truncate(len);
+                qubits
+            }
+            _ => Err(QccErrorKind::TranslationError)?,
+        };
+        for (i, &qubit) in qubits.iter().enumerate() {
+            name_bit(&mut self.qubit_names, qubit, var.name(), i);
+        }
+        allocator.bind_qubits(var.name().clone(), qubits);
+        Ok(())
+    }
+
+    /// Flips `qubit` from |0〉 into |b〉, where `b` is the value of a classical
+    /// bit expression. Bits are applied as conditional `x` gates, so only
+    /// bits combined by `~` and `^` can be lowered. Returns false otherwise.
+    fn lower_bit(&mut self, cregs: &HashMap<Ident, Vec<usize>>, expr: &Expr, qubit: usize) -> bool {
+        let x = Op::Gate {
+            name: Builtin::X.to_string(),
+            params: vec![],
+            qubits: vec![qubit],
+        };
+        match expr {
+            Expr::Var(var) => {
+                let Some(&[clbit]) = cregs.get(var.name()).map(Vec::as_slice) else {
+                    return false;
+                };
+                self.ops.push(Op::Conditional {
+                    clbit,
+                    value: true,
+                    op: Box::new(x),
+                });
+                true
+            }
+            Expr::BinaryExpr(lhs, Opcode::BitXor, rhs) => {
+                self.lower_bit(cregs, &lhs.as_ref().borrow(), qubit)
+                    && self.lower_bit(cregs, &rhs.as_ref().borrow(), qubit)
+            }
+            Expr::FnCall(f, args) if Builtin::lookup(f.get_name()) == Some(Builtin::BitNot) => {
+                let [bit] = args.as_slice() else {
+                    return false;
+                };
+                self.ops.push(x);
+                self.lower_bit(cregs, &bit.as_ref().borrow(), qubit)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Functions of the program by mangled name, which calls applied on no qubit
+/// are lowered from, see `Circuit::inline`.
+#[derive(Default)]
+struct Callees {
+    functions: HashMap<Ident, (Ident, QccCell<FunctionAST>)>,
+    /// module of the function being lowered, which its calls are local to
+    module: Ident,
+    /// mangled names of functions being inlined, innermost last
+    inlining: Vec<Ident>,
+}
+
+impl Callees {
+    /// Returns the function called by `name` from the module being lowered,
+    /// with its mangled name and its module.
+    fn lookup(&self, name: &Ident) -> Option<(Ident, Ident, QccCell<FunctionAST>)> {
+        let local = mangle(&self.module, name);
+        [local, name.clone()].into_iter().find_map(|name| {
+            let (module, f) = self.functions.get(&name)?;
+            Some((name, module.clone(), f.clone()))
+        })
+    }
+}
+
+impl TryFrom<&FunctionAST> for Circuit {
+    type Error = crate::error::QccError;
+
+    fn try_from(f: &FunctionAST) -> Result<Self> {
+        Ok(Self::lower_function(f, &mut Callees::default())?)
+    }
+}
+
+impl Circuit {
+    /// Lowers a function of `callees.module` into a circuit.
+    fn lower_function(
+        f: &FunctionAST,
+        callees: &mut Callees,
+    ) -> core::result::Result<Self, QccErrorLoc> {
+        let mut circuit = Self {
+            name: f.get_name().clone(),
+            module: callees.module.clone(),
+            ..Default::default()
+        };
+        let mut allocator = QubitAllocator::new();
+
+        // qubits and registers given as parameters get their own qubits and
+        // bits, which are prepared by the caller
+        let mut cregs: HashMap<Ident, Vec<usize>> = HashMap::new();
+        let mut scope = Param::scope(f.iter_params().map(|param| param.name()));
+        for param in f.iter_params() {
+            match param.get_type() {
+                Type::Qbit => {
+                    let qubit = allocator.alloc();
+                    allocator.bind(param.name().clone(), qubit);
+                    name_bit(&mut circuit.qubit_names, qubit, param.name(), 0);
+                    circuit.qargs.push(param.name().clone());
+                }
+                Type::F64 => circuit.params.push(param.name().clone()),
+                Type::Qreg(len) => {
+                    let qubits = allocator.bind_register(param.name().clone(), len);
+                    for (i, qubit) in qubits.into_iter().enumerate() {
+                        name_bit(&mut circuit.qubit_names, qubit, param.name(), i);
+                        circuit.qargs.push(format!("{}_{}", param.name(), i));
+                    }
+                }
+                Type::Creg(len) => {
+                    let clbits = (circuit.clbits..circuit.clbits + len).collect::<Vec<usize>>();
+                    for (i, &clbit) in clbits.iter().enumerate() {
+                        name_bit(&mut circuit.clbit_names, clbit, param.name(), i);
+                    }
+                    cregs.insert(param.name().clone(), clbits);
+                    circuit.clbits += len;
+                    for i in 0..len {
+                        circuit.cargs.push(format!("{}_{}", param.name(), i));
+                    }
+                }
+                Type::Bit => {
+                    name_bit(&mut circuit.clbit_names, circuit.clbits, param.name(), 0);
+                    cregs.insert(param.name().clone(), vec![circuit.clbits]);
+                    circuit.clbits += 1;
+                    circuit.cargs.push(param.name().clone());
+                }
+                _ => {}
+            }
+        }
+
+        for expr in f {
+            circuit.lower_expr(&mut allocator, &mut cregs, &mut scope, callees, expr)?;
+        }
+
+        circuit.qubits = allocator.size();
+        Ok(circuit)
+    }
+}
+
+/// Returns the qubit `reg[i]` refers to, for `args` of a call indexing a
+/// register. The index must be known once loops are unrolled.
+fn lower_index(
+    allocator: &QubitAllocator,
+    scope: &HashMap<Ident, Param>,
+    args: &[QccCell<Expr>],
+) -> Result<Option<usize>> {
+    let [reg, index] = args else {
+        return Ok(None);
+    };
+    let Expr::Var(ref reg) = *reg.as_ref().borrow() else {
+        return Ok(None);
+    };
+    let index = Param::lower(&index.as_ref().borrow(), scope)
+        .and_then(|index| index.value())
+        .ok_or(QccErrorKind::TranslationError)?;
+    let qubits = allocator.lookup_register(reg.name()).unwrap_or_default();
+    if index < 0.0 || index.fract() != 0.0 || index as usize >= qubits.len() {
+        Err(QccErrorKind::IndexOutOfBounds)?
+    }
+    Ok(Some(qubits[index as usize]))
+}
+
+/// Returns parameters `[theta, phi, lambda]` of a `U` gate which prepares
+/// `amp_0|0〉 + amp_1|1〉` from |0〉, or None if the state is already |0〉.
+///
+/// `U(theta, phi, 0)|0〉 = cos(theta/2)|0〉 + e^(i phi) sin(theta/2)|1〉`, so the
+/// relative phase between both amplitudes is kept and only the unobservable
+/// global phase is dropped.
+pub(crate) fn state_prep((amp_0, amp_1): (Complex, Complex)) -> Option<[f64; 3]> {
+    if amp_1.abs() == 0.0 {
+        return None;
+    }
+    let theta = 2.0 * amp_1.abs().atan2(amp_0.abs());
+    let phi = if amp_0.abs() == 0.0 {
+        0.0
+    } else {
+        amp_1.arg() - amp_0.arg()
+    };
+    Some([theta, phi, 0.0])
+}
+
+/// Returns a table of the operations of each circuit, one per row, in the
+/// order they are applied. Measured bits are listed with the qubits.
+pub(crate) fn listing(circuits: &[Circuit]) -> String {
+    let mut listing = String::new();
+    for circuit in circuits {
+        listing += &format!(
+            "circuit {} ({} qubits, {} clbits)\n",
+            circuit.name, circuit.qubits, circuit.clbits
+        );
+        listing += &format!("{:<6}{:<10}{:<16}{}\n", "step", "gate", "qubits", "params");
+        for (step, op) in circuit.ops.iter().enumerate() {
+            let (gate, operands, params) = match op {
+                Op::Reset(qubit) => ("reset", format!("q[{}]", qubit), vec![]),
+                Op::Measure { qubit, clbit } => {
+                    ("measure", format!("q[{}] -> c[{}]", qubit, clbit), vec![])
+                }
+                Op::Gate {
+                    name,
+                    params,
+                    qubits,
+                } => (
+                    name.as_str(),
+                    qubits
+                        .iter()
+                        .map(|qubit| format!("q[{}]", qubit))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    params.iter().map(Param::to_string).collect(),
+                ),
+                Op::Conditional { .. } => {
+                    let (clbits, Op::Gate { name, qubits, .. }) = op.conditions() else {
+                        continue;
+                    };
+                    let qubits = qubits
+                        .iter()
+                        .map(|qubit| format!("q[{}]", qubit))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    let clbits = clbits
+                        .iter()
+                        .map(|&(clbit, value)| match value {
+                            true => format!("c[{}]", clbit),
+                            false => format!("!c[{}]", clbit),
+                        })
+                        .collect::<Vec<String>>()
+                        .join(" and ");
+                    (name.as_str(), format!("{} if {}", qubits, clbits), vec![])
+                }
+            };
+            let row = format!(
+                "{:<6}{:<10}{:<16}{}",
+                step,
+                gate,
+                operands,
+                params.join(", ")
+            );
+            listing += row.trim_end();
+            listing += "\n";
+        }
+    }
+    listing
+}
+
+/// Lowers every quantum function in the AST into a circuit. Conditionals and
+/// loops are resolved first, see `unroll`, and an error there is at the
+/// offending condition.
+pub(crate) fn lower(mut ast: Qast) -> core::result::Result<Vec<Circuit>, QccErrorLoc> {
+    // circuits have no notion of nested functions, loops or conditionals
+    // known at compile time
+    lift_nested(&mut ast)?;
+    for mut module in &mut ast {
+        // classical functions aren't lowered, so their loops may depend on
+        // values only known at runtime
+        for mut function in &mut *module {
+            if function.get_attrs().qasm_name().is_some() || is_quantum(&function) {
+                let body = unroll(function.take_body(), &mut HashMap::new())?;
+                function.set_body(body);
+            }
+        }
+    }
+
+    let mut callees = Callees::default();
+    for module in &ast {
+        for f in module.iter_functions() {
+            let name = mangle(&module.get_name(), f.as_ref().borrow().get_name());
+            callees
+                .functions
+                .insert(name, (module.get_name(), f.clone()));
+        }
+    }
+
+    // functions named after OpenQASM gates are applied as those gates, which
+    // need no circuit of their own
+    // they are keyed by mangled name, since functions of other modules may
+    // have the same name
+    let mut aliases = HashMap::new();
+    for module in &ast {
+        for f in &*module {
+            if let Some(qasm_name) = f.get_attrs().qasm_name() {
+                aliases.insert(mangle(&module.get_name(), f.get_name()), qasm_name.clone());
+            }
+        }
+    }
+
+    let mut circuits = vec![];
+    let mut locations = HashMap::new();
+    for module in &ast {
+        let module_name = module.get_name();
+        callees.module = module_name.clone();
+        for f in &*module {
+            if let Some(qasm_name) = f.get_attrs().qasm_name() {
+                let mut body = Circuit::lower_function(&f, &mut callees)?;
+                for op in &mut body.ops {
+                    rename_alias(op, &module_name, &aliases);
+                }
+                check_alias(&body, qasm_name)?;
+                continue;
+            }
+            if is_quantum(&f) {
+                let mut circuit = Circuit::lower_function(&f, &mut callees)?;
+                circuit.entry = f.get_name() == "main" || f.get_attrs().is_entry();
+                locations.insert(circuit.mangled_name(), f.get_loc().clone());
+                for op in &mut circuit.ops {
+                    rename_alias(op, &module_name, &aliases);
+                }
+                circuits.push(circuit);
+            }
+        }
+    }
+
+    // functions of different modules may have the same name, so every
+    // application of a circuit is named by its mangled name
+    let mangled = circuits
+        .iter()
+        .map(Circuit::mangled_name)
+        .collect::<Vec<Ident>>();
+    for circuit in &mut circuits {
+        for op in &mut circuit.ops {
+            mangle_application(op, &circuit.module, &mangled);
+        }
+    }
+
+    // a gate applying itself, even through other gates, would never end, and
+    // the first function or gate of such a group is reported
+    let mut graph = vec![];
+    for circuit in &circuits {
+        let applied = circuit.ops.iter().filter_map(|op| match op.conditions().1 {
+            Op::Gate { name, .. } => Some(name.clone()),
+            _ => None,
+        });
+        graph.push((circuit.mangled_name(), applied.collect()));
+    }
+    for module in &ast {
+        for gate in module.iter_gates() {
+            let applied = gate
+                .into_iter()
+                .filter_map(|expr| match *expr.as_ref().borrow() {
+                    Expr::FnCall(ref f, _) => Some(f.get_name().clone()),
+                    _ => None,
+                });
+            graph.push((gate.get_name().clone(), applied.collect()));
+            locations.insert(gate.get_name().clone(), gate.get_loc().clone());
+        }
+    }
+    if let Some(cycle) = graph.into_iter().collect::<CallGraph>().cycles().first() {
+        Err((QccErrorKind::TranslationError, locations[cycle[0]].clone()))?
+    }
+    Ok(circuits)
+}
+
+/// Mangles the name of a gate applying a circuit of `module`, which is called
+/// by its name as declared.
+fn mangle_application(op: &mut Op, module: &str, mangled: &[Ident]) {
+    match op {
+        Op::Gate { name, .. } => {
+            let local = mangle(module, name);
+            if mangled.contains(&local) {
+                *name = local;
+            }
+        }
+        Op::Conditional { op, .. } => mangle_application(op, module, mangled),
+        Op::Reset(_) | Op::Measure { .. } => {}
+    }
+}
+
+/// Renames a gate applying a function named after an OpenQASM gate, either
+/// of `module` or called by its mangled name from another.
+fn rename_alias(op: &mut Op, module: &str, aliases: &HashMap<Ident, Ident>) {
+    match op {
+        Op::Gate { name, .. } => {
+            let alias = aliases
+                .get(&mangle(module, name))
+                .or_else(|| aliases.get(name));
+            if let Some(alias) = alias {
+                *name = alias.clone();
+            }
+        }
+        Op::Conditional { op, .. } => rename_alias(op, module, aliases),
+        Op::Reset(_) | Op::Measure { .. } => {}
+    }
+}
+
+/// Checks that the body of a function named after an OpenQASM gate applies
+/// that gate, up to a global phase. Both are applied on half of a maximally
+/// entangled state, which tells their unitaries apart. Angles are only known
+/// once it is applied and gates declared in the source aren't simulated, so
+/// those are taken as they are.
+fn check_alias(body: &Circuit, qasm_name: &Ident) -> Result<()> {
+    let qargs = body.qargs.len();
+    let entangled = |ops: &[Op]| {
+        let mut circuit = Circuit {
+            qubits: 2 * qargs,
+            ..Default::default()
+        };
+        for qubit in 0..qargs {
+            let gate = |name: Builtin, qubits| Op::Gate {
+                name: name.to_string(),
+                params: vec![],
+                qubits,
+            };
+            circuit.ops.push(gate(Builtin::H, vec![qargs + qubit]));
+            circuit
+                .ops
+                .push(gate(Builtin::Cx, vec![qargs + qubit, qubit]));
+        }
+        circuit.ops.extend_from_slice(ops);
+        circuit
+    };
+
+    let gate = Op::Gate {
+        name: qasm_name.clone(),
+        params: vec![],
+        qubits: (0..qargs).collect(),
+    };
+    let Ok(expected) = simulate_unmeasured(&entangled(&[gate])) else {
+        return Ok(());
+    };
+    if !body.params.is_empty() {
+        return Ok(());
+    }
+
+    let is_gate = body.qubits == qargs && body.ops.iter().all(|op| matches!(op, Op::Gate { .. }));
+    let applied = is_gate
+        && simulate_unmeasured(&entangled(&body.ops))
+            .is_ok_and(|state| state.equals_up_to_phase(&expected, Tolerance::default()));
+    if !applied {
+        Err(QccErrorKind::NotEquivalent)?
+    }
+    Ok(())
+}
+
+/// Iterations after which a loop is taken to never end.
+const MAX_ITERATIONS: usize = 1 << 16;
+
+/// Replaces each loop in `body` by its body repeated once per iteration. Its
+/// condition must be known at compile time, i.e. built from digits and from
+/// variables bound to them, whose values are kept in `consts`. Bindings in
+/// the body carry over to the next iteration, so that `let n = n - 1;` counts
+/// down `while (n > 0)`. Otherwise the loop cannot be unrolled. Likewise, a
+/// conditional is replaced by the block its condition picks. Only a bit may
+/// decide it while running, in which case bindings in its block may not
+/// happen, so their values are forgotten afterwards.
+fn unroll(
+    body: Vec<QccCell<Expr>>,
+    consts: &mut HashMap<Ident, f64>,
+) -> core::result::Result<Vec<QccCell<Expr>>, QccErrorLoc> {
+    let mut unrolled = vec![];
+    for expr in body {
+        let (cond, loop_body) = match *expr.as_ref().borrow_mut() {
+            Expr::Loop(ref cond, ref loop_body) => (cond.clone(), loop_body.clone()),
+            Expr::Conditional(ref cond, ref mut truth, ref mut otherwise) => {
+                // a condition known at compile time picks a block
+                let holds = match *cond.as_ref().borrow() {
+                    Expr::Let(..) => None,
+                    ref cond => eval_with(cond, consts, &Type::Bottom),
+                };
+                if let Some(holds) = holds {
+                    let block = if holds != 0.0 { truth } else { otherwise };
+                    unrolled.extend(unroll(block.clone(), consts)?);
+                    continue;
+                }
+
+                // otherwise a bit decides while running, and operations of
+                // each block are conditioned on its value
+                let cond = cond.as_ref().borrow();
+                let is_bit =
+                    matches!(*cond, Expr::Var(_) | Expr::Let(..)) && cond.get_type() == Type::Bit;
+                if !is_bit {
+                    Err((QccErrorKind::TranslationError, cond.get_location()))?
+                }
+                *truth = unroll(std::mem::take(truth), &mut consts.clone())?;
+                *otherwise = unroll(std::mem::take(otherwise), &mut consts.clone())?;
+                forget_bindings(truth, consts);
+                forget_bindings(otherwise, consts);
+                unrolled.push(expr.clone());
+                continue;
+            }
+            Expr::Let(ref var, ref val) => {
+                match eval_with(&val.as_ref().borrow(), consts, &var.get_type()) {
+                    Some(value) => consts.insert(var.name().clone(), value),
+                    None => consts.remove(var.name()),
+                };
+                unrolled.push(expr.clone());
+                continue;
+            }
+            _ => {
+                unrolled.push(expr.clone());
+                continue;
+            }
+        };
+
+        let location = cond.as_ref().borrow().get_location();
+        let mut iterations = 0;
+        loop {
+            match eval_with(&cond.as_ref().borrow(), consts, &Type::Bottom) {
+                Some(holds) if holds != 0.0 => {}
+                Some(_) => break,
+                None => Err((QccErrorKind::TranslationError, location.clone()))?,
+            }
+            if iterations == MAX_ITERATIONS {
+                Err((QccErrorKind::TranslationError, location.clone()))?
+            }
+            iterations += 1;
+            unrolled.extend(unroll(loop_body.clone(), consts)?);
+        }
+    }
+    Ok(unrolled)
+}
+
+/// Removes values of variables bound in an unrolled `block` from `consts`.
+fn forget_bindings(block: &[QccCell<Expr>], consts: &mut HashMap<Ident, f64>) {
+    for expr in block {
+        match *expr.as_ref().borrow() {
+            Expr::Let(ref var, _) => {
+                consts.remove(var.name());
+            }
+            Expr::Conditional(_, ref truth, ref otherwise) => {
+                forget_bindings(truth, consts);
+                forget_bindings(otherwise, consts);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Evaluates a classical expression like `Expr::eval_const`, looking up
+/// variables in `consts`. The expression is of type `expected` if it is made
+/// of literals only, e.g. the value of a let binding of a declared type.
+fn eval_with(expr: &Expr, consts: &HashMap<Ident, f64>, expected: &Type) -> Option<f64> {
+    match expr {
+        Expr::Var(var) => {
+            let value = *consts.get(var.name())?;
+            Some(if var.is_negative() { -value } else { value })
+        }
+        // integer operands are divided as integers
+        Expr::BinaryExpr(lhs, op, rhs) => {
+            let ty = expr.get_type_as(expected);
+            op.eval_as(
+                eval_with(&lhs.as_ref().borrow(), consts, &ty)?,
+                eval_with(&rhs.as_ref().borrow(), consts, &ty)?,
+                &ty,
+            )
+        }
+        _ => expr.eval_const(),
+    }
+}
+
+/// Checks if a function works on qubits, which makes it a circuit.
+fn is_quantum(f: &FunctionAST) -> bool {
+    f.get_output_type().is_quantum()
+        || f.get_input_type().iter().any(Type::is_quantum)
+        || binds_qubits(f)
+}
+
+/// Checks if a function binds any qubit in its body.
+fn binds_qubits(f: &FunctionAST) -> bool {
+    f.into_iter().any(|expr| match *expr.as_ref().borrow() {
+        Expr::Let(ref var, _) => var.get_type().is_quantum(),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_unrolled_loops() -> Result<()> {
+        let src = std::fs::read_to_string("tests/while.ql")?;
+        let ast = crate::session::parse_and_infer(&src, "while.ql", &Default::default())?;
+        // the bound of `repeat` is only known at runtime, the error is at
+        // its loop
+        let err = lower(ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::TranslationError));
+        assert_eq!(err.get_loc().borrow().row(), 3);
+
+        let src = "fn main() : qbit {
+    let q = 0q(1, 0);
+    while (0 > 1) {
+        let q = x(q);
+    }
+    let n = 3;
+    while (n > 0) {
+        let q = h(q);
+        let n = n - 1;
+    }
+    return q;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "while.ql", &Default::default())?;
+        let circuits = lower(ast)?;
+        let gates = |gate: &str| {
+            circuits[0]
+                .ops
+                .iter()
+                .filter(|op| matches!(op, Op::Gate { name, .. } if name == gate))
+                .count()
+        };
+        assert_eq!(gates("x"), 0);
+        assert_eq!(gates("h"), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_register_index() -> Result<()> {
+        let src = std::fs::read_to_string("tests/register-index.ql")?;
+        let ast = crate::session::parse_and_infer(&src, "register-index.ql", &Default::default())?;
+        let circuits = lower(ast)?;
+        let ops = circuits[0]
+            .ops
+            .iter()
+            .map(Op::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(ops, ["h q[0];", "cx q[0], q[15];"]);
+
+        // an index counted by a loop is only known once it is unrolled
+        let src = "fn main(q: qbit[4]) {
+    let i = 1;
+    while (i < 5) {
+        h(q[i]);
+        let i = i + 1;
+    }
+}
+";
+        let ast = crate::session::parse_and_infer(src, "register-index.ql", &Default::default())?;
+        assert!(lower(ast)
+            .err()
+            .unwrap()
+            .get_error()
+            .is(QccErrorKind::IndexOutOfBounds));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_lowered_conditionals() -> Result<()> {
+        let src = std::fs::read_to_string("tests/if-else.ql")?;
+        let ast = crate::session::parse_and_infer(&src, "if-else.ql", &Default::default())?;
+        let circuits = lower(ast)?;
+        // `n > 1` picks the `h`, the bit `b` is only known while running
+        let ops = circuits[0]
+            .ops
+            .iter()
+            .map(Op::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(ops, ["reset q[0];", "h q[0];", "if(c[0]==1) y q[0];"]);
+
+        // operations of the else block are conditioned on the bit being 0
+        let src = "fn main(b: bit, q: qbit) : qbit {
+    if b {
+        let q = x(q);
+    } else {
+        let q = z(q);
+    }
+    return q;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "if-else.ql", &Default::default())?;
+        let ops = lower(ast)?[0]
+            .ops
+            .iter()
+            .map(Op::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(ops, ["if(c[0]==1) x q[0];", "if(c[0]==0) z q[0];"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_literal_arguments() -> Result<()> {
+        // a qubit literal given to a gate is prepared before it is applied
+        let src = "fn main(out: bit) {
+    let q = h(0q(1, 0));
+    measure q -> out;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "literal.ql", &Default::default())?;
+        let ops = lower(ast)?[0]
+            .ops
+            .iter()
+            .map(Op::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(ops, ["reset q[0];", "h q[0];", "measure q[0] -> c[0];"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_prepared_bits() -> Result<()> {
+        // `^` flips the prepared qubit once for each bit
+        let src = "fn main(a: bit, b: bit) : qbit {
+    let q: qbit = a ^ ~b;
+    return q;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "bits.ql", &Default::default())?;
+        let ops = lower(ast)?[0]
+            .ops
+            .iter()
+            .map(Op::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [
+                "reset q[0];",
+                "if(c[0]==1) x q[0];",
+                "x q[0];",
+                "if(c[1]==1) x q[0];"
+            ]
+        );
+
+        // other bit operations can't be applied as gates, and the error is at
+        // the bit expression
+        let src = "fn main(a: bit, b: bit) : qbit {
+    let q: qbit = a | b;
+    return q;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "bits.ql", &Default::default())?;
+        let err = lower(ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::TranslationError));
+        let loc = err.get_loc();
+        assert_eq!(loc.borrow().row(), 2);
+        assert_eq!(loc.borrow().col(), 19);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_substituted_bindings() -> Result<()> {
+        // classical bindings are substituted into angles with their grouping
+        let src = "fn turn(a: f64, q: qbit) : qbit {
+    let b = a * 3.0;
+    let c = b + 1.0;
+    let d = 2.0 * 0.5;
+    return rz(c * d, q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "bindings.ql", &Default::default())?;
+        let circuits = lower(ast)?;
+        let Op::Gate { ref params, .. } = circuits[0].ops[0] else {
+            panic!("expected a gate");
+        };
+        assert_eq!(params, &[Param::Expr("((a*3)+1)*1".into())]);
+
+        // a binding which isn't an angle can't be applied as one
+        let src = "fn double(x: f64) : f64 {
+    return x * 2.0;
+}
+
+fn turn(a: f64, q: qbit) : qbit {
+    let b = double(a);
+    return rz(b, q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "bindings.ql", &Default::default())?;
+        assert!(lower(ast)
+            .err()
+            .unwrap()
+            .get_error()
+            .is(QccErrorKind::TranslationError));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_integer_division() -> Result<()> {
+        // integers are divided as integers, while untyped digits are floats
+        let src = "fn main(q: qbit) : qbit {
+    let n: i64 = 7 / 2;
+    let m: i64 = n / 2;
+    let k: i32 = 0 - 7 / 2;
+    let x = 7 / 2;
+    return rz(n + m + k + x, q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "division.ql", &Default::default())?;
+        let Op::Gate { ref params, .. } = lower(ast)?[0].ops[0] else {
+            panic!("expected a gate");
+        };
+        assert_eq!(params, &[Param::Const(4.5)]);
+
+        // an integer halved in a loop is unrolled down to 0
+        let src = "fn main(q: qbit) {
+    let n: i64 = 5;
+    while (n > 0) {
+        h(q);
+        let n: i64 = n / 2;
+    }
+}
+";
+        let ast = crate::session::parse_and_infer(src, "division.ql", &Default::default())?;
+        assert_eq!(lower(ast)?[0].ops.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_matrix_product() -> Result<()> {
+        // a matrix isn't decomposed into gates, so it can't be lowered
+        let src = std::fs::read_to_string("tests/matrix.ql")?;
+        let ast = crate::session::parse_and_infer(&src, "matrix.ql", &Default::default())?;
+        assert!(lower(ast)
+            .err()
+            .unwrap()
+            .get_error()
+            .is(QccErrorKind::TranslationError));
+
+        let src = "fn flip(q: qbit) : qbit {
+    let x = [[0, 1], [1, 0]];
+    return x * q;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "matrix.ql", &Default::default())?;
+        assert!(lower(ast)
+            .err()
+            .unwrap()
+            .get_error()
+            .is(QccErrorKind::TranslationError));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_recursive_gates() -> Result<()> {
+        // `f` and `g` apply each other, and `f` comes first
+        let src = std::fs::read_to_string("tests/recursion.ql")?;
+        let ast = crate::session::parse_and_infer(&src, "recursion.ql", &Default::default())?;
+        let err = lower(ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::TranslationError));
+        let location = err.get_loc();
+        assert_eq!((location.borrow().row(), location.borrow().col()), (2, 4));
+
+        let src = "fn spin(q: qbit) : qbit {
+    return spin(q);
+}
+
+fn main(q: qbit) : qbit {
+    return spin(q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "spin.ql", &Default::default())?;
+        let err = lower(ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::TranslationError));
+        let location = err.get_loc();
+        assert_eq!((location.borrow().row(), location.borrow().col()), (1, 4));
+
+        // declared gates may not apply themselves either
+        let src = "gate spin a {
+    spin(a);
+}
+
+fn main(q: qbit) : qbit {
+    return spin(q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "spin.ql", &Default::default())?;
+        let err = lower(ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::TranslationError));
+        let location = err.get_loc();
+        assert_eq!((location.borrow().row(), location.borrow().col()), (1, 6));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_nested_calls() -> Result<()> {
+        let ops = |src: &str| -> Result<Vec<String>> {
+            let ast = crate::session::parse_and_infer(src, "nested.ql", &Default::default())?;
+            let circuits = lower(ast)?;
+            let main = circuits.iter().find(|circuit| circuit.name == "main");
+            Ok(main.unwrap().ops.iter().map(Op::to_string).collect())
+        };
+
+        // arguments are applied on first
+        let src = "fn main(q: qbit) : qbit {
+    return h(x(q));
+}
+";
+        assert_eq!(ops(src)?, ["x q[0];", "h q[0];"]);
+        let src = "fn main(q: qbit, r: qbit) {
+    cx(h(q), r);
+}
+";
+        assert_eq!(ops(src)?, ["h q[0];", "cx q[0], q[1];"]);
+
+        // a function applied on no qubit is inlined, on qubits of its own
+        let src = "fn prep(a: f64) : qbit {
+    let q = alloc();
+    return ry(a, q);
+}
+
+fn main() : qbit {
+    let q = alloc();
+    let r = prep(0.5);
+    cx(r, q);
+    return q;
+}
+";
+        assert_eq!(
+            ops(src)?,
+            [
+                "reset q[0];",
+                "reset q[1];",
+                "ry(0.5) q[1];",
+                "cx q[1], q[0];"
+            ]
+        );
+
+        // a binding keeps gates its value applies
+        let src = "fn main(a: qbit, b: qbit) : qbit {
+    let c = cx(a, b);
+    return a;
+}
+";
+        assert_eq!(ops(src)?, ["cx q[0], q[1];"]);
+
+        // a function inlined into itself would never end
+        let src = "fn spin() : qbit {
+    return spin();
+}
+
+fn main() : qbit {
+    return h(spin());
+}
+";
+        let ast = crate::session::parse_and_infer(src, "nested.ql", &Default::default())?;
+        assert!(lower(ast)
+            .err()
+            .unwrap()
+            .get_error()
+            .is(QccErrorKind::TranslationError));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_circuit_depth() {
+        let circuit = Circuit {
+            name: "bell".into(),
+            ops: vec![
+                Op::Reset(0),
+                Op::Reset(1),
+                Op::Gate {
+                    name: "h".into(),
+                    params: vec![],
+                    qubits: vec![0],
+                },
+                Op::Gate {
+                    name: "cx".into(),
+                    params: vec![],
+                    qubits: vec![0, 1],
+                },
+            ],
+            qubits: 2,
+            clbits: 0,
+            ..Default::default()
+        };
+        assert_eq!(circuit.depth(), 3);
+        assert_eq!(circuit.ops[3].to_string(), "cx q[0], q[1];");
+        assert_eq!(Circuit::default().depth(), 0);
+    }
+
+    #[test]
+    fn check_circuit_listing() -> Result<()> {
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/hadamard-measure.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let listing = listing(&lower(ast)?);
+        let rows = listing.lines().collect::<Vec<&str>>();
+        assert_eq!(rows[0], "circuit main (1 qubits, 1 clbits)");
+        assert_eq!(rows[1], "step  gate      qubits          params");
+        assert_eq!(rows[2], "0     h         q[0]");
+        assert_eq!(rows[3], "1     rz        q[0]            0.5");
+        assert_eq!(rows[4], "2     measure   q[0] -> c[0]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_phase_preserving_state_prep() -> Result<()> {
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/phase.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let circuits = lower(ast)?;
+        let prepared = circuits[0]
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Gate { name, params, .. } if name == "U" => params
+                    .iter()
+                    .map(Param::value)
+                    .collect::<Option<Vec<f64>>>(),
+                _ => None,
+            })
+            .collect::<Vec<Vec<f64>>>();
+        assert_eq!(prepared.len(), 2);
+
+        // probability of measuring |0〉 in the X basis, i.e. after H
+        let x_basis_zero = |params: &[f64]| {
+            let amp_0 = Complex::from((params[0] / 2.0).cos());
+            let amp_1 = Complex::from_phase(params[1]) * (params[0] / 2.0).sin().into();
+            let plus = (amp_0 + amp_1) * std::f64::consts::FRAC_1_SQRT_2.into();
+            plus.norm_sqr()
+        };
+
+        // |+〉 and |-〉 have the same probabilities in the Z basis, but only
+        // the relative phase tells them apart in the X basis
+        assert!((x_basis_zero(&prepared[0]) - 1.0).abs() < 1e-9);
+        assert!(x_basis_zero(&prepared[1]).abs() < 1e-9);
+
+        assert_eq!(state_prep((Complex::ONE, Complex::ZERO)), None);
+        assert_eq!(
+            state_prep((Complex::ZERO, Complex::I)),
+            Some([std::f64::consts::PI, 0.0, 0.0])
+        );
+
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
+pub mod circuit;
 pub mod qasm;
+pub mod qelib;
+pub mod regalloc;
+pub mod statevector;
+pub mod unitary;
 use crate::error::Result;
 
 /// A translator trait can be implemented by IRs to provide a translation
@@ -1,24 +1,39 @@
 //! OpenQASM Codegen Backend
-use crate::ast::{Expr, FunctionAST, Ident, Qast};
+use crate::ast::{Expr, FunctionAST, GateAST, Ident, Qast};
 use crate::attributes::Attribute;
+use crate::codegen::circuit::{self, Circuit, Op, Param};
+use crate::codegen::qelib;
 use crate::codegen::Translator;
-use crate::error::Result;
-use crate::types::Type;
+use crate::error::{QccErrorKind, Result};
+use crate::utils::SEPARATOR;
 use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 
 use std::io::Write;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum QasmVersion {
     V2_0,
+    /// declares `qubit`/`bit` registers and has `if` blocks and measurements
+    /// as assignments, see `--qasm-version`
+    V3_0,
 }
 
 impl From<&str> for QasmVersion {
     fn from(value: &str) -> Self {
-        use QasmVersion::*;
-        match value {
-            "2.0" => V2_0,
-            _ => panic!("Qasm: Unexpected version number"),
+        value.parse().expect("Qasm: Unexpected version number")
+    }
+}
+
+impl std::str::FromStr for QasmVersion {
+    type Err = QccErrorKind;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "2.0" => Ok(Self::V2_0),
+            "3.0" => Ok(Self::V3_0),
+            _ => Err(QccErrorKind::NoSuchArg),
         }
     }
 }
@@ -28,6 +43,7 @@ impl fmt::Display for QasmVersion {
         use QasmVersion::*;
         match self {
             V2_0 => write!(f, "2.0"),
+            V3_0 => write!(f, "3.0"),
         }
     }
 }
@@ -40,6 +56,9 @@ pub struct QasmModule {
     version: QasmVersion,
     includes: Vec<QasmInclude>,
     gates: Vec<QasmGate>,
+    /// code of entry points, which runs at top level after all gates are
+    /// defined
+    blocks: Vec<QasmBlock>,
 }
 
 impl QasmModule {
@@ -48,13 +67,19 @@ impl QasmModule {
             version: version.into(),
             includes: vec![],
             gates: vec![],
+            blocks: vec![],
         }
     }
 
     /// It outputs the translated `QasmModule` to a file at `path`.
     pub(crate) fn generate(&self, path: &str) -> Result<()> {
         let mut asm_path = std::fs::File::create(path)?;
-        asm_path.write(self.to_string().as_bytes())?;
+        self.write_to(&mut asm_path)
+    }
+
+    /// Writes the translated `QasmModule` to any sink, e.g. stdout.
+    pub(crate) fn write_to(&self, w: &mut dyn Write) -> Result<()> {
+        w.write_all(self.to_string().as_bytes())?;
         Ok(())
     }
 }
@@ -64,25 +89,293 @@ impl Translator<Qast> for QasmModule {
     /// It takes a `Qast` object and translates it recursively into a
     /// `QasmModule`.
     fn translate(ast: Qast) -> Result<Self> {
-        let mut gates: Vec<QasmGate> = vec![];
-        for module in &ast {
-            for f in &*module {
-                if *f.get_output_type() == Type::Qbit || f.get_input_type().contains(&Type::Qbit) {
-                    let g: &FunctionAST = f.borrow();
-                    gates.push(g.into());
-                }
+        let gates = declared_gates(&ast);
+        Self::translate_to(circuit::lower(ast)?, gates, QasmVersion::V2_0)
+    }
+}
+
+impl Translator<Vec<Circuit>> for QasmModule {
+    /// Translates already lowered circuits, e.g. after optimizing them.
+    fn translate(circuits: Vec<Circuit>) -> Result<Self> {
+        Self::translate_to(circuits, vec![], QasmVersion::V2_0)
+    }
+}
+
+impl QasmModule {
+    /// Translates circuits like `translate`, into a given version of
+    /// OpenQASM, along with `gates` declared in the source.
+    /// Entry points run at top level, while every other function is defined
+    /// as a gate which they apply. Gate bodies can only apply other gates, so
+    /// functions which allocate, measure or condition on bits are inlined
+    /// where they are applied instead. A library without entry points runs
+    /// such a function at top level, as long as there is only one, since
+    /// several would run one after the other on the same qubits.
+    pub(crate) fn translate_to(
+        mut circuits: Vec<Circuit>,
+        gates: Vec<QasmGate>,
+        version: QasmVersion,
+    ) -> Result<Self> {
+        let names = gate_names(&circuits, &gates);
+        // registers aren't shared, each entry point has its own, nor named
+        // like any gate
+        let mut registers = names
+            .values()
+            .chain(gates.iter().map(|gate| &gate.name))
+            .cloned()
+            .collect::<Vec<Ident>>();
+        for circuit in &mut circuits {
+            circuit.name = names[&circuit.mangled_name()].clone();
+            for op in &mut circuit.ops {
+                rename_application(op, &names);
+            }
+        }
+
+        let mut circuits = inline_applications(applied_first(circuits))?;
+        if !circuits.iter().any(|circuit| circuit.entry) {
+            let mut programs = circuits.iter_mut().filter(|circuit| !is_gate(circuit));
+            match (programs.next(), programs.next()) {
+                (Some(program), None) => program.entry = true,
+                (None, _) => {}
+                (Some(_), Some(_)) => Err(QccErrorKind::TranslationError)?,
             }
         }
-        Ok(gates.into())
+
+        // gates declared in the source are defined ahead of all other gates,
+        // since OpenQASM gates must be defined before they are applied
+        let mut module: Self = gates.into();
+        module.version = version;
+        for circuit in &circuits {
+            if circuit.entry {
+                module
+                    .blocks
+                    .push(QasmBlock::lower(circuit, version, &mut registers)?);
+            } else if is_gate(circuit) {
+                module.gates.push(QasmGate::lower(circuit, version));
+            }
+        }
+        Ok(module)
     }
 }
 
+/// Names which can't be given to gates or registers, since OpenQASM has them
+/// already.
+const RESERVED: &[&str] = &[
+    "OPENQASM", "include", "qreg", "creg", "gate", "opaque", "measure", "reset", "barrier", "if",
+    "else", "U", "CX", "pi", "qubit", "bit", "def", "for", "while", "in", "return", "let", "const",
+    "input", "output", "int", "uint", "float", "angle", "bool", "complex", "array", "ctrl", "inv",
+    "pow", "gphase", "box", "delay", "duration", "stretch", "cal", "defcal", "extern", "switch",
+    "case", "default", "break", "continue", "end", "true", "false", "sin", "cos", "tan", "arcsin",
+    "arccos", "arctan", "exp", "ln", "sqrt", "mod", "popcount", "rotl", "rotr", "euler", "tau",
+];
+
+/// Checks if a name is taken by OpenQASM, either as a keyword, a constant or
+/// a standard gate.
+pub(crate) fn is_reserved(name: &str) -> bool {
+    RESERVED.contains(&name) || qelib::lookup(name).is_some()
+}
+
+/// Returns the name each circuit is defined by as a gate, keyed by its
+/// mangled name. A circuit keeps the name of its function unless a function
+/// of another module, a declared gate or OpenQASM has it, in which case it is
+/// named after its module as well, e.g. `lib_flip`, with a suffix if that is
+/// taken too.
+fn gate_names(circuits: &[Circuit], gates: &[QasmGate]) -> HashMap<Ident, Ident> {
+    let mut taken = gates
+        .iter()
+        .map(|gate| gate.name.clone())
+        .collect::<Vec<Ident>>();
+    let mut names = HashMap::new();
+    for circuit in circuits {
+        let shared = circuits.iter().filter(|c| c.name == circuit.name).count() > 1;
+        let mut name = circuit.name.clone();
+        if shared || is_reserved(&name) || taken.contains(&name) {
+            name = circuit.mangled_name().replace(SEPARATOR, "_");
+        }
+        let mut unique = name.clone();
+        let mut suffix = 0;
+        while is_reserved(&unique) || taken.contains(&unique) {
+            suffix += 1;
+            unique = format!("{}_{}", name, suffix);
+        }
+        taken.push(unique.clone());
+        names.insert(circuit.mangled_name(), unique);
+    }
+    names
+}
+
+/// Renames a gate applying a circuit after the name it is defined by.
+fn rename_application(op: &mut Op, names: &HashMap<Ident, Ident>) {
+    match op {
+        Op::Gate { name, .. } => {
+            if let Some(defined) = names.get(name) {
+                *name = defined.clone();
+            }
+        }
+        Op::Conditional { op, .. } => rename_application(op, names),
+        Op::Reset(_) | Op::Measure { .. } => {}
+    }
+}
+
+/// Orders circuits so that each comes after the circuits it applies, since
+/// OpenQASM gates must be defined before they are applied.
+fn applied_first(circuits: Vec<Circuit>) -> Vec<Circuit> {
+    fn visit(
+        circuit: usize,
+        circuits: &[Circuit],
+        visited: &mut Vec<usize>,
+        ordered: &mut Vec<usize>,
+    ) {
+        if visited.contains(&circuit) {
+            return;
+        }
+        visited.push(circuit);
+        for op in &circuits[circuit].ops {
+            let Op::Gate { name, .. } = op.conditions().1 else {
+                continue;
+            };
+            if let Some(callee) = circuits.iter().position(|callee| callee.name == *name) {
+                visit(callee, circuits, visited, ordered);
+            }
+        }
+        ordered.push(circuit);
+    }
+
+    let mut visited = vec![];
+    let mut ordered = vec![];
+    for circuit in 0..circuits.len() {
+        visit(circuit, &circuits, &mut visited, &mut ordered);
+    }
+    let mut circuits = circuits
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<Option<Circuit>>>();
+    ordered
+        .into_iter()
+        .filter_map(|circuit| circuits[circuit].take())
+        .collect()
+}
+
+/// Checks if a circuit can be defined as an OpenQASM gate, whose body only
+/// applies gates on its qargs.
+fn is_gate(circuit: &Circuit) -> bool {
+    !circuit.entry
+        && !circuit.qargs.is_empty()
+        && circuit.qubits == circuit.qargs.len()
+        && circuit.clbits == 0
+        && circuit.ops.iter().all(|op| matches!(op, Op::Gate { .. }))
+}
+
+/// Replaces each application of a circuit which isn't a gate by its
+/// operations. Circuits must be ordered by `applied_first`, so that inlined
+/// circuits have their own applications inlined already.
+fn inline_applications(circuits: Vec<Circuit>) -> Result<Vec<Circuit>> {
+    let mut inlined: Vec<Circuit> = vec![];
+    for mut circuit in circuits {
+        let mut ops = vec![];
+        for op in std::mem::take(&mut circuit.ops) {
+            let (clbits, applied) = op.conditions();
+            let callee = match applied {
+                Op::Gate { name, .. } => inlined
+                    .iter()
+                    .find(|callee| callee.name == *name && !is_gate(callee)),
+                _ => None,
+            };
+            match callee {
+                Some(callee) => ops.extend(inline(&mut circuit, callee, applied, &clbits)?),
+                None => ops.push(op),
+            }
+        }
+        circuit.ops = ops;
+        inlined.push(circuit);
+    }
+    Ok(inlined)
+}
+
+/// Returns operations of `callee` applied by `op` in `caller`, on the qubits
+/// it is applied on. Qubits and bits the callee uses on its own become new
+/// ones of the caller. An application conditioned on `clbits` conditions each
+/// of them instead, on top of their own conditions. Only qubits are given to
+/// gates, so callees with classical parameters can't be inlined.
+fn inline(
+    caller: &mut Circuit,
+    callee: &Circuit,
+    op: &Op,
+    clbits: &[(usize, bool)],
+) -> Result<Vec<Op>> {
+    let Op::Gate { qubits, .. } = op else {
+        unreachable!("only gates apply circuits")
+    };
+    if !callee.params.is_empty() || !callee.cargs.is_empty() || qubits.len() != callee.qargs.len() {
+        Err(QccErrorKind::TranslationError)?
+    }
+
+    // bits of the callee are named after it, apart from those of the caller
+    let (first_qubit, first_clbit) = (caller.qubits, caller.clbits);
+    let prefixed = |names: &[Option<(Ident, usize)>], bit: usize| {
+        let (name, index) = names.get(bit).cloned().flatten()?;
+        Some((format!("{}_{}", callee.name, name), index))
+    };
+    caller.qubit_names.resize(first_qubit, None);
+    for qubit in callee.qargs.len()..callee.qubits {
+        caller
+            .qubit_names
+            .push(prefixed(&callee.qubit_names, qubit));
+    }
+    caller.clbit_names.resize(first_clbit, None);
+    for clbit in 0..callee.clbits {
+        caller
+            .clbit_names
+            .push(prefixed(&callee.clbit_names, clbit));
+    }
+    caller.qubits += callee.qubits - callee.qargs.len();
+    caller.clbits += callee.clbits;
+    let qubit = |q: usize| match qubits.get(q) {
+        Some(&qubit) => qubit,
+        None => first_qubit + q - callee.qargs.len(),
+    };
+    let rename = |op: &Op| -> Op {
+        match op {
+            Op::Reset(q) => Op::Reset(qubit(*q)),
+            Op::Measure { qubit: q, clbit } => Op::Measure {
+                qubit: qubit(*q),
+                clbit: first_clbit + clbit,
+            },
+            Op::Gate {
+                name,
+                params,
+                qubits,
+            } => Op::Gate {
+                name: name.clone(),
+                params: params.clone(),
+                qubits: qubits.iter().map(|q| qubit(*q)).collect(),
+            },
+            Op::Conditional { .. } => unreachable!("conditions are renamed on their own"),
+        }
+    };
+
+    let mut ops = vec![];
+    for op in &callee.ops {
+        let (own, applied) = op.conditions();
+        let conditions = clbits
+            .iter()
+            .copied()
+            .chain(
+                own.into_iter()
+                    .map(|(clbit, value)| (first_clbit + clbit, value)),
+            )
+            .collect::<Vec<(usize, bool)>>();
+        ops.push(rename(applied).conditioned(&conditions));
+    }
+    Ok(ops)
+}
+
 impl From<Vec<QasmGate>> for QasmModule {
     fn from(gates: Vec<QasmGate>) -> Self {
         Self {
             version: QasmVersion::V2_0,
             includes: vec![],
             gates,
+            blocks: vec![],
         }
     }
 }
@@ -91,14 +384,13 @@ impl Default for QasmModule {
     fn default() -> Self {
         Self {
             version: QasmVersion::V2_0,
-            includes: vec![QasmInclude(
-                "/home/manas/workspace/quale/openqasm-examples/qelib1.inc",
-            )],
+            includes: vec![QasmInclude("qelib1.inc".into())],
             gates: vec![QasmGate::new(
                 "def",
                 &["lambda", "theta"],
                 vec![Qreg::new("a", 8), Qreg::new("b", 8)],
             )],
+            blocks: vec![],
         }
     }
 }
@@ -111,9 +403,50 @@ impl fmt::Display for QasmModule {
             writeln!(f, "{}", include)?;
         }
 
+        // only standard gates used by the program are defined, while 3.0
+        // has all of them in its standard library
+        let used = self
+            .gates
+            .iter()
+            .flat_map(|g| g.uses.iter())
+            .chain(self.blocks.iter().flat_map(|b| b.uses.iter()))
+            .map(|u| u.as_str())
+            .collect::<Vec<&str>>();
+        match self.version {
+            QasmVersion::V2_0 => {
+                for definition in qelib::definitions(used) {
+                    writeln!(f, "{}", definition)?;
+                }
+            }
+            QasmVersion::V3_0 if !used.is_empty() => {
+                writeln!(f, "{}", QasmInclude("stdgates.inc".into()))?;
+            }
+            QasmVersion::V3_0 => {}
+        }
+
+        // entry points declare a register per variable, while gates only
+        // apply on their qargs
+        let qregs = self.blocks.iter().flat_map(|block| block.qregs.iter());
+        let cregs = self.blocks.iter().flat_map(|block| block.cregs.iter());
+        for (name, len) in qregs {
+            match self.version {
+                QasmVersion::V2_0 => writeln!(f, "qreg {}[{}];", name, len)?,
+                QasmVersion::V3_0 => writeln!(f, "qubit[{}] {};", len, name)?,
+            }
+        }
+        for (name, len) in cregs {
+            match self.version {
+                QasmVersion::V2_0 => writeln!(f, "creg {}[{}];", name, len)?,
+                QasmVersion::V3_0 => writeln!(f, "bit[{}] {};", len, name)?,
+            }
+        }
+
         for gate in &self.gates {
             write!(f, "{}", gate)?;
         }
+        for block in &self.blocks {
+            write!(f, "{}", block)?;
+        }
         Ok(())
     }
 }
@@ -244,6 +577,12 @@ pub(crate) struct QasmGate {
     params: Vec<Ident>,
     qargs: Vec<Qreg>,
     instructions: Vec<Ident>,
+    /// number of physical qubits used by the body
+    qubits: usize,
+    /// number of classical bits used by the body
+    clbits: usize,
+    /// standard gates applied in the body
+    uses: BTreeSet<Ident>,
 }
 
 impl QasmGate {
@@ -253,30 +592,286 @@ impl QasmGate {
             params: params.to_vec().iter().map(|p| p.to_string()).collect(),
             qargs,
             instructions: Default::default(),
+            qubits: 0,
+            clbits: 0,
+            uses: Default::default(),
         }
     }
 }
 
-impl From<&FunctionAST> for QasmGate {
-    fn from(f: &FunctionAST) -> Self {
-        let mut instructions: Vec<Ident> = Default::default();
-        for expr in f {
-            match *expr.as_ref().borrow() {
-                Expr::Let(ref var, ref val) => {
-                    if var.is_typed() && var.get_type() == Type::Qbit {
-                        instructions.push(format!("qreg {}[1];", var.name()));
-                    }
+impl From<&Circuit> for QasmGate {
+    fn from(circuit: &Circuit) -> Self {
+        Self::lower(circuit, QasmVersion::V2_0)
+    }
+}
+
+impl QasmGate {
+    /// Lowers the operations of a circuit into instructions of a version of
+    /// OpenQASM.
+    /// Its qargs are the qubit parameters of the circuit, which its
+    /// instructions are applied on.
+    fn lower(circuit: &Circuit, version: QasmVersion) -> Self {
+        let params = circuit
+            .params
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<&str>>();
+        let qargs = circuit
+            .qargs
+            .iter()
+            .map(|qarg| Qreg::new(qarg.as_str(), 1))
+            .collect();
+        let mut gate = Self::new(&circuit.name, &params, qargs);
+        let qubit = |qubit: usize| match circuit.qargs.get(qubit) {
+            Some(qarg) => qarg.clone(),
+            None => format!("q[{}]", qubit),
+        };
+        for op in &circuit.ops {
+            uses(op, &mut gate.uses);
+            let clbit = |clbit: usize| format!("c[{}]", clbit);
+            gate.instructions
+                .push(instruction(op, version, &qubit, &clbit));
+        }
+        gate.qubits = circuit.qubits;
+        gate.clbits = circuit.clbits;
+        gate
+    }
+}
+
+/// Code of an entry point, which runs at top level on the registers of the
+/// module.
+pub(crate) struct QasmBlock {
+    name: Ident,
+    instructions: Vec<Ident>,
+    /// quantum registers used, with their lengths
+    qregs: Vec<(Ident, usize)>,
+    /// classical registers used, with their lengths
+    cregs: Vec<(Ident, usize)>,
+    /// standard gates applied
+    uses: BTreeSet<Ident>,
+}
+
+impl QasmBlock {
+    /// Lowers the operations of a circuit into top level instructions, on a
+    /// register for each variable of the circuit, apart from `registers` of
+    /// other blocks. Its classical parameters are only known when it is
+    /// applied, so it can't have any.
+    fn lower(circuit: &Circuit, version: QasmVersion, registers: &mut Vec<Ident>) -> Result<Self> {
+        if !circuit.params.is_empty() {
+            Err(QccErrorKind::TranslationError)?
+        }
+
+        // qubits and bits share a namespace, bits not bound to any variable
+        // are named like the registers of older versions
+        let mut qregs = vec![];
+        let mut cregs = vec![];
+        let qubits = (0..circuit.qubits)
+            .map(|qubit| {
+                let name = circuit.qubit_names.get(qubit).cloned().flatten();
+                register(registers, &mut qregs, name.unwrap_or(("q".into(), 0)))
+            })
+            .collect::<Vec<String>>();
+        let clbits = (0..circuit.clbits)
+            .map(|clbit| {
+                let name = circuit.clbit_names.get(clbit).cloned().flatten();
+                register(registers, &mut cregs, name.unwrap_or(("c".into(), 0)))
+            })
+            .collect::<Vec<String>>();
+
+        let mut block = Self {
+            name: circuit.name.clone(),
+            instructions: vec![],
+            qregs,
+            cregs,
+            uses: Default::default(),
+        };
+        for op in &circuit.ops {
+            // OpenQASM 2 compares whole registers, so only one of a single
+            // bit can condition an operation, and it has no nested `if`
+            if let (QasmVersion::V2_0, Op::Conditional { clbit, .. }) = (version, op) {
+                let (register, _) = clbits[*clbit].split_once('[').unwrap();
+                if block
+                    .cregs
+                    .iter()
+                    .any(|(name, len)| name == register && *len > 1)
+                {
+                    Err(QccErrorKind::TranslationError)?
+                }
+                if op.conditions().0.len() > 1 {
+                    Err(QccErrorKind::TranslationError)?
                 }
-                _ => {}
             }
+            uses(op, &mut block.uses);
+            block.instructions.push(instruction(
+                op,
+                version,
+                &|qubit| qubits[qubit].clone(),
+                &|clbit| clbits[clbit].clone(),
+            ));
         }
+        Ok(block)
+    }
+}
 
-        Self {
-            name: f.get_name().clone(),
-            params: vec![],
-            qargs: vec![],
-            instructions,
+/// Returns the operand of a bit named after element `index` of variable
+/// `name`, adding a register to `kind` for a new variable. Each element
+/// follows the first one, which starts a register named after the variable,
+/// or after it with a suffix if another one of `registers` or OpenQASM is
+/// already, e.g. `x_1` for a variable `x`.
+fn register(
+    registers: &mut Vec<Ident>,
+    kind: &mut Vec<(Ident, usize)>,
+    (name, index): (Ident, usize),
+) -> String {
+    if index == 0 || kind.is_empty() {
+        let mut unique = name.clone();
+        let mut suffix = 0;
+        while is_reserved(&unique) || registers.contains(&unique) {
+            suffix += 1;
+            unique = format!("{}_{}", name, suffix);
         }
+        registers.push(unique.clone());
+        kind.push((unique, 0));
+    }
+    let (register, len) = kind.last_mut().unwrap();
+    *len += 1;
+    format!("{}[{}]", register, *len - 1)
+}
+
+impl fmt::Display for QasmBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\n// {}", self.name)?;
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the standard gate applied by an operation to `uses`. Conditional
+/// gates need their definition all the same.
+fn uses(op: &Op, uses: &mut BTreeSet<Ident>) {
+    if let Op::Gate { ref name, .. } = op.conditions().1 {
+        if qelib::lookup(name).is_some() {
+            uses.insert(name.clone());
+        }
+    }
+}
+
+/// Returns an operation as an instruction of a version of OpenQASM, naming
+/// each physical qubit by `qubit` and each classical bit by `bit`.
+fn instruction(
+    op: &Op,
+    version: QasmVersion,
+    qubit: &dyn Fn(usize) -> String,
+    bit: &dyn Fn(usize) -> String,
+) -> String {
+    match (version, op) {
+        (_, Op::Reset(q)) => format!("reset {};", qubit(*q)),
+        (QasmVersion::V2_0, Op::Measure { qubit: q, clbit }) => {
+            format!("measure {} -> {};", qubit(*q), bit(*clbit))
+        }
+        (QasmVersion::V3_0, Op::Measure { qubit: q, clbit }) => {
+            format!("{} = measure {};", bit(*clbit), qubit(*q))
+        }
+        (
+            _,
+            Op::Gate {
+                name,
+                params,
+                qubits,
+            },
+        ) => {
+            let mut instruction = name.clone();
+            if !params.is_empty() {
+                let params = params.iter().map(Param::to_string).collect::<Vec<String>>();
+                instruction += &format!("({})", params.join(","));
+            }
+            let qargs = qubits.iter().map(|q| qubit(*q)).collect::<Vec<String>>();
+            instruction + &format!(" {};", qargs.join(", "))
+        }
+        (QasmVersion::V2_0, Op::Conditional { clbit, value, op }) => {
+            // a whole register of a single bit is compared
+            let creg = bit(*clbit);
+            let (creg, _) = creg.split_once('[').unwrap_or((&creg, ""));
+            format!(
+                "if({}=={}) {}",
+                creg,
+                u8::from(*value),
+                instruction(op, version, qubit, bit)
+            )
+        }
+        (QasmVersion::V3_0, Op::Conditional { clbit, value, op }) => {
+            format!(
+                "if ({}{}) {{ {} }}",
+                if *value { "" } else { "!" },
+                bit(*clbit),
+                instruction(op, version, qubit, bit)
+            )
+        }
+    }
+}
+
+impl From<&GateAST> for QasmGate {
+    /// Qargs of a declared gate are kept by name, and each application in its
+    /// body is emitted with its angles as QASM arithmetic.
+    fn from(decl: &GateAST) -> Self {
+        let params = decl
+            .iter_params()
+            .map(|p| p.name().as_str())
+            .collect::<Vec<&str>>();
+        let qargs = decl
+            .iter_qargs()
+            .map(|q| Qreg::new(q.name().as_str(), 1))
+            .collect();
+        let mut gate = Self::new(decl.get_name(), &params, qargs);
+        // inference made sure angles only use `pi` and parameters
+        let scope = Param::scope(decl.iter_params().map(|p| p.name()));
+
+        for expr in decl {
+            let Expr::FnCall(ref callee, ref args) = *expr.as_ref().borrow() else {
+                continue;
+            };
+            let mut angles = vec![];
+            let mut qubits = vec![];
+            for arg in args {
+                let arg = arg.as_ref().borrow();
+                match *arg {
+                    Expr::Var(ref var) if decl.iter_qargs().any(|q| q.name() == var.name()) => {
+                        qubits.push(var.name().clone())
+                    }
+                    _ => angles.extend(Param::lower(&arg, &scope).map(|p| p.to_string())),
+                }
+            }
+
+            if qelib::lookup(callee.get_name()).is_some() {
+                gate.uses.insert(callee.get_name().clone());
+            }
+            let mut instruction = callee.get_name().clone();
+            if !angles.is_empty() {
+                instruction += &format!("({})", angles.join(","));
+            }
+            instruction += &format!(" {};", qubits.join(", "));
+            gate.instructions.push(instruction);
+        }
+        gate
+    }
+}
+
+/// Returns QASM definitions of gates declared in the source.
+pub(crate) fn declared_gates(ast: &Qast) -> Vec<QasmGate> {
+    let mut gates = vec![];
+    for module in ast {
+        gates.extend(module.iter_gates().map(QasmGate::from));
+    }
+    gates
+}
+
+impl TryFrom<&FunctionAST> for QasmGate {
+    type Error = crate::error::QccError;
+
+    fn try_from(f: &FunctionAST) -> Result<Self> {
+        Ok((&Circuit::try_from(f)?).into())
     }
 }
 
@@ -326,7 +921,9 @@ gate {} {}
     }
 }
 
-pub(crate) struct QasmInclude(&'static str);
+/// An include of a file by its path, relative to the generated module so that
+/// it can be compiled anywhere.
+pub(crate) struct QasmInclude(String);
 
 impl fmt::Display for QasmInclude {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -367,8 +964,10 @@ mod tests {
         let qgate = QasmGate::new("cu1", &["lambda", "theta"], vec![qreg_a, qreg_b]);
         println!("{qgate}");
 
-        // let qmod: QasmModule = Default::default();
-        // println!("{qmod}");
+        // includes are relative to the generated file
+        let qmod: QasmModule = Default::default();
+        let qasm = qmod.to_string();
+        assert!(qasm.starts_with("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n"));
     }
 
     use crate::error::Result;
@@ -384,4 +983,568 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn check_lazy_std_gates() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/hadamard.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("gate h a { u2(0,pi) a; }"));
+        assert!(qasm.contains("gate u2(phi,lambda) q { U(pi/2,phi,lambda) q; }"));
+        assert!(qasm.contains("\n// main\nreset q[0];\nh q[0];\n"));
+        assert!(!qasm.contains("gate cx"));
+        assert!(!qasm.contains("include"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_measure_register() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/measure.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("qreg reg[3];"));
+        assert!(qasm.contains("creg results[3];"));
+        assert!(qasm.contains(
+            "measure reg[0] -> results[0];\nmeasure reg[1] -> results[1];\n\
+             measure reg[2] -> results[2];\n"
+        ));
+        assert_eq!(qasm.matches("measure").count(), 3);
+
+        // an alias names the qubits of the register it is bound to
+        let src = "fn main(reg: qbit[3], results: bit[3]) {
+    let other = reg;
+    measure other -> results;
+}
+";
+        let qasm = crate::session::compile_source(src, "measure.ql", &Default::default())?;
+        let qasm = qasm.to_string();
+        assert!(!qasm.contains("qreg other"));
+        assert!(qasm.contains(
+            "measure reg[0] -> results[0];\nmeasure reg[1] -> results[1];\n\
+             measure reg[2] -> results[2];\n"
+        ));
+
+        // a tensor of qubits is a register of those qubits
+        let src = "fn main(a: qbit, b: qbit, results: bit[2]) {
+    let reg = [a, b];
+    measure reg -> results;
+}
+";
+        let qasm = crate::session::compile_source(src, "measure.ql", &Default::default())?;
+        let qasm = qasm.to_string();
+        assert!(qasm.contains("measure a[0] -> results[0];\nmeasure b[0] -> results[1];\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_generic_instances() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/generic.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // an instance applies `x` on each qubit of the register it is given
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains(
+            "gate flip_3 reg_0, reg_1, reg_2\n{\n    x reg_0;\n    x reg_1;\n    x reg_2;\n}\n"
+        ));
+        assert!(qasm.contains(
+            "\n// main\nflip_3 reg[0], reg[1], reg[2];\nidentity_3 reg[0], reg[1], reg[2];\n\
+             measure reg[0] -> results[0];\nmeasure reg[1] -> results[1];\n\
+             measure reg[2] -> results[2];\n"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_write_to() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/hadamard.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        let qasm = QasmModule::translate(ast)?;
+
+        // lib and bin tests run at the same time, so each gets its own
+        let path = std::env::temp_dir().join(format!("qcc-write-to-{}.s", std::process::id()));
+        let path = path.to_str().unwrap();
+        qasm.generate(path)?;
+        let mut written = vec![];
+        qasm.write_to(&mut written)?;
+        assert_eq!(written, std::fs::read(path)?);
+        assert_eq!(written, qasm.to_string().into_bytes());
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_variable_registers() -> Result<()> {
+        use crate::session::compile_source;
+
+        // parameters and lets get a register each, named after the first
+        // variable bound to the qubit
+        let src = "fn flip(q: qbit) : qbit {\n    return x(q);\n}\n\n\
+                   fn main(a: qbit, b: qbit, r: bit) {\n    let c = h(a);\n    \
+                   measure c -> r;\n    free(b);\n}\n";
+        let qasm = compile_source(src, "registers.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("qreg a[1];\nqreg b[1];\ncreg r[1];\n"));
+        assert!(qasm.contains("\n// main\nh a[0];\nmeasure a[0] -> r[0];\n"));
+        assert_eq!(qasm.matches("qreg").count(), 2);
+
+        // variables shadowing one another are told apart
+        let src = "fn main() {\n    let a = alloc();\n    let a = alloc();\n    cx(a, a);\n}\n";
+        let qasm = compile_source(src, "registers.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("qreg a[1];\nqreg a_1[1];\n"));
+
+        // nor named like gates, builtins or OpenQASM keywords
+        let src = "fn flip(q: qbit) : qbit {\n    return h(q);\n}\n\n\
+                   fn main(x: qbit, pi: qbit, flip: qbit) {\n    x(x);\n    \
+                   h(pi);\n    flip(flip);\n}\n";
+        let qasm = compile_source(src, "registers.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("qreg x_1[1];\nqreg pi_1[1];\nqreg flip_1[1];\n"));
+        assert!(qasm.contains("\n// main\nx x_1[0];\nh pi_1[0];\nflip flip_1[0];\n"));
+
+        // entry points don't share registers of the same name
+        let src = std::fs::read_to_string("tests/test12.ql")?;
+        let qasm = compile_source(&src, "test12.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("\n// create_new_state\nreset q[0];\nif(b==1) x q[0];\n"));
+        assert!(qasm.contains("\n// measure_state\nmeasure q_1[0] -> b_1[0];\n"));
+
+        // a library runs its only function which isn't a gate, but several
+        // would run one after the other on the same qubits
+        let src = "fn flip(q: qbit) : qbit {\n    return x(q);\n}\n\n\
+                   fn toss(q: qbit, r: bit) {\n    measure q -> r;\n}\n";
+        let qasm = compile_source(src, "registers.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("gate flip q\n"));
+        assert!(qasm.contains("\n// toss\nmeasure q[0] -> r[0];\n"));
+        let src = src.replace(
+            "fn flip",
+            "fn other(q: qbit, r: bit) {\n    measure q -> r;\n}\n\nfn flip",
+        );
+        match compile_source(&src, "registers.ql", &Default::default()) {
+            Ok(_) => unreachable!(),
+            Err(err) => assert!(err.is(QccErrorKind::TranslationError)),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_gate_params() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/gate-params.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("gate rotate(theta) q\n"));
+        assert!(qasm.contains("    U(pi/2,0,0) q;\n"));
+        // `theta` is only known when the gate is applied
+        assert!(qasm.contains("    rz(theta/2) q;\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_declared_gate() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/gate.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("gate mygate(theta) a\n{\n    rx(theta) a;\n}\n"));
+        assert!(qasm.contains("gate bell a, b\n{\n    h a;\n    cx a, b;\n}\n"));
+        // standard gates used only by declared gates are defined as well
+        assert!(qasm.contains("gate rx(theta) a { u3(theta,-pi/2,pi/2) a; }"));
+        // declared gates are defined before they are applied
+        assert!(qasm.find("gate mygate") < qasm.find("// main"));
+        assert!(qasm.contains("\nmygate(0.5) q[0];\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_gate_body() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/test13.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("gate foo(theta) a, b\n{\n    U(theta,0,0) a;\n    cx a, b;\n}"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_swap() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/swap.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("\nswap a[0], b[0];\n"));
+        assert!(qasm.contains("gate swap a,b { cx a,b; cx b,a; cx a,b; }"));
+        assert!(qasm.contains("gate cx c,t { CX c,t; }"));
+
+        // both arguments must be qubits
+        let mut parser = Parser::from_source(
+            "fn main(q: qbit, theta: f64) {\n    swap(q, theta);\n}\n",
+            Default::default(),
+        );
+        let mut ast = parser.parse(&"swap.ql".into())?;
+        assert!(infer(&mut ast).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_float_precision() -> Result<()> {
+        use crate::session::compile_source;
+
+        let src = "fn main(q: qbit) : qbit {\n    rz(0.7071067811865476, q)\n}\n";
+        let qasm = compile_source(src, "rotation.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("\nrz(0.7071067811865476) q[0];\n"));
+
+        let config =
+            Parser::parse_cmdline(vec!["tests/test1.ql", "--float-precision", "6"])?.unwrap();
+        assert_eq!(config.float_precision, Some(6));
+        let qasm = compile_source(src, "rotation.ql", &config)?.to_string();
+        assert!(qasm.contains("\nrz(0.707107) q[0];\n"));
+
+        assert!(Parser::parse_cmdline(vec!["tests/test1.ql", "--float-precision", "-1"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qasm_versions() -> Result<()> {
+        use crate::session::compile_source;
+
+        let src = std::fs::read_to_string("tests/test12.ql")?;
+        let qasm = compile_source(&src, "test12.ql", &Default::default())?.to_string();
+        assert!(qasm.starts_with("OPENQASM 2.0;\n"));
+        assert!(qasm.contains("qreg q[1];\nqreg q_1[1];\ncreg b[1];\ncreg b_1[1];\n"));
+        assert!(qasm.contains("\nif(b==1) x q[0];\n"));
+        assert!(qasm.contains("gate x a { u3(pi,0,pi) a; }"));
+
+        let config =
+            Parser::parse_cmdline(vec!["tests/test1.ql", "--qasm-version", "3.0"])?.unwrap();
+        assert_eq!(config.qasm_version, QasmVersion::V3_0);
+        let qasm = compile_source(&src, "test12.ql", &config)?.to_string();
+        assert!(qasm.starts_with("OPENQASM 3.0;\ninclude \"stdgates.inc\";\n"));
+        assert!(qasm.contains("qubit[1] q;\nqubit[1] q_1;\nbit[1] b;\nbit[1] b_1;\n"));
+        assert!(qasm.contains("\nif (b[0]) { x q[0]; }\n"));
+        assert!(!qasm.contains("gate x a"));
+
+        let src = "fn main(q: qbit, result: bit) {\n    measure q -> result;\n}\n";
+        let qasm = compile_source(src, "measure.ql", &config)?.to_string();
+        assert!(qasm.contains("\nresult[0] = measure q[0];\n"));
+
+        // the else block of a bit holds on it being 0
+        let src = "fn main(b: bit, q: qbit) {\n    if (b) {\n        x(q);\n    } else {\n        \
+                   z(q);\n    }\n}\n";
+        let qasm = compile_source(src, "else.ql", &config)?.to_string();
+        assert!(qasm.contains("\nif (b[0]) { x q[0]; }\nif (!b[0]) { z q[0]; }\n"));
+        let qasm = compile_source(src, "else.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("\nif(b==1) x q[0];\nif(b==0) z q[0];\n"));
+
+        // an operation of nested conditionals holds on both bits, which only
+        // OpenQASM 3 can express
+        let src = "fn main(a: bit, b: bit, q: qbit) {\n    if (a) {\n        \
+                   if (b) {\n            x(q);\n        }\n        h(q);\n    }\n}\n";
+        let qasm = compile_source(src, "nested.ql", &config)?.to_string();
+        assert!(qasm.contains("\nif (a[0]) { if (b[0]) { x q[0]; } }\nif (a[0]) { h q[0]; }\n"));
+        match compile_source(src, "nested.ql", &Default::default()) {
+            Ok(_) => unreachable!(),
+            Err(err) => assert!(err.is(QccErrorKind::TranslationError)),
+        }
+
+        assert!(Parser::parse_cmdline(vec!["tests/test1.ql", "--qasm-version", "1.0"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_bit_to_qubit() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/test12.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // `let q: qbit = b` allocates a qubit and flips it if `b` is 1
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("\nreset q[0];\nif(b==1) x q[0];\n"));
+        assert!(qasm.contains("gate x a { u3(pi,0,pi) a; }"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qubit_to_bit() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/test12.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // `let b: bit = q` measures `q` into a bit of its own, which a gate
+        // can't do, so it is only run as an entry point
+        let qasm = QasmModule::translate(ast)?.to_string();
+        assert!(qasm.contains("\n// measure_state\nmeasure q_1[0] -> b_1[0];\n"));
+        assert!(!qasm.contains("gate measure_state"));
+        assert_well_formed(&qasm);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qasm_name() -> Result<()> {
+        use crate::diagnostics::Diagnostics;
+        use crate::inference::infer_ast;
+        use crate::session::compile_source;
+
+        let src = std::fs::read_to_string("tests/qasm-name.ql")?;
+        let qasm = compile_source(&src, "qasm-name.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("\n// main\nh q[0];\n"));
+        assert!(qasm.contains("gate h a { u2(0,pi) a; }"));
+        assert!(!qasm.contains("Hadamard"));
+
+        // a function of another module with the same name is its own gate
+        let src = "module other {\n\n\
+                   #[qasm_name(\"h\")]\nfn Hadamard(q: qbit) : qbit {\n    return h(q);\n}\n\n}\n\n\
+                   fn Hadamard(q: qbit) : qbit {\n    return x(q);\n}\n\n\
+                   fn main(q: qbit) : qbit {\n    let a = Hadamard(q);\n    \
+                   return other::Hadamard(a);\n}\n";
+        let qasm = compile_source(src, "qasm-name.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("\n// main\nHadamard q[0];\nh q[0];\n"));
+
+        // the gate must be a standard or declared one ...
+        let src = "#[qasm_name(\"myflip\")]\nfn flip(q: qbit) : qbit {\n    return x(q);\n}\n";
+        let mut ast = Parser::from_source(src, Default::default()).parse(&"flip.ql".into())?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors
+            .iter()
+            .all(|err| err.is(QccErrorKind::UnexpectedAttr)));
+
+        // ... which the body applies
+        let src = "#[qasm_name(\"h\")]\nfn flip(q: qbit) : qbit {\n    return x(q);\n}\n";
+        let err = compile_source(src, "qasm-name.ql", &Default::default()).err();
+        assert!(err.unwrap().is(QccErrorKind::NotEquivalent));
+        let src = "#[qasm_name(\"cx\")]\nfn flip(a: qbit, b: qbit) {\n    cx(b, a);\n}\n";
+        let err = compile_source(src, "qasm-name.ql", &Default::default()).err();
+        assert!(err.unwrap().is(QccErrorKind::NotEquivalent));
+
+        Ok(())
+    }
+
+    /// Checks that OpenQASM 2.0 is well-formed: gates and registers are
+    /// defined before they are used, and gate bodies only apply gates on
+    /// qargs of the gate.
+    fn assert_well_formed(qasm: &str) {
+        use std::collections::HashMap;
+
+        // `name(params) a, b` splits into the name and its operands
+        fn split(statement: &str) -> (&str, Vec<&str>) {
+            let statement = statement.trim().trim_end_matches(';');
+            let (name, operands) = match statement.find(')') {
+                Some(end) if statement.starts_with(|c: char| c.is_alphabetic()) => {
+                    let name = &statement[..statement.find('(').unwrap_or(end)];
+                    (name, &statement[end + 1..])
+                }
+                _ => statement.split_once(' ').unwrap_or((statement, "")),
+            };
+            let name = name.split('(').next().unwrap().trim();
+            let operands = operands
+                .split([',', ' '])
+                .filter(|operand| !operand.is_empty())
+                .collect();
+            (name, operands)
+        }
+
+        let mut gates = vec!["U", "CX"];
+        let mut registers: HashMap<&str, usize> = HashMap::new();
+        let mut lines = qasm.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            if line.is_empty() || line.starts_with("//") || line.starts_with("OPENQASM") {
+                continue;
+            }
+            if let Some(gate) = line.strip_prefix("gate ") {
+                let (head, body) = match gate.split_once('{') {
+                    Some((head, body)) => (head, body.trim_end_matches('}').to_string()),
+                    None => {
+                        assert_eq!(lines.next(), Some("{"));
+                        let body = lines
+                            .by_ref()
+                            .take_while(|line| *line != "}")
+                            .collect::<Vec<&str>>();
+                        (gate, body.join("\n"))
+                    }
+                };
+                let (name, qargs) = split(head);
+                assert!(!qargs.is_empty(), "gate `{}` has no qargs", name);
+                for statement in body.split(';').filter(|s| !s.trim().is_empty()) {
+                    let (applied, operands) = split(statement);
+                    assert!(gates.contains(&applied), "`{}` isn't defined", applied);
+                    for operand in operands {
+                        assert!(qargs.contains(&operand), "`{}` isn't a qarg", operand);
+                    }
+                }
+                gates.push(name);
+                continue;
+            }
+
+            // a conditioned operation compares a whole declared register
+            let line = match line.strip_prefix("if(") {
+                Some(condition) => {
+                    let (condition, op) = condition.split_once(')').unwrap();
+                    let (creg, _) = condition.split_once("==").unwrap();
+                    assert!(registers.contains_key(creg), "`{}` isn't declared", creg);
+                    op.trim()
+                }
+                None => line,
+            };
+            let (name, operands) = split(line);
+            let register = |operand: &str| {
+                let (register, index) = operand.trim_end_matches(']').split_once('[').unwrap();
+                let index: usize = index.parse().unwrap();
+                assert!(index < registers[register], "`{}` is out of range", operand);
+            };
+            match name {
+                "qreg" | "creg" => {
+                    let (register, size) =
+                        operands[0].trim_end_matches(']').split_once('[').unwrap();
+                    registers.insert(register, size.parse().unwrap());
+                }
+                "reset" => register(operands[0]),
+                "measure" => {
+                    register(operands[0]);
+                    assert_eq!(operands[1], "->");
+                    register(operands[2]);
+                }
+                _ => {
+                    assert!(gates.contains(&name), "`{}` isn't defined", name);
+                    operands.into_iter().for_each(register);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_well_formed() -> Result<()> {
+        use crate::session::compile_source;
+
+        for path in [
+            "tests/bell.ql",
+            "tests/alloc.ql",
+            "tests/gate.ql",
+            "tests/if-let.ql",
+            "tests/test13.ql",
+        ] {
+            let src = std::fs::read_to_string(path)?;
+            let qasm = compile_source(&src, path, &Default::default())?.to_string();
+            assert_well_formed(&qasm);
+        }
+
+        // `main` runs at top level, and other functions are gates applied
+        // on their qubit parameters
+        let src = std::fs::read_to_string("tests/alloc.ql")?;
+        let qasm = compile_source(&src, "alloc.ql", &Default::default())?.to_string();
+        assert!(qasm.contains("gate flip q\n"));
+        assert!(qasm.contains("\n// main\nreset a[0];\nreset b[0];\nflip a[0];\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_inlined_functions() -> Result<()> {
+        use crate::session::compile_source;
+
+        // functions which allocate or measure are inlined into their callers
+        // on new qubits and bits each time, named after the function
+        let src = "fn ancilla(q: qbit) : qbit {\n    let a = alloc();\n    cx(q, a);\n    \
+                   let b: bit = a;\n    return q;\n}\n\n\
+                   fn main() {\n    let q = alloc();\n    let p = h(q);\n    \
+                   ancilla(p);\n    ancilla(p);\n}\n";
+        let qasm = compile_source(src, "inline.ql", &Default::default())?.to_string();
+        assert!(!qasm.contains("gate ancilla"));
+        assert!(qasm.contains(
+            "\n// main\nreset q[0];\nh q[0];\n\
+             reset ancilla_a[0];\ncx q[0], ancilla_a[0];\nmeasure ancilla_a[0] -> ancilla_b[0];\n\
+             reset ancilla_a_1[0];\ncx q[0], ancilla_a_1[0];\n\
+             measure ancilla_a_1[0] -> ancilla_b_1[0];\n"
+        ));
+        assert_well_formed(&qasm);
+
+        // bits can't be given to gates, so neither to inlined functions
+        let src = "fn prepare(q: qbit, b: bit) : qbit {\n    let r: qbit = b;\n    \
+                   return q;\n}\n\n\
+                   fn main(b: bit) {\n    let q = alloc();\n    prepare(q, b);\n}\n";
+        assert!(compile_source(src, "inline.ql", &Default::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qubit_reuse() -> Result<()> {
+        use crate::inference::infer;
+
+        let mut parser = Parser::new(vec!["tests/alloc.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        for module in &ast {
+            for f in &*module {
+                if f.get_name() != "main" {
+                    continue;
+                }
+                let gate = QasmGate::try_from(&*f)?;
+                assert_eq!(
+                    gate.instructions,
+                    [
+                        "reset q[0];",
+                        "reset q[1];",
+                        "flip q[0];",
+                        "reset q[0];",
+                        "flip q[0];",
+                    ]
+                );
+                assert_eq!(gate.qubits, 2);
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,130 @@
+//! Standard gates of OpenQASM 2.0 as found in `qelib1.inc`.
+//!
+//! Instead of including the entire library, codegen only emits definitions of
+//! gates a program uses along with the gates they are defined in terms of.
+
+pub(crate) struct StdGate {
+    pub(crate) name: &'static str,
+    /// standard gates used in the definition
+    deps: &'static [&'static str],
+    definition: &'static str,
+}
+
+pub(crate) const STD_GATES: &[StdGate] = &[
+    StdGate {
+        name: "u3",
+        deps: &[],
+        definition: "gate u3(theta,phi,lambda) q { U(theta,phi,lambda) q; }",
+    },
+    StdGate {
+        name: "u2",
+        deps: &[],
+        definition: "gate u2(phi,lambda) q { U(pi/2,phi,lambda) q; }",
+    },
+    StdGate {
+        name: "u1",
+        deps: &[],
+        definition: "gate u1(lambda) q { U(0,0,lambda) q; }",
+    },
+    StdGate {
+        name: "cx",
+        deps: &[],
+        definition: "gate cx c,t { CX c,t; }",
+    },
+    StdGate {
+        name: "swap",
+        deps: &["cx"],
+        definition: "gate swap a,b { cx a,b; cx b,a; cx a,b; }",
+    },
+    StdGate {
+        name: "x",
+        deps: &["u3"],
+        definition: "gate x a { u3(pi,0,pi) a; }",
+    },
+    StdGate {
+        name: "y",
+        deps: &["u3"],
+        definition: "gate y a { u3(pi,pi/2,pi/2) a; }",
+    },
+    StdGate {
+        name: "z",
+        deps: &["u1"],
+        definition: "gate z a { u1(pi) a; }",
+    },
+    StdGate {
+        name: "h",
+        deps: &["u2"],
+        definition: "gate h a { u2(0,pi) a; }",
+    },
+    StdGate {
+        name: "s",
+        deps: &["u1"],
+        definition: "gate s a { u1(pi/2) a; }",
+    },
+    StdGate {
+        name: "t",
+        deps: &["u1"],
+        definition: "gate t a { u1(pi/4) a; }",
+    },
+    StdGate {
+        name: "rx",
+        deps: &["u3"],
+        definition: "gate rx(theta) a { u3(theta,-pi/2,pi/2) a; }",
+    },
+    StdGate {
+        name: "ry",
+        deps: &["u3"],
+        definition: "gate ry(theta) a { u3(theta,0,0) a; }",
+    },
+    StdGate {
+        name: "rz",
+        deps: &["u1"],
+        definition: "gate rz(phi) a { u1(phi) a; }",
+    },
+];
+
+/// Returns the standard gate of given name.
+pub(crate) fn lookup(name: &str) -> Option<&'static StdGate> {
+    STD_GATES.iter().find(|gate| gate.name == name)
+}
+
+/// Returns definitions for used gates along with their dependencies, where
+/// each definition precedes any of its uses.
+pub(crate) fn definitions<'a>(used: impl IntoIterator<Item = &'a str>) -> Vec<&'static str> {
+    let mut needed: Vec<&'static str> = vec![];
+    let mut worklist: Vec<&'static StdGate> = used.into_iter().filter_map(lookup).collect();
+
+    while let Some(gate) = worklist.pop() {
+        if needed.contains(&gate.name) {
+            continue;
+        }
+        needed.push(gate.name);
+        worklist.extend(gate.deps.iter().filter_map(|dep| lookup(dep)));
+    }
+
+    // `STD_GATES` is already ordered by dependencies
+    STD_GATES
+        .iter()
+        .filter(|gate| needed.contains(&gate.name))
+        .map(|gate| gate.definition)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_definitions() {
+        assert_eq!(
+            definitions(["h"]),
+            [
+                "gate u2(phi,lambda) q { U(pi/2,phi,lambda) q; }",
+                "gate h a { u2(0,pi) a; }"
+            ]
+        );
+        assert_eq!(definitions(["cx", "cx"]), ["gate cx c,t { CX c,t; }"]);
+        assert_eq!(definitions(["z", "s"]).len(), 3);
+        assert!(definitions(["foo"]).is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+//! Register allocator for qubits.
+//!
+//! Every qubit binding in a function is mapped onto a physical qubit. Qubits
+//! released with `free` go back into a pool and are handed out again before a
+//! new physical qubit is requested.
+use crate::ast::Ident;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct QubitAllocator {
+    /// number of physical qubits handed out so far
+    size: usize,
+    /// released physical qubits which can be reused
+    released: Vec<usize>,
+    /// qubit variables bound to their physical qubits
+    bindings: HashMap<Ident, usize>,
+    /// qubit registers bound to their physical qubits
+    registers: HashMap<Ident, Vec<usize>>,
+}
+
+impl QubitAllocator {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a physical qubit, reusing the lowest released one if any.
+    pub(crate) fn alloc(&mut self) -> usize {
+        if let Some((idx, _)) = self.released.iter().enumerate().min_by_key(|(_, q)| **q) {
+            return self.released.swap_remove(idx);
+        }
+
+        self.size += 1;
+        self.size - 1
+    }
+
+    /// Returns a physical qubit back to the allocator.
+    pub(crate) fn free(&mut self, qubit: usize) {
+        if qubit < self.size && !self.released.contains(&qubit) {
+            self.released.push(qubit);
+        }
+    }
+
+    /// Binds a qubit variable to a physical qubit.
+    pub(crate) fn bind(&mut self, name: Ident, qubit: usize) {
+        self.bindings.insert(name, qubit);
+    }
+
+    /// Returns the physical qubit bound to a variable.
+    pub(crate) fn lookup(&self, name: &Ident) -> Option<usize> {
+        self.bindings.get(name).copied()
+    }
+
+    /// Binds a register of `len` fresh physical qubits to a variable.
+    pub(crate) fn bind_register(&mut self, name: Ident, len: usize) -> Vec<usize> {
+        let qubits = (0..len).map(|_| self.alloc()).collect::<Vec<usize>>();
+        self.registers.insert(name, qubits.clone());
+        qubits
+    }
+
+    /// Binds a register variable to physical qubits which are bound already,
+    /// e.g. to another register.
+    pub(crate) fn bind_qubits(&mut self, name: Ident, qubits: Vec<usize>) {
+        self.registers.insert(name, qubits);
+    }
+
+    /// Returns the physical qubits bound to a register variable.
+    pub(crate) fn lookup_register(&self, name: &Ident) -> Option<&[usize]> {
+        self.registers.get(name).map(|qubits| qubits.as_slice())
+    }
+
+    /// Unbinds a variable and frees its physical qubit, which is returned.
+    pub(crate) fn release(&mut self, name: &Ident) -> Option<usize> {
+        let qubit = self.bindings.remove(name)?;
+        self.free(qubit);
+        Some(qubit)
+    }
+
+    /// Returns an allocator handing out the same physical qubits, with no
+    /// variable bound, for the body of another function to be lowered in
+    /// place. Qubits are taken back from it by `join`.
+    pub(crate) fn nested(&self) -> Self {
+        Self {
+            size: self.size,
+            released: self.released.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Takes back the physical qubits handed out or released by an allocator
+    /// returned by `nested`, keeping bindings as they were.
+    pub(crate) fn join(&mut self, nested: Self) {
+        self.size = nested.size;
+        self.released = nested.released;
+    }
+
+    /// Number of physical qubits required.
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_qubit_allocator() {
+        let mut allocator = QubitAllocator::new();
+        let a = allocator.alloc();
+        let b = allocator.alloc();
+        let c = allocator.alloc();
+        assert_eq!((a, b, c), (0, 1, 2));
+
+        allocator.free(c);
+        allocator.free(a);
+        assert_eq!(allocator.alloc(), a);
+        assert_eq!(allocator.alloc(), c);
+        assert_eq!(allocator.alloc(), 3);
+        assert_eq!(allocator.size(), 4);
+
+        allocator.bind("x".into(), b);
+        assert_eq!(allocator.lookup(&"x".into()), Some(b));
+        assert_eq!(allocator.release(&"x".into()), Some(b));
+        assert_eq!(allocator.lookup(&"x".into()), None);
+        assert_eq!(allocator.alloc(), b);
+
+        assert_eq!(allocator.bind_register("r".into(), 2), [4, 5]);
+        assert_eq!(allocator.lookup_register(&"r".into()), Some(&[4, 5][..]));
+        assert_eq!(allocator.size(), 6);
+
+        let mut nested = allocator.nested();
+        assert_eq!(nested.lookup_register(&"r".into()), None);
+        assert_eq!(nested.alloc(), 6);
+        allocator.join(nested);
+        assert_eq!(allocator.lookup_register(&"r".into()), Some(&[4, 5][..]));
+        assert_eq!(allocator.alloc(), 7);
+    }
+}
@@ -0,0 +1,99 @@
+//! State vector backend.
+//!
+//! Instead of assembly, it simulates each circuit and exports the amplitudes
+//! of its final state as JSON, so results can be verified against a reference
+//! simulator.
+use crate::codegen::circuit::Circuit;
+use crate::error::Result;
+use crate::simulator::{simulate_unmeasured, StateVector};
+use std::fmt;
+use std::io::Write;
+
+pub(crate) struct StateExport {
+    function: String,
+    qubits: usize,
+    state: StateVector,
+}
+
+impl TryFrom<&Circuit> for StateExport {
+    type Error = crate::error::QccError;
+
+    fn try_from(circuit: &Circuit) -> Result<Self> {
+        Ok(Self {
+            function: circuit.name.clone(),
+            qubits: circuit.qubits,
+            state: simulate_unmeasured(circuit)?,
+        })
+    }
+}
+
+impl fmt::Display for StateExport {
+    /// Formats the state as a JSON object, each amplitude being a `[re, im]`
+    /// pair.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amplitudes = self
+            .state
+            .amplitudes()
+            .iter()
+            .map(|amp| format!("[{:?}, {:?}]", amp.re, amp.im))
+            .collect::<Vec<String>>();
+        write!(
+            f,
+            "{{\"function\": \"{}\", \"qubits\": {}, \"amplitudes\": [{}]}}",
+            self.function.escape_default(),
+            self.qubits,
+            amplitudes.join(", ")
+        )
+    }
+}
+
+/// Returns a JSON array of final states for each circuit.
+pub(crate) fn to_json(circuits: &[Circuit]) -> Result<String> {
+    let states = circuits
+        .iter()
+        .map(|circuit| Ok(format!("  {}", StateExport::try_from(circuit)?)))
+        .collect::<Result<Vec<String>>>()?;
+
+    if states.is_empty() {
+        Ok("[]".into())
+    } else {
+        Ok(format!("[\n{}\n]", states.join(",\n")))
+    }
+}
+
+/// Writes final states of circuits to a `.json` file next to `path`.
+pub(crate) fn generate(circuits: &[Circuit], path: &str) -> Result<()> {
+    let path = std::path::Path::new(path).with_extension("json");
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(to_json(circuits)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::circuit;
+    use crate::inference::infer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn check_statevector_export() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/hadamard.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let circuits = circuit::lower(ast)?;
+        let export = StateExport::try_from(&circuits[0])?;
+        let amplitudes = export.state.amplitudes();
+        assert_eq!(amplitudes.len(), 2);
+        // H|0〉 = (|0〉 + |1〉)/sqrt(2)
+        for amp in amplitudes {
+            assert!((amp.re - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-9);
+            assert!(amp.im.abs() < 1e-9);
+        }
+        assert!(to_json(&circuits)?.contains("\"function\": \"main\", \"qubits\": 1"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Unitary backend.
+//!
+//! Instead of assembly, it exports the unitary matrix of each circuit as JSON,
+//! for analysis of small circuits. Column `j` is the state the circuit leaves
+//! basis state `j` in, so matrices are ordered like state vectors.
+use crate::codegen::circuit::Circuit;
+use crate::complex::Complex;
+use crate::error::Result;
+use crate::simulator::{simulate_unitary, StateVector};
+use std::fmt;
+use std::io::Write;
+
+pub(crate) struct UnitaryExport {
+    function: String,
+    qubits: usize,
+    columns: Vec<StateVector>,
+}
+
+impl UnitaryExport {
+    /// Entry in row `row` and column `col`.
+    pub(crate) fn entry(&self, row: usize, col: usize) -> Complex {
+        self.columns[col].amplitudes()[row]
+    }
+
+    /// Rows and columns of the matrix, i.e. `2^qubits`.
+    pub(crate) fn dim(&self) -> usize {
+        self.columns.len()
+    }
+}
+
+impl TryFrom<&Circuit> for UnitaryExport {
+    type Error = crate::error::QccError;
+
+    fn try_from(circuit: &Circuit) -> Result<Self> {
+        Ok(Self {
+            function: circuit.name.clone(),
+            qubits: circuit.qubits,
+            columns: simulate_unitary(circuit)?,
+        })
+    }
+}
+
+impl fmt::Display for UnitaryExport {
+    /// Formats the matrix as a JSON object, as an array of rows whose entries
+    /// are `[re, im]` pairs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = (0..self.dim())
+            .map(|row| {
+                let entries = (0..self.dim())
+                    .map(|col| self.entry(row, col))
+                    .map(|entry| format!("[{:?}, {:?}]", entry.re, entry.im))
+                    .collect::<Vec<String>>();
+                format!("[{}]", entries.join(", "))
+            })
+            .collect::<Vec<String>>();
+        write!(
+            f,
+            "{{\"function\": \"{}\", \"qubits\": {}, \"unitary\": [{}]}}",
+            self.function.escape_default(),
+            self.qubits,
+            rows.join(", ")
+        )
+    }
+}
+
+/// Returns a JSON array of unitaries for each circuit.
+pub(crate) fn to_json(circuits: &[Circuit]) -> Result<String> {
+    let unitaries = circuits
+        .iter()
+        .map(|circuit| Ok(format!("  {}", UnitaryExport::try_from(circuit)?)))
+        .collect::<Result<Vec<String>>>()?;
+
+    if unitaries.is_empty() {
+        Ok("[]".into())
+    } else {
+        Ok(format!("[\n{}\n]", unitaries.join(",\n")))
+    }
+}
+
+/// Writes unitaries of circuits to a `.json` file next to `path`.
+pub(crate) fn generate(circuits: &[Circuit], path: &str) -> Result<()> {
+    let path = std::path::Path::new(path).with_extension("json");
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(to_json(circuits)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::circuit;
+    use crate::error::QccErrorKind;
+    use crate::session::parse_and_infer;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    #[test]
+    fn check_unitary_export() -> Result<()> {
+        let src = "fn main(q: qbit) : qbit {\n    return h(q);\n}\n";
+        let ast = parse_and_infer(src, "hadamard.ql", &Default::default())?;
+        let circuits = circuit::lower(ast)?;
+
+        let export = UnitaryExport::try_from(&circuits[0])?;
+        assert_eq!(export.dim(), 2);
+        // H = [[1, 1], [1, -1]]/sqrt(2)
+        let expected = [[1.0, 1.0], [1.0, -1.0]];
+        for (row, entries) in expected.iter().enumerate() {
+            for (col, sign) in entries.iter().enumerate() {
+                let entry = export.entry(row, col);
+                assert!((entry.re - sign * FRAC_1_SQRT_2).abs() < 1e-9);
+                assert!(entry.im.abs() < 1e-9);
+            }
+        }
+        assert!(to_json(&circuits)?.contains("\"function\": \"main\", \"qubits\": 1"));
+
+        // allocated qubits are reset first
+        let src = std::fs::read_to_string("tests/hadamard.ql")?;
+        let ast = parse_and_infer(&src, "hadamard.ql", &Default::default())?;
+        let circuits = circuit::lower(ast)?;
+        let err = UnitaryExport::try_from(&circuits[0]).err().unwrap();
+        assert!(err.is(QccErrorKind::NonUnitary));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Complex numbers for probability amplitudes.
+use crate::error::QccErrorKind;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Complex {
+    pub(crate) re: f64,
+    pub(crate) im: f64,
+}
+
+impl Complex {
+    pub(crate) const ZERO: Self = Self::new(0.0, 0.0);
+    pub(crate) const ONE: Self = Self::new(1.0, 0.0);
+    pub(crate) const I: Self = Self::new(0.0, 1.0);
+
+    pub(crate) const fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// Returns `e^(i * theta)`.
+    pub(crate) fn from_phase(theta: f64) -> Self {
+        Self::new(theta.cos(), theta.sin())
+    }
+
+    pub(crate) fn norm_sqr(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub(crate) fn abs(&self) -> f64 {
+        self.norm_sqr().sqrt()
+    }
+
+    /// Phase angle in (-pi, pi].
+    pub(crate) fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub(crate) fn conj(&self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub(crate) fn is_real(&self) -> bool {
+        self.im == 0.0
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self {
+        Self::new(re, 0.0)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let denominator = rhs.norm_sqr();
+        let numerator = self * rhs.conj();
+        Self::new(numerator.re / denominator, numerator.im / denominator)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+}
+
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_real() {
+            write!(f, "{}", self.re)
+        } else if self.re == 0.0 {
+            write!(f, "{}i", self.im)
+        } else if self.im < 0.0 {
+            write!(f, "{}-{}i", self.re, -self.im)
+        } else {
+            write!(f, "{}+{}i", self.re, self.im)
+        }
+    }
+}
+
+impl std::str::FromStr for Complex {
+    type Err = QccErrorKind;
+
+    /// Reads a complex number written as `<re>`, `<im>i` or `<re>(+|-)<im>i`.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let s = s.replace(' ', "");
+
+        let Some(imaginary) = s.strip_suffix('i') else {
+            let re = s
+                .parse::<f64>()
+                .map_err(|_| QccErrorKind::UnexpectedDigit)?;
+            return Ok(re.into());
+        };
+
+        // split at the sign between both parts, which isn't a leading sign or a
+        // sign of an exponent
+        let bytes = imaginary.as_bytes();
+        let split = (1..bytes.len())
+            .rev()
+            .find(|&i| (bytes[i] == b'+' || bytes[i] == b'-') && bytes[i - 1] != b'e');
+
+        let (re, im) = match split {
+            Some(i) => (&imaginary[..i], &imaginary[i..]),
+            None => ("0", imaginary),
+        };
+        let im = match im {
+            "" | "+" => "1",
+            "-" => "-1",
+            _ => im,
+        };
+
+        let re = re
+            .parse::<f64>()
+            .map_err(|_| QccErrorKind::UnexpectedDigit)?;
+        let im = im
+            .parse::<f64>()
+            .map_err(|_| QccErrorKind::UnexpectedDigit)?;
+        Ok(Self::new(re, im))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_complex() {
+        let z = Complex::new(3.0, 4.0);
+        assert_eq!(z.abs(), 5.0);
+        assert_eq!(z * z.conj(), Complex::from(25.0));
+        assert_eq!(Complex::I * Complex::I, -Complex::ONE);
+        assert_eq!(z / z, Complex::ONE);
+        assert_eq!(Complex::from_phase(0.0), Complex::ONE);
+
+        assert_eq!("0.6".parse::<Complex>(), Ok(Complex::from(0.6)));
+        assert_eq!("-1i".parse::<Complex>(), Ok(Complex::new(0.0, -1.0)));
+        assert_eq!("i".parse::<Complex>(), Ok(Complex::I));
+        assert_eq!("0.6-0.8i".parse::<Complex>(), Ok(Complex::new(0.6, -0.8)));
+        assert_eq!("1e-2+2i".parse::<Complex>(), Ok(Complex::new(0.01, 2.0)));
+        assert!("0.6+i0.8".parse::<Complex>().is_err());
+
+        assert_eq!(format!("{}", Complex::new(0.6, -0.8)), "0.6-0.8i");
+        assert_eq!(format!("{}", Complex::from(0.5)), "0.5");
+    }
+}
@@ -1,13 +1,121 @@
 //! Configuration file for compilation session in qcc.
 use crate::analyzer::config::*;
+use crate::codegen::qasm::QasmVersion;
+use crate::complex::Complex;
+use crate::error::QccErrorKind;
 use crate::optimizer::config::*;
 
+/// Output path which writes assembly to stdout, i.e. `-o -`.
+pub(crate) const STDOUT: &str = "-";
+
+/// Artifacts which can be emitted in place of assembly, selected by
+/// `--emit=<kind>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Emit {
+    /// circuit metrics of each quantum function in JSON
+    Metrics,
+    /// final state vector of each quantum function in JSON, skipping
+    /// measurements
+    StateVector,
+    /// operations of each quantum function as a table
+    Circuit,
+    /// unitary matrix of each quantum function in JSON, which must not
+    /// measure or reset qubits
+    Unitary,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = QccErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "metrics" => Ok(Self::Metrics),
+            "statevector" => Ok(Self::StateVector),
+            "circuit" => Ok(Self::Circuit),
+            "unitary" => Ok(Self::Unitary),
+            _ => Err(QccErrorKind::NoSuchArg),
+        }
+    }
+}
+
+impl std::fmt::Display for Emit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Metrics => write!(f, "metrics"),
+            Self::StateVector => write!(f, "statevector"),
+            Self::Circuit => write!(f, "circuit"),
+            Self::Unitary => write!(f, "unitary"),
+        }
+    }
+}
+
+/// Tolerance of comparisons between amplitudes, set by `--tol <eps>`. Two
+/// amplitudes are close if they differ by at most `abs`, or by at most `rel`
+/// times the larger of both, so that both tiny and large amplitudes can be
+/// compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tolerance {
+    pub(crate) abs: f64,
+    pub(crate) rel: f64,
+}
+
+impl Tolerance {
+    pub(crate) fn new(eps: f64) -> Self {
+        Self { abs: eps, rel: eps }
+    }
+
+    pub(crate) fn close(&self, lhs: Complex, rhs: Complex) -> bool {
+        let diff = (lhs - rhs).abs();
+        diff <= self.abs || diff <= self.rel * lhs.abs().max(rhs.abs())
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self::new(1e-9)
+    }
+}
+
+impl std::str::FromStr for Tolerance {
+    type Err = QccErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(eps) if eps.is_finite() && eps >= 0.0 => Ok(Self::new(eps)),
+            _ => Err(QccErrorKind::NoSuchArg),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub(crate) debug: bool,
     pub(crate) dump_ast: bool,
     pub(crate) dump_ast_only: bool,
+    /// print locations in AST dumps, see `--ast-locations`
+    pub(crate) ast_locations: bool,
     pub(crate) dump_qasm: bool,
+    pub(crate) dump_symbols: bool,
+    pub(crate) strict: bool,
+    /// exit with a failure if warnings were printed, see `--fail-on-warning`
+    pub(crate) fail_on_warning: bool,
+    /// stop compiling if the source has warnings, see `-Werror`
+    pub(crate) deny_warnings: bool,
+    pub(crate) emit: Option<Emit>,
+    /// directory which artifacts are written into, see `--output-dir`
+    pub(crate) output_dir: Option<String>,
+    /// package directory whose files are modules, see `--package`
+    pub(crate) package: Option<String>,
+    /// directories searched for versioned preludes, see `-I`
+    pub(crate) include_paths: Vec<String>,
+    /// source file to check the compiled source against, see `--verify`
+    pub(crate) verify: Option<String>,
+    /// tolerance of comparisons between amplitudes, see `--tol`
+    pub(crate) tolerance: Tolerance,
+    /// decimal places of emitted parameters, see `--float-precision`
+    pub(crate) float_precision: Option<usize>,
+    /// version of OpenQASM emitted, see `--qasm-version`
+    pub(crate) qasm_version: QasmVersion,
     pub analyzer: AnalyzerConfig,
     pub optimizer: OptConfig,
 }
@@ -18,16 +126,88 @@ impl Config {
             debug: false,
             dump_ast: false,
             dump_ast_only: false,
+            ast_locations: true,
             dump_qasm: false,
+            dump_symbols: false,
+            strict: false,
+            fail_on_warning: false,
+            deny_warnings: false,
+            emit: None,
+            output_dir: None,
+            package: None,
+            include_paths: vec![],
+            verify: None,
+            tolerance: Default::default(),
+            float_precision: None,
+            qasm_version: QasmVersion::V2_0,
             optimizer: OptConfig::new(),
             analyzer: AnalyzerConfig::new(),
         }
     }
+
+    /// Version of qcc followed by the commit it was built from, e.g.
+    /// `0.1.0+c368f81`. Builds outside a git checkout only have the version.
+    pub(crate) fn version() -> String {
+        match env!("GIT_HASH") {
+            "unknown" => env!("CARGO_PKG_VERSION").into(),
+            hash => format!("{}+{}", env!("CARGO_PKG_VERSION"), hash),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}\n{}", self.analyzer, self.optimizer)
+        let emit = match self.emit {
+            Some(emit) => emit.to_string(),
+            None => "qasm".into(),
+        };
+        let passes = self
+            .optimizer
+            .passes()
+            .iter()
+            .map(|pass| pass.name())
+            .collect::<Vec<&str>>();
+        let passes = if passes.is_empty() {
+            "none".into()
+        } else {
+            passes.join(", ")
+        };
+        let precision = match self.float_precision {
+            Some(digits) => digits.to_string(),
+            None => "full".into(),
+        };
+        let include_paths = if self.include_paths.is_empty() {
+            "none".into()
+        } else {
+            self.include_paths.join(", ")
+        };
+        write!(
+            f,
+            "{}\n{}
+Passes: {}
+
+Codegen Configuration
+---------------------
+Emit: {}
+OpenQASM: {}
+Precision: {}
+Output: {}
+Include paths: {}",
+            self.analyzer,
+            self.optimizer,
+            passes,
+            emit,
+            self.qasm_version,
+            precision,
+            self.optimizer.asm,
+            include_paths
+        )
     }
 }
 
@@ -40,7 +220,29 @@ mod tests {
         let config = Config::new();
         assert!(!config.dump_ast);
         assert!(!config.dump_ast_only);
+        assert!(config.ast_locations);
         assert!(!config.dump_qasm);
+        assert!(!config.dump_symbols);
+        assert!(!config.strict);
+        assert!(!config.fail_on_warning);
+        assert!(!config.deny_warnings);
+        assert_eq!(config.emit, None);
+        assert_eq!(config.output_dir, None);
+        assert_eq!(config.package, None);
+        assert!(config.include_paths.is_empty());
+        assert_eq!(config.verify, None);
+        assert_eq!(config.tolerance, Tolerance::new(1e-9));
+        assert_eq!(config.float_precision, None);
+        assert_eq!(config.qasm_version, QasmVersion::V2_0);
+        assert_eq!("3.0".parse::<QasmVersion>(), Ok(QasmVersion::V3_0));
+        assert_eq!("1.0".parse::<QasmVersion>(), Err(QccErrorKind::NoSuchArg));
+        assert_eq!("1e-6".parse::<Tolerance>(), Ok(Tolerance::new(1e-6)));
+        assert_eq!("-1".parse::<Tolerance>(), Err(QccErrorKind::NoSuchArg));
+        assert_eq!("metrics".parse::<Emit>(), Ok(Emit::Metrics));
+        assert_eq!("statevector".parse::<Emit>(), Ok(Emit::StateVector));
+        assert_eq!("circuit".parse::<Emit>(), Ok(Emit::Circuit));
+        assert_eq!("unitary".parse::<Emit>(), Ok(Emit::Unitary));
+        assert_eq!("foo".parse::<Emit>(), Err(QccErrorKind::NoSuchArg));
         assert_eq!(
             format!("{}", config.analyzer),
             "
@@ -54,7 +256,7 @@ Analyzer Configuration
             "
 Optimizer Configuration
 -----------------------
-Stage: O0"
+Stage: O0 (none)"
         );
 
         assert_eq!(
@@ -66,7 +268,20 @@ Analyzer Configuration
 
 Optimizer Configuration
 -----------------------
-Stage: O0"
+Stage: O0 (none)
+Passes: none
+
+Codegen Configuration
+---------------------
+Emit: qasm
+OpenQASM: 2.0
+Precision: full
+Output: 
+Include paths: none"
         );
+
+        let mut config = Config::new();
+        config.include_paths = vec!["tests/prelude".into(), "lib".into()];
+        assert!(format!("{}", config).ends_with("\nInclude paths: tests/prelude, lib"));
     }
 }
@@ -0,0 +1,139 @@
+//! Diagnostics collected while compiling a program.
+//!
+//! Stages push the errors they find into a `Diagnostics` sink instead of
+//! printing them as they go, and the driver prints all of them once at the
+//! end. This keeps stages free of I/O, so their errors can be inspected.
+use crate::error::{QccErrorKind, QccErrorLoc};
+
+/// Whether a diagnostic stops compilation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Severity {
+    /// compilation goes on, unless warnings are denied by `-Werror`
+    Warning,
+    Error,
+}
+
+/// An error or a warning found in the source along with what is known about
+/// it.
+pub(crate) struct Diagnostic {
+    severity: Severity,
+    error: QccErrorLoc,
+    /// explanation in terms of the source, e.g. the offending expression
+    note: String,
+    /// line of source the error is on, printed with the error marked
+    line: Option<String>,
+}
+
+impl Diagnostic {
+    #[inline]
+    pub(crate) fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    #[inline]
+    pub(crate) fn error(&self) -> &QccErrorLoc {
+        &self.error
+    }
+
+    #[inline]
+    pub(crate) fn note(&self) -> &str {
+        &self.note
+    }
+
+    /// Checks if the diagnostic is an error of a certain kind.
+    pub(crate) fn is(&self, kind: QccErrorKind) -> bool {
+        self.error.get_error().is(kind)
+    }
+}
+
+#[derive(Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Adds an error explained by `note`.
+    pub(crate) fn error(&mut self, error: QccErrorLoc, note: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            error,
+            note: note.into(),
+            line: None,
+        });
+    }
+
+    /// Adds an error on `line` of the source, which is shown when printed.
+    pub(crate) fn error_on_line(&mut self, error: QccErrorLoc, line: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            error,
+            note: Default::default(),
+            line: Some(line),
+        });
+    }
+
+    /// Adds a warning explained by `note`, which doesn't stop compilation.
+    pub(crate) fn warning(&mut self, warning: QccErrorLoc, note: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            error: warning,
+            note: note.into(),
+            line: None,
+        });
+    }
+
+    /// Moves all diagnostics of `other` after those of `self`.
+    pub(crate) fn append(&mut self, other: Diagnostics) {
+        self.diagnostics.extend(other.diagnostics);
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    /// Number of diagnostics which stop compilation.
+    pub(crate) fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub(crate) fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Diagnostic> + '_ {
+        self.diagnostics.iter()
+    }
+
+    /// Prints every diagnostic in the order they were found.
+    pub(crate) fn report(&self) {
+        for diagnostic in &self.diagnostics {
+            if diagnostic.severity == Severity::Warning {
+                eprintln!(
+                    "\x1b[99;1mqcc\x1b[0m: \x1b[93;1mwarning:\x1b[0m {} {} {}",
+                    diagnostic.error.get_error().kind(),
+                    diagnostic.error.get_loc().borrow(),
+                    diagnostic.note
+                );
+                continue;
+            }
+            match &diagnostic.line {
+                Some(line) => diagnostic.error.report(line.clone()),
+                None if diagnostic.note.is_empty() => eprintln!("{}", diagnostic.error),
+                None => eprintln!("{} {}", diagnostic.error, diagnostic.note),
+            }
+        }
+    }
+}
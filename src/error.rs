@@ -23,7 +23,7 @@ pub(crate) type Result<T> = std::result::Result<T, QccError>;
 // richer information down the call stack.
 pub(crate) type LocationRef = std::cell::RefCell<Location>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum QccErrorKind {
     CmdlineErr,
     InvalidArgs,
@@ -64,7 +64,98 @@ pub enum QccErrorKind {
     ExpectedAmpinQbit,
     ExpectedColon,
     UnknownImport,
+    DuplicateModule,
     TranslationError,
+    ArityMismatch,
+    NotEquivalent,
+    UndeclaredQarg,
+    InvalidRegisterSize,
+    InvalidBasis,
+    UnknownPrelude,
+    PreludeVersionMismatch,
+    IntegerOverflow,
+    CyclicImport,
+    FailedOnWarning,
+    UnusedVariable,
+    DeniedWarning,
+    NonUnitary,
+    UnnormalizedQbit,
+    ShapeMismatch,
+    DuplicateFunction,
+    IndexOutOfBounds,
+    DivisionByZero,
+    FreedQubit,
+}
+
+impl QccErrorKind {
+    /// Returns a stable code identifying the kind of error, which can be
+    /// looked up with `--explain`. Codes must never be reused or renumbered,
+    /// new kinds get the next free code.
+    pub fn code(&self) -> &'static str {
+        use QccErrorKind::*;
+        match self {
+            CmdlineErr => "E0001",
+            InvalidArgs => "E0002",
+            NoSuchArg => "E0003",
+            NoFile => "E0004",
+            ExpectedAttr => "E0005",
+            UnexpectedAttr => "E0006",
+            LexerError => "E0007",
+            ParseError => "E0008",
+            ExpectedFnForAttr => "E0009",
+            ExpectedFn => "E0010",
+            ExpectedFnName => "E0011",
+            ExpectedFnArgs => "E0012",
+            ExpectedParamType => "E0013",
+            ExpectedType => "E0014",
+            UnexpectedType => "E0015",
+            ExpectedFnBody => "E0016",
+            ExpectedFnReturnType => "E0017",
+            ExpectedFnBodyEnd => "E0018",
+            ExpectedMod => "E0019",
+            UnknownModName => "E0020",
+            ExpectedLet => "E0021",
+            ExpectedAssign => "E0022",
+            ExpectedSemicolon => "E0023",
+            UnexpectedStr => "E0024",
+            UnexpectedDigit => "E0025",
+            ExpectedExpr => "E0026",
+            ExpectedParenth => "E0027",
+            UnexpectedExpr => "E0028",
+            UnknownOpcode => "E0029",
+            UnknownBinaryExpr => "E0030",
+            ExpectedOpcode => "E0031",
+            ExpectedComma => "E0032",
+            TypeError => "E0033",
+            TypeMismatch => "E0034",
+            UnknownType => "E0035",
+            ExpectedQbit => "E0036",
+            ExpectedAmpinQbit => "E0037",
+            ExpectedColon => "E0038",
+            UnknownImport => "E0039",
+            DuplicateModule => "E0040",
+            TranslationError => "E0041",
+            ArityMismatch => "E0042",
+            NotEquivalent => "E0043",
+            UndeclaredQarg => "E0044",
+            InvalidRegisterSize => "E0045",
+            InvalidBasis => "E0046",
+            UnknownPrelude => "E0047",
+            PreludeVersionMismatch => "E0048",
+            IntegerOverflow => "E0049",
+            CyclicImport => "E0050",
+            FailedOnWarning => "E0051",
+            UnusedVariable => "E0052",
+            DeniedWarning => "E0053",
+            NonUnitary => "E0054",
+            UnnormalizedQbit => "E0055",
+            ShapeMismatch => "E0056",
+            DuplicateFunction => "E0057",
+            IndexOutOfBounds => "E0058",
+            DivisionByZero => "E0059",
+            FreedQubit => "E0060",
+        }
+    }
 }
 
 impl Display for QccErrorKind {
@@ -111,7 +202,27 @@ impl Display for QccErrorKind {
                 ExpectedAmpinQbit => "expected pairs of probability amplitudes",
                 ExpectedColon => "expected colon `:`",
                 UnknownImport => "unknown imported function",
+                DuplicateModule => "module is defined more than once",
                 TranslationError => "translation failed",
+                ArityMismatch => "function is called with a wrong number of arguments",
+                NotEquivalent => "programs are not equivalent",
+                UndeclaredQarg => "gate body uses an undeclared qarg or parameter",
+                InvalidRegisterSize => "register size is not a non-negative integer",
+                InvalidBasis => "measurement basis is not one of X, Y or Z",
+                UnknownPrelude => "no such prelude in include paths",
+                PreludeVersionMismatch => "prelude is not available in this version",
+                IntegerOverflow => "integer arithmetic overflows its type",
+                CyclicImport => "modules import each other",
+                FailedOnWarning => "warnings were found with --fail-on-warning",
+                UnusedVariable => "variable is never read",
+                DeniedWarning => "warnings are denied by -Werror",
+                NonUnitary => "circuit measures or resets qubits, so it has no unitary",
+                UnnormalizedQbit => "squared amplitudes of a quantum bit don't sum to one",
+                ShapeMismatch => "shapes of tensors don't match",
+                DuplicateFunction => "function is defined more than once in a module",
+                IndexOutOfBounds => "index is out of the bounds of the register",
+                DivisionByZero => "constant expression divides by zero",
+                FreedQubit => "qubit is used after it is freed",
             }
         })(self))
     }
@@ -134,6 +245,12 @@ impl QccError {
     pub(crate) fn is(&self, kind: QccErrorKind) -> bool {
         self.0 == kind
     }
+
+    #[inline]
+    /// Kind of the error, for matching on categories of errors.
+    pub fn kind(&self) -> &QccErrorKind {
+        &self.0
+    }
 }
 
 impl Display for QccError {
@@ -228,30 +345,25 @@ impl QccErrorLoc {
 
     /// Reporter to print source with annotation.
     pub(crate) fn report(&self, src: String) {
-        let row = self.1.borrow().row().to_string();
-        let mut col = self.1.borrow().col();
-
-        let src_fmt = format!("\t{}\t{}", row, src);
-
         eprintln!("{}", self);
-        eprint!("{src_fmt}");
-
-        col += 1 + row.len(); // +2 for inserted tabs, -1 for starting index
-                              // with 1, effectively +1
+        eprint!("{}", self.annotate(&src));
+    }
 
-        for c in src_fmt.chars() {
-            if col > 0 {
-                col -= 1;
-            } else {
-                eprintln!("^");
-                break;
-            }
-            if c.is_whitespace() {
-                eprint!("{c}");
-            } else {
-                eprint!(" ");
-            }
-        }
+    /// Echoes a line of source with a caret under the column of the error.
+    /// Whitespace up to the caret is copied from the line, so that tabs line
+    /// up however wide they are shown.
+    pub(crate) fn annotate(&self, src: &str) -> String {
+        let row = self.1.borrow().row().to_string();
+        let col = self.1.borrow().col();
+
+        // the last line of a source may not end with a newline
+        let src_fmt = format!("\t{}\t{}", row, src.trim_end_matches('\n'));
+        let indent = src_fmt
+            .chars()
+            .take(2 + row.len() + col.saturating_sub(1))
+            .map(|c| if c.is_whitespace() { c } else { ' ' })
+            .collect::<String>();
+        format!("{src_fmt}\n{indent}^\n")
     }
 }
 
@@ -339,13 +451,19 @@ mod tests {
         let e1: Result<()> = Err(QccError(UnexpectedAttr));
         match e1 {
             Ok(_) => unreachable!(),
-            Err(ref e) => assert_eq!(e.to_string(), "qcc: unexpected attribute"),
+            Err(ref e) => assert_eq!(
+                e.to_string(),
+                "\x1b[99;1mqcc\x1b[0m: \x1b[91;1merror:\x1b[0m unexpected attribute"
+            ),
         }
 
         let e2: Result<()> = Err(QccError(NoFile));
         match e2 {
             Ok(_) => unreachable!(),
-            Err(ref e) => assert_eq!(e.to_string(), "qcc: no such file"),
+            Err(ref e) => assert_eq!(
+                e.to_string(),
+                "\x1b[99;1mqcc\x1b[0m: \x1b[91;1merror:\x1b[0m no such file"
+            ),
         }
         Ok(())
     }
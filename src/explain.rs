@@ -0,0 +1,376 @@
+//! Extended explanations for error codes, printed by `qcc --explain <code>`.
+
+/// Explanations keyed by error code, see `QccErrorKind::code`. Codes without an
+/// entry only have their one-line message.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0004",
+        "The source file given on the command line doesn't exist or isn't a
+regular file. qcc expects exactly one `.ql` file to compile.
+
+    qcc examples/hello.ql -o hello.s
+
+A module which isn't defined in the source is imported from the file named
+after it, next to the importing file, which must exist.
+
+    import lib::square;                 // error, unless lib.ql exists
+",
+    ),
+    (
+        "E0005",
+        "An attribute list was expected but it wasn't written as `#[<attr>]`.
+Attributes are placed right before a function, in square brackets after a
+`#`, and multiple attributes are separated by commas.
+
+    #[deter, nondeter]     // ok
+    #deter                 // error: missing brackets
+    fn main() {}
+",
+    ),
+    (
+        "E0006",
+        "An attribute which qcc doesn't know about was used. The attributes
+currently understood are `deter` and `nondeter`.
+
+    #[deterministic]       // error: unknown attribute
+    fn main() {}
+
+    #[deter]               // ok
+    fn main() {}
+",
+    ),
+    (
+        "E0007",
+        "The lexer couldn't split the source into tokens. This usually happens
+when the file ends in the middle of a token, like an unclosed quantum numeral.
+
+    let q = 0q(1, 0        // error: `)` is missing before the end of file
+",
+    ),
+    (
+        "E0011",
+        "The `fn` keyword must be followed by the name of the function.
+
+    fn (x: f64) {}         // error: missing name
+    fn rotate(x: f64) {}   // ok
+",
+    ),
+    (
+        "E0012",
+        "A function definition must list its parameters in parenthesis after
+its name, even if it takes none.
+
+    fn main {}             // error: missing `()`
+    fn main() {}           // ok
+",
+    ),
+    (
+        "E0013",
+        "Every function parameter needs a type annotation after a colon.
+
+    fn rotate(theta) {}        // error: type of `theta` is missing
+    fn rotate(theta: f64) {}   // ok
+",
+    ),
+    (
+        "E0023",
+        "An import statement ends with a semicolon.
+
+    import math::sin       // error: `;` is missing
+    import math::sin;      // ok
+",
+    ),
+    (
+        "E0034",
+        "Two types which must agree are different, for example the declared type
+of a `let` binding and the type of its value, or the return type of a function
+and the value returned from it.
+
+    fn angle(): f64 {
+        let q: f64 = 0q(1, 0);   // error: a qubit isn't a float64
+        return q;
+    }
+",
+    ),
+    (
+        "E0035",
+        "The type of an expression couldn't be inferred from its uses. Annotate
+the binding or the function with the intended type.
+
+    let x = foo();         // error if the return type of `foo` is unknown
+    let x: f64 = foo();    // ok
+",
+    ),
+    (
+        "E0039",
+        "An imported function isn't defined by the module it's imported from, or
+the module itself doesn't exist.
+
+    module math {
+        fn sin(x: f64): f64 { return x; }
+    }
+    import math::cos;      // error: `math` has no `cos`
+    import math::sin;      // ok
+",
+    ),
+    (
+        "E0040",
+        "Two modules with the same name were combined into one program. Module
+names must be unique, rename one of them.
+
+    module util { fn id(x: f64): f64 { return x; } }
+    module util { fn zero(): f64 { return 0; } }    // error
+",
+    ),
+    (
+        "E0042",
+        "A function is called with more or fewer arguments than it has
+parameters.
+
+    fn scale(x: f64, by: f64): f64 { return x * by; }
+    let y = scale(2.0);        // error: `by` is missing
+    let y = scale(2.0, 3.0);   // ok
+",
+    ),
+    (
+        "E0043",
+        "Two programs given to `--verify` prepare different states. Functions of
+the same name are simulated without their measurements, and their final
+states must be equal up to a global phase.
+
+    fn main() { let q = alloc(); let p = h(q); }   // a.ql
+    fn main() { let q = alloc(); let p = x(q); }   // b.ql
+
+    qcc --verify a.ql b.ql     // error: `main` differs
+",
+    ),
+    (
+        "E0044",
+        "The body of a `gate` applies gates only to the qubits it declares as
+qargs, and uses only its parameters as angles.
+
+    gate flip(theta) a, b {
+        rx(theta, c);          // error: `c` is not a qarg
+        rx(theta, a);          // ok
+        cx(a, b);              // ok
+    }
+",
+    ),
+    (
+        "E0045",
+        "The size of a register is a constant expression which must evaluate to
+a non-negative integer.
+
+    fn main(reg: qbit[2 * 2]) {}       // ok, 4 qubits
+    fn main(reg: qbit[1 - 2]) {}       // error: size is negative
+    fn main(reg: qbit[3 / 2]) {}       // error: size is not an integer
+",
+    ),
+    (
+        "E0046",
+        "A qubit can be measured in the X, Y or Z basis, given after the qubit.
+Measuring without a basis measures in the Z basis.
+
+    measure(q, X) -> result;           // ok
+    measure q -> result;               // ok, same as measure(q, Z)
+    measure(q, W) -> result;           // error: no such basis
+",
+    ),
+    (
+        "E0047",
+        "`use <name>@<version>;` merges the prelude `<name>@<version>.ql` found in
+one of the directories given by `-I`, but no file of that name exists.
+
+    qcc -I lib main.ql                 // lib/std@1.ql defines module `std`
+    use std@1;                         // ok
+    use foo@1;                         // error: there is no lib/foo@<n>.ql
+",
+    ),
+    (
+        "E0048",
+        "The prelude asked for by `use` exists, but not in the requested version.
+
+    qcc -I lib main.ql                 // only lib/std@1.ql is present
+    use std@1;                         // ok
+    use std@2;                         // error: version 2 is missing
+",
+    ),
+    (
+        "E0049",
+        "Integers evaluated at compile time are 64 bits wide, and arithmetic on
+them is checked instead of wrapping around. A value bound to an integer of a
+declared type must also be in the range of that type.
+
+    fn main(reg: qbit[2 * 2]) {}                           // ok
+    fn main(reg: qbit[3037000500 * 3037000500 * 2]) {}     // error: overflows
+    let n: i32 = 2147483647 + 1;                           // error: overflows
+",
+    ),
+    (
+        "E0050",
+        "A function imported with `pub import` is re-exported, and importing it
+from the re-exporting module follows the chain to the module defining it. A
+chain which comes back to a module already visited defines the function
+nowhere. The error is at the imported function.
+
+    // a.ql
+    pub import b::f;
+    // b.ql
+    pub import a::f;
+    // main.ql
+    import a::f;                        // error, a and b re-export each other
+
+Likewise, imported files are parsed as they are imported, so a file
+can't import a module whose file is still being parsed because it imports
+the file, directly or through other files. The error is at the import which
+closes the cycle.
+
+    // main.ql
+    import other::f;
+    // other.ql
+    import main::g;                     // error, main.ql imports other.ql
+",
+    ),
+    (
+        "E0051",
+        "Compilation finished, but warnings were printed and `--fail-on-warning`
+was given. Artifacts are written as usual, only the exit status is non-zero.
+
+    qcc --fail-on-warning main.ql      // main.s is written, exit status is 1
+",
+    ),
+    (
+        "E0052",
+        "A `let` binding is never read after it is bound. This is a warning, the
+program is compiled regardless. Prefix the name with `_` if it is meant to be
+unused.
+
+    let tmp = 1 + 2;                   // warning: `tmp` is never read
+    let _tmp = 1 + 2;                  // ok
+",
+    ),
+    (
+        "E0053",
+        "Warnings were found and `-Werror` asks to treat them as errors, so
+compilation stops after checking the source and nothing is written.
+
+    qcc -Werror main.ql                // error if main.ql has any warning
+",
+    ),
+    (
+        "E0054",
+        "Only circuits made of gates have a unitary matrix, so `--emit=unitary`
+rejects functions which measure qubits, reset them or depend on classical
+bits. Qubits allocated inside a function are reset, so take them as
+parameters instead.
+
+    fn main() { let q = alloc(); ... }  // error, `q` is reset
+    fn main(q: qbit) : qbit { h(q) }    // ok
+",
+    ),
+    (
+        "E0055",
+        "The amplitudes of a quantum numeral are probability amplitudes, so
+their squared magnitudes must sum to one, up to a small tolerance which
+allows for rounded digits. Amplitudes computed at runtime aren't checked.
+
+    let q = 0q(5, 5);                   // error, 25 + 25 is not 1
+    let q = 0q(0.707, 0.707);           // ok
+    let q = 0q(0.6, 0.8i);              // ok
+",
+    ),
+    (
+        "E0056",
+        "A tensor is a rectangular block of elements, so the tensors it is made
+of must all have the same shape. Ragged tensors have no shape.
+
+    let t = [[1, 2], [3]];              // error, rows of 2 and 1 elements
+    let t = [1, [2, 3]];                // error, a number next to a row
+    let t = [[1, 2], [3, 4]];           // ok, float64[2][2]
+
+A matrix times qubits transforms their state, which has 2^n amplitudes for
+n qubits, so the matrix must have 2^n rows and columns.
+
+    let r = [[1, 0, 0], [0, 1, 0], [0, 0, 1]] * q;   // error, 3x3
+    let r = [[0, 1], [1, 0]] * q;                    // ok, a qubit
+",
+    ),
+    (
+        "E0057",
+        "A module imported by more than one file is parsed once for each of
+them and kept once. Its functions are the same if they have the same name
+and signature, so two functions of the same name which differ can't be told
+apart.
+
+    // lib.ql is imported by both a.ql and b.ql
+    fn flip(q: qbit) : qbit { ... }     // ok, kept once
+
+A nested function is compiled as a function of the module named after the
+functions enclosing it, which must not be taken already.
+
+    fn rotate_angle() : f64 { ... }
+    fn rotate(q: qbit) : qbit {
+        fn angle() : f64 { ... }        // error: named `rotate_angle` too
+    }
+",
+    ),
+    (
+        "E0058",
+        "A qubit of a register is indexed from zero, so the index must be less
+than the size of the register. Indices known at compile time are checked,
+whether written in decimal or hexadecimal.
+
+    fn main(q: qbit[16]) {
+        h(q[0xF]);                      // ok, the last qubit
+        h(q[0x10]);                     // error, there is no 17th qubit
+    }
+",
+    ),
+    (
+        "E0059",
+        "A divisor known at compile time is zero. This is a warning, the division
+is not folded and is left for the program to do.
+
+    let angle = pi / 2;                 // ok, folded
+    let angle = pi / (2 - 2);           // warning: divides by zero
+",
+    ),
+    (
+        "E0060",
+        "A qubit given to `free` goes back to the allocator, which may hand it out
+again, so its variable can't be used afterwards. A qubit freed in the block
+of a conditional or a loop may be freed after it as well. Binding the name
+again gives a new qubit.
+
+    let q = alloc();
+    free(q);
+    h(q);                               // error, `q` is freed
+    let q = alloc();
+    h(q);                               // ok, another qubit
+",
+    ),
+];
+
+/// Returns the extended explanation of an error code.
+pub(crate) fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::QccErrorKind;
+
+    #[test]
+    fn check_explain() {
+        let explanation = explain("E0012");
+        assert!(explanation.is_some_and(|text| !text.is_empty()));
+        assert_eq!(explain("e0012"), explanation);
+        assert_eq!(QccErrorKind::ExpectedFnArgs.code(), "E0012");
+
+        assert!(explain("E9999").is_none());
+    }
+}
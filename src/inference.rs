@@ -1,30 +1,40 @@
 //! Type inference mechanism for qcc.
-use crate::ast::{Expr, FunctionAST, LiteralAST, Qast, QccCell, VarAST};
-use crate::error::{QccError, QccErrorKind, Result};
-use crate::types::Type;
+use crate::ast::{Expr, FunctionAST, GateAST, Ident, LiteralAST, Opcode, Qast, QccCell, VarAST};
+use crate::builtins::{Basis, Builtin};
+use crate::diagnostics::Diagnostics;
+use crate::error::{QccError, QccErrorKind, QccErrorLoc, Result};
+use crate::lexer::Location;
+use crate::optimizer::passes::is_read;
+use crate::types::{Signature, Type};
+use crate::utils::{demangle_expr, mangle};
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::HashMap;
 
-/// A generic symbol table implementation.
+/// A generic symbol table implementation. Entries are kept in insertion order
+/// so that lookups, and hence diagnostics, are the same on every run.
+#[derive(Clone)]
 struct SymbolTable<T> {
-    table: std::collections::HashSet<T>,
+    table: Vec<T>,
 }
 
 impl<T> SymbolTable<T>
 where
-    T: std::cmp::Eq + std::hash::Hash,
+    T: std::cmp::Eq,
 {
     fn new() -> Self {
-        Self {
-            table: std::collections::HashSet::new(),
-        }
+        Self { table: Vec::new() }
     }
 
     fn push(&mut self, value: T) {
-        self.table.insert(value);
+        if !self.table.contains(&value) {
+            self.table.push(value);
+        }
     }
 
     fn extend(&mut self, values: Vec<T>) {
-        self.table.extend(values);
+        for value in values {
+            self.push(value);
+        }
     }
 
     fn iter(&self) -> impl Iterator<Item = &T> + '_ {
@@ -34,7 +44,7 @@ where
 
 impl<T> std::fmt::Display for SymbolTable<T>
 where
-    T: std::fmt::Display + std::cmp::Eq + std::hash::Hash,
+    T: std::fmt::Display + std::cmp::Eq,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for entry in self.iter() {
@@ -44,116 +54,919 @@ where
     }
 }
 
-/// Sanity type checker for entire Qast.
-pub(crate) fn checker(ast: &Qast) -> Result<()> {
+/// Signatures of functions visible at a call site, keyed by their name as it
+/// appears in calls, i.e. mangled for functions of other modules.
+#[derive(Clone, Default)]
+struct SignatureTable {
+    table: std::collections::HashMap<Ident, Signature>,
+}
+
+impl SignatureTable {
+    fn insert(&mut self, name: Ident, signature: Signature) {
+        self.table.insert(name, signature);
+    }
+
+    /// Returns the signature of a function or builtin.
+    fn get(&self, name: &str) -> Option<Signature> {
+        match Builtin::lookup(name) {
+            Some(builtin) => Some(builtin.signature()),
+            None => self.table.get(name).cloned(),
+        }
+    }
+}
+
+/// Sanity type checker for entire Qast. It is run after inference in strict
+/// mode, where any expression left untyped is an error.
+pub fn checker(ast: &Qast) -> Result<()> {
+    let mut diagnostics = Diagnostics::default();
+    let checked = check_ast(ast, &mut diagnostics);
+    diagnostics.report();
+    checked
+}
+
+/// Checks types of the entire Qast like `checker`, collecting each error into
+/// `diagnostics` instead of reporting it.
+pub(crate) fn check_ast(ast: &Qast, diagnostics: &mut Diagnostics) -> Result<()> {
+    let seen = diagnostics.error_count();
     for module in ast {
         for function in &*module {
-            for expr in &*function {
-                check_expr(expr);
-            }
+            check_function(&function, diagnostics);
         }
     }
 
-    Ok(())
+    if diagnostics.error_count() > seen {
+        Err(QccErrorKind::TypeError)?
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks all expressions in a function and its nested functions.
+fn check_function(function: &FunctionAST, diagnostics: &mut Diagnostics) {
+    for nested in function.iter_nested() {
+        check_function(nested, diagnostics);
+    }
+
+    for expr in function {
+        if let Err(err) = check_expr(expr) {
+            diagnostics.error(err, format!("in `{}`", demangle_expr(expr)));
+        }
+    }
 }
 
 /// Checks type of an expression and returns it, an unknown type or a mismatch
-/// results in an error being returned.
-fn check_expr(expr: &QccCell<Expr>) -> Result<Type> {
+/// results in an error being returned along with its location.
+fn check_expr(expr: &QccCell<Expr>) -> core::result::Result<Type, QccErrorLoc> {
     match *expr.as_ref().borrow() {
         Expr::Var(ref v) => {
             if !v.is_typed() {
-                return Err(QccErrorKind::UnknownType)?;
+                return Err((QccErrorKind::UnknownType, v.location().clone()))?;
             } else {
                 return Ok(v.get_type());
             }
         }
-        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
-            let lhs_type = check_expr(lhs)?;
-            let rhs_type = check_expr(rhs)?;
+        Expr::BinaryExpr(ref lhs, ref op, ref rhs) => {
+            check_expr(lhs)?;
+            check_expr(rhs)?;
 
-            if lhs_type != rhs_type {
-                return Err(QccErrorKind::TypeMismatch)?;
+            // operands are promoted to a common type, qubits can't be compared
+            // without measuring them
+            let type_ = Expr::binary_type(
+                &lhs.as_ref().borrow(),
+                op,
+                &rhs.as_ref().borrow(),
+                &Type::Bottom,
+            )
+            .map_err(|kind| (kind, lhs.as_ref().borrow().get_location()))?;
+            let quantum = lhs.as_ref().borrow().get_type().is_quantum()
+                || rhs.as_ref().borrow().get_type().is_quantum();
+            if type_ == Type::Bottom
+                || (op.is_bitwise() && !is_bits(&type_))
+                || (op.is_comparison() && quantum)
+            {
+                let location = lhs.as_ref().borrow().get_location();
+                return Err((QccErrorKind::TypeMismatch, location))?;
             }
 
-            Ok(lhs_type)
+            Ok(type_)
         }
         Expr::FnCall(ref f, ref args) => {
-            for arg in args {
-                check_expr(arg)?;
+            // the basis of a measurement is checked during inference
+            let args = match Builtin::lookup(f.get_name()) {
+                Some(builtin) => builtin.split_basis(args).0,
+                None => args,
+            };
+            let arg_types = args
+                .iter()
+                .map(check_expr)
+                .collect::<core::result::Result<Vec<_>, _>>()?;
+
+            // builtins may return nothing
+            if let Some(builtin) = Builtin::lookup(f.get_name()) {
+                builtin
+                    .check_args(&arg_types)
+                    .map_err(|kind| (kind, f.get_loc().clone()))?;
+                return Ok(builtin.output_type_of(&arg_types));
             }
 
             if *f.get_output_type() == Type::Bottom {
-                return Err(QccErrorKind::UnknownType)?;
+                return Err((QccErrorKind::UnknownType, f.get_loc().clone()))?;
             }
 
-            Ok(*f.get_output_type())
+            Ok(f.get_output_type().clone())
         }
         Expr::Let(ref var, ref val) => {
             if !var.is_typed() {
-                return Err(QccErrorKind::UnknownType)?;
+                return Err((QccErrorKind::UnknownType, var.location().clone()))?;
             }
-            let val_type = check_expr(val)?;
+            let val_type = match check_expr(val)? {
+                // digit literals may be integers of the declared type
+                Type::F64 => val.as_ref().borrow().get_type_as(&var.get_type()),
+                val_type => val_type,
+            };
+
+            // qubits and bits can be assigned to each other, see
+            // `infer_from_table`
+            let coerced = (var.get_type() == Type::Qbit || var.get_type() == Type::Bit)
+                && (val_type == Type::Qbit || val_type == Type::Bit);
 
-            if var.get_type() != val_type {
-                return Err(QccErrorKind::TypeMismatch)?;
+            if var.get_type() != val_type && !coerced {
+                return Err((QccErrorKind::TypeMismatch, var.location().clone()))?;
+            }
+            if val_type.is_integer() && !fits(&val.as_ref().borrow(), &val_type) {
+                return Err((QccErrorKind::IntegerOverflow, var.location().clone()))?;
             }
 
-            Ok(val_type)
+            Ok(var.get_type())
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            // which block runs is decided classically
+            if check_expr(cond)?.is_quantum() {
+                let location = cond.as_ref().borrow().get_location();
+                return Err((QccErrorKind::TypeMismatch, location))?;
+            }
+            for expr in truth.iter().chain(otherwise) {
+                check_expr(expr)?;
+            }
+            Ok(Type::Unit)
+        }
+        Expr::Loop(ref cond, ref body) => {
+            // whether a loop goes on is decided classically
+            if check_expr(cond)?.is_quantum() {
+                let location = cond.as_ref().borrow().get_location();
+                return Err((QccErrorKind::TypeMismatch, location))?;
+            }
+            for expr in body {
+                check_expr(expr)?;
+            }
+            Ok(Type::Unit)
         }
         Expr::Literal(ref lit) => match *lit.as_ref().borrow() {
-            LiteralAST::Lit_Digit(ref digit) => Ok(Type::F64),
-            LiteralAST::Lit_Str(ref s) => Ok(Type::Bottom),
+            LiteralAST::Lit_Digit(_) | LiteralAST::Lit_Int(_) => Ok(Type::F64),
+            LiteralAST::Lit_Imag(_) => Ok(Type::Complex),
+            LiteralAST::Lit_Str(_) => Ok(Type::Str),
             LiteralAST::Lit_Qbit(_) => Ok(Type::Qbit),
+            LiteralAST::Lit_Tensor(ref elems, ref location) => {
+                let elem_types = elems
+                    .iter()
+                    .map(check_expr)
+                    .collect::<core::result::Result<Vec<Type>, QccErrorLoc>>()?;
+                let location = location.clone();
+                match Type::tensor(&elem_types) {
+                    Ok(Type::Bottom) => Err((QccErrorKind::TypeMismatch, location))?,
+                    Ok(ty) => Ok(ty),
+                    Err(kind) => Err((kind, location))?,
+                }
+            }
         },
     }
 }
 
-/// Type inference method.
+/// Checks if bitwise operators work on values of a type.
+fn is_bits(ty: &Type) -> bool {
+    matches!(ty, Type::Bit | Type::Creg(_))
+}
+
+/// Type inference method. Errors found are reported before returning.
 pub fn infer(ast: &mut Qast) -> Result<()> {
-    let mut seen_errors = false;
-    let mut function_table: SymbolTable<VarAST> = SymbolTable::new();
+    let mut diagnostics = Diagnostics::default();
+    let inferred = infer_ast(ast, &mut diagnostics);
+    diagnostics.report();
+    inferred
+}
+
+/// Lists the functions of each module by their mangled and declared names,
+/// with the types of their parameters and local bindings as inferred.
+pub(crate) fn dump_symbols(ast: &Qast) -> String {
+    let mut dump = String::new();
+    for module in ast {
+        dump += &format!("module {}\n", module.get_name());
+        for function in &*module {
+            dump += &format!(
+                "    fn {} ({}) : {}\n",
+                mangle(&module.get_name(), function.get_name()),
+                function.get_name(),
+                function.get_output_type()
+            );
+            for param in function.iter_params() {
+                dump += &format!("        param {}: {}\n", param.name(), param.get_type());
+            }
+            for expr in &*function {
+                if let Expr::Let(var, _) = &*expr.as_ref().borrow() {
+                    dump += &format!("        let {}: {}\n", var.name(), var.get_type());
+                }
+            }
+        }
+    }
+    dump
+}
 
-    for mut module in ast {
-        // functions but only collect their names and return types.
+/// Infers types in all modules, collecting each error seen along with the
+/// location of the offending subexpression into `diagnostics`.
+pub(crate) fn infer_ast(ast: &mut Qast, diagnostics: &mut Diagnostics) -> Result<()> {
+    let seen = diagnostics.error_count();
+    monomorphize(ast, diagnostics);
+    let mut function_table = SignatureTable::default();
+    for mut module in &mut *ast {
+        let module_name = module.get_name();
+        for function in &*module {
+            let signature = function.signature();
+            // imported functions are called by their mangled name
+            let mangled = mangle(&module_name, function.get_name());
+            function_table.insert(mangled, signature.clone());
+            function_table.insert(function.get_name().clone(), signature);
+        }
+
+        let mut gate_table = SignatureTable::default();
+        for gate in module.iter_gates() {
+            gate_table.insert(gate.get_name().clone(), gate.signature());
+            function_table.insert(gate.get_name().clone(), gate.signature());
+        }
+        for gate in module.iter_gates() {
+            check_gate(gate, &gate_table, diagnostics);
+        }
         for function in &*module {
-            function_table.push(VarAST::new_with_type(
-                function.get_name().clone(),
-                function.get_loc().clone(),
-                function.get_output_type().clone(),
-            ));
+            check_qasm_name(&function, &gate_table, diagnostics);
         }
 
         for mut function in &mut *module {
-            // parameter symbols
-            let mut parameter_table: SymbolTable<VarAST> = SymbolTable::new();
-            for param in function.iter_params() {
-                parameter_table.push(param.clone());
-            }
-
-            // local variables
-            let mut local_var_table: SymbolTable<VarAST> = SymbolTable::new();
-            for instruction in &*function {
-                // only add let-lhs and only if they are type checked
-                match *instruction.as_ref().borrow() {
-                    Expr::Let(ref def, _) => {
-                        // don't type check lhs-rhs, otherwise along with a
-                        // mismatch error, an unknown type error would also be
-                        // raised if local st doesn't find typed lhs.
-                        let checked: Result<Type> = Ok(def.get_type());
-                        if checked.is_ok_and(|ty| ty != Type::Bottom) {
-                            local_var_table.push(def.clone());
-                        }
+            infer_function(
+                &mut function,
+                &SymbolTable::new(),
+                &function_table,
+                diagnostics,
+            );
+            check_freed(&function, diagnostics);
+        }
+    }
+
+    // unused bindings are only worth a warning once the program type checks
+    if diagnostics.error_count() == seen {
+        for module in &*ast {
+            for function in &*module {
+                warn_unused(&function, diagnostics);
+            }
+        }
+    }
+
+    if diagnostics.error_count() > seen {
+        Err(QccErrorKind::TypeError)?
+    } else {
+        Ok(())
+    }
+}
+
+/// Warns about let bindings which are never read. Bindings named with a
+/// leading `_` are meant to be unused, and parameters are never reported.
+fn warn_unused(function: &FunctionAST, diagnostics: &mut Diagnostics) {
+    for nested in function.iter_nested() {
+        warn_unused(nested, diagnostics);
+    }
+
+    let body = function
+        .into_iter()
+        .cloned()
+        .collect::<Vec<QccCell<Expr>>>();
+    warn_unused_in(&body, &[], diagnostics);
+}
+
+/// Warns about let bindings of a block which are never read in the rest of
+/// it. A binding in the block of a conditional or a loop rebinds the name for
+/// the rest of the enclosing block, `later`, so reading it there counts too.
+/// Bindings in the body of a loop may also be read by the next iteration.
+fn warn_unused_in(body: &[QccCell<Expr>], later: &[QccCell<Expr>], diagnostics: &mut Diagnostics) {
+    for (index, expr) in body.iter().enumerate() {
+        let rest = || {
+            body[index + 1..]
+                .iter()
+                .chain(later)
+                .cloned()
+                .collect::<Vec<QccCell<Expr>>>()
+        };
+        match *expr.as_ref().borrow() {
+            Expr::Let(ref var, _) => {
+                if !var.name().starts_with('_') && !is_read(var.name(), &rest()) {
+                    let warning = (QccErrorKind::UnusedVariable, var.location().clone()).into();
+                    diagnostics.warning(warning, format!("`{}`", var.name()));
+                }
+            }
+            Expr::Conditional(_, ref truth, ref otherwise) => {
+                let rest = rest();
+                warn_unused_in(truth, &rest, diagnostics);
+                warn_unused_in(otherwise, &rest, diagnostics);
+            }
+            Expr::Loop(ref cond, ref block) => {
+                let looped = std::iter::once(cond)
+                    .chain(block)
+                    .cloned()
+                    .chain(rest())
+                    .collect::<Vec<QccCell<Expr>>>();
+                warn_unused_in(block, &looped, diagnostics);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reports each use of a qubit after it is freed, at the use. A qubit freed in
+/// the block of a conditional or a loop may be freed after it as well, while
+/// a new binding of its name is another qubit.
+fn check_freed(function: &FunctionAST, diagnostics: &mut Diagnostics) {
+    for nested in function.iter_nested() {
+        check_freed(nested, diagnostics);
+    }
+
+    let (mut freed, mut uses) = (vec![], vec![]);
+    for expr in function {
+        freed_in(&expr.as_ref().borrow(), &mut freed, &mut uses);
+    }
+    for var in uses {
+        let err = (QccErrorKind::FreedQubit, var.location().clone()).into();
+        diagnostics.error(err, format!("`{}`", var.name()));
+    }
+}
+
+/// Adds variables `expr` frees to `freed`, and variables it uses while freed
+/// to `uses`.
+fn freed_in(expr: &Expr, freed: &mut Vec<Ident>, uses: &mut Vec<VarAST>) {
+    let mut block = |block: &[QccCell<Expr>], freed: &mut Vec<Ident>, uses: &mut Vec<VarAST>| {
+        for expr in block {
+            freed_in(&expr.as_ref().borrow(), freed, uses);
+        }
+    };
+    match expr {
+        Expr::Var(var) => {
+            if freed.contains(var.name()) {
+                uses.push(var.clone());
+            }
+        }
+        Expr::BinaryExpr(lhs, _, rhs) => {
+            freed_in(&lhs.as_ref().borrow(), freed, uses);
+            freed_in(&rhs.as_ref().borrow(), freed, uses);
+        }
+        Expr::FnCall(f, args) => {
+            block(args, freed, uses);
+            if Builtin::lookup(f.get_name()) == Some(Builtin::Free) {
+                freed.extend(args.iter().flat_map(|arg| vars_of(&arg.as_ref().borrow())));
+            }
+        }
+        Expr::Let(var, val) => {
+            freed_in(&val.as_ref().borrow(), freed, uses);
+            freed.retain(|name| name != var.name());
+        }
+        Expr::Literal(lit) => {
+            if let LiteralAST::Lit_Tensor(ref elems, _) = *lit.as_ref().borrow() {
+                block(elems, freed, uses);
+            }
+        }
+        Expr::Conditional(cond, truth, otherwise) => {
+            freed_in(&cond.as_ref().borrow(), freed, uses);
+            let mut other = freed.clone();
+            block(truth, freed, uses);
+            block(otherwise, &mut other, uses);
+            freed.extend(other);
+        }
+        Expr::Loop(cond, body) => {
+            // the next iteration sees what the previous one freed
+            let mut once = freed.clone();
+            freed_in(&cond.as_ref().borrow(), &mut once, &mut vec![]);
+            block(body, &mut once, &mut vec![]);
+            freed.extend(once);
+            freed_in(&cond.as_ref().borrow(), freed, uses);
+            block(body, freed, uses);
+        }
+    }
+}
+
+/// Checks that the body of a gate applies only standard or declared gates,
+/// with its qargs as their qubits and its parameters in their angles.
+fn check_gate(gate: &GateAST, gate_table: &SignatureTable, diagnostics: &mut Diagnostics) {
+    let params = gate
+        .iter_params()
+        .map(VarAST::name)
+        .collect::<Vec<&Ident>>();
+    let qargs = gate.iter_qargs().map(VarAST::name).collect::<Vec<&Ident>>();
+
+    for expr in gate {
+        let expr = expr.as_ref().borrow();
+        let Expr::FnCall(ref callee, ref args) = *expr else {
+            let err = QccErrorLoc::new(QccErrorKind::UnexpectedExpr, expr.get_location());
+            diagnostics.error(err, format!("`{}` in gate `{}`", *expr, gate.get_name()));
+            continue;
+        };
+        let is_gate = Builtin::lookup(callee.get_name()).map_or(true, |b| b.is_gate());
+        let Some(signature) = gate_table.get(callee.get_name()).filter(|_| is_gate) else {
+            let err = QccErrorLoc::new(QccErrorKind::ExpectedFn, callee.get_loc().clone());
+            diagnostics.error(err, format!("`{}` is not a gate", callee.get_name()));
+            continue;
+        };
+        if signature.params.len() != args.len() {
+            let err = QccErrorLoc::new(QccErrorKind::ArityMismatch, callee.get_loc().clone());
+            diagnostics.error(err, format!("in call to `{}`", callee.get_name()));
+            continue;
+        }
+
+        for (type_, arg) in signature.params.iter().zip(args) {
+            let arg = arg.as_ref().borrow();
+            let declared = match (type_, &*arg) {
+                (Type::Qbit, Expr::Var(var)) => qargs.contains(&var.name()),
+                (Type::Qbit, _) => false,
+                // angles are arithmetic over `pi` and parameters
+                _ => vars_of(&arg)
+                    .iter()
+                    .all(|name| name == "pi" || params.contains(&name)),
+            };
+            if !declared {
+                let err = QccErrorLoc::new(QccErrorKind::UndeclaredQarg, arg.get_location());
+                diagnostics.error(err, format!("`{}` in gate `{}`", *arg, gate.get_name()));
+            }
+        }
+    }
+}
+
+/// Checks that a function named after an OpenQASM gate by `qasm_name` is
+/// named after a standard or declared gate, which it is applied as.
+fn check_qasm_name(
+    function: &FunctionAST,
+    gate_table: &SignatureTable,
+    diagnostics: &mut Diagnostics,
+) {
+    let Some(name) = function.get_attrs().qasm_name() else {
+        return;
+    };
+    let is_gate = Builtin::lookup(name).map_or(true, |b| b.is_gate());
+    if gate_table.get(name).is_none() || !is_gate {
+        let err = QccErrorLoc::new(QccErrorKind::UnexpectedAttr, function.get_loc().clone());
+        diagnostics.error(
+            err,
+            format!("`{}` is not a standard or declared gate", name),
+        );
+    }
+}
+
+/// Finds a call to a user function within an expression, however deeply
+/// nested, whose argument count differs from the function's parameter count.
+/// Builtins check their own arguments.
+fn arity_mismatch(expr: &Expr, function_table: &SignatureTable) -> Option<QccErrorLoc> {
+    let nested = |exprs: &[QccCell<Expr>]| {
+        exprs
+            .iter()
+            .find_map(|expr| arity_mismatch(&expr.as_ref().borrow(), function_table))
+    };
+    match expr {
+        Expr::FnCall(f, args) => {
+            let mismatch = Builtin::lookup(f.get_name()).is_none()
+                && function_table
+                    .get(f.get_name())
+                    .is_some_and(|signature| signature.params.len() != args.len());
+            if mismatch {
+                Some(QccErrorLoc::new(
+                    QccErrorKind::ArityMismatch,
+                    f.get_loc().clone(),
+                ))
+            } else {
+                nested(args)
+            }
+        }
+        Expr::BinaryExpr(lhs, _, rhs) => nested(&[lhs.clone(), rhs.clone()]),
+        Expr::Let(_, val) => arity_mismatch(&val.as_ref().borrow(), function_table),
+        Expr::Conditional(cond, truth, otherwise) => nested(std::slice::from_ref(cond))
+            .or_else(|| nested(truth))
+            .or_else(|| nested(otherwise)),
+        Expr::Loop(cond, body) => nested(std::slice::from_ref(cond)).or_else(|| nested(body)),
+        Expr::Var(_) | Expr::Literal(_) => None,
+    }
+}
+
+/// Finds a register indexed out of its bounds in an expression, where both the
+/// size of the register and the index are known at compile time.
+fn index_out_of_bounds(
+    expr: &Expr,
+    param_st: &SymbolTable<VarAST>,
+    local_st: &SymbolTable<VarAST>,
+) -> Option<QccErrorLoc> {
+    let nested = |exprs: &[QccCell<Expr>]| {
+        exprs
+            .iter()
+            .find_map(|expr| index_out_of_bounds(&expr.as_ref().borrow(), param_st, local_st))
+    };
+    match expr {
+        Expr::FnCall(f, args) if Builtin::lookup(f.get_name()) == Some(Builtin::Index) => {
+            let [reg, index] = args.as_slice() else {
+                return None;
+            };
+            let size = match *reg.as_ref().borrow() {
+                Expr::Var(ref reg) => param_st
+                    .iter()
+                    .chain(local_st.iter())
+                    .filter(|var| var.name() == reg.name())
+                    .find_map(|var| match var.get_type() {
+                        Type::Qreg(size) => Some(size),
+                        _ => None,
+                    }),
+                _ => None,
+            };
+            let index = index.as_ref().borrow().eval_int().ok().flatten();
+            match (size, index) {
+                (Some(size), Some(index)) if index < 0 || index as usize >= size => Some(
+                    QccErrorLoc::new(QccErrorKind::IndexOutOfBounds, f.get_loc().clone()),
+                ),
+                _ => nested(args),
+            }
+        }
+        Expr::FnCall(_, args) => nested(args),
+        Expr::BinaryExpr(lhs, _, rhs) => nested(&[lhs.clone(), rhs.clone()]),
+        Expr::Let(_, val) => index_out_of_bounds(&val.as_ref().borrow(), param_st, local_st),
+        Expr::Conditional(cond, truth, otherwise) => nested(std::slice::from_ref(cond))
+            .or_else(|| nested(truth))
+            .or_else(|| nested(otherwise)),
+        Expr::Loop(cond, body) => nested(std::slice::from_ref(cond)).or_else(|| nested(body)),
+        Expr::Var(_) | Expr::Literal(_) => None,
+    }
+}
+
+/// Names of all variables used in an expression.
+fn vars_of(expr: &Expr) -> Vec<Ident> {
+    match expr {
+        Expr::Var(var) => vec![var.name().clone()],
+        Expr::BinaryExpr(lhs, _, rhs) => {
+            let mut vars = vars_of(&lhs.as_ref().borrow());
+            vars.extend(vars_of(&rhs.as_ref().borrow()));
+            vars
+        }
+        Expr::FnCall(_, args) => args
+            .iter()
+            .flat_map(|arg| vars_of(&arg.as_ref().borrow()))
+            .collect(),
+        Expr::Let(_, val) => vars_of(&val.as_ref().borrow()),
+        Expr::Conditional(cond, truth, otherwise) => std::iter::once(cond)
+            .chain(truth)
+            .chain(otherwise)
+            .flat_map(|expr| vars_of(&expr.as_ref().borrow()))
+            .collect(),
+        Expr::Loop(cond, body) => std::iter::once(cond)
+            .chain(body)
+            .flat_map(|expr| vars_of(&expr.as_ref().borrow()))
+            .collect(),
+        Expr::Literal(_) => vec![],
+    }
+}
+
+/// A generic function along with the module defining it.
+struct Generic {
+    module: Ident,
+    name: Ident,
+    generics: Vec<Ident>,
+    params: Vec<Type>,
+    ret: Type,
+}
+
+/// Instantiates generic functions for each register size they are called
+/// with. A call `qft(r)` with `r: qbit[3]` becomes a call to `qft_3`, a copy of
+/// `fn qft<n>(reg: qbit[n])` where `n` is 3. Instances may call other generic
+/// functions, so this repeats until no new instance is needed. Generic
+/// functions are dropped afterwards, leaving only their instances.
+fn monomorphize(ast: &mut Qast, diagnostics: &mut Diagnostics) {
+    let mut generics: Vec<(Ident, std::rc::Rc<Generic>)> = vec![];
+    for module in &*ast {
+        for function in &*module {
+            if function.get_generics().is_empty() {
+                continue;
+            }
+            let generic = std::rc::Rc::new(Generic {
+                module: module.get_name(),
+                name: function.get_name().clone(),
+                generics: function.get_generics().to_vec(),
+                params: function.get_input_type().clone(),
+                ret: function.get_output_type().clone(),
+            });
+            let mangled = mangle(&module.get_name(), function.get_name());
+            generics.push((mangled, generic.clone()));
+            generics.push((function.get_name().clone(), generic));
+        }
+    }
+    if generics.is_empty() {
+        return;
+    }
+
+    let mut instantiated: Vec<(Ident, Ident)> = vec![];
+    loop {
+        let mut pending = vec![];
+        for mut module in &mut *ast {
+            for mut function in &mut *module {
+                if function.get_generics().is_empty() {
+                    instantiate_calls(&mut function, &generics, &mut pending, diagnostics);
+                }
+            }
+        }
+
+        pending.retain(
+            |(generic, instance, _): &(std::rc::Rc<Generic>, Ident, _)| {
+                let key = (generic.module.clone(), instance.clone());
+                if instantiated.contains(&key) {
+                    false
+                } else {
+                    instantiated.push(key);
+                    true
+                }
+            },
+        );
+        if pending.is_empty() {
+            break;
+        }
+
+        for (generic, instance, sizes) in pending {
+            for mut module in &mut *ast {
+                if module.get_name() != generic.module {
+                    continue;
+                }
+                let function = module
+                    .into_iter()
+                    .find(|function| *function.get_name() == generic.name)
+                    .map(|function| function.instantiate(instance.clone(), &sizes));
+                if let Some(function) = function {
+                    module.append_function(function);
+                }
+            }
+        }
+    }
+
+    for mut module in &mut *ast {
+        module.retain_functions(|function| function.get_generics().is_empty());
+    }
+}
+
+/// Renames each call to a generic function in `function` to the instance for
+/// the sizes of its arguments, pushing the instance onto `pending`.
+fn instantiate_calls(
+    function: &mut FunctionAST,
+    generics: &[(Ident, std::rc::Rc<Generic>)],
+    pending: &mut Vec<(std::rc::Rc<Generic>, Ident, HashMap<Ident, usize>)>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut env: HashMap<Ident, Type> = function
+        .iter_params()
+        .map(|param| (param.name().clone(), param.get_type()))
+        .collect();
+
+    for expr in &*function {
+        // a binding may be typed by the instance its value calls
+        instantiate_expr(expr, &env, generics, pending, diagnostics);
+        if let Expr::Let(var, val) = &*expr.as_ref().borrow() {
+            let type_ = if var.is_typed() {
+                var.get_type()
+            } else {
+                type_of(&val.as_ref().borrow(), &env)
+            };
+            env.insert(var.name().clone(), type_);
+        }
+    }
+}
+
+fn instantiate_expr(
+    expr: &QccCell<Expr>,
+    env: &HashMap<Ident, Type>,
+    generics: &[(Ident, std::rc::Rc<Generic>)],
+    pending: &mut Vec<(std::rc::Rc<Generic>, Ident, HashMap<Ident, usize>)>,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut expr = expr.as_ref().borrow_mut();
+    match &mut *expr {
+        Expr::Var(_) => {}
+        Expr::Literal(lit) => {
+            if let LiteralAST::Lit_Tensor(elems, _) = &*lit.as_ref().borrow() {
+                for elem in elems {
+                    instantiate_expr(elem, env, generics, pending, diagnostics);
+                }
+            }
+        }
+        Expr::Let(_, val) => instantiate_expr(val, env, generics, pending, diagnostics),
+        Expr::Conditional(cond, truth, otherwise) => {
+            instantiate_expr(cond, env, generics, pending, diagnostics);
+            for expr in truth.iter().chain(otherwise.iter()) {
+                instantiate_expr(expr, env, generics, pending, diagnostics);
+            }
+        }
+        Expr::Loop(cond, body) => {
+            instantiate_expr(cond, env, generics, pending, diagnostics);
+            for expr in body {
+                instantiate_expr(expr, env, generics, pending, diagnostics);
+            }
+        }
+        Expr::BinaryExpr(lhs, _, rhs) => {
+            instantiate_expr(lhs, env, generics, pending, diagnostics);
+            instantiate_expr(rhs, env, generics, pending, diagnostics);
+        }
+        Expr::FnCall(callee, args) => {
+            for arg in args.iter() {
+                instantiate_expr(arg, env, generics, pending, diagnostics);
+            }
+
+            let Some((called_as, generic)) = generics
+                .iter()
+                .find(|(called_as, _)| called_as == callee.get_name())
+            else {
+                return;
+            };
+
+            let mut sizes = HashMap::new();
+            for (param, arg) in generic.params.iter().zip(args.iter()) {
+                let arg = arg.as_ref().borrow();
+                if !bind_size(param, &type_of(&arg, env), &mut sizes) {
+                    let err = QccErrorLoc::new(QccErrorKind::TypeMismatch, arg.get_location());
+                    diagnostics.error(
+                        err,
+                        format!("`{}` cannot be passed as `{}`", type_of(&arg, env), param),
+                    );
+                    return;
+                }
+            }
+            let mut instance = generic.name.clone();
+            for size in &generic.generics {
+                match sizes.get(size) {
+                    Some(len) => instance += &format!("_{len}"),
+                    None => {
+                        let err =
+                            QccErrorLoc::new(QccErrorKind::TypeMismatch, callee.get_loc().clone());
+                        diagnostics.error(
+                            err,
+                            format!("cannot infer `{}` in call to `{}`", size, generic.name),
+                        );
+                        return;
                     }
-                    _ => {}
                 }
             }
 
-            // infer local var types
-            for instruction in &mut *function {
-                let instruction_type = infer_expr(instruction);
+            // keep the module prefix of a call to an imported generic
+            let prefix = &called_as[..called_as.len() - generic.name.len()];
+            callee.set_name(prefix.to_string() + &instance);
+            callee.set_output_type(generic.ret.substitute(&sizes));
+            pending.push((generic.clone(), instance, sizes));
+        }
+    }
+}
+
+/// Binds the size of a generic register `param` to the length of `arg`.
+/// Returns false if `arg` cannot be passed for `param`, either because it is
+/// not a register of the same elements or because the size is already bound
+/// to a different length.
+fn bind_size(param: &Type, arg: &Type, sizes: &mut HashMap<Ident, usize>) -> bool {
+    let Type::GenericRegister(elem, size) = param else {
+        return true;
+    };
+    let len = match (elem.as_ref(), arg) {
+        (Type::Qbit, Type::Qreg(len)) | (Type::Bit, Type::Creg(len)) => *len,
+        _ => return false,
+    };
+    *sizes.entry(size.clone()).or_insert(len) == len
+}
+
+/// Type of an expression as far as it is known before inference, looking up
+/// variables in `env`.
+fn type_of(expr: &Expr, env: &HashMap<Ident, Type>) -> Type {
+    match expr {
+        Expr::Var(var) if !var.is_typed() => env.get(var.name()).cloned().unwrap_or(Type::Bottom),
+        Expr::FnCall(f, args) => match Builtin::lookup(f.get_name()) {
+            Some(builtin) => {
+                let args = args
+                    .iter()
+                    .map(|arg| type_of(&arg.as_ref().borrow(), env))
+                    .collect::<Vec<Type>>();
+                builtin.output_type_of(&args)
+            }
+            None => expr.get_type(),
+        },
+        _ => expr.get_type(),
+    }
+}
+
+/// Infers types for a single function. Nested functions are inferred first,
+/// with parameters of the enclosing function available to them as `captures`.
+/// Each error is collected into `diagnostics`.
+fn infer_function(
+    function: &mut FunctionAST,
+    captures: &SymbolTable<VarAST>,
+    function_table: &SignatureTable,
+    diagnostics: &mut Diagnostics,
+) {
+    // parameter symbols, shadowing any captured symbol of the same name
+    let mut parameter_table: SymbolTable<VarAST> = SymbolTable::new();
+    for captured in captures.iter() {
+        if function
+            .iter_params()
+            .all(|param| param.name() != captured.name())
+        {
+            parameter_table.push(captured.clone());
+        }
+    }
+    for param in function.iter_params() {
+        parameter_table.push(param.clone());
+    }
+
+    // nested functions are only visible inside their enclosing function
+    let mut function_table = function_table.clone();
+    for nested in function.iter_nested_mut() {
+        infer_function(nested, &parameter_table, &function_table, diagnostics);
+        function_table.insert(nested.get_name().clone(), nested.signature());
+    }
+
+    // local variables
+    let mut local_var_table: SymbolTable<VarAST> = SymbolTable::new();
+    for instruction in &*function {
+        // only add let-lhs and only if they are type checked
+        match *instruction.as_ref().borrow() {
+            Expr::Let(ref def, _) => {
+                // don't type check lhs-rhs, otherwise along with a
+                // mismatch error, an unknown type error would also be
+                // raised if local st doesn't find typed lhs.
+                let checked: Result<Type> = Ok(def.get_type());
+                if checked.is_ok_and(|ty| ty != Type::Bottom) {
+                    local_var_table.push(def.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // infer local var types
+    for instruction in &mut *function {
+        let outer_var_table = local_var_table.clone();
+        // calls nested in arguments of builtins are typed by the builtin alone,
+        // so arities and indices are checked up front for the whole expression
+        let mismatch =
+            arity_mismatch(&instruction.as_ref().borrow(), &function_table).or_else(|| {
+                let instruction = instruction.as_ref().borrow();
+                index_out_of_bounds(&instruction, &parameter_table, &local_var_table)
+            });
+        if let Some(err) = mismatch {
+            diagnostics.error(err, format!("in `{}`", demangle_expr(instruction)));
+            continue;
+        }
+
+        type_gate_registers(instruction, &parameter_table, &local_var_table);
+        let instruction_type = infer_expr(instruction);
+
+        if instruction_type
+            .as_ref()
+            .is_some_and(|ty| *ty != Type::Bottom)
+        {
+            match *instruction.as_ref().borrow() {
+                Expr::Let(ref var, _) => {
+                    if var.is_typed() {
+                        local_var_table.push(var.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
 
-                if instruction_type.is_some_and(|ty| ty != Type::Bottom) {
+        // arguments of calls returning nothing are still to be checked
+        if instruction_type.is_none()
+            || instruction_type == Some(Type::Bottom)
+            || instruction_type == Some(Type::Unit)
+        {
+            // we couldn't infer all types for expression
+            // see if either symbol table contains any information
+            match infer_from_table(
+                instruction,
+                &parameter_table,
+                &local_var_table,
+                &function_table,
+            ) {
+                None => {
+                    // This infers type for let expressions based on the
+                    // symbol table but doesn't update the table
+                    // entries. For e.g.,
+                    // ```quale
+                    //   let a: f64 = 42;
+                    //   let b = a;  // this is inferred as f64 type,
+                    //               // but symbol table
+                    //               // doesn't contain it after
+                    //               // inferring
+                    //   let c = b;  // hence, this would fail to be
+                    //               // inferred
+                    // ```
+                    // So we have to update symbol tables accordingly.
                     match *instruction.as_ref().borrow() {
                         Expr::Let(ref var, _) => {
                             if var.is_typed() {
@@ -163,116 +976,172 @@ pub fn infer(ast: &mut Qast) -> Result<()> {
                         _ => {}
                     }
                 }
-
-                if instruction_type.is_none() || instruction_type == Some(Type::Bottom) {
-                    // we couldn't infer all types for expression
-                    // see if either symbol table contains any information
-                    match infer_from_table(
-                        instruction,
-                        &parameter_table,
-                        &local_var_table,
-                        &function_table,
-                    ) {
-                        None => {
-                            // This infers type for let expressions based on the
-                            // symbol table but doesn't update the table
-                            // entries. For e.g.,
-                            // ```quale
-                            //   let a: f64 = 42;
-                            //   let b = a;  // this is inferred as f64 type,
-                            //               // but symbol table
-                            //               // doesn't contain it after
-                            //               // inferring
-                            //   let c = b;  // hence, this would fail to be
-                            //               // inferred
-                            // ```
-                            // So we have to update symbol tables accordingly.
-                            match *instruction.as_ref().borrow() {
-                                Expr::Let(ref var, _) => {
-                                    if var.is_typed() {
-                                        local_var_table.push(var.clone());
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        Some(untyped) => {
-                            seen_errors = true;
-                            match untyped {
-                                Ok(expr) => {
-                                    // unknown type of expression err
-                                    let err: QccError = QccErrorKind::UnknownType.into();
-                                    let expr = expr.as_ref().borrow();
-                                    err.report(
-                                        format!("for `{}` {}", expr, expr.get_location()).as_str(),
-                                    );
-                                }
-                                Err(err) => {
-                                    // err is returned
-                                    let row = instruction.as_ref().borrow().get_location().row();
-                                    err.report(&format!(
-                                        "on\n\t{}\t{}",
-                                        row,
-                                        instruction.as_ref().borrow()
-                                    ));
-                                }
-                            }
+                Some(untyped) => {
+                    let err = match untyped {
+                        // unknown type of expression err
+                        Ok(expr) => {
+                            let location = expr.as_ref().borrow().get_location();
+                            QccErrorLoc::new(QccErrorKind::UnknownType, location)
                         }
-                    }
+                        Err(err) => err,
+                    };
+                    diagnostics.error(err, format!("in `{}`", demangle_expr(instruction)));
                 }
             }
+        }
 
-            // type check between function return type and the last returned
-            // expression
-            let fn_return_type = *function.get_output_type();
-            let fn_name = function.borrow().get_name().clone();
+        // variables whose type isn't needed to infer that of the instruction,
+        // e.g. `a` in `h(a)`, are typed all the same
+        type_vars(instruction, &parameter_table, outer_var_table);
+    }
 
-            let last_instruction = function.last_mut();
-            if last_instruction.is_some() {
-                let last = last_instruction.unwrap();
+    // type check between function return type and the last returned
+    // expression
+    let fn_return_type = function.get_output_type().clone();
+    let fn_name = function.get_name().clone();
 
-                // get last expression's type
-                let last_instruction_type = infer_expr(last);
+    let last_instruction = function.last_mut();
+    if last_instruction.is_some() {
+        let last = last_instruction.unwrap();
 
-                if fn_return_type == Type::Bottom
-                    && last_instruction_type.is_some()
-                    && last_instruction_type != Some(Type::Bottom)
-                {
-                    function.set_output_type(last_instruction_type.unwrap());
-                } else {
-                    if last_instruction_type != Some(fn_return_type) {
-                        seen_errors = true;
-                        let err: QccError = QccErrorKind::TypeMismatch.into();
-                        let last_expr = last.as_ref().borrow();
-                        if last_instruction_type.is_none() {
-                            err.report(&format!(
-                                "between\n\t`{}` ({}) and `{}` ({}) {}",
-                                last_expr,
-                                Type::Bottom,
-                                fn_name,
-                                fn_return_type,
-                                last.as_ref().borrow().get_location()
-                            ));
-                        } else {
-                            err.report(&format!(
-                                "between\n\t`{}` ({}) and `{}` ({}) {}",
-                                last_expr,
-                                last_instruction_type.unwrap(),
-                                fn_name,
-                                fn_return_type,
-                                last.as_ref().borrow().get_location()
-                            ));
-                        }
-                    }
+        // get last expression's type
+        let last_instruction_type = infer_expr(last);
+
+        match last_instruction_type {
+            Some(ty) if fn_return_type == Type::Bottom && ty != Type::Bottom => {
+                function.set_output_type(ty);
+            }
+            // the last expression is reported on its own, and there is
+            // nothing to compare it with, so the function returns nothing
+            None | Some(Type::Bottom) if fn_return_type == Type::Bottom => {
+                function.set_output_type(Type::Unit);
+            }
+            ty if ty.as_ref() != Some(&fn_return_type) => {
+                let last_expr = last.as_ref().borrow();
+                let err = QccErrorLoc::new(QccErrorKind::TypeMismatch, last_expr.get_location());
+                diagnostics.error(
+                    err,
+                    format!(
+                        "between\n\t`{}` ({}) and `{}` ({})",
+                        demangle_expr(last),
+                        ty.unwrap_or_default(),
+                        fn_name,
+                        fn_return_type
+                    ),
+                );
+            }
+            _ => {}
+        }
+    } else if fn_return_type == Type::Bottom {
+        function.set_output_type(Type::Unit);
+    }
+}
+
+/// Types registers which gates on a single qubit are applied on in `expr` from
+/// the symbol tables, since such a gate returns the register it is applied on,
+/// see `Builtin::output_type_of`.
+fn type_gate_registers(
+    expr: &QccCell<Expr>,
+    param_st: &SymbolTable<VarAST>,
+    local_st: &SymbolTable<VarAST>,
+) {
+    match *expr.as_ref().borrow() {
+        Expr::FnCall(ref f, ref args) => {
+            for arg in args {
+                type_gate_registers(arg, param_st, local_st);
+            }
+            if !Builtin::lookup(f.get_name()).is_some_and(|b| b.is_single_qubit_gate()) {
+                return;
+            }
+            let Some(arg) = args.last() else {
+                return;
+            };
+            if let Expr::Var(ref mut var) = *arg.as_ref().borrow_mut() {
+                let register = param_st
+                    .iter()
+                    .chain(local_st.iter())
+                    .find(|reg| reg.name() == var.name())
+                    .map(VarAST::get_type)
+                    .filter(|ty| matches!(ty, Type::Qreg(_)));
+                if let (false, Some(register)) = (var.is_typed(), register) {
+                    var.set_type(register);
                 }
             }
         }
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
+            type_gate_registers(lhs, param_st, local_st);
+            type_gate_registers(rhs, param_st, local_st);
+        }
+        Expr::Let(_, ref val) => type_gate_registers(val, param_st, local_st),
+        _ => {}
     }
+}
 
-    if seen_errors {
-        return Err(QccErrorKind::TypeError)?;
-    } else {
-        Ok(())
+/// Types variables in `expr` from the symbol tables, wherever they are. Like in
+/// `infer_from_table`, parameters are looked up first. Bindings in blocks are
+/// seen by the rest of the block.
+fn type_vars(
+    expr: &QccCell<Expr>,
+    param_st: &SymbolTable<VarAST>,
+    mut local_st: SymbolTable<VarAST>,
+) {
+    let block = |block: &[QccCell<Expr>], mut local_st: SymbolTable<VarAST>| {
+        for expr in block {
+            type_vars(expr, param_st, local_st.clone());
+            if let Expr::Let(ref var, _) = *expr.as_ref().borrow() {
+                if var.is_typed() {
+                    local_st.push(var.clone());
+                }
+            }
+        }
+    };
+    match *expr.as_ref().borrow_mut() {
+        Expr::Var(ref mut var) => {
+            let is_var = |known: &&VarAST| known.name() == var.name() && known.is_typed();
+            let known = param_st
+                .iter()
+                .find(is_var)
+                .or_else(|| local_st.iter().filter(is_var).last())
+                .map(VarAST::get_type);
+            // `pi` is a constant, unless a variable shadows it
+            let known = known.or_else(|| (var.name() == "pi").then_some(Type::F64));
+            if let (false, Some(known)) = (var.is_typed(), known) {
+                var.set_type(known);
+            }
+        }
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
+            type_vars(lhs, param_st, local_st.clone());
+            type_vars(rhs, param_st, local_st);
+        }
+        Expr::FnCall(_, ref args) => {
+            for arg in args {
+                type_vars(arg, param_st, local_st.clone());
+            }
+        }
+        Expr::Let(_, ref val) => type_vars(val, param_st, local_st),
+        Expr::Literal(ref lit) => {
+            if let LiteralAST::Lit_Tensor(ref elems, _) = *lit.as_ref().borrow() {
+                block(elems, local_st);
+            }
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            type_vars(cond, param_st, local_st.clone());
+            // a bit bound by `if let` is only seen by the truth block
+            if let Expr::Let(ref var, _) = *cond.as_ref().borrow() {
+                block(otherwise, local_st.clone());
+                if var.is_typed() {
+                    local_st.push(var.clone());
+                }
+                block(truth, local_st);
+            } else {
+                block(truth, local_st.clone());
+                block(otherwise, local_st);
+            }
+        }
+        Expr::Loop(ref cond, ref body) => {
+            type_vars(cond, param_st, local_st.clone());
+            block(body, local_st);
+        }
     }
 }
 
@@ -290,16 +1159,43 @@ fn infer_expr(expr: &QccCell<Expr>) -> Option<Type> {
         }
 
         Expr::BinaryExpr(ref lhs, ref op, ref rhs) => {
-            let lhs_type = infer_expr(&lhs)?;
-            let rhs_type = infer_expr(&rhs)?;
+            infer_expr(&lhs)?;
+            infer_expr(&rhs)?;
 
-            if lhs_type != rhs_type {
+            // operands are promoted to a common type
+            let type_ = Expr::binary_type(
+                &lhs.as_ref().borrow(),
+                op,
+                &rhs.as_ref().borrow(),
+                &Type::Bottom,
+            )
+            .ok()?;
+            if type_ == Type::Bottom || (op.is_bitwise() && !is_bits(&type_)) {
                 return None;
             }
-            return Some(lhs_type);
+            return Some(type_);
         }
 
         Expr::FnCall(ref mut f, ref args) => {
+            if let Some(builtin) = Builtin::lookup(f.get_name()) {
+                // builtins have a fixed signature
+                let arg_types = args
+                    .iter()
+                    .map(|arg| arg.as_ref().borrow().get_type())
+                    .collect::<Vec<Type>>();
+                let output_type = builtin.output_type_of(&arg_types);
+                f.set_output_type(output_type.clone());
+                // no builtin takes a complex number, which is left for
+                // `infer_from_table` to report
+                if args
+                    .iter()
+                    .any(|arg| arg.as_ref().borrow().get_type() == Type::Complex)
+                {
+                    return None;
+                }
+                return Some(output_type);
+            }
+
             if *f.get_output_type() == Type::Bottom && args.len() != 0 {
                 // we can only infer input types by matching against args
                 for arg in args {
@@ -308,34 +1204,70 @@ fn infer_expr(expr: &QccCell<Expr>) -> Option<Type> {
                 }
                 // TODO: we cannot infer function return type and it may return
                 // a Bottom type.
-                return Some(*f.get_output_type());
+                return Some(f.get_output_type().clone());
             } else {
-                return Some(*f.get_output_type());
+                return Some(f.get_output_type().clone());
             }
         }
 
         Expr::Let(ref mut var, ref val) => {
             // val is an expression and it must have the same type as var
             if var.get_type() == Type::Bottom {
-                // we need to type check from expression first
+                // we need to type check from expression first, there is no
+                // value to bind if it returns nothing
                 let rhs_type = infer_expr(&val)?;
-                var.set_type(rhs_type);
+                if rhs_type == Type::Unit {
+                    return None;
+                }
+                var.set_type(rhs_type.clone());
                 return Some(rhs_type);
             } else {
                 let lhs_type = var.get_type();
-                let rhs_type = infer_expr(&val)?;
-                if lhs_type != rhs_type {
+                infer_expr(&val)?;
+                // digit literals may be integers of the declared type
+                let rhs_type = val.as_ref().borrow().get_type_as(&lhs_type);
+                // `infer_from_table` reports either error
+                if lhs_type != rhs_type
+                    || (lhs_type.is_integer() && !fits(&val.as_ref().borrow(), &lhs_type))
+                {
                     return None;
                 }
                 return Some(lhs_type);
             }
         }
 
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            infer_expr(cond)?;
+            for expr in truth.iter().chain(otherwise) {
+                infer_expr(expr)?;
+            }
+            return Some(Type::Unit);
+        }
+
+        Expr::Loop(ref cond, ref body) => {
+            infer_expr(cond)?;
+            for expr in body {
+                infer_expr(expr)?;
+            }
+            return Some(Type::Unit);
+        }
+
         Expr::Literal(ref lit) => {
             return match *lit.as_ref().borrow() {
-                LiteralAST::Lit_Digit(_) => Some(Type::F64),
-                LiteralAST::Lit_Str(_) => Some(Type::Bottom),
+                LiteralAST::Lit_Digit(_) | LiteralAST::Lit_Int(_) => Some(Type::F64),
+                LiteralAST::Lit_Imag(_) => Some(Type::Complex),
+                LiteralAST::Lit_Str(_) => Some(Type::Str),
                 LiteralAST::Lit_Qbit(_) => Some(Type::Qbit),
+                LiteralAST::Lit_Tensor(ref elems, _) => {
+                    let elem_types = elems
+                        .iter()
+                        .map(infer_expr)
+                        .collect::<Option<Vec<Type>>>()?;
+                    match Type::tensor(&elem_types).ok()? {
+                        Type::Bottom => None,
+                        ty => Some(ty),
+                    }
+                }
             };
         }
     }
@@ -381,12 +1313,33 @@ fn gather_already_typed(expr: &QccCell<Expr>) -> Vec<VarAST> {
 /// expression cannot be typed, because no information was found in symbol
 /// tables, then return that expression. Otherwise if complete expression is
 /// typed then return None. If any mismatch is seen, return appropriate error.
+/// Returns the location of the value bound to `var`, or of `var` if the value
+/// doesn't carry its location, e.g. a literal other than a tensor, or an
+/// expression built only from such literals.
+fn value_location(var: &VarAST, val: &QccCell<Expr>) -> Location {
+    match val.as_ref().borrow().get_location() {
+        location if location.row() == 0 => var.location().clone(),
+        location => location,
+    }
+}
+
+/// Checks that an integer expression known at compile time is in the range of
+/// its type. Integers are evaluated in 64 bits, so an `int32` is checked once
+/// evaluated, while an `int64` overflows while evaluating it.
+fn fits(val: &Expr, ty: &Type) -> bool {
+    match val.eval_int() {
+        Ok(Some(n)) => ty.represents(n as f64),
+        Ok(None) => true,
+        Err(_) => false,
+    }
+}
+
 fn infer_from_table(
     expr: &QccCell<Expr>,
     param_st: &SymbolTable<VarAST>,
     local_st: &SymbolTable<VarAST>,
-    function_st: &SymbolTable<VarAST>,
-) -> Option<Result<QccCell<Expr>>> {
+    function_st: &SignatureTable,
+) -> Option<core::result::Result<QccCell<Expr>, QccErrorLoc>> {
     match *expr.as_ref().borrow_mut() {
         Expr::Var(ref mut var) => {
             let mut param_type = Type::Bottom;
@@ -430,10 +1383,43 @@ fn infer_from_table(
             if rhs_info.is_some() {
                 return rhs_info;
             }
+
+            let lhs_type = lhs.as_ref().borrow().get_type();
+            let rhs_type = rhs.as_ref().borrow().get_type();
+            if op.is_bitwise() && (!is_bits(&lhs_type) || lhs_type != rhs_type) {
+                let location = lhs.as_ref().borrow().get_location();
+                return Some(Err((QccErrorKind::TypeMismatch, location).into()));
+            }
+            if op.is_comparison() && (lhs_type.is_quantum() || rhs_type.is_quantum()) {
+                let location = lhs.as_ref().borrow().get_location();
+                return Some(Err((QccErrorKind::TypeMismatch, location).into()));
+            }
+            if *op == Opcode::Mul {
+                if let Some(Err(kind)) = lhs_type.transform(&rhs_type) {
+                    let location = lhs.as_ref().borrow().get_location();
+                    return Some(Err((kind, location).into()));
+                }
+            }
             None
         }
 
         Expr::FnCall(ref mut f, ref args) => {
+            let builtin = Builtin::lookup(f.get_name());
+            // the basis of a measurement isn't a variable
+            let (args, basis) = match builtin {
+                Some(builtin) => builtin.split_basis(args),
+                None => (&args[..], None),
+            };
+            if let Some(basis) = basis {
+                let basis = basis.as_ref().borrow();
+                let is_basis =
+                    matches!(*basis, Expr::Var(ref var) if Basis::lookup(var.name()).is_some());
+                if !is_basis {
+                    let err = (QccErrorKind::InvalidBasis, basis.get_location());
+                    return Some(Err(err.into()));
+                }
+            }
+
             for arg in args {
                 let info = infer_from_table(arg, param_st, local_st, function_st);
                 if info.is_some() {
@@ -441,9 +1427,21 @@ fn infer_from_table(
                 }
             }
 
-            for func in function_st.iter() {
-                if func.name() == f.get_name() && func.is_typed() {
-                    f.set_output_type(func.get_type());
+            if let Some(builtin) = builtin {
+                let arg_types = args
+                    .iter()
+                    .map(|arg| arg.as_ref().borrow().get_type())
+                    .collect::<Vec<Type>>();
+                f.set_output_type(builtin.output_type_of(&arg_types));
+                return builtin
+                    .check_args(&arg_types)
+                    .err()
+                    .map(|kind| Err((kind, f.get_loc().clone()).into()));
+            }
+
+            if let Some(signature) = function_st.get(f.get_name()) {
+                if signature.ret != Type::Bottom {
+                    f.set_output_type(signature.ret);
                     return None;
                 }
             }
@@ -455,7 +1453,7 @@ fn infer_from_table(
                     f.get_loc().clone(),
                     Default::default(),
                     Default::default(),
-                    *f.get_output_type(),
+                    f.get_output_type().clone(),
                     Default::default(),
                     Default::default(),
                 ),
@@ -471,10 +1469,14 @@ fn infer_from_table(
             }
 
             let var_type = var.get_type();
-            let val_type = val.as_ref().borrow().get_type();
+            let val_type = val.as_ref().borrow().get_type_as(&var_type);
 
             if !var.is_typed() {
-                var.set_type(val.as_ref().borrow().get_type());
+                // there is no value to bind if it returns nothing, which
+                // leaves `var` untyped for `checker` to report
+                if val_type != Type::Unit {
+                    var.set_type(val_type);
+                }
                 None
             } else if (var_type == Type::Qbit || var_type == Type::Bit)
                 && (val_type == Type::Qbit || val_type == Type::Bit)
@@ -500,26 +1502,92 @@ fn infer_from_table(
                 // automatically puts required stub to create a logical qubit.
                 None
             } else if var_type != val_type {
-                // if one is qbit and other is bit, pass
-                Some(Err(QccErrorKind::TypeMismatch.into()))
+                let location = value_location(var, val);
+                Some(Err((QccErrorKind::TypeMismatch, location).into()))
+            } else if var_type.is_integer() && !fits(&val.as_ref().borrow(), &var_type) {
+                let location = value_location(var, val);
+                Some(Err((QccErrorKind::IntegerOverflow, location).into()))
             } else {
-                Some(Ok(Expr::Var(VarAST::new(
-                    var.name().clone(),
-                    var.location().clone(),
-                ))
-                .into()))
+                None
+            }
+        }
+        Expr::Literal(ref lit) => {
+            // A literal always carries its own type, so symbol tables have
+            // nothing more to offer, except for variables inside a tensor.
+            let LiteralAST::Lit_Tensor(ref elems, ref location) = *lit.as_ref().borrow() else {
+                return None;
+            };
+            for elem in elems {
+                let info = infer_from_table(elem, param_st, local_st, function_st);
+                if info.is_some() {
+                    return info;
+                }
+            }
+
+            let elem_types = elems
+                .iter()
+                .map(|elem| elem.as_ref().borrow().get_type())
+                .collect::<Vec<Type>>();
+            let location = location.clone();
+            match Type::tensor(&elem_types) {
+                Ok(Type::Bottom) => Some(Err((QccErrorKind::TypeMismatch, location).into())),
+                Ok(_) => None,
+                Err(kind) => Some(Err((kind, location).into())),
+            }
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            // the bit bound by `if let` is typed by `infer_expr` already
+            let binding = match *cond.as_ref().borrow() {
+                Expr::Let(_, ref val) => Some(val.clone()),
+                _ => None,
+            };
+            let cond_info = match binding {
+                Some(val) => infer_from_table(&val, param_st, local_st, function_st),
+                None => infer_from_table(cond, param_st, local_st, function_st),
+            };
+            if cond_info.is_some() {
+                return cond_info;
+            }
+
+            // a bit bound by `if let` is only seen by the truth block
+            let mut truth_st = local_st.clone();
+            if let Expr::Let(ref var, _) = *cond.as_ref().borrow() {
+                if var.is_typed() {
+                    truth_st.push(var.clone());
+                }
             }
+            infer_block(truth, param_st, truth_st, function_st)
+                .or_else(|| infer_block(otherwise, param_st, local_st.clone(), function_st))
         }
-        Expr::Literal(ref mut l) => {
-            // A literal is usually typed but if it isn't then it should follow
-            // based on what the context says.
-            match *l.as_ref().borrow() {
-                LiteralAST::Lit_Qbit(_) => todo!("perhaps a qubit"),
-                LiteralAST::Lit_Digit(_) => todo!("perhaps a digit"),
-                LiteralAST::Lit_Str(_) => todo!("perhaps a string"),
+        Expr::Loop(ref cond, ref body) => infer_from_table(cond, param_st, local_st, function_st)
+            .or_else(|| infer_block(body, param_st, local_st.clone(), function_st)),
+    }
+}
+
+/// Infers types of a block like the body of a function, with its own bindings
+/// in `local_st` on top of those outside of the block.
+fn infer_block(
+    block: &[QccCell<Expr>],
+    param_st: &SymbolTable<VarAST>,
+    mut local_st: SymbolTable<VarAST>,
+    function_st: &SignatureTable,
+) -> Option<core::result::Result<QccCell<Expr>, QccErrorLoc>> {
+    for expr in block {
+        type_gate_registers(expr, param_st, &local_st);
+        let inferred = infer_expr(expr);
+        if matches!(inferred, None | Some(Type::Bottom) | Some(Type::Unit)) {
+            let info = infer_from_table(expr, param_st, &local_st, function_st);
+            if info.is_some() {
+                return info;
+            }
+        }
+        if let Expr::Let(ref var, _) = *expr.as_ref().borrow() {
+            if var.is_typed() {
+                local_st.push(var.clone());
             }
         }
     }
+    None
 }
 
 /// Given an expression return a vector of all variable references irrespective
@@ -527,3 +1595,617 @@ fn infer_from_table(
 fn gather_all_vars(expr: &mut Box<Expr>) -> Vec<&mut VarAST> {
     vec![]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn check_nested_function() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/nested-fn.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        for module in &ast {
+            for function in &*module {
+                let nested = function.iter_nested().next().unwrap();
+                assert_eq!(nested.get_name(), "angle");
+                // `theta` is captured from `rotate`
+                assert_eq!(*nested.get_output_type(), Type::F64);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_strict_checker() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/strict.ql", "--strict"])?.unwrap();
+        let config = parser.get_config();
+        assert!(config.strict);
+
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        // `done` is left untyped, which inference tolerates ...
+        infer(&mut ast)?;
+        // ... but strict mode doesn't
+        assert_eq!(checker(&ast), Err(QccErrorKind::TypeError.into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_strict_corpus() -> Result<()> {
+        // every fixture which type checks passes the strict checker as well,
+        // apart from `strict.ql`, which is meant not to
+        let mut checked = 0;
+        for entry in std::fs::read_dir("tests")? {
+            let path = entry?.path();
+            let Some(src) = path.to_str().filter(|src| src.ends_with(".ql")) else {
+                continue;
+            };
+            if src.ends_with("strict.ql") {
+                continue;
+            }
+            let mut parser = Parser::new(vec![src, "-I", "tests/prelude"])?.unwrap();
+            let config = parser.get_config();
+            let Ok(mut ast) = parser.parse(&config.analyzer.src) else {
+                continue;
+            };
+            if infer_ast(&mut ast, &mut Diagnostics::default()).is_err() {
+                continue;
+            }
+            let mut errors = Diagnostics::default();
+            let strict = check_ast(&ast, &mut errors);
+            let notes = errors
+                .iter()
+                .map(|error| error.note())
+                .collect::<Vec<&str>>();
+            assert!(strict.is_ok(), "{}: {:?}", src, notes);
+            checked += 1;
+        }
+        assert!(checked > 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_unit_return() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/unit.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        for module in &ast {
+            for function in &*module {
+                assert_eq!(*function.get_output_type(), Type::Unit);
+            }
+        }
+        // nothing is left uninferred
+        checker(&ast)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_dump_symbols() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/test11.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        assert_eq!(
+            dump_symbols(&ast),
+            "module test11
+    fn test11::main (main) : float64
+        let x: float64
+        let y: float64
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_integer_types() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/integers.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        checker(&ast)?;
+
+        // `n + 3.0` is widened to a float
+        assert_eq!(
+            dump_symbols(&ast),
+            "module integers
+    fn integers::main (main) : float64
+        let n: int64
+        let m: int32
+        let k: int64
+        let x: float64
+"
+        );
+
+        let mut parser = Parser::new(vec!["tests/integers-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|err| err.is(QccErrorKind::TypeMismatch)));
+
+        // only values one past the bounds of their type overflow
+        let mut parser = Parser::new(vec!["tests/integers-overflow.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors
+            .iter()
+            .all(|err| err.is(QccErrorKind::IntegerOverflow)));
+        let rows = errors
+            .iter()
+            .map(|err| err.error().get_loc().borrow().row())
+            .collect::<Vec<usize>>();
+        assert_eq!(rows, [7, 8, 9, 10]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_gate_qargs() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/gate-qargs.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        // neither `b` nor `phi` is declared by the gate
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|err| err.is(QccErrorKind::UndeclaredQarg)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_generic_instance() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/generic.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let mut names = vec![];
+        for module in &ast {
+            for function in &*module {
+                names.push(function.get_name().clone());
+                if function.get_name() == "identity_3" {
+                    assert_eq!(*function.get_input_type(), vec![Type::Qreg(3)]);
+                    assert_eq!(*function.get_output_type(), Type::Qreg(3));
+                }
+            }
+        }
+        // only instances are left of generic functions
+        assert_eq!(names, vec!["main", "flip_3", "identity_3"]);
+
+        let mut parser = Parser::new(vec!["tests/generic-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors.iter().any(|err| err.is(QccErrorKind::TypeMismatch)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_bitwise_types() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/bits-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        // floats and qubits aren't bits
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|err| err.is(QccErrorKind::TypeMismatch)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_measure_register_size() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/measure-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        assert_eq!(infer(&mut ast), Err(QccErrorKind::TypeError.into()));
+
+        let mut parser = Parser::new(vec!["tests/measure.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_measure_basis() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/measure-basis-invalid.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors.iter().all(|err| err.is(QccErrorKind::InvalidBasis)));
+
+        let mut parser = Parser::new(vec!["tests/measure-basis.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_error_location() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/mismatch-location.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        let mismatch = errors
+            .iter()
+            .next()
+            .filter(|err| err.is(QccErrorKind::TypeMismatch))
+            .unwrap();
+        // `q` in `let x: f64 = q;`
+        let location = mismatch.error().get_loc();
+        assert_eq!((location.borrow().row(), location.borrow().col()), (3, 18));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_deterministic_errors() -> Result<()> {
+        let errors = || -> Result<Diagnostics> {
+            let mut parser = Parser::new(vec!["tests/many-errors.ql"])?.unwrap();
+            let config = parser.get_config();
+            let mut ast = parser.parse(&config.analyzer.src)?;
+            let mut errors = Diagnostics::default();
+            assert!(infer_ast(&mut ast, &mut errors).is_err());
+            Ok(errors)
+        };
+        let printed = |errors: Diagnostics| {
+            errors
+                .iter()
+                .map(|err| format!("{} {}", err.error(), err.note()))
+                .collect::<Vec<String>>()
+        };
+
+        // the last expression of `main` isn't compared with its return type
+        // once it is reported
+        let first = errors()?;
+        assert_eq!(first.len(), 4);
+        let rows = first
+            .iter()
+            .map(|err| err.error().get_loc().borrow().row())
+            .collect::<Vec<usize>>();
+        assert_eq!(rows, [3, 4, 5, 6]);
+        assert_eq!(printed(first), printed(errors()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_collected_diagnostics() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/mismatch-location.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        assert!(parser.take_diagnostics().is_empty());
+
+        // errors are kept along with what they are about, to be printed by
+        // whoever asked for inference
+        let mut diagnostics = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut diagnostics).is_err());
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics.iter().all(|err| !err.note().is_empty()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_demangled_diagnostics() -> Result<()> {
+        let mut parser =
+            Parser::new(vec!["-I", "tests/prelude", "tests/import-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut diagnostics = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut diagnostics).is_err());
+
+        // the call is still mangled in the program itself
+        let notes = diagnostics
+            .iter()
+            .map(|error| error.note())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            notes,
+            ["between\n\t`flip (in std): qubit (q: qubit)` (qubit) and `main` (bit)"]
+        );
+        let main = (&ast)
+            .into_iter()
+            .find(|module| module.get_name() != "std")
+            .unwrap();
+        let mut callees = vec![];
+        crate::optimizer::passes::gather_callees(&main.into_iter().next().unwrap(), &mut callees);
+        assert!(callees.contains(&"std::flip".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_signatures() -> Result<()> {
+        let table = SignatureTable::default();
+        let u = table.get("U").unwrap();
+        assert_eq!(u.params, [Type::F64, Type::F64, Type::F64, Type::Qbit]);
+        assert_eq!(u.ret, Type::Qbit);
+
+        let mut parser = Parser::new(vec!["tests/arity.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        for module in &ast {
+            let scale = module.into_iter().next().unwrap();
+            assert_eq!(
+                scale.signature().to_string(),
+                "(float64, float64) -> float64"
+            );
+        }
+
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert_eq!(errors.iter().count(), 2);
+        assert!(errors.iter().all(|err| err.is(QccErrorKind::ArityMismatch)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_qubit_tensor() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/qubit-tensor.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let types = ast
+            .into_iter()
+            .flat_map(|module| {
+                let function = module.into_iter().next().unwrap();
+                function
+                    .into_iter()
+                    .map(|expr| expr.as_ref().borrow().get_type())
+                    .collect::<Vec<Type>>()
+            })
+            .collect::<Vec<Type>>();
+        // a tensor of qubits is a register
+        assert_eq!(
+            types,
+            [Type::Qreg(2), Type::Tensor(Box::new(Type::F64), vec![2])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_tensor_shapes() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/tensor-shape.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        let ragged = errors
+            .iter()
+            .find(|err| err.is(QccErrorKind::ShapeMismatch))
+            .unwrap();
+        assert_eq!(
+            ragged.error().get_loc().borrow().to_string(),
+            "@tensor-shape.ql:3:18"
+        );
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let square = main.into_iter().next().unwrap();
+        assert_eq!(
+            square.as_ref().borrow().get_type(),
+            Type::Tensor(Box::new(Type::F64), vec![2, 2])
+        );
+        assert_eq!(
+            square.as_ref().borrow().get_type().to_string(),
+            "float64[2][2]"
+        );
+
+        // an empty tensor is fine, tensors of them may still be ragged
+        let mut parser = Parser::new(vec!["tests/nested-tensor.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        let ragged = errors
+            .iter()
+            .filter(|err| err.is(QccErrorKind::ShapeMismatch))
+            .map(|err| err.error().get_loc().borrow().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(ragged, ["@nested-tensor.ql:2:13", "@nested-tensor.ql:4:18"]);
+        assert!(errors.iter().all(|err| !err.note().contains("empty")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_unused_variable() -> Result<()> {
+        use crate::diagnostics::Severity;
+        use crate::session::check_source;
+
+        // warnings don't stop inference
+        let unused = |path| -> Result<Vec<String>> {
+            let mut parser = Parser::new(vec![path])?.unwrap();
+            let config = parser.get_config();
+            let mut diagnostics = Diagnostics::default();
+            check_source(&mut parser, &config, &mut diagnostics)?;
+            assert_eq!(diagnostics.error_count(), 0);
+            assert!(diagnostics
+                .iter()
+                .all(|warning| warning.severity() == Severity::Warning
+                    && warning.is(QccErrorKind::UnusedVariable)));
+            Ok(diagnostics
+                .iter()
+                .map(|warning| warning.note().to_string())
+                .collect())
+        };
+        assert_eq!(unused("tests/unused.ql")?, ["`tmp`"]);
+        assert_eq!(
+            unused("tests/dead-store.ql")?,
+            ["`angle`", "`r`", "`unused`"]
+        );
+
+        // bindings in blocks are read in the rest of the block, or after it
+        // if they rebind a name of the enclosing block, or by the next
+        // iteration of a loop
+        assert_eq!(unused("tests/unused-block.ql")?, ["`t`", "`s`"]);
+        assert!(unused("tests/if-else.ql")?.is_empty());
+        assert!(unused("tests/if-let.ql")?.is_empty());
+
+        let mut parser = Parser::new(vec!["tests/dead-store.ql", "-Werror"])?.unwrap();
+        let config = parser.get_config();
+        let mut diagnostics = Diagnostics::default();
+        match check_source(&mut parser, &config, &mut diagnostics) {
+            Ok(_) => unreachable!(),
+            Err(err) => assert!(err.is(QccErrorKind::DeniedWarning)),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_matrix_types() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/matrix.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let flip = module.into_iter().next().unwrap();
+        let types = flip
+            .into_iter()
+            .map(|expr| expr.as_ref().borrow().get_type())
+            .collect::<Vec<Type>>();
+        let matrix = Type::Tensor(Box::new(Type::F64), vec![2, 2]);
+        assert_eq!(types, [matrix, Type::Qbit, Type::Qbit]);
+
+        // a 3x3 matrix doesn't transform a single qubit
+        let mut parser = Parser::new(vec!["tests/matrix-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors
+            .iter()
+            .next()
+            .unwrap()
+            .is(QccErrorKind::ShapeMismatch));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_comparison_types() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/comparisons.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let body = main.into_iter().collect::<Vec<_>>();
+        let less = body[0].as_ref().borrow();
+        let Expr::Let(_, ref val) = *less else {
+            panic!("expected a let binding");
+        };
+        assert_eq!(val.as_ref().borrow().to_string(), "(1 < 2)");
+        assert_eq!(val.as_ref().borrow().get_type(), Type::Bit);
+        assert!(body
+            .iter()
+            .all(|expr| expr.as_ref().borrow().get_type() == Type::Bit));
+
+        // qubits can't be compared without measuring them
+        let src = "fn main(q: qbit, r: qbit) : bit {\n    return (q == r);\n}\n";
+        assert!(crate::session::parse_and_infer(src, "cmp.ql", &Default::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_register_index() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/register-index.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // `0x10` is past the last of 16 qubits
+        let mut parser = Parser::new(vec!["tests/register-index-bounds.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert_eq!(errors.len(), 1);
+        let error = errors.iter().next().unwrap();
+        assert!(error.is(QccErrorKind::IndexOutOfBounds));
+        assert_eq!(error.note(), "in `h(q[16])`");
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_freed_qubits() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/alloc.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // `a` is freed for sure and `b` may be, the error is at each use
+        let mut parser = Parser::new(vec!["tests/freed.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        let uses = errors
+            .iter()
+            .map(|error| {
+                assert!(error.is(QccErrorKind::FreedQubit));
+                let loc = error.error().get_loc();
+                let loc = loc.borrow();
+                (loc.row(), loc.col())
+            })
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(uses, [(6, 7), (11, 7)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_imaginary_types() -> Result<()> {
+        use crate::session::parse_and_infer;
+
+        // imaginary literals are complex, and so is arithmetic with them
+        let src = "fn main() {\n    let z = 1.0 + 2i;\n    let _w = z * 2;\n}\n";
+        let ast = parse_and_infer(src, "complex.ql", &Default::default())?;
+        assert!(dump_symbols(&ast).contains("let z: complex\n        let _w: complex\n"));
+
+        // angles are real
+        let src = "fn main(q: qbit) : qbit {\n    return rz(2i, q);\n}\n";
+        let mut ast = Parser::from_source(src, Default::default()).parse(&"rz.ql".into())?;
+        let mut errors = Diagnostics::default();
+        assert!(infer_ast(&mut ast, &mut errors).is_err());
+        assert!(errors.iter().all(|err| err.is(QccErrorKind::TypeMismatch)));
+
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 //! Lexer for qcc
 use crate::ast::Token;
-use crate::error::{QccErrorKind, Result};
+use crate::error::{QccErrorKind, QccErrorLoc, Result};
 
 use std::fmt;
 
@@ -9,6 +9,8 @@ pub(crate) struct Location {
     path: String, // TODO: immutable std::rc::Rc<>
     row: usize,
     col: usize,
+    /// row and column a range of tokens ends at, see `Location::merge`
+    end: Option<(usize, usize)>,
 }
 
 impl Location {
@@ -17,6 +19,7 @@ impl Location {
             path: path.into(),
             row,
             col,
+            end: None,
         }
     }
 
@@ -33,6 +36,32 @@ impl Location {
     pub(crate) fn col(&self) -> usize {
         self.col
     }
+
+    /// Row and column the location ends at, which is where it starts unless
+    /// it spans several tokens.
+    pub(crate) fn end(&self) -> (usize, usize) {
+        self.end.unwrap_or((self.row, self.col))
+    }
+
+    /// Returns the range enclosing both locations. Locations which aren't
+    /// known, e.g. of literals, or are in another file don't extend it.
+    pub(crate) fn merge(&self, other: &Location) -> Location {
+        if other.row == 0 || other.path != self.path {
+            return self.clone();
+        }
+        if self.row == 0 {
+            return other.clone();
+        }
+
+        let start = (self.row, self.col).min((other.row, other.col));
+        let end = self.end().max(other.end());
+        Self {
+            path: self.path.clone(),
+            row: start.0,
+            col: start.1,
+            end: (end != start).then_some(end),
+        }
+    }
 }
 
 impl Default for Location {
@@ -41,6 +70,7 @@ impl Default for Location {
             path: "unknown".into(),
             row: 0,
             col: 0,
+            end: None,
         }
     }
 }
@@ -58,6 +88,7 @@ impl fmt::Debug for Location {
             .field("path", &self.path)
             .field("row", &self.row)
             .field("col", &self.col)
+            .field("end", &self.end)
             .finish()
     }
 }
@@ -134,6 +165,10 @@ pub(crate) struct Lexer {
     pub(crate) location: Location,
     /// stores current token
     pub(crate) token: Option<Token>,
+    /// comments seen so far with where they start, kept only if asked for
+    comments: Option<Vec<(Location, String)>>,
+    /// operators defined by functions, see `Attribute::Operator`
+    operators: Vec<String>,
 }
 
 impl Lexer {
@@ -141,15 +176,49 @@ impl Lexer {
         Self {
             buffer,
             ptr: Pointer::new(),
-            location: Location {
-                path: path.to_string(),
-                row: 0,
-                col: 0,
-            },
+            location: Location::new(&path, 0, 0),
             token: None,
+            comments: None,
+            operators: Default::default(),
         }
     }
 
+    /// Lexes `op` as a single `Token::Operator` from now on.
+    pub(crate) fn add_operator(&mut self, op: String) {
+        if !self.operators.contains(&op) {
+            self.operators.push(op);
+        }
+    }
+
+    /// Keeps comments lexed from now on instead of discarding them.
+    pub(crate) fn retain_comments(&mut self) {
+        self.comments.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns the retained comments in source order, each starting at `//`.
+    pub(crate) fn comments(&self) -> &[(Location, String)] {
+        self.comments.as_deref().unwrap_or_default()
+    }
+
+    /// Retains the comment starting at `start` and running to the end of the
+    /// current line.
+    fn retain_comment(&mut self, start: usize) {
+        let Some(ref mut comments) = self.comments else {
+            return;
+        };
+        let line_start = self.buffer[..start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let text = String::from_utf8_lossy(&self.buffer[start..self.ptr.end]);
+        let location = Location::new(
+            &self.location.path(),
+            self.location.row,
+            start - line_start + 1,
+        );
+        comments.push((location, text.trim_end().into()));
+    }
+
     /// Returns a string in `buffer` given valid indices. This is prone to panic
     /// if out of range indices are provided.
     pub(crate) fn slice(&self, lhs: usize, rhs: usize) -> String {
@@ -165,23 +234,70 @@ impl Lexer {
         self.buffer[self.ptr.prev]
     }
 
-    /// Returns the current pointing character in buffer.
-    fn current(&self) -> u8 {
-        self.buffer[self.ptr.current]
+    /// Returns the current pointing character in buffer. Reaching past the end
+    /// of buffer means the source ended in the middle of a token.
+    fn current(&self) -> Result<u8> {
+        match self.buffer.get(self.ptr.current) {
+            Some(&byte) => Ok(byte),
+            None => Err(QccErrorKind::LexerError)?,
+        }
     }
 
-    /// Returns the digit as a string after trimming whitespaces.
+    /// Returns the digit as a string after trimming whitespaces. The suffix of
+    /// an imaginary number is dropped, a hexadecimal integer is prefixed with
+    /// `0x`.
     pub(crate) fn digit(&self) -> Option<f64> {
-        let digit = self.identifier().replace(" ", "").parse::<f64>();
+        let identifier = self.identifier();
+        if let Some(hex) = identifier
+            .strip_prefix("0x")
+            .or_else(|| identifier.strip_prefix("0X"))
+        {
+            return i64::from_str_radix(hex, 16).ok().map(|hex| hex as f64);
+        }
+
+        let digit = self
+            .identifier()
+            .replace(" ", "")
+            .trim_end_matches('i')
+            .parse::<f64>();
         if digit.is_err() {
             return None;
         }
         Some(digit.unwrap())
     }
 
+    /// Returns the current digit as an integer, if it is written as one. Unlike
+    /// `digit`, it is exact for integers too large for a float to hold.
+    pub(crate) fn integer(&self) -> Option<i64> {
+        let identifier = self.identifier();
+        match identifier
+            .strip_prefix("0x")
+            .or_else(|| identifier.strip_prefix("0X"))
+        {
+            Some(hex) => i64::from_str_radix(hex, 16).ok(),
+            None => identifier.parse::<i64>().ok(),
+        }
+    }
+
     /// Returns current identifier contained in `self.prev` and `self.current`.
+    /// The `r#` prefix of a raw identifier is stripped.
     pub(crate) fn identifier(&self) -> String {
-        self.slice(self.ptr.prev, self.ptr.current)
+        let identifier = self.slice(self.ptr.prev, self.ptr.current);
+        match identifier.strip_prefix("r#") {
+            Some(raw) => raw.into(),
+            None => identifier,
+        }
+    }
+
+    /// Checks if the buffer continues with a raw identifier, i.e. `#` and the
+    /// start of an identifier, right after a lexed `r`.
+    fn is_raw_identifier(&self) -> bool {
+        let rest = &self.buffer[self.ptr.current..];
+        self.slice(self.ptr.prev, self.ptr.current) == "r"
+            && rest.first() == Some(&b'#')
+            && rest
+                .get(1)
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
     }
 
     /// Utility function to dump vector of bytes in string format.
@@ -203,9 +319,14 @@ impl Lexer {
         println!();
     }
 
-    /// Returns the entire line which is being parsed at the moment.
+    /// Returns the entire line which is being parsed at the moment, including
+    /// its indentation so that columns of `self.location` index into it.
     pub(crate) fn line(&self) -> String {
-        self.slice(self.ptr.start, self.ptr.end)
+        let line_start = self.buffer[..self.ptr.start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |newline| newline + 1);
+        self.slice(line_start, self.ptr.end)
     }
 
     #[inline]
@@ -245,7 +366,9 @@ impl Lexer {
     /// `self.line` is returned.
     pub(crate) fn next_token(&mut self) -> Result<Option<Token>> {
         // Skip all leading whitespaces and trailing newlines.
-        while self.buffer[self.ptr.current].is_ascii_whitespace() {
+        while self.ptr.current < self.buffer.len()
+            && self.buffer[self.ptr.current].is_ascii_whitespace()
+        {
             self.ptr.current += 1;
             self.location.col += 1;
 
@@ -265,11 +388,19 @@ impl Lexer {
             }
         }
 
-        while self.ptr.current >= self.ptr.end
+        if self.ptr.current >= self.ptr.end
             || self.buffer[self.ptr.start..].starts_with(&['/' as u8, '/' as u8])
             || self.buffer[self.ptr.range()] == ['\n' as u8]
             || self.buffer[self.ptr.current..].starts_with(&['/' as u8, '/' as u8])
         {
+            if self.ptr.current < self.ptr.end {
+                if self.buffer[self.ptr.current..].starts_with(b"//") {
+                    self.retain_comment(self.ptr.current);
+                } else if self.buffer[self.ptr.start..].starts_with(b"//") {
+                    self.retain_comment(self.ptr.start);
+                }
+            }
+
             // TODO: FromResidual trait impl (but nightly) to use ?
             // TODO: == None blob should be rechecked because bug was present
             // because of no return of self.next_token after a new line was
@@ -292,7 +423,37 @@ impl Lexer {
 
         self.ptr = self.ptr.reset();
 
-        let single_token = match self.current().into() {
+        // a string runs up to the next unescaped quote on the same line
+        if self.current()? == b'"' {
+            self.ptr.current += 1;
+            loop {
+                match self.current()? {
+                    b'"' => break,
+                    b'\\' => self.ptr.current += 2,
+                    b'\n' => Err(QccErrorKind::LexerError)?,
+                    _ => self.ptr.current += 1,
+                }
+            }
+            self.ptr.current += 1;
+            self.token = Some(Token::Literal);
+            return Ok(self.token);
+        }
+
+        // the longest operator defined by a function wins over builtin ones
+        let rest = &self.buffer[self.ptr.current..];
+        let operator = self
+            .operators
+            .iter()
+            .filter(|op| rest.starts_with(op.as_bytes()))
+            .map(String::len)
+            .max();
+        if let Some(len) = operator {
+            self.ptr.current += len;
+            self.token = Some(Token::Operator);
+            return Ok(self.token);
+        }
+
+        let single_token = match self.current()?.into() {
             '#' => Token::Hash,
             '[' => Token::OBracket,
             ']' => Token::CBracket,
@@ -309,6 +470,13 @@ impl Lexer {
             '-' => Token::Sub,
             '*' => Token::Mul,
             '/' => Token::Div,
+            '&' => Token::BitAnd,
+            '|' => Token::BitOr,
+            '^' => Token::BitXor,
+            '~' => Token::Tilde,
+            '@' => Token::At,
+            '<' => Token::Lt,
+            '>' => Token::Gt,
             _ => Token::Multi,
         };
 
@@ -317,24 +485,106 @@ impl Lexer {
             self.ptr.current += 1;
             self.token = Some(single_token);
 
+            if !matches!(
+                single_token,
+                Token::Sub | Token::Assign | Token::Bang | Token::Lt | Token::Gt
+            ) {
+                return Ok(self.token);
+            }
+
+            // `=` and `!` stand alone unless `=` follows right after them
+            let double_token = match (single_token, self.current()?.into()) {
+                (Token::Sub, '>') => Some(Token::Arrow),
+                (Token::Assign, '=') => Some(Token::Eq),
+                (Token::Bang, '=') => Some(Token::Neq),
+                (Token::Lt, '=') => Some(Token::Le),
+                (Token::Gt, '=') => Some(Token::Ge),
+                _ => None,
+            };
+            if double_token.is_some() {
+                self.ptr.current += 1;
+                self.token = double_token;
+                return Ok(self.token);
+            }
+
+            // a minus may be the sign of a digit
             if single_token != Token::Sub {
                 return Ok(self.token);
             }
         }
 
-        if self.current().is_ascii_digit() {
+        if self.current()?.is_ascii_digit() {
             self.ptr.current += 1;
 
             // lexing quantum bit
-            if self.current() == 'q' as u8 {
-                while self.current() != ')' as u8 {
+            if self.current()? == 'q' as u8 {
+                self.ptr.current += 1;
+
+                // basis states `0q0` and `0q1` are a single token
+                if matches!(self.current()?, b'0' | b'1') {
+                    let next = self.buffer.get(self.ptr.current + 1);
+                    let ends = next.map_or(true, |c| !c.is_ascii_alphanumeric() && *c != b'_');
+                    if ends && next != Some(&b'.') {
+                        self.ptr.current += 1;
+                        self.token = Some(Token::Qbit);
+                        return Ok(self.token);
+                    }
+                }
+
+                // Only `0q` is the token, amplitudes are lexed as expressions.
+                // But the literal must still be closed within the buffer.
+                let amplitudes = self.ptr.current;
+                let mut depth = 0;
+                loop {
+                    match self.current()? {
+                        b'(' => depth += 1,
+                        b')' if depth <= 1 => break,
+                        b')' => depth -= 1,
+                        _ => {}
+                    }
                     self.ptr.current += 1;
                 }
+                self.ptr.current = amplitudes;
+                self.token = Some(Token::Qbit);
+                return Ok(self.token);
+            }
+
+            // hexadecimal integers like `0xF` must have digits after `0x`
+            let leading_zero = self.buffer[self.ptr.prev] == b'0';
+            if leading_zero && matches!(self.current()?, b'x' | b'X') {
                 self.ptr.current += 1;
-                return Ok(Some(Token::Qbit));
+                if !self.current()?.is_ascii_hexdigit() {
+                    Err(QccErrorKind::UnexpectedDigit)?
+                }
+                while self.current()?.is_ascii_hexdigit() {
+                    self.ptr.current += 1;
+                }
+                // integers are 64 bits wide
+                if self.digit().is_none() {
+                    Err(QccErrorKind::IntegerOverflow)?
+                }
+                self.token = Some(Token::Digit);
+                return Ok(self.token);
             }
 
-            while self.current().is_ascii_digit() || self.current() == '.' as u8 {
+            while self.current()?.is_ascii_digit() || self.current()? == '.' as u8 {
+                self.ptr.current += 1;
+            }
+            // an exponent like `e-9` must have digits after its sign
+            if matches!(self.current()?, b'e' | b'E') {
+                self.ptr.current += 1;
+                if matches!(self.current()?, b'+' | b'-') {
+                    self.ptr.current += 1;
+                }
+                if !self.current()?.is_ascii_digit() {
+                    Err(QccErrorKind::UnexpectedDigit)?
+                }
+                while self.current()?.is_ascii_digit() {
+                    self.ptr.current += 1;
+                }
+            }
+            // imaginary numbers are suffixed with `i`
+            if self.current()? == 'i' as u8 {
                 self.ptr.current += 1;
             }
             self.token = Some(Token::Digit);
@@ -346,12 +596,16 @@ impl Lexer {
             return Ok(self.token);
         }
 
-        if self.current().is_ascii_alphanumeric() || self.current() == '_' as u8 {
+        if self.current()?.is_ascii_alphanumeric() || self.current()? == '_' as u8 {
             self.ptr.current += 1;
-            while self.current().is_ascii_alphanumeric() || self.current() == '_' as u8 {
+            if self.is_raw_identifier() {
                 self.ptr.current += 1;
             }
-            self.token = match self.identifier().as_str() {
+            while self.current()?.is_ascii_alphanumeric() || self.current()? == '_' as u8 {
+                self.ptr.current += 1;
+            }
+            // raw identifiers like `r#let` are never keywords
+            self.token = match self.slice(self.ptr.prev, self.ptr.current).as_str() {
                 "fn" => Some(Token::Function),
                 "return" => Some(Token::Return),
                 "const" => Some(Token::Const),
@@ -359,15 +613,22 @@ impl Lexer {
                 "module" => Some(Token::Module),
                 "let" => Some(Token::Let),
                 "import" => Some(Token::Import),
+                "measure" => Some(Token::Measure),
+                "alias" => Some(Token::Alias),
+                "gate" => Some(Token::Gate),
+                "if" => Some(Token::If),
+                "else" => Some(Token::Else),
+                "use" => Some(Token::Use),
+                "while" => Some(Token::While),
+                "pub" => Some(Token::Pub),
                 _ => Some(Token::Identifier),
             };
             return Ok(self.token);
         }
 
-        self.ptr.current += 1;
-        self.token = Some(Token::Identifier);
-
-        Ok(self.token)
+        // any other character doesn't start a token, `self.location` is left
+        // pointing at it
+        Err(QccErrorKind::LexerError)?
     }
 
     /// Get the current token.
@@ -378,20 +639,19 @@ impl Lexer {
         Ok(self.next_token()?)
     }
 
-    /// Consumes last set token and moves onto the next token in buffer.
-    pub(crate) fn consume(&mut self, token: Token) -> Result<()> {
-        // TODO: use Lexer::is_token?
+    /// Consumes last set token and moves onto the next token in buffer. It is a
+    /// parse error at the last set token if it isn't what the caller expects,
+    /// and the token is left unconsumed so that the caller can recover.
+    pub(crate) fn consume(&mut self, token: Token) -> core::result::Result<(), QccErrorLoc> {
         if let Some(last_token) = &self.token {
-            assert_eq!(
-                token, *last_token,
-                "
-Internal Compiler Error: Lexer failed {}
-Please report this bug to {}",
-                self.location, "https://github.com/quale-lang/quale/issues"
-            );
+            if token != *last_token {
+                Err((QccErrorKind::ParseError, self.location.clone()))?
+            }
             self.location.col += self.ptr.current - self.ptr.prev;
             self.ptr = self.ptr.reset();
-            self.token = self.next_token()?;
+            self.token = self
+                .next_token()
+                .map_err(|err| (err, self.location.clone()))?;
         }
         Ok(())
     }
@@ -406,7 +666,10 @@ Please report this bug to {}",
 
         while self.buffer[self.ptr.end] != '\n' as u8 {
             if self.ptr.end == self.buffer.len() - 1 {
+                // the last line isn't terminated by a newline
+                self.ptr.end += 1;
                 self.location.row += 1;
+                self.location.col = 1;
 
                 return Some(());
             }
@@ -424,3 +687,220 @@ Please report this bug to {}",
         Some(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::QccError;
+
+    /// Lexes the whole source, returning the first error seen.
+    fn lex(src: &str) -> Result<Vec<Token>> {
+        let mut lexer = Lexer::new(src.as_bytes().to_vec(), "test.ql".into());
+        let mut tokens = vec![];
+        while let Some(token) = lexer.next_token()? {
+            tokens.push(token);
+            lexer.consume(token)?;
+            if lexer.token.is_none() {
+                break;
+            }
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn check_truncated_source() {
+        let lexer_error = Err(QccError(QccErrorKind::LexerError));
+
+        assert_eq!(lex("let q = 0q(1,"), lexer_error);
+        assert_eq!(lex("let q = 0q"), lexer_error);
+        assert_eq!(lex("let x = 1."), lexer_error);
+        assert_eq!(lex("let x = 42"), lexer_error);
+        assert_eq!(lex("fn ma"), lexer_error);
+
+        assert!(lex("let x = 42;\n").is_ok());
+        assert!(lex("let q = 0q(1, 0);").is_ok());
+        assert!(lex("let q = 0q1;").is_ok());
+    }
+
+    #[test]
+    fn check_unknown_character() {
+        let mut lexer = Lexer::new(b"let x = 1 % 2;\n".to_vec(), "test.ql".into());
+        let mut result = lexer.next_token();
+        while let Ok(Some(token)) = result {
+            result = lexer
+                .consume(token)
+                .map(|_| lexer.token)
+                .map_err(QccError::from);
+        }
+        assert_eq!(result, Err(QccError(QccErrorKind::LexerError)));
+        assert_eq!((lexer.location.row(), lexer.location.col()), (1, 11));
+
+        assert_eq!(
+            lex("let q$ = 0q(1, 0);"),
+            Err(QccError(QccErrorKind::LexerError))
+        );
+    }
+
+    #[test]
+    fn check_tabbed_caret() {
+        use crate::error::QccErrorLoc;
+
+        // columns count bytes from the start of the line, tabs included, on
+        // the last line too
+        for src in [
+            "fn main() {\n\t\treturn f(q, $);\n}\n",
+            "fn main() {\n\t\treturn f(q, $);",
+        ] {
+            let mut lexer = Lexer::new(src.as_bytes().to_vec(), "test.ql".into());
+            let mut result = lexer.next_token();
+            while let Ok(Some(token)) = result {
+                result = lexer
+                    .consume(token)
+                    .map(|_| lexer.token)
+                    .map_err(QccError::from);
+            }
+            assert_eq!(result, Err(QccError(QccErrorKind::LexerError)));
+            assert_eq!((lexer.location.row(), lexer.location.col()), (2, 15));
+
+            let err = QccErrorLoc::new(QccErrorKind::LexerError, lexer.location.clone());
+            let annotated = err.annotate(&lexer.line());
+            let lines = annotated.lines().collect::<Vec<&str>>();
+            assert_eq!(
+                lines,
+                ["\t2\t\t\treturn f(q, $);", "\t \t\t\t            ^"]
+            );
+        }
+    }
+
+    #[test]
+    fn check_string_literal() -> Result<()> {
+        use Token::*;
+        let src = b"let s = \"a \\\"b\\\" // c\";\n";
+        let mut lexer = Lexer::new(src.to_vec(), "test.ql".into());
+        let mut tokens = vec![];
+        let mut token = lexer.next_token()?;
+        while let Some(next) = token {
+            tokens.push(next);
+            if next == Literal {
+                assert_eq!(lexer.identifier(), r#""a \"b\" // c""#);
+            }
+            lexer.consume(next)?;
+            token = lexer.token;
+        }
+        assert_eq!(tokens, [Let, Identifier, Assign, Literal, Semicolon]);
+
+        let lexer_error = Err(QccError(QccErrorKind::LexerError));
+        assert_eq!(lex("let s = \"ab;\nfn"), lexer_error);
+        assert_eq!(lex("let s = \"ab\\\";"), lexer_error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_raw_identifier() -> Result<()> {
+        use Token::*;
+        let mut lexer = Lexer::new(b"let r#let = r#fn;\n".to_vec(), "test.ql".into());
+        let mut tokens = vec![];
+        let mut token = lexer.next_token()?;
+        while let Some(next) = token {
+            tokens.push(next);
+            lexer.consume(next)?;
+            token = lexer.token;
+        }
+        assert_eq!(tokens, [Let, Identifier, Assign, Identifier, Semicolon]);
+
+        let mut lexer = Lexer::new(b"r#module(r)\n".to_vec(), "test.ql".into());
+        assert_eq!(lexer.next_token()?, Some(Identifier));
+        assert_eq!(lexer.identifier(), "module");
+        lexer.consume(Identifier)?;
+        lexer.consume(OParenth)?;
+        assert_eq!(lexer.identifier(), "r");
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_comparison_operators() -> Result<()> {
+        use Token::*;
+        let tokens = |src: &str| -> Result<Vec<Token>> {
+            let mut lexer = Lexer::new(format!("{}\n", src).into(), "test.ql".into());
+            let mut tokens = vec![];
+            let mut token = lexer.next_token()?;
+            while let Some(next) = token {
+                tokens.push(next);
+                lexer.consume(next)?;
+                token = lexer.token;
+            }
+            Ok(tokens)
+        };
+        assert_eq!(tokens("a == b")?, [Identifier, Eq, Identifier]);
+        assert_eq!(tokens("a != b")?, [Identifier, Neq, Identifier]);
+        assert_eq!(tokens("a <= b")?, [Identifier, Le, Identifier]);
+        assert_eq!(tokens("a >= b")?, [Identifier, Ge, Identifier]);
+        assert_eq!(tokens("a < b")?, [Identifier, Lt, Identifier]);
+
+        // an assignment and a bang are single characters when apart
+        assert_eq!(tokens("a = = b")?, [Identifier, Assign, Assign, Identifier]);
+        assert_eq!(tokens("! =")?, [Bang, Assign]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_exponent_digits() -> Result<()> {
+        for (src, expected) in [("1e-9", 1e-9), ("2.5E+3", 2500.0), ("6.022e23", 6.022e23)] {
+            let mut lexer = Lexer::new(format!("{};\n", src).into(), "test.ql".into());
+            assert_eq!(lexer.next_token()?, Some(Token::Digit));
+            assert_eq!(lexer.digit(), Some(expected));
+            lexer.consume(Token::Digit)?;
+            assert_eq!(lexer.token, Some(Token::Semicolon));
+        }
+
+        let unexpected_digit = Err(QccError(QccErrorKind::UnexpectedDigit));
+        assert_eq!(lex("let x = 1e;\n"), unexpected_digit);
+        assert_eq!(lex("let x = 1e+;\n"), unexpected_digit);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_hex_digits() -> Result<()> {
+        for (src, expected) in [("0xF", 15.0), ("0x10", 16.0), ("0XfF", 255.0)] {
+            let mut lexer = Lexer::new(format!("{};\n", src).into(), "test.ql".into());
+            assert_eq!(lexer.next_token()?, Some(Token::Digit));
+            assert_eq!(lexer.digit(), Some(expected));
+            lexer.consume(Token::Digit)?;
+            assert_eq!(lexer.token, Some(Token::Semicolon));
+        }
+
+        let unexpected_digit = Err(QccError(QccErrorKind::UnexpectedDigit));
+        assert_eq!(lex("let x = 0x;\n"), unexpected_digit);
+        assert_eq!(lex("let x = 0xG;\n"), unexpected_digit);
+        assert_eq!(
+            lex("let x = 0xFFFFFFFFFFFFFFFFF;\n"),
+            Err(QccError(QccErrorKind::IntegerOverflow))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_consume_mismatch() -> Result<()> {
+        let mut lexer = Lexer::new(b"let x = 1;\n".to_vec(), "test.ql".into());
+        lexer.next_token()?;
+
+        let err = lexer.consume(Token::Function).unwrap_err();
+        assert!(err.get_error().is(QccErrorKind::ParseError));
+        // mismatched token is still available to recover from
+        assert!(lexer.is_token(Token::Let));
+        lexer.consume(Token::Let)?;
+        assert!(lexer.is_token(Token::Identifier));
+
+        // the error is at the mismatched token
+        let err = lexer.consume(Token::Digit).unwrap_err();
+        let location = err.get_loc();
+        assert_eq!((location.borrow().row(), location.borrow().col()), (1, 5));
+
+        Ok(())
+    }
+}
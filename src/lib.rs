@@ -3,12 +3,23 @@
 mod analyzer;
 mod ast;
 mod attributes;
+mod builtins;
 pub mod codegen;
+mod complex;
 mod config;
+mod diagnostics;
 pub mod error;
+mod explain;
 pub mod inference;
 mod lexer;
 mod optimizer;
 pub mod parser;
+mod session;
+mod simulator;
 mod types;
 mod utils;
+mod verify;
+
+pub use ast::Qast;
+pub use config::Config;
+pub use session::{compile_source, parse_and_infer};
@@ -4,20 +4,32 @@
 mod analyzer;
 mod ast;
 mod attributes;
+mod builtins;
 mod codegen;
+mod complex;
 mod config;
+mod diagnostics;
 mod error;
+mod explain;
 mod inference;
 mod lexer;
 mod optimizer;
 mod parser;
+mod session;
+mod simulator;
 mod types;
 mod utils;
-
-use crate::codegen::{qasm, Translator};
-use crate::error::Result;
-use crate::inference::infer;
+mod verify;
+
+use crate::analyzer::metrics;
+use crate::ast::Qast;
+use crate::codegen::{circuit, statevector, unitary};
+use crate::config::{Config, Emit, STDOUT};
+use crate::diagnostics::Diagnostics;
+use crate::error::{QccErrorKind, Result};
+use crate::inference::dump_symbols;
 use crate::parser::Parser;
+use crate::session::check_source;
 
 fn init_session(args: Vec<&str>) -> Result<()> {
     let session = Parser::new(args)?;
@@ -25,34 +37,110 @@ fn init_session(args: Vec<&str>) -> Result<()> {
     match session {
         Some(mut parser) => {
             let config = parser.get_config();
+            let warnings = compile(&mut parser, &config)?;
 
-            let mut qast = parser.parse(&config.analyzer.src)?;
+            // artifacts are kept, only the exit status tells about warnings
+            if config.fail_on_warning && warnings > 0 {
+                Err(QccErrorKind::FailedOnWarning)?
+            }
+        }
+        None => {} /* help was asked, no errors */
+    }
 
-            // TODO: Error handling and bug reporting
-            infer(&mut qast)?;
+    Ok(())
+}
 
-            if config.dump_ast_only {
-                println!("{qast}");
-                return Ok(());
-            }
-            if config.dump_ast {
-                println!("{qast}");
-            }
+/// Compiles the source given on the command line, returning the number of
+/// warnings found in it.
+fn compile(parser: &mut Parser, config: &Config) -> Result<usize> {
+    if config.verify.is_some() {
+        verify::verify(config)?;
+        return Ok(0);
+    }
 
-            if config.analyzer.status {
-                config.analyzer.analyze(&qast)?;
-            }
+    // errors of the source are printed at once, whichever stage
+    // stopped compilation
+    let mut diagnostics = Diagnostics::default();
+    let qast = match check_source(parser, config, &mut diagnostics) {
+        Ok(qast) => qast,
+        Err(err) => {
+            diagnostics.report();
+            return Err(err);
+        }
+    };
+
+    // the AST is dumped as inferred, before optimizations rewrite it
+    if config.dump_symbols {
+        print!("{}", dump_symbols(&qast));
+    }
+    if config.dump_ast || config.dump_ast_only {
+        if config.ast_locations {
+            println!("{qast}");
+        } else {
+            println!("{qast:#}");
+        }
+    }
+    if config.dump_ast_only {
+        diagnostics.report();
+        return Ok(diagnostics.warning_count());
+    }
+
+    let compiled = session::compile(qast, config, &mut diagnostics);
+    diagnostics.report();
+    let mut compiled = compiled?;
+    let mut warnings = diagnostics.warning_count();
+
+    if let Some(report) = &compiled.report {
+        warnings += report.warnings.len();
+        print!("{}", report);
+        if config.deny_warnings && !report.warnings.is_empty() {
+            Err(QccErrorKind::DeniedWarning)?
+        }
+    }
+
+    // artifacts are written inside `--output-dir`, or wherever `-o` puts
+    // them, so the directory they are in is created either way
+    if config.optimizer.asm != STDOUT {
+        if let Some(dir) = std::path::Path::new(&config.optimizer.asm).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
 
-            let asm = qasm::QasmModule::translate(qast)?;
+    match config.emit {
+        Some(Emit::Metrics) => println!("{}", metrics::to_json(&compiled.circuits)),
+        Some(Emit::StateVector) => {
+            if config.optimizer.asm == STDOUT {
+                print!("{}", statevector::to_json(&compiled.circuits)?);
+            } else {
+                statevector::generate(&compiled.circuits, &config.optimizer.asm)?
+            }
+        }
+        Some(Emit::Unitary) => {
+            if config.optimizer.asm == STDOUT {
+                print!("{}", unitary::to_json(&compiled.circuits)?);
+            } else {
+                unitary::generate(&compiled.circuits, &config.optimizer.asm)?
+            }
+        }
+        Some(Emit::Circuit) => {
+            compiled.round(config);
+            print!("{}", circuit::listing(&compiled.circuits));
+        }
+        _ => {
+            compiled.round(config);
+            let asm = compiled.translate(config)?;
             if config.dump_qasm {
                 println!("{asm}");
             }
-            asm.generate(&config.optimizer.asm)?;
+            if config.optimizer.asm == STDOUT {
+                asm.write_to(&mut std::io::stdout())?;
+            } else {
+                asm.generate(&config.optimizer.asm)?;
+            }
         }
-        None => {} /* help was asked, no errors */
     }
 
-    Ok(())
+    Ok(warnings)
 }
 
 fn main() -> Result<()> {
@@ -61,6 +149,7 @@ fn main() -> Result<()> {
 
     if let Err(err) = init_session(args) {
         eprintln!("{err}");
+        std::process::exit(1);
     }
 
     Ok(())
@@ -1,16 +1,127 @@
 //! Configuration for Quale optimizer.
+use crate::ast::Qast;
+use crate::codegen::circuit::Circuit;
+use crate::diagnostics::Diagnostics;
+use crate::error::QccErrorKind;
+use crate::optimizer::passes::{Pass, PASSES};
+
+/// Optimization level selected by `-O`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptLevel {
+    /// `-O0`, no pass runs
+    #[default]
+    None,
+    /// `-O1`
+    Less,
+    /// `-O2`
+    Default,
+    /// `-O3`, an alias of `-O2` since no pass sits above the second level
+    Aggressive,
+    /// `-Og`, only passes which keep bindings and functions as written
+    Debug,
+    /// `-Oz`, only passes which make circuits smaller
+    Size,
+}
+
+impl OptLevel {
+    /// Checks if a pass runs at this level by default.
+    pub(crate) fn enables(&self, pass: &Pass) -> bool {
+        match self {
+            Self::None => false,
+            Self::Less => pass.level() <= 1,
+            // dead functions are kept so that they can still be inspected
+            Self::Debug => pass.level() <= 1 && *pass != Pass::Dce,
+            Self::Default | Self::Aggressive => pass.level() <= 2,
+            // scheduling only makes circuits shallower
+            Self::Size => *pass != Pass::Schedule,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Less => "less",
+            Self::Default => "default",
+            Self::Aggressive => "aggressive",
+            Self::Debug => "debug",
+            Self::Size => "size",
+        }
+    }
+}
+
+impl std::str::FromStr for OptLevel {
+    type Err = QccErrorKind;
+
+    /// Parses the flag selecting a level, e.g. `-O2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "-O0" => Ok(Self::None),
+            "-O1" => Ok(Self::Less),
+            "-O2" => Ok(Self::Default),
+            "-O3" => Ok(Self::Aggressive),
+            "-Og" => Ok(Self::Debug),
+            "-Oz" => Ok(Self::Size),
+            _ => Err(QccErrorKind::NoSuchArg),
+        }
+    }
+}
+
+impl std::fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = match self {
+            Self::None => "O0",
+            Self::Less => "O1",
+            Self::Default => "O2",
+            Self::Aggressive => "O3",
+            Self::Debug => "Og",
+            Self::Size => "Oz",
+        };
+        write!(f, "{} ({})", flag, self.name())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OptConfig {
     pub asm: String,
-    pub level: u8, // 0, 1, 2
+    pub level: OptLevel,
+    /// passes run regardless of level
+    pub(crate) enabled: Vec<Pass>,
+    /// passes skipped regardless of level
+    pub(crate) disabled: Vec<Pass>,
 }
 
 impl OptConfig {
     pub fn new() -> Self {
         OptConfig {
             asm: "".into(),
-            level: 0,
+            level: Default::default(),
+            enabled: vec![],
+            disabled: vec![],
+        }
+    }
+
+    /// Passes to run, i.e. those enabled by level or explicitly, minus the
+    /// explicitly disabled ones.
+    pub(crate) fn passes(&self) -> Vec<Pass> {
+        PASSES
+            .iter()
+            .filter(|pass| self.level.enables(pass) || self.enabled.contains(pass))
+            .filter(|pass| !self.disabled.contains(pass))
+            .copied()
+            .collect()
+    }
+
+    pub(crate) fn optimize(&self, ast: &mut Qast, diagnostics: &mut Diagnostics) {
+        for pass in self.passes() {
+            pass.run(ast, diagnostics);
+        }
+    }
+
+    pub(crate) fn optimize_circuits(&self, circuits: &mut [Circuit]) {
+        for pass in self.passes() {
+            for circuit in circuits.iter_mut() {
+                pass.run_circuit(circuit);
+            }
         }
     }
 }
@@ -22,7 +133,7 @@ impl std::fmt::Display for OptConfig {
             "
 Optimizer Configuration
 -----------------------
-Stage: O{}",
+Stage: {}",
             self.level
         )
     }
@@ -1 +1,96 @@
+//! Optimizer for qcc
 pub mod config;
+pub mod passes;
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Result;
+    use crate::inference::infer;
+    use crate::optimizer::passes::Pass;
+    use crate::parser::Parser;
+
+    /// Returns passes which run with given flags.
+    fn passes(args: Vec<&str>) -> Result<Vec<Pass>> {
+        let parser = Parser::new(args)?.unwrap();
+        Ok(parser.get_config().optimizer.passes())
+    }
+
+    /// Returns names of functions left after optimizing with given flags.
+    fn optimized_functions(args: Vec<&str>) -> Result<Vec<String>> {
+        let mut parser = Parser::new(args)?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        config.optimizer.optimize(&mut ast, &mut Default::default());
+
+        let mut names = vec![];
+        for module in &ast {
+            for function in &*module {
+                names.push(function.get_name().clone());
+            }
+        }
+        Ok(names)
+    }
+
+    #[test]
+    fn check_pass_control() -> Result<()> {
+        assert_eq!(
+            passes(vec!["tests/hadamard.ql", "-O2"])?,
+            [
+                Pass::ConstFold,
+                Pass::AngleReduce,
+                Pass::Dse,
+                Pass::Dce,
+                Pass::GateCancel,
+                Pass::Schedule
+            ]
+        );
+        assert_eq!(
+            passes(vec![
+                "tests/hadamard.ql",
+                "-O2",
+                "--disable-pass=gate-cancel"
+            ])?,
+            [
+                Pass::ConstFold,
+                Pass::AngleReduce,
+                Pass::Dse,
+                Pass::Dce,
+                Pass::Schedule
+            ]
+        );
+        assert_eq!(
+            passes(vec!["tests/hadamard.ql", "--enable-pass=gate-cancel"])?,
+            [Pass::GateCancel]
+        );
+        assert_eq!(passes(vec!["tests/hadamard.ql"])?, []);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_dead_functions() -> Result<()> {
+        assert_eq!(
+            optimized_functions(vec!["tests/dead-fn.ql", "-O1"])?,
+            ["main"]
+        );
+        assert_eq!(
+            optimized_functions(vec!["tests/dead-fn.ql", "-O1", "--disable-pass=dce"])?,
+            ["unused", "main"]
+        );
+        assert_eq!(
+            optimized_functions(vec!["tests/dead-fn.ql", "--enable-pass=dce"])?,
+            ["main"]
+        );
+        assert_eq!(
+            optimized_functions(vec!["tests/dead-fn.ql"])?,
+            ["unused", "main"]
+        );
+        assert_eq!(
+            optimized_functions(vec!["tests/entry-fn.ql", "-O1"])?,
+            ["helper", "calibrate"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,783 @@
+//! Registry of optimization passes.
+//!
+//! Each pass has a name used by `--enable-pass`/`--disable-pass` and the
+//! lowest `-O` level which runs it by default.
+use crate::ast::{Expr, FunctionAST, Ident, LiteralAST, Opcode, Qast, QccCell};
+use crate::builtins::Builtin;
+use crate::codegen::circuit::{Circuit, Op};
+use crate::diagnostics::Diagnostics;
+use crate::error::QccErrorKind;
+use crate::lexer::Location;
+use crate::utils::mangle;
+use std::f64::consts::{PI, TAU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pass {
+    /// Folds constant classical expressions into literals.
+    ConstFold,
+    /// Reduces constant rotation angles modulo 2π, dropping identity
+    /// rotations.
+    AngleReduce,
+    /// Removes `let` bindings of pure values which are never read.
+    Dse,
+    /// Removes functions which aren't reachable from an entry point.
+    Dce,
+    /// Cancels adjacent pairs of a self-inverse gate.
+    GateCancel,
+    /// Orders operations by the earliest layer they can run in, so that
+    /// gates on disjoint qubits overlap.
+    Schedule,
+}
+
+/// All passes, in the order they run.
+pub(crate) const PASSES: &[Pass] = &[
+    Pass::ConstFold,
+    Pass::AngleReduce,
+    Pass::Dse,
+    Pass::Dce,
+    Pass::GateCancel,
+    Pass::Schedule,
+];
+
+impl Pass {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::ConstFold => "constfold",
+            Self::AngleReduce => "angle-reduce",
+            Self::Dse => "dse",
+            Self::Dce => "dce",
+            Self::GateCancel => "gate-cancel",
+            Self::Schedule => "schedule",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::ConstFold => "fold constant expressions",
+            Self::AngleReduce => "reduce constant angles modulo 2π",
+            Self::Dse => "remove unused lets of pure values",
+            Self::Dce => "remove functions unreachable from entry points",
+            Self::GateCancel => "cancel adjacent self-inverse gates",
+            Self::Schedule => "reorder independent gates by layer",
+        }
+    }
+
+    /// Lowest optimization level which enables the pass by default.
+    pub(crate) fn level(&self) -> u8 {
+        match self {
+            Self::ConstFold | Self::AngleReduce | Self::Dce => 1,
+            Self::Dse | Self::GateCancel | Self::Schedule => 2,
+        }
+    }
+
+    /// Runs the pass if it works on the AST, adding warnings about the source
+    /// to `diagnostics`.
+    pub(crate) fn run(&self, ast: &mut Qast, diagnostics: &mut Diagnostics) {
+        match self {
+            Self::ConstFold => {
+                for mut module in ast {
+                    for mut function in &mut *module {
+                        fold_function(&mut function, diagnostics);
+                    }
+                }
+            }
+            Self::AngleReduce => {
+                for mut module in ast {
+                    for mut function in &mut *module {
+                        reduce_angles(&mut function);
+                    }
+                }
+            }
+            Self::Dse => {
+                for mut module in ast {
+                    for mut function in &mut *module {
+                        eliminate_dead_stores(&mut function);
+                    }
+                }
+            }
+            Self::Dce => eliminate_dead_functions(ast),
+            Self::GateCancel | Self::Schedule => {}
+        }
+    }
+
+    /// Runs the pass if it works on circuits.
+    pub(crate) fn run_circuit(&self, circuit: &mut Circuit) {
+        match self {
+            Self::GateCancel => cancel_gates(circuit),
+            Self::Schedule => schedule(circuit),
+            Self::ConstFold | Self::AngleReduce | Self::Dse | Self::Dce => {}
+        }
+    }
+}
+
+impl std::str::FromStr for Pass {
+    type Err = QccErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PASSES
+            .iter()
+            .find(|pass| pass.name() == s)
+            .copied()
+            .ok_or(QccErrorKind::NoSuchArg)
+    }
+}
+
+impl std::fmt::Display for Pass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Prints every pass with the level enabling it, for `--list-passes`.
+pub(crate) fn list_passes() {
+    for pass in PASSES {
+        println!(
+            "{:14}\t-O{}\t{}",
+            pass.name(),
+            pass.level(),
+            pass.description()
+        );
+    }
+}
+
+fn fold_function(function: &mut FunctionAST, diagnostics: &mut Diagnostics) {
+    for nested in function.iter_nested_mut() {
+        fold_function(nested, diagnostics);
+    }
+    for expr in &*function {
+        let at = expr.as_ref().borrow().get_location();
+        fold_expr(expr, &at, diagnostics);
+    }
+}
+
+/// Folds constant subexpressions of `expr`, which is in a statement at `at`.
+/// Warnings are put at `at` if the location of the subexpression isn't known,
+/// e.g. if it is built only from literals.
+fn fold_expr(expr: &QccCell<Expr>, at: &Location, diagnostics: &mut Diagnostics) {
+    let located = |expr: &Expr| match expr.get_location() {
+        location if location.row() == 0 => at.clone(),
+        location => location,
+    };
+    let folded = match *expr.as_ref().borrow() {
+        Expr::Literal(_) | Expr::Var(_) => return,
+        Expr::Let(ref var, ref val) => {
+            // integers of a declared type are divided as integers
+            let integer = match var.get_type().is_integer() {
+                true => val.as_ref().borrow().eval_int().ok().flatten(),
+                false => None,
+            };
+            if let Some(n) = integer {
+                *val.as_ref().borrow_mut() =
+                    Expr::Literal(std::rc::Rc::new(LiteralAST::Lit_Int(n).into()));
+                return;
+            }
+            return fold_expr(val, var.location(), diagnostics);
+        }
+        ref expr if is_integral(expr) => match expr.eval_int() {
+            Ok(n) => n.map(|n| Expr::Literal(std::rc::Rc::new(LiteralAST::Lit_Int(n).into()))),
+            Err(_) => {
+                let warning = (QccErrorKind::IntegerOverflow, located(expr)).into();
+                diagnostics.warning(warning, format!("in `{}`", expr));
+                None
+            }
+        },
+        // dividing by zero is left for the program to do
+        ref expr => expr
+            .eval_const()
+            .filter(|value| value.is_finite())
+            .map(digit),
+    };
+
+    if let Some(folded) = folded {
+        *expr.as_ref().borrow_mut() = folded;
+        return;
+    }
+
+    match *expr.as_ref().borrow() {
+        Expr::BinaryExpr(ref lhs, ref op, ref rhs) => {
+            if *op == Opcode::Div && rhs.as_ref().borrow().eval_const() == Some(0.0) {
+                let expr = expr.as_ref().borrow();
+                let warning = (QccErrorKind::DivisionByZero, located(&expr)).into();
+                diagnostics.warning(warning, format!("in `{}`", expr));
+            }
+            fold_expr(lhs, at, diagnostics);
+            fold_expr(rhs, at, diagnostics);
+        }
+        Expr::FnCall(_, ref args) => {
+            for arg in args {
+                fold_expr(arg, at, diagnostics);
+            }
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            fold_expr(cond, at, diagnostics);
+            for expr in truth.iter().chain(otherwise) {
+                fold_expr(expr, at, diagnostics);
+            }
+        }
+        Expr::Loop(ref cond, ref body) => {
+            fold_expr(cond, at, diagnostics);
+            for expr in body {
+                fold_expr(expr, at, diagnostics);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Checks if an expression only adds, subtracts or multiplies integers, so
+/// that its value is an integer too.
+fn is_integral(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(lit) => matches!(*lit.as_ref().borrow(), LiteralAST::Lit_Int(_)),
+        Expr::BinaryExpr(lhs, op, rhs) => {
+            matches!(op, Opcode::Add | Opcode::Sub | Opcode::Mul)
+                && is_integral(&lhs.as_ref().borrow())
+                && is_integral(&rhs.as_ref().borrow())
+        }
+        _ => false,
+    }
+}
+
+fn reduce_angles(function: &mut FunctionAST) {
+    for nested in function.iter_nested_mut() {
+        reduce_angles(nested);
+    }
+    for expr in &*function {
+        reduce_expr(expr);
+    }
+}
+
+fn reduce_expr(expr: &QccCell<Expr>) {
+    let identity = match *expr.as_ref().borrow() {
+        Expr::Let(_, ref val) => return reduce_expr(val),
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
+            reduce_expr(lhs);
+            reduce_expr(rhs);
+            None
+        }
+        Expr::FnCall(ref f, ref args) => {
+            args.iter().for_each(reduce_expr);
+            reduce_rotation(f, args)
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            reduce_expr(cond);
+            truth.iter().chain(otherwise).for_each(reduce_expr);
+            None
+        }
+        Expr::Loop(ref cond, ref body) => {
+            reduce_expr(cond);
+            body.iter().for_each(reduce_expr);
+            None
+        }
+        _ => None,
+    };
+
+    // an identity rotation is replaced by the qubit it rotates
+    if let Some(qubit) = identity {
+        let qubit = std::mem::replace(&mut *qubit.as_ref().borrow_mut(), digit(0.0));
+        *expr.as_ref().borrow_mut() = qubit;
+    }
+}
+
+fn digit(value: f64) -> Expr {
+    Expr::Literal(std::rc::Rc::new(LiteralAST::Lit_Digit(value).into()))
+}
+
+/// Rotations by less than this are considered the identity, which allows for
+/// angles like 2π being written with a few digits.
+const ANGLE_EPSILON: f64 = 1e-6;
+
+/// Reduces the constant angle of a rotation gate into (-π, π]. Returns the
+/// rotated qubit if the rotation turns out to be the identity.
+fn reduce_rotation(f: &FunctionAST, args: &[QccCell<Expr>]) -> Option<QccCell<Expr>> {
+    if !matches!(
+        Builtin::lookup(f.get_name())?,
+        Builtin::Rx | Builtin::Ry | Builtin::Rz
+    ) {
+        return None;
+    }
+    let [angle, qubit] = args else {
+        return None;
+    };
+
+    let theta = angle.as_ref().borrow().eval_const()?;
+    let mut reduced = theta.rem_euclid(TAU);
+    if reduced > PI {
+        reduced -= TAU;
+    }
+    if reduced.abs() < ANGLE_EPSILON {
+        return Some(qubit.clone());
+    }
+
+    *angle.as_ref().borrow_mut() = digit(reduced);
+    None
+}
+
+/// Removes bindings which aren't read by a later instruction. A pure value is
+/// dropped with its binding, while a call with side effects is kept as a bare
+/// call. The last instruction is the value of the function and is never
+/// touched.
+fn eliminate_dead_stores(function: &mut FunctionAST) {
+    for nested in function.iter_nested_mut() {
+        eliminate_dead_stores(nested);
+    }
+
+    // going backwards, a binding read only by dead bindings is dead as well
+    let body: Vec<QccCell<Expr>> = (&*function).into_iter().cloned().collect();
+    for index in (0..body.len().saturating_sub(1)).rev() {
+        let (var, val) = match *body[index].as_ref().borrow() {
+            Expr::Let(ref var, ref val) => (var.clone(), val.clone()),
+            _ => continue,
+        };
+        let later: Vec<QccCell<Expr>> = (&*function).into_iter().skip(index + 1).cloned().collect();
+        // nested functions may refer to anything bound before them
+        let nested: Vec<QccCell<Expr>> = function
+            .iter_nested()
+            .flat_map(|nested| nested.into_iter().cloned())
+            .collect();
+        if is_read(var.name(), &later) || is_read(var.name(), &nested) {
+            continue;
+        }
+
+        // binding a qubit to a bit measures it, so such a binding stays
+        let coerced = var.get_type().is_quantum() || val.as_ref().borrow().get_type().is_quantum();
+        if !coerced && is_pure(&val) {
+            function.remove(index);
+        } else if matches!(*val.as_ref().borrow(), Expr::FnCall(..)) {
+            let call = std::mem::replace(&mut *val.as_ref().borrow_mut(), digit(0.0));
+            *body[index].as_ref().borrow_mut() = call;
+        }
+    }
+}
+
+/// Checks if `name` is read by any of `exprs` before being bound again.
+pub(crate) fn is_read(name: &str, exprs: &[QccCell<Expr>]) -> bool {
+    for expr in exprs {
+        if let Expr::Let(ref var, ref val) = *expr.as_ref().borrow() {
+            if reads(name, val) {
+                return true;
+            }
+            if var.name() == name {
+                return false;
+            }
+        } else if reads(name, expr) {
+            return true;
+        }
+    }
+    false
+}
+
+fn reads(name: &str, expr: &QccCell<Expr>) -> bool {
+    match *expr.as_ref().borrow() {
+        Expr::Var(ref var) => var.name() == name,
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => reads(name, lhs) || reads(name, rhs),
+        Expr::FnCall(_, ref args) => args.iter().any(|arg| reads(name, arg)),
+        Expr::Let(_, ref val) => reads(name, val),
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            reads(name, cond) || truth.iter().chain(otherwise).any(|expr| reads(name, expr))
+        }
+        Expr::Loop(ref cond, ref body) => {
+            reads(name, cond) || body.iter().any(|expr| reads(name, expr))
+        }
+        Expr::Literal(ref lit) => match *lit.as_ref().borrow() {
+            LiteralAST::Lit_Tensor(ref elems, _) => elems.iter().any(|elem| reads(name, elem)),
+            _ => false,
+        },
+    }
+}
+
+/// Checks if evaluating `expr` has no effect besides its value. Gates, qubit
+/// allocation and calls of user functions aren't pure.
+fn is_pure(expr: &QccCell<Expr>) -> bool {
+    match *expr.as_ref().borrow() {
+        Expr::Var(_) => true,
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => is_pure(lhs) && is_pure(rhs),
+        Expr::FnCall(ref f, ref args) => {
+            matches!(
+                Builtin::lookup(f.get_name()),
+                Some(Builtin::Sin | Builtin::Cos | Builtin::Sqrt | Builtin::BitNot)
+            ) && args.iter().all(is_pure)
+        }
+        Expr::Let(..) | Expr::Conditional(..) | Expr::Loop(..) => false,
+        Expr::Literal(ref lit) => match *lit.as_ref().borrow() {
+            LiteralAST::Lit_Tensor(ref elems, _) => elems.iter().all(is_pure),
+            _ => true,
+        },
+    }
+}
+
+/// Removes functions which can't be called from `main` or a `#[entry]`
+/// function, following calls transitively. Functions emitted as OpenQASM
+/// gates through `qasm_name` are always kept.
+fn eliminate_dead_functions(ast: &mut Qast) {
+    let mut functions: Vec<(Ident, Vec<Ident>)> = vec![];
+    let mut live: Vec<Ident> = vec!["main".into()];
+    let mut gates: Vec<Ident> = vec![];
+    for module in &*ast {
+        let module_name = module.get_name();
+        for function in &*module {
+            if function.get_attrs().is_entry() {
+                live.push(function.get_name().clone());
+            }
+            if function.get_attrs().qasm_name().is_some() {
+                gates.push(function.get_name().clone());
+            }
+            let mut callees = vec![];
+            gather_callees(&function, &mut callees);
+            functions.push((function.get_name().clone(), callees.clone()));
+            // calls from other modules are mangled with the module name
+            functions.push((mangle(&module_name, function.get_name()), callees));
+        }
+    }
+
+    // a program without `main` or any `#[entry]` function is a library,
+    // everything may be used
+    if functions.iter().all(|(name, _)| !live.contains(name)) {
+        return;
+    }
+    live.extend(gates);
+
+    let mut worklist = live.clone();
+    while let Some(name) = worklist.pop() {
+        for (_, callees) in functions.iter().filter(|(f, _)| *f == name) {
+            for callee in callees {
+                if !live.contains(callee) {
+                    live.push(callee.clone());
+                    worklist.push(callee.clone());
+                }
+            }
+        }
+    }
+
+    for mut module in ast {
+        let module_name = module.get_name();
+        module.retain_functions(|f| {
+            live.contains(f.get_name()) || live.contains(&mangle(&module_name, f.get_name()))
+        });
+    }
+}
+
+/// Collects names of all functions called in `function`, including calls of
+/// its nested functions and builtins.
+pub(crate) fn gather_callees(function: &FunctionAST, callees: &mut Vec<Ident>) {
+    for nested in function.iter_nested() {
+        gather_callees(nested, callees);
+    }
+    for expr in function {
+        gather_expr_callees(expr, callees);
+    }
+}
+
+fn gather_expr_callees(expr: &QccCell<Expr>, callees: &mut Vec<Ident>) {
+    match *expr.as_ref().borrow() {
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
+            gather_expr_callees(lhs, callees);
+            gather_expr_callees(rhs, callees);
+        }
+        Expr::FnCall(ref f, ref args) => {
+            callees.push(f.get_name().clone());
+            for arg in args {
+                gather_expr_callees(arg, callees);
+            }
+        }
+        Expr::Let(_, ref val) => gather_expr_callees(val, callees),
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            gather_expr_callees(cond, callees);
+            for expr in truth.iter().chain(otherwise) {
+                gather_expr_callees(expr, callees);
+            }
+        }
+        Expr::Loop(ref cond, ref body) => {
+            gather_expr_callees(cond, callees);
+            for expr in body {
+                gather_expr_callees(expr, callees);
+            }
+        }
+        Expr::Literal(ref lit) => {
+            if let LiteralAST::Lit_Tensor(ref elems, _) = *lit.as_ref().borrow() {
+                for elem in elems {
+                    gather_expr_callees(elem, callees);
+                }
+            }
+        }
+        Expr::Var(_) => {}
+    }
+}
+
+/// Checks if applying a gate twice is the identity.
+fn is_self_inverse(op: &Op) -> bool {
+    match op {
+        Op::Gate { name, params, .. } => {
+            params.is_empty() && matches!(name.as_str(), "h" | "x" | "y" | "z" | "cx" | "swap")
+        }
+        _ => false,
+    }
+}
+
+/// Checks if two operations apply the same gate. Swapping `a` with `b` is the
+/// same as swapping `b` with `a`.
+fn is_same_gate(lhs: &Op, rhs: &Op) -> bool {
+    match (lhs, rhs) {
+        (
+            Op::Gate { name, qubits, .. },
+            Op::Gate {
+                name: other,
+                qubits: others,
+                ..
+            },
+        ) if name == "swap" && other == "swap" => {
+            qubits.len() == 2 && qubits.iter().rev().eq(others) || qubits == others
+        }
+        _ => lhs == rhs,
+    }
+}
+
+fn cancel_gates(circuit: &mut Circuit) {
+    // the next operation on any of the same qubits must be the same gate
+    let find_pair = |ops: &[Op]| {
+        (0..ops.len()).find_map(|i| {
+            if !is_self_inverse(&ops[i]) {
+                return None;
+            }
+            let qubits = ops[i].qubits();
+            let j =
+                (i + 1..ops.len()).find(|&j| ops[j].qubits().iter().any(|q| qubits.contains(q)))?;
+            is_same_gate(&ops[i], &ops[j]).then_some((i, j))
+        })
+    };
+
+    while let Some((i, j)) = find_pair(&circuit.ops) {
+        circuit.ops.remove(j);
+        circuit.ops.remove(i);
+    }
+}
+
+/// Reorders operations layer by layer, where an operation's layer is one past
+/// the latest layer of an earlier operation sharing a qubit with it. Sorting is
+/// stable and dependent operations are in later layers, so each qubit still
+/// sees its operations in the original order. Measurements act as barriers
+/// which nothing is moved across.
+fn schedule(circuit: &mut Circuit) {
+    let ops = std::mem::take(&mut circuit.ops);
+    let mut segment: Vec<(usize, Op)> = vec![];
+    let mut levels = vec![0; circuit.qubits];
+
+    let flush = |segment: &mut Vec<(usize, Op)>, ops: &mut Vec<Op>| {
+        segment.sort_by_key(|(level, _)| *level);
+        ops.extend(segment.drain(..).map(|(_, op)| op));
+    };
+
+    for op in ops {
+        if matches!(op, Op::Measure { .. }) {
+            flush(&mut segment, &mut circuit.ops);
+            levels.fill(0);
+            circuit.ops.push(op);
+            continue;
+        }
+
+        let level = op.qubits().iter().map(|&q| levels[q]).max().unwrap_or(0) + 1;
+        for &qubit in op.qubits() {
+            levels[qubit] = level;
+        }
+        segment.push((level, op));
+    }
+    flush(&mut segment, &mut circuit.ops);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_gate_cancel() {
+        let gate = |name: &str, qubits: Vec<usize>| Op::Gate {
+            name: name.into(),
+            params: vec![],
+            qubits,
+        };
+        let mut circuit = Circuit {
+            ops: vec![
+                gate("h", vec![0]),
+                gate("x", vec![1]),
+                gate("h", vec![0]),
+                gate("cx", vec![0, 1]),
+                gate("cx", vec![1, 0]),
+                gate("swap", vec![0, 1]),
+                gate("swap", vec![1, 0]),
+            ],
+            qubits: 2,
+            ..Default::default()
+        };
+        Pass::GateCancel.run_circuit(&mut circuit);
+        // reversed control and target aren't the same gate, but swaps are
+        assert_eq!(
+            circuit.ops,
+            [
+                gate("x", vec![1]),
+                gate("cx", vec![0, 1]),
+                gate("cx", vec![1, 0])
+            ]
+        );
+
+        assert_eq!("gate-cancel".parse::<Pass>(), Ok(Pass::GateCancel));
+        assert_eq!("schedule".parse::<Pass>(), Ok(Pass::Schedule));
+        assert_eq!("foo".parse::<Pass>(), Err(QccErrorKind::NoSuchArg));
+    }
+
+    #[test]
+    fn check_schedule() {
+        let gate = |name: &str, qubit: usize| Op::Gate {
+            name: name.into(),
+            params: vec![],
+            qubits: vec![qubit],
+        };
+        let mut circuit = Circuit {
+            ops: vec![
+                gate("x", 0),
+                gate("h", 0),
+                gate("h", 1),
+                Op::Measure { qubit: 1, clbit: 0 },
+                gate("z", 0),
+            ],
+            qubits: 2,
+            clbits: 1,
+            ..Default::default()
+        };
+        let depth = circuit.depth();
+        Pass::Schedule.run_circuit(&mut circuit);
+
+        // `h q[1]` runs alongside `x q[0]`, but `z q[0]` stays after the
+        // measurement
+        assert_eq!(
+            circuit.ops,
+            [
+                gate("x", 0),
+                gate("h", 1),
+                gate("h", 0),
+                Op::Measure { qubit: 1, clbit: 0 },
+                gate("z", 0),
+            ]
+        );
+        assert_eq!(circuit.depth(), depth);
+    }
+
+    #[test]
+    fn check_dead_store() -> crate::error::Result<()> {
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/dead-store.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        Pass::Dse.run(&mut ast, &mut Default::default());
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let body = main
+            .into_iter()
+            .map(|expr| expr.as_ref().borrow().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            body,
+            [
+                "r: bit = q: qubit",
+                "h: qubit (q: qubit)",
+                "theta: float64 = 0.5",
+                "theta: float64"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_constant_folding() -> crate::error::Result<()> {
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/fold.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        let mut diagnostics = crate::diagnostics::Diagnostics::default();
+        Pass::ConstFold.run(&mut ast, &mut diagnostics);
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let vals = main
+            .into_iter()
+            .filter_map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(_, ref val) => Some(val.as_ref().borrow().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // the folded sum is still an integer
+        // an overflowing product is left as it is, past what fits in 64 bits
+        assert_eq!(
+            vals,
+            [
+                "7",
+                "(x: float64 / 0)",
+                "2.5",
+                "(2 / 0)",
+                "(6074001000 * 3037000500)",
+                "3",
+                "3.5"
+            ]
+        );
+        let Expr::Let(_, ref x) = *main.into_iter().next().unwrap().as_ref().borrow() else {
+            panic!("expected a let binding");
+        };
+        let Expr::Literal(ref lit) = *x.as_ref().borrow() else {
+            panic!("expected a literal");
+        };
+        assert!(matches!(*lit.as_ref().borrow(), LiteralAST::Lit_Int(7)));
+
+        // warnings are kept with the compilation which found them
+        let warnings = diagnostics
+            .iter()
+            .map(|warning| warning.error().get_error().kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                &QccErrorKind::DivisionByZero,
+                &QccErrorKind::DivisionByZero,
+                &QccErrorKind::IntegerOverflow
+            ]
+        );
+        assert_eq!(diagnostics.warning_count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    // angles in `tests/rotations.ql` are written close to, but not exactly, π
+    #[allow(clippy::approx_constant)]
+    fn check_angle_reduction() -> crate::error::Result<()> {
+        use crate::codegen::circuit;
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/rotations.ql", "-O1"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        config.optimizer.optimize(&mut ast, &mut Default::default());
+
+        let circuits = circuit::lower(ast)?;
+        let rotations = circuits[0]
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Gate { name, params, .. } if name == "rz" => params[0].value(),
+                _ => None,
+            })
+            .collect::<Vec<f64>>();
+        // rotating by about 2π does nothing
+        assert_eq!(rotations, [3.14159, -PI / 2.0]);
+
+        Ok(())
+    }
+}
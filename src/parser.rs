@@ -2,17 +2,101 @@
 //! It translates the given code into an AST.
 use crate::ast::*;
 use crate::attributes::{Attribute, Attributes};
+use crate::builtins::Builtin;
 use crate::config::*;
+use crate::diagnostics::Diagnostics;
 use crate::error::{QccError, QccErrorKind, QccErrorLoc, Result};
+use crate::explain::explain;
 use crate::lexer::{Lexer, Location};
+use crate::optimizer::config::OptLevel;
+use crate::optimizer::passes::{list_passes, Pass};
 use crate::types::Type;
 use crate::utils::{mangle, mangle_module, sanitize, usage};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// File parsed first when compiling a package.
+const PACKAGE_ENTRY: &str = "main.ql";
 
 pub struct Parser {
     // args: Vec<String>,
     config: Config,
     lexer: Box<Lexer>,
+    /// module aliases mapped to the modules they stand for
+    aliases: std::collections::HashMap<Ident, Ident>,
+    /// operators mapped to the functions defining them
+    operators: std::collections::HashMap<String, Ident>,
+    /// modules being parsed which led to parsing this one, so that cyclic
+    /// imports aren't followed forever
+    importers: Vec<Ident>,
+    /// errors found in the source, reported by the driver
+    diagnostics: Diagnostics,
+}
+
+/// A binary operator, either a builtin one or one defined by a function.
+enum BinOp {
+    Builtin(Opcode),
+    /// desugared to a call to the function
+    Defined(Ident, Location),
+}
+
+impl BinOp {
+    /// Operators defined by functions bind tighter than builtin ones.
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Builtin(op) => op.precedence(),
+            Self::Defined(..) => Opcode::Mul.precedence() + 1,
+        }
+    }
+}
+
+/// Evaluates the size of a register, which must fold to a non-negative
+/// integer. Integer arithmetic is checked, so an overflowing size is an error
+/// rather than a wrapped one, and a division which leaves a remainder is a
+/// fractional size rather than a truncated one.
+fn eval_size(size: &Expr) -> Result<usize> {
+    if size.eval_const().is_some_and(|len| len.fract() != 0.0) {
+        Err(QccErrorKind::InvalidRegisterSize)?
+    }
+    if let Some(len) = size.eval_int()? {
+        return usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= u32::MAX as usize)
+            .ok_or_else(|| QccErrorKind::InvalidRegisterSize.into());
+    }
+
+    match size.eval_const() {
+        Some(len) if len >= 0.0 && len.fract() == 0.0 && len <= u32::MAX as f64 => Ok(len as usize),
+        _ => Err(QccErrorKind::InvalidRegisterSize)?,
+    }
+}
+
+/// Looks up the prelude `<name>@<version>.ql` in include paths, in the order
+/// they were given. A prelude found only in other versions is a mismatch.
+fn find_prelude(include_paths: &[String], name: &str, version: &str) -> Result<PathBuf> {
+    let file = format!("{}@{}.ql", name, version);
+    if let Some(path) = include_paths
+        .iter()
+        .map(|dir| Path::new(dir).join(&file))
+        .find(|path| path.is_file())
+    {
+        return Ok(path);
+    }
+
+    let versioned = format!("{}@", name);
+    let other_version = include_paths
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            file.starts_with(&versioned) && file.ends_with(".ql")
+        });
+    if other_version {
+        Err(QccErrorKind::PreludeVersionMismatch)?
+    } else {
+        Err(QccErrorKind::UnknownPrelude)?
+    }
 }
 
 impl Parser {
@@ -22,19 +106,54 @@ impl Parser {
     /// - If some error occurs, returns the error.
     pub fn new(args: Vec<&str>) -> Result<Option<Self>> {
         if let Some(config) = Parser::parse_cmdline(args)? {
-            let lines = std::fs::read(&config.analyzer.src)?;
-            let mut lexer = Lexer::new(lines, config.analyzer.src.clone());
-
-            Ok(Some(Self {
-                config,
-                lexer: lexer.into(),
-            }))
+            Ok(Some(Self::from_config(config)?))
         } else {
             // if help is asked, return without creating an object
             Ok(None)
         }
     }
 
+    /// Creates a parser for the source file of an already resolved `Config`.
+    pub(crate) fn from_config(config: Config) -> Result<Self> {
+        let lines = std::fs::read(&config.analyzer.src)?;
+        let lexer = Lexer::new(lines, config.analyzer.src.clone());
+
+        Ok(Self {
+            config,
+            lexer: lexer.into(),
+            aliases: Default::default(),
+            operators: Default::default(),
+            importers: Default::default(),
+            diagnostics: Default::default(),
+        })
+    }
+
+    /// Creates a parser for source code given as a string, named after the
+    /// source file of `config`.
+    pub(crate) fn from_source(src: &str, config: Config) -> Self {
+        let lexer = Lexer::new(src.as_bytes().to_vec(), config.analyzer.src.clone());
+
+        Self {
+            config,
+            lexer: lexer.into(),
+            aliases: Default::default(),
+            operators: Default::default(),
+            importers: Default::default(),
+            diagnostics: Default::default(),
+        }
+    }
+
+    /// Keeps comments of the source along with their locations, e.g. to
+    /// generate documentation from them.
+    pub(crate) fn retain_comments(&mut self) {
+        self.lexer.retain_comments();
+    }
+
+    /// Returns comments of the source in order, if they were retained.
+    pub(crate) fn comments(&self) -> &[(Location, String)] {
+        self.lexer.comments()
+    }
+
     /// Returns a reference to `Config` for current parser session.
     pub fn get_config(&self) -> Config {
         self.config.clone()
@@ -51,41 +170,178 @@ impl Parser {
             Err(QccErrorKind::InvalidArgs)?;
         }
 
+        // asking for help or the version is honoured wherever it is, before
+        // other options or the source file are checked
+        for option in &args {
+            match *option {
+                "-h" | "--help" => {
+                    usage();
+                    return Ok(None);
+                }
+                "-v" | "--version" => {
+                    println!("qcc {}", Config::version());
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+
         let mut config = Config::new();
         let mut output_direct: u8 = 0x0;
+        let mut explain_code = false;
+        let mut print_config = false;
+        let mut expect_output_dir = false;
+        let mut expect_package = false;
+        let mut expect_verify = false;
+        let mut expect_tol = false;
+        let mut expect_precision = false;
+        let mut expect_qasm_version = false;
+        let mut expect_include = false;
 
         // Parse cmdline options
         for option in args {
-            if option.starts_with("--") {
-                match option {
-                    "--help" => {
-                        usage();
+            if explain_code {
+                // explaining an error code doesn't need a source file
+                match explain(option) {
+                    Some(explanation) => {
+                        print!("{}", explanation);
                         return Ok(None);
                     }
+                    None => {
+                        let err: QccError = QccErrorKind::NoSuchArg.into();
+                        err.report(option);
+                        return Err(QccErrorKind::CmdlineErr)?;
+                    }
+                }
+            }
+
+            if expect_output_dir {
+                config.output_dir = Some(option.into());
+                expect_output_dir = false;
+                continue;
+            }
+            if expect_package {
+                config.package = Some(option.into());
+                expect_package = false;
+                continue;
+            }
+            if expect_verify {
+                config.verify = Some(option.into());
+                expect_verify = false;
+                continue;
+            }
+            if expect_tol {
+                match option.parse() {
+                    Ok(tolerance) => config.tolerance = tolerance,
+                    Err(kind) => {
+                        let err: QccError = kind.into();
+                        err.report(option);
+                        return Err(QccErrorKind::CmdlineErr)?;
+                    }
+                }
+                expect_tol = false;
+                continue;
+            }
+            if expect_precision {
+                match option.parse() {
+                    Ok(digits) => config.float_precision = Some(digits),
+                    Err(_) => {
+                        let err: QccError = QccErrorKind::NoSuchArg.into();
+                        err.report(option);
+                        return Err(QccErrorKind::CmdlineErr)?;
+                    }
+                }
+                expect_precision = false;
+                continue;
+            }
+            if expect_qasm_version {
+                match option.parse() {
+                    Ok(version) => config.qasm_version = version,
+                    Err(kind) => {
+                        let err: QccError = kind.into();
+                        err.report(option);
+                        return Err(QccErrorKind::CmdlineErr)?;
+                    }
+                }
+                expect_qasm_version = false;
+                continue;
+            }
+            if expect_include {
+                config.include_paths.push(option.into());
+                expect_include = false;
+                continue;
+            }
+
+            if option.starts_with("--") {
+                match option {
                     "--analyze" => config.analyzer.status = true,
                     "--dump-ast" => config.dump_ast = true,
                     "--dump-ast-only" => config.dump_ast_only = true,
                     "--dump-qasm" => config.dump_qasm = true,
+                    "--dump-symbols" => config.dump_symbols = true,
                     "--debug" => config.debug = true,
+                    "--strict" => config.strict = true,
+                    "--fail-on-warning" => config.fail_on_warning = true,
+                    "--explain" => explain_code = true,
+                    "--print-config" => print_config = true,
+                    "--output-dir" => expect_output_dir = true,
+                    "--package" => expect_package = true,
+                    "--verify" => expect_verify = true,
+                    "--tol" => expect_tol = true,
+                    "--float-precision" => expect_precision = true,
+                    "--qasm-version" => expect_qasm_version = true,
+                    "--list-passes" => {
+                        list_passes();
+                        return Ok(None);
+                    }
+                    "--ast-locations=on" => config.ast_locations = true,
+                    "--ast-locations=off" => config.ast_locations = false,
+                    _ if option.starts_with("--emit=") => {
+                        match option.trim_start_matches("--emit=").parse() {
+                            Ok(emit) => config.emit = Some(emit),
+                            Err(err) => {
+                                let err: QccError = err.into();
+                                err.report(option);
+                                return Err(QccErrorKind::CmdlineErr)?;
+                            }
+                        }
+                    }
+                    _ if option.starts_with("--enable-pass=")
+                        || option.starts_with("--disable-pass=") =>
+                    {
+                        let (flag, name) = option.split_once('=').unwrap();
+                        match name.parse::<Pass>() {
+                            Ok(pass) if flag == "--enable-pass" => {
+                                config.optimizer.enabled.push(pass)
+                            }
+                            Ok(pass) => config.optimizer.disabled.push(pass),
+                            Err(err) => {
+                                let err: QccError = err.into();
+                                err.report(option);
+                                return Err(QccErrorKind::CmdlineErr)?;
+                            }
+                        }
+                    }
                     _ => {
                         let err: QccError = QccErrorKind::NoSuchArg.into();
                         err.report(option);
                         return Err(QccErrorKind::CmdlineErr)?;
                     }
                 }
-            } else if option.starts_with('-') {
-                // Parse opt level
+            } else if option.starts_with('-') && option != STDOUT {
                 match option {
-                    "-O0" => config.optimizer.level = 0x0,
-                    "-O1" => config.optimizer.level = 0x1,
-                    "-O2" => config.optimizer.level = 0x2,
-                    "-Og" => config.optimizer.level = 0x3,
+                    level if level.starts_with("-O") => match level.parse::<OptLevel>() {
+                        Ok(level) => config.optimizer.level = level,
+                        Err(err) => {
+                            let err: QccError = err.into();
+                            err.report(option);
+                            return Err(QccErrorKind::CmdlineErr)?;
+                        }
+                    },
                     "-d" => config.debug = true,
                     "-o" => output_direct |= 0x1,
-                    "-h" => {
-                        usage();
-                        return Ok(None);
-                    }
+                    "-I" => expect_include = true,
+                    "-Werror" => config.deny_warnings = true,
                     _ => {
                         let err: QccError = QccErrorKind::NoSuchArg.into();
                         err.report(option);
@@ -105,6 +361,82 @@ impl Parser {
             }
         }
 
+        if explain_code {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--explain expects an error code");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+
+        if expect_output_dir {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--output-dir expects a directory");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_package {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--package expects a directory");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_verify {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--verify expects two source files");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_tol {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--tol expects a tolerance");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_precision {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--float-precision expects a number of digits");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_qasm_version {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("--qasm-version expects 2.0 or 3.0");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+        if expect_include {
+            let err: QccError = QccErrorKind::InvalidArgs.into();
+            err.report("-I expects a directory");
+            return Err(QccErrorKind::CmdlineErr)?;
+        }
+
+        // a package is compiled starting from its entry file
+        if let Some(dir) = &config.package {
+            let entry = Path::new(dir).join(PACKAGE_ENTRY);
+            config.analyzer.src = entry.to_string_lossy().into();
+            if output_direct == 0x0 {
+                config.optimizer.asm = config.analyzer.src.replace(".ql", ".s");
+            }
+        }
+
+        // artifacts are named after the source unless `-o` names them, in
+        // which case a relative output is taken to be inside the directory
+        if let Some(dir) = config
+            .output_dir
+            .as_ref()
+            .filter(|_| config.optimizer.asm != STDOUT)
+        {
+            let output = if output_direct == 0x0 {
+                Path::new(&config.optimizer.asm)
+                    .file_name()
+                    .map(Path::new)
+                    .unwrap_or(Path::new(""))
+            } else {
+                Path::new(&config.optimizer.asm)
+            };
+            config.optimizer.asm = Path::new(dir).join(output).to_string_lossy().into();
+        }
+
+        // printed once every option is seen, so that the order of options
+        // doesn't matter
+        if print_config {
+            println!("{}", config);
+            return Ok(None);
+        }
+
         let path = &config.analyzer.src;
         if path.is_empty() {
             Err(QccErrorKind::NoFile)?;
@@ -141,9 +473,12 @@ impl Parser {
 
         while !self.lexer.is_token(Token::CBracket) {
             if self.lexer.is_token(Token::Identifier) {
-                let attr = self.lexer.identifier().parse::<Attribute>()?;
-                attrs.push(attr);
+                let mut attr = self.lexer.identifier();
                 self.lexer.consume(Token::Identifier)?;
+                if self.lexer.is_token(Token::OParenth) {
+                    attr += &self.parse_attribute_args()?;
+                }
+                attrs.push(attr.parse::<Attribute>()?);
             }
 
             if !self.lexer.is_token(Token::Comma) && !self.lexer.is_token(Token::CBracket) {
@@ -159,6 +494,153 @@ impl Parser {
         Ok(attrs)
     }
 
+    /// Parses the arguments of an attribute like `qasm_name("h")` or
+    /// `controlled(2)`, returning them as written, i.e. `("h")`. Arguments are
+    /// strings or integers.
+    fn parse_attribute_args(&mut self) -> Result<String> {
+        self.lexer.consume(Token::OParenth)?;
+        let mut args = vec![];
+        while !self.lexer.is_token(Token::CParenth) {
+            let token = match self.lexer.token {
+                Some(token @ (Token::Literal | Token::Digit)) => token,
+                _ => return Err(QccErrorKind::ExpectedAttr)?,
+            };
+            args.push(self.lexer.identifier().trim().to_string());
+            self.lexer.consume(token)?;
+
+            if self.lexer.is_token(Token::Comma) {
+                self.lexer.consume(Token::Comma)?;
+            } else if !self.lexer.is_token(Token::CParenth) {
+                return Err(QccErrorKind::ExpectedAttr)?;
+            }
+        }
+        self.lexer.consume(Token::CParenth)?;
+        Ok(format!("({})", args.join(", ")))
+    }
+
+    /// Parses a type, which is either a scalar type like `qbit` or a register
+    /// of scalars like `qbit[3]`. The size of a register is a constant
+    /// expression like `qbit[2 * 3]`, or a generic of the function.
+    fn parse_type(&mut self) -> Result<Type> {
+        if !self.lexer.is_token(Token::Identifier) {
+            return Err(QccErrorKind::ExpectedType)?;
+        }
+        let type_ = self.lexer.identifier().parse::<Type>()?;
+        self.lexer.consume(Token::Identifier)?;
+
+        if !self.lexer.is_token(Token::OBracket) {
+            return Ok(type_);
+        }
+        self.lexer.consume(Token::OBracket)?;
+
+        let size = self.parse_expr()?;
+        if !self.lexer.is_token(Token::CBracket) {
+            return Err(QccErrorKind::ExpectedType)?;
+        }
+        self.lexer.consume(Token::CBracket)?;
+
+        // sized by a generic of the function
+        if let Expr::Var(ref var) = *size.as_ref().borrow() {
+            // only qubits and bits make registers
+            Type::register(type_.clone(), 0)?;
+            return Ok(Type::GenericRegister(type_.into(), var.name().clone()));
+        }
+
+        let len = eval_size(&size.as_ref().borrow())?;
+        Ok(Type::register(type_, len)?)
+    }
+
+    /// Parses compile-time parameters of a function, `<n, m>`.
+    fn parse_generics(&mut self) -> Result<Vec<Ident>> {
+        self.lexer.consume(Token::Lt)?;
+
+        let mut generics = vec![];
+        while !self.lexer.is_token(Token::Gt) {
+            if !self.lexer.is_token(Token::Identifier) {
+                return Err(QccErrorKind::ExpectedParamType)?;
+            }
+            generics.push(self.lexer.identifier());
+            self.lexer.consume(Token::Identifier)?;
+
+            if !self.lexer.is_any_token(&[Token::Comma, Token::Gt]) {
+                return Err(QccErrorKind::ExpectedComma)?;
+            }
+            if self.lexer.is_token(Token::Comma) {
+                self.lexer.consume(Token::Comma)?;
+            }
+        }
+        self.lexer.consume(Token::Gt)?;
+
+        Ok(generics)
+    }
+
+    /// Parses a gate declaration, `gate name(params) qargs { body }`, where
+    /// the body is a sequence of gate applications. Parentheses may be left
+    /// out for gates without parameters.
+    fn parse_gate(&mut self) -> Result<GateAST> {
+        self.lexer.consume(Token::Gate)?;
+
+        if !self.lexer.is_token(Token::Identifier) {
+            return Err(QccErrorKind::ExpectedFnName)?;
+        }
+        let name = self.lexer.identifier();
+        let location = self.lexer.location.clone();
+        self.lexer.consume(Token::Identifier)?;
+
+        let mut params = vec![];
+        if self.lexer.is_token(Token::OParenth) {
+            self.lexer.consume(Token::OParenth)?;
+            params = self.parse_gate_args(Token::CParenth, Type::F64)?;
+            self.lexer.consume(Token::CParenth)?;
+        }
+        let qargs = self.parse_gate_args(Token::OCurly, Type::Qbit)?;
+        if qargs.is_empty() {
+            return Err(QccErrorKind::ExpectedQbit)?;
+        }
+
+        if !self.lexer.is_token(Token::OCurly) {
+            return Err(QccErrorKind::ExpectedFnBody)?;
+        }
+        self.lexer.consume(Token::OCurly)?;
+
+        let mut body = vec![];
+        while !self.lexer.is_token(Token::CCurly) {
+            if self.lexer.is_token(Token::Identifier) {
+                body.push(self.parse_expr()?);
+            } else if self.lexer.is_token(Token::Semicolon) {
+                self.lexer.consume(Token::Semicolon)?;
+            } else {
+                return Err(QccErrorKind::ExpectedFnBodyEnd)?;
+            }
+        }
+        self.lexer.consume(Token::CCurly)?;
+
+        Ok(GateAST::new(name, location, params, qargs, body))
+    }
+
+    /// Parses comma separated names of gate parameters or qargs up to `end`,
+    /// giving each the type `type_`.
+    fn parse_gate_args(&mut self, end: Token, type_: Type) -> Result<Vec<VarAST>> {
+        let mut args = vec![];
+        while !self.lexer.is_token(end) {
+            if !self.lexer.is_token(Token::Identifier) {
+                return Err(QccErrorKind::ExpectedParamType)?;
+            }
+            let name = self.lexer.identifier();
+            let location = self.lexer.location.clone();
+            self.lexer.consume(Token::Identifier)?;
+            args.push(VarAST::new_with_type(name, location, type_.clone()));
+
+            if !self.lexer.is_any_token(&[Token::Comma, end]) {
+                return Err(QccErrorKind::ExpectedComma)?;
+            }
+            if self.lexer.is_token(Token::Comma) {
+                self.lexer.consume(Token::Comma)?;
+            }
+        }
+        Ok(args)
+    }
+
     /// Parses a function.
     fn parse_function(&mut self) -> Result<FunctionAST> {
         let mut attrs: Attributes = Default::default();
@@ -184,6 +666,17 @@ impl Parser {
 
         self.lexer.consume(Token::Identifier)?;
 
+        // the operator can be used from now on, in this function as well
+        if let Some(op) = attrs.operator() {
+            self.add_operator(op.clone(), name.clone());
+        }
+
+        let generics = if self.lexer.is_token(Token::Lt) {
+            self.parse_generics()?
+        } else {
+            vec![]
+        };
+
         if !self.lexer.is_token(Token::OParenth) {
             return Err(QccErrorKind::ExpectedFnArgs)?;
         }
@@ -204,8 +697,7 @@ impl Parser {
                     return Err(QccErrorKind::ExpectedParamType)?;
                 }
 
-                let type_ = self.lexer.identifier().parse::<Type>()?;
-                self.lexer.consume(Token::Identifier)?;
+                let type_ = self.parse_type()?;
 
                 input_type.push(type_.clone());
                 params.push(VarAST::new_with_type(name, location, type_));
@@ -236,8 +728,7 @@ impl Parser {
                 return Err(QccErrorKind::ExpectedFnReturnType)?;
             }
 
-            output_type = self.lexer.identifier().parse::<Type>()?;
-            self.lexer.consume(Token::Identifier)?;
+            output_type = self.parse_type()?;
         }
 
         if !self.lexer.is_token(Token::OCurly) {
@@ -246,6 +737,7 @@ impl Parser {
         self.lexer.consume(Token::OCurly)?;
 
         let mut body: Vec<QccCell<Expr>> = Default::default();
+        let mut nested_functions: Vec<FunctionAST> = Default::default();
         while !self.lexer.is_token(Token::CCurly) {
             if self.lexer.is_token(Token::Let) {
                 let expr = self.parse_let()?;
@@ -253,9 +745,25 @@ impl Parser {
             } else if self.lexer.is_token(Token::Return) {
                 let expr = self.parse_return()?;
                 body.push(expr);
+            } else if self.lexer.is_token(Token::Hash) || self.lexer.is_token(Token::Function) {
+                let nested = self.parse_function()?;
+                nested_functions.push(nested);
+            } else if self.lexer.is_token(Token::Measure) {
+                let expr = self.parse_measure()?;
+                body.push(expr);
+            } else if self.lexer.is_token(Token::If) {
+                let expr = self.parse_if()?;
+                body.push(expr);
+            } else if self.lexer.is_token(Token::While) {
+                let expr = self.parse_while()?;
+                body.push(expr);
+            } else if self.lexer.is_token(Token::Identifier) {
+                // expression statement, e.g. `free(q);`
+                let expr = self.parse_expr()?;
+                body.push(expr);
             } else {
                 if self.lexer.token.is_some() {
-                    self.lexer.consume(self.lexer.token.unwrap());
+                    self.lexer.consume(self.lexer.token.unwrap())?;
                 } else {
                     break;
                 }
@@ -263,20 +771,29 @@ impl Parser {
         }
         self.lexer.consume(Token::CCurly)?;
 
-        Ok(FunctionAST::new(
-            name,
-            location,
-            params,
-            input_type,
-            output_type,
-            attrs,
-            body,
-        ))
+        let mut function =
+            FunctionAST::new(name, location, params, input_type, output_type, attrs, body);
+        function.set_generics(generics);
+        for nested in nested_functions {
+            function.append_nested(nested);
+        }
+
+        Ok(function)
+    }
+
+    /// Desugars binary operator `op` to calls to `function`.
+    fn add_operator(&mut self, op: String, function: Ident) {
+        self.lexer.add_operator(op.clone());
+        self.operators.insert(op, function);
     }
 
     /// Parses the import statement and returns a pair of module name and
-    /// function name that is being imported.
-    fn parse_import(&mut self, qast: &Qast) -> core::result::Result<(Ident, Ident), QccErrorLoc> {
+    /// function name that is being imported. A function re-exported by the
+    /// module is looked up in the module defining it.
+    fn parse_import(
+        &mut self,
+        qast: &mut Qast,
+    ) -> core::result::Result<(Ident, Ident), QccErrorLoc> {
         let line_loc = self.lexer.location.clone();
         self.lexer.consume(Token::Import)?;
 
@@ -284,50 +801,368 @@ impl Parser {
             return Err(QccErrorKind::ExpectedMod)?;
         }
         let mod_name = self.lexer.identifier();
+        let mut mod_name = self.aliases.get(&mod_name).cloned().unwrap_or(mod_name);
         let mod_location = self.lexer.location.clone();
         self.lexer.consume(Token::Identifier)?;
 
-        // TODO: Colon location in error reporting is incorrect.
         if !self.lexer.is_token(Token::Colon) {
-            return Err(QccErrorKind::ExpectedColon)?;
+            Err((QccErrorKind::ExpectedColon, self.lexer.location.clone()))?
         }
         self.lexer.consume(Token::Colon)?;
         if !self.lexer.is_token(Token::Colon) {
-            return Err(QccErrorKind::ExpectedColon)?;
+            Err((QccErrorKind::ExpectedColon, self.lexer.location.clone()))?
         }
         self.lexer.consume(Token::Colon)?;
 
         if !self.lexer.is_token(Token::Identifier) {
             Err((QccErrorKind::ExpectedFnName, self.lexer.location.clone()))?
         }
-        let fn_name = self.lexer.identifier();
-        let fn_location = self.lexer.location.clone();
+        let mut fn_name = self.lexer.identifier();
+        let mut fn_location = self.lexer.location.clone();
         self.lexer.consume(Token::Identifier)?;
 
+        // a path through nested modules, e.g. `a::b::f`, names the module
+        // `a_b` they are flattened into
+        while self.lexer.is_token(Token::Colon) {
+            self.lexer.consume(Token::Colon)?;
+            if !self.lexer.is_token(Token::Colon) {
+                Err((QccErrorKind::ExpectedColon, self.lexer.location.clone()))?
+            }
+            self.lexer.consume(Token::Colon)?;
+            if !self.lexer.is_token(Token::Identifier) {
+                Err((QccErrorKind::ExpectedFnName, self.lexer.location.clone()))?
+            }
+            mod_name = format!("{}_{}", mod_name, fn_name);
+            fn_name = self.lexer.identifier();
+            fn_location = self.lexer.location.clone();
+            self.lexer.consume(Token::Identifier)?;
+        }
+
         if !self.lexer.is_token(Token::Semicolon) {
             Err((QccErrorKind::ExpectedSemicolon, line_loc))?
         }
-        self.lexer.consume(Token::Semicolon);
+        self.lexer.consume(Token::Semicolon)?;
+
+        if qast.into_iter().all(|module| module.get_name() != mod_name) {
+            // errors of the module's own file are reported while parsing it
+            let at_import = |err| (err, mod_location.clone());
+            let module = self.parse_sibling_module(&mod_name).map_err(at_import)?;
+            *qast = std::mem::take(qast).merge(module).map_err(at_import)?;
+        }
+
+        // TODO: Move these checks when mod_name and fn_name are parsed. That
+        // way it can return QccErrorLoc back. But this may be more costly!
+        let mut unknown_module = true;
+        let mut visited = vec![];
+        'reexported: loop {
+            visited.push(mod_name.clone());
+            for module in &*qast {
+                if module.get_name() == mod_name {
+                    unknown_module = false;
+                    for function in &*module {
+                        if *function.get_name() == fn_name {
+                            if let Some(op) = function.get_attrs().operator() {
+                                self.add_operator(op.clone(), fn_name.clone());
+                            }
+                            return Ok((mod_name, fn_name));
+                        }
+                    }
+                    if let Some(origin) = module.reexport(&fn_name) {
+                        if visited.contains(origin) {
+                            Err((QccErrorKind::CyclicImport, fn_location.clone()))?
+                        }
+                        mod_name = origin.clone();
+                        continue 'reexported;
+                    }
+                }
+            }
+            break;
+        }
+
+        if unknown_module {
+            Err((QccErrorKind::UnknownModName, mod_location))?
+        } else {
+            Err((QccErrorKind::UnknownImport, fn_location))?
+        }
+    }
+
+    /// Parses the file of a module next to the file being parsed, e.g.
+    /// `<dir>/foo.ql` for `import foo::bar;` in `<dir>/main.ql`. Importing a
+    /// file which is still being parsed, because it imports this one directly
+    /// or not, is a cyclic import.
+    fn parse_sibling_module(&mut self, mod_name: &Ident) -> Result<Qast> {
+        let this = self.lexer.location.path();
+        let dir = Path::new(&this).parent().unwrap_or(Path::new(""));
+        let path = dir.join(format!("{}.ql", mod_name));
+        if !path.is_file() {
+            Err(QccErrorKind::NoFile)?
+        }
+        if self.importers.contains(mod_name) || self.file_stem() == *mod_name {
+            Err(QccErrorKind::CyclicImport)?
+        }
+
+        self.parse_dependency(&path)
+    }
+
+    /// Name of the file being parsed without its extension, which is also the
+    /// name of its module.
+    fn file_stem(&self) -> Ident {
+        let path = self.lexer.location.path();
+        let stem = Path::new(&path).file_stem().unwrap_or_default();
+        stem.to_string_lossy().into()
+    }
+
+    /// Parses another file which this one depends on, keeping its errors
+    /// along with those of this file.
+    fn parse_dependency(&mut self, path: &Path) -> Result<Qast> {
+        let src: String = path.to_string_lossy().into();
+        let mut importers = self.importers.clone();
+        importers.push(self.file_stem());
+        let mut parser = Self {
+            config: self.config.clone(),
+            lexer: Lexer::new(std::fs::read(path)?, src.clone()).into(),
+            aliases: Default::default(),
+            operators: Default::default(),
+            importers,
+            diagnostics: Default::default(),
+        };
+        let parsed = parser.parse(&src);
+        self.diagnostics.append(parser.take_diagnostics());
+        parsed
+    }
+
+    /// Parses `use <name>@<version>;` and merges the module `<name>` of the
+    /// prelude `<name>@<version>.ql`, looked up in include paths.
+    fn parse_use(&mut self, qast: &mut Qast) -> core::result::Result<(), QccErrorLoc> {
+        let line_loc = self.lexer.location.clone();
+        self.lexer.consume(Token::Use)?;
+
+        if !self.lexer.is_token(Token::Identifier) {
+            Err((QccErrorKind::ExpectedMod, self.lexer.location.clone()))?
+        }
+        let name = self.lexer.identifier();
+        let location = self.lexer.location.clone();
+        self.lexer.consume(Token::Identifier)?;
+
+        if !self.lexer.is_token(Token::At) {
+            Err((QccErrorKind::ParseError, self.lexer.location.clone()))?
+        }
+        self.lexer.consume(Token::At)?;
+        let version = self.lexer.identifier();
+        if !self.lexer.is_token(Token::Digit) || version.parse::<u32>().is_err() {
+            Err((QccErrorKind::UnexpectedDigit, self.lexer.location.clone()))?
+        }
+        self.lexer.consume(Token::Digit)?;
+
+        if !self.lexer.is_token(Token::Semicolon) {
+            Err((QccErrorKind::ExpectedSemicolon, line_loc))?
+        }
+        self.lexer.consume(Token::Semicolon)?;
+
+        if qast.into_iter().any(|module| module.get_name() == name) {
+            return Ok(());
+        }
+        let at_use = |err| (err, location.clone());
+        let path = find_prelude(&self.config.include_paths, &name, &version).map_err(at_use)?;
+        let prelude = self.parse_dependency(&path).map_err(at_use)?;
+        *qast = std::mem::take(qast).merge(prelude).map_err(at_use)?;
+        Ok(())
+    }
+
+    /// Parses `alias <name> = <module>;`, after which `<name>` can be used in
+    /// place of an already defined module in imports and qualified calls.
+    fn parse_alias(&mut self, qast: &Qast) -> core::result::Result<(), QccErrorLoc> {
+        let line_loc = self.lexer.location.clone();
+        self.lexer.consume(Token::Alias)?;
+
+        if !self.lexer.is_token(Token::Identifier) {
+            Err((QccErrorKind::ExpectedMod, self.lexer.location.clone()))?
+        }
+        let alias = self.lexer.identifier();
+        self.lexer.consume(Token::Identifier)?;
+
+        if !self.lexer.is_token(Token::Assign) {
+            Err((QccErrorKind::ExpectedAssign, self.lexer.location.clone()))?
+        }
+        self.lexer.consume(Token::Assign)?;
+
+        if !self.lexer.is_token(Token::Identifier) {
+            Err((QccErrorKind::ExpectedMod, self.lexer.location.clone()))?
+        }
+        let mod_name = self.lexer.identifier();
+        let mod_location = self.lexer.location.clone();
+        self.lexer.consume(Token::Identifier)?;
+
+        if !self.lexer.is_token(Token::Semicolon) {
+            Err((QccErrorKind::ExpectedSemicolon, line_loc))?
+        }
+        self.lexer.consume(Token::Semicolon)?;
+
+        if qast.into_iter().all(|module| module.get_name() != mod_name) {
+            Err((QccErrorKind::UnknownModName, mod_location))?
+        }
+        self.aliases.insert(alias, mod_name);
+        Ok(())
+    }
+
+    /// Parses `measure <qubits> -> <bits>` into a call to the `measure`
+    /// builtin with both variables as arguments. The basis of
+    /// `measure(<qubits>, <basis>) -> <bits>` is passed as a third argument,
+    /// which is checked during inference.
+    fn parse_measure(&mut self) -> Result<QccCell<Expr>> {
+        if !self.lexer.is_token(Token::Measure) {
+            return Err(QccErrorKind::ExpectedExpr)?;
+        }
+        let location = self.lexer.location.clone();
+        self.lexer.consume(Token::Measure)?;
+
+        let with_basis = self.lexer.is_token(Token::OParenth);
+        if with_basis {
+            self.lexer.consume(Token::OParenth)?;
+        }
+        let qubits = self.parse_measured_var()?;
+        let basis = if with_basis {
+            self.expect_token(Token::Comma)?;
+            let basis = self.parse_measured_var()?;
+            self.expect_token(Token::CParenth)?;
+            Some(basis)
+        } else {
+            None
+        };
+        self.expect_token(Token::Arrow)?;
+        let bits = self.parse_measured_var()?;
+
+        let mut args = vec![qubits, bits];
+        args.extend(basis);
+
+        let function = FunctionAST::new(
+            Builtin::Measure.to_string(),
+            location,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+
+        Ok(Expr::FnCall(function, args).into())
+    }
+
+    /// Parses a variable given to `measure`.
+    fn parse_measured_var(&mut self) -> Result<QccCell<Expr>> {
+        if !self.lexer.is_token(Token::Identifier) {
+            return Err(QccErrorKind::ExpectedExpr)?;
+        }
+        let var = VarAST::new(self.lexer.identifier(), self.lexer.location.clone());
+        self.lexer.consume(Token::Identifier)?;
+        Ok(Expr::Var(var).into())
+    }
+
+    /// Consumes `token`, which must be the next one.
+    fn expect_token(&mut self, token: Token) -> Result<()> {
+        if !self.lexer.is_token(token) {
+            return Err(QccErrorKind::UnexpectedExpr)?;
+        }
+        Ok(self.lexer.consume(token)?)
+    }
+
+    /// Parses a conditional, `if <cond> { <truth> } else { <otherwise> }`.
+    /// The condition may be parenthesized and the else block is optional. An
+    /// `else if` is a conditional of its own in the else block.
+    fn parse_if(&mut self) -> Result<QccCell<Expr>> {
+        self.expect_token(Token::If)?;
+        let cond = if self.lexer.is_token(Token::Let) {
+            self.parse_if_let()?
+        } else {
+            self.parse_expr()?
+        };
+        let truth = self.parse_block()?;
+
+        let mut otherwise = vec![];
+        if self.lexer.is_token(Token::Else) {
+            self.lexer.consume(Token::Else)?;
+            if self.lexer.is_token(Token::If) {
+                otherwise.push(self.parse_if()?);
+            } else {
+                otherwise = self.parse_block()?;
+            }
+        }
+
+        Ok(Expr::Conditional(cond, truth, otherwise).into())
+    }
+
+    /// Parses the condition of `if let <bit> = measure(<qubit>)`, which
+    /// measures the qubit and holds if it is measured as 1. The measured bit
+    /// is bound in the truth block.
+    fn parse_if_let(&mut self) -> Result<QccCell<Expr>> {
+        self.lexer.consume(Token::Let)?;
+        if !self.lexer.is_token(Token::Identifier) {
+            return Err(QccErrorKind::ExpectedLet)?;
+        }
+        let var = VarAST::new(self.lexer.identifier(), self.lexer.location.clone());
+        self.lexer.consume(Token::Identifier)?;
+        if !self.lexer.is_token(Token::Assign) {
+            return Err(QccErrorKind::ExpectedAssign)?;
+        }
+        self.lexer.consume(Token::Assign)?;
+
+        if !self.lexer.is_token(Token::Measure) {
+            return Err(QccErrorKind::ExpectedExpr)?;
+        }
+        let location = self.lexer.location.clone();
+        self.lexer.consume(Token::Measure)?;
+        if !self.lexer.is_token(Token::OParenth) {
+            return Err(QccErrorKind::ExpectedParenth)?;
+        }
+        self.lexer.consume(Token::OParenth)?;
+        let qubit = self.parse_measured_var()?;
+        self.expect_token(Token::CParenth)?;
+
+        let measure = FunctionAST::new(
+            Builtin::Measure.to_string(),
+            location,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        Ok(Expr::Let(var, Expr::FnCall(measure, vec![qubit]).into()).into())
+    }
+
+    /// Parses a loop, `while (<cond>) { <body> }`.
+    fn parse_while(&mut self) -> Result<QccCell<Expr>> {
+        self.expect_token(Token::While)?;
+        if !self.lexer.is_token(Token::OParenth) {
+            return Err(QccErrorKind::ExpectedParenth)?;
+        }
+        let cond = self.parse_expr()?;
+        let body = self.parse_block()?;
 
-        // TODO: Move these checks when mod_name and fn_name are parsed. That
-        // way it can return QccErrorLoc back. But this may be more costly!
-        let mut unknown_module = true;
-        for module in qast {
-            if module.get_name() == mod_name {
-                unknown_module = false;
-                for function in &*module {
-                    if *function.get_name() == fn_name {
-                        return Ok((mod_name, fn_name));
-                    }
-                }
+        Ok(Expr::Loop(cond, body).into())
+    }
+
+    /// Parses `{ <body> }` of a conditional or a loop. It holds the same
+    /// statements as the body of a function, except for returns and nested
+    /// functions.
+    fn parse_block(&mut self) -> Result<Vec<QccCell<Expr>>> {
+        self.expect_token(Token::OCurly)?;
+
+        let mut body = vec![];
+        while !self.lexer.is_token(Token::CCurly) {
+            match self.lexer.token {
+                Some(Token::Let) => body.push(self.parse_let()?),
+                Some(Token::Measure) => body.push(self.parse_measure()?),
+                Some(Token::If) => body.push(self.parse_if()?),
+                Some(Token::While) => body.push(self.parse_while()?),
+                Some(Token::Identifier) => body.push(self.parse_expr()?),
+                Some(token) => self.lexer.consume(token)?,
+                None => return Err(QccErrorKind::ExpectedFnBodyEnd)?,
             }
         }
+        self.lexer.consume(Token::CCurly)?;
 
-        if unknown_module {
-            Err((QccErrorKind::UnknownModName, mod_location))?
-        } else {
-            Err((QccErrorKind::UnknownImport, fn_location))?
-        }
+        Ok(body)
     }
 
     fn parse_return(&mut self) -> Result<QccCell<Expr>> {
@@ -404,13 +1239,76 @@ impl Parser {
         self.parse_fn_call_args(name, location)
     }
 
+    /// Parses a quantum numeral `0q(<amplitude>, <amplitude>)`, where both
+    /// amplitudes are `float64` expressions. Amplitudes known at compile time
+    /// must be normalized. Basis states are written `0q0` and `0q1`.
+    fn parse_qbit(&mut self) -> Result<QccCell<Expr>> {
+        let numeral = self.lexer.identifier();
+        self.lexer.consume(Token::Qbit)?;
+
+        if numeral != "0q" {
+            let qbit = numeral.parse::<Qbit>()?;
+            return Ok(Expr::Literal(LiteralAST::Lit_Qbit(qbit).into()).into());
+        }
+
+        if !self.lexer.is_token(Token::OParenth) {
+            return Err(QccErrorKind::ExpectedParenth)?;
+        }
+        self.lexer.consume(Token::OParenth)?;
+
+        let amp_0 = self.parse_expr()?;
+
+        if !self.lexer.is_token(Token::Comma) {
+            return Err(QccErrorKind::ExpectedComma)?;
+        }
+        self.lexer.consume(Token::Comma)?;
+
+        let amp_1 = self.parse_expr()?;
+
+        if !self.lexer.is_token(Token::CParenth) {
+            return Err(QccErrorKind::ExpectedParenth)?;
+        }
+        self.lexer.consume(Token::CParenth)?;
+
+        let qbit = Qbit::new(amp_0, amp_1);
+        if !qbit.is_normalized() {
+            return Err(QccErrorKind::UnnormalizedQbit)?;
+        }
+        Ok(Expr::Literal(LiteralAST::Lit_Qbit(qbit).into()).into())
+    }
+
     /// Returns the parsed expression.
     fn parse_expr(&mut self) -> Result<QccCell<Expr>> {
+        let operand = self.parse_operand()?;
+        if self.is_binop() {
+            self.parse_binary_expr_with_lhs(operand)
+        } else {
+            Ok(operand)
+        }
+    }
+
+    /// Parses an expression which is an operand of binary operators, i.e. one
+    /// without binary operators outside of parentheses.
+    fn parse_operand(&mut self) -> Result<QccCell<Expr>> {
         if self.lexer.is_token(Token::Qbit) {
-            let qbit = self.lexer.identifier().parse::<Qbit>()?;
-            self.lexer.consume(Token::Qbit)?;
-            let expr = Expr::Literal(LiteralAST::Lit_Qbit(qbit).into());
-            return Ok(expr.into());
+            return self.parse_qbit();
+        }
+
+        // `~b` is a call to the builtin flipping a bit
+        if self.lexer.is_token(Token::Tilde) {
+            let location = self.lexer.location.clone();
+            self.lexer.consume(Token::Tilde)?;
+            let operand = self.parse_operand()?;
+            let function = FunctionAST::new(
+                Builtin::BitNot.to_string(),
+                location,
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            );
+            return Ok(Expr::FnCall(function, vec![operand]).into());
         }
 
         let mut unary_negative = false;
@@ -431,32 +1329,60 @@ impl Parser {
             ))
             .into();
 
-            if self.lexer.is_none_token(&[
-                Token::OParenth, /* function call */
-                Token::Add,      /* binary expressions */
-                Token::Sub,
-                Token::Mul,
-                Token::Div,
-            ]) {
-                // if none of the above tokens are seen then it is a named
-                // variable
-                return Ok(var);
+            // a call qualified by a module or its alias, `<module>::<fn>(..)`
+            if self.lexer.is_token(Token::Colon) {
+                self.lexer.consume(Token::Colon)?;
+                if !self.lexer.is_token(Token::Colon) {
+                    return Err(QccErrorKind::ExpectedColon)?;
+                }
+                self.lexer.consume(Token::Colon)?;
+
+                if !self.lexer.is_token(Token::Identifier) {
+                    return Err(QccErrorKind::ExpectedFnName)?;
+                }
+                let fn_name = self.lexer.identifier();
+                self.lexer.consume(Token::Identifier)?;
+
+                // calls to other modules are mangled, see `mangle_module`
+                let mod_name = self.aliases.get(&name).cloned().unwrap_or(name);
+                return self.parse_fn_call_args(mangle(&mod_name, &fn_name), location);
+            }
+
+            // `reg[i]` is a call to the builtin indexing a register
+            if self.lexer.is_token(Token::OBracket) {
+                self.lexer.consume(Token::OBracket)?;
+                let index = self.parse_expr()?;
+                if !self.lexer.is_token(Token::CBracket) {
+                    return Err(QccErrorKind::UnexpectedExpr)?;
+                }
+                self.lexer.consume(Token::CBracket)?;
+                let function = FunctionAST::new(
+                    Builtin::Index.to_string(),
+                    location,
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                );
+                return Ok(Expr::FnCall(function, vec![var, index]).into());
             }
 
             if self.lexer.is_token(Token::OParenth) {
                 // if open parenthesis is seen, then it is a function call
                 self.parse_fn_call_args(name, location)
-            } else if self.lexer.is_any_token(Token::all_binops()) {
-                self.parse_binary_expr_with_lhs(var)
             } else {
-                // NOTE: Comma will always be inside a function call
-                return Err(QccErrorKind::UnexpectedExpr)?;
+                // otherwise it is a named variable
+                Ok(var)
             }
         } else if self.lexer.is_token(Token::Digit) {
             let digit = self.lexer.digit();
             if digit.is_none() {
                 return Err(QccErrorKind::UnexpectedDigit)?;
             }
+            let imaginary = self.lexer.identifier().ends_with('i');
+            let integral = !self.lexer.identifier().contains('.');
+            let integer = self.lexer.integer();
             self.lexer.consume(Token::Digit)?;
 
             let mut digit_value = digit.unwrap();
@@ -464,15 +1390,26 @@ impl Parser {
                 digit_value = -digit_value;
             }
 
-            let digit = Expr::Literal(std::rc::Rc::new(std::cell::RefCell::new(
-                LiteralAST::Lit_Digit(digit_value),
-            )));
-
-            if self.lexer.is_any_token(Token::all_binops()) {
-                return self.parse_binary_expr_with_lhs(digit.into());
-            }
+            let digit = Expr::Literal(std::rc::Rc::new(std::cell::RefCell::new(if imaginary {
+                LiteralAST::Lit_Imag(digit_value)
+            } else if let Some(n) = integer {
+                LiteralAST::Lit_Int(if unary_negative { -n } else { n })
+            } else if integral && Type::I64.represents(digit_value) {
+                LiteralAST::Lit_Int(digit_value as i64)
+            } else {
+                LiteralAST::Lit_Digit(digit_value)
+            })));
 
             Ok(digit.into())
+        } else if self.lexer.is_token(Token::Literal) {
+            if unary_negative {
+                return Err(QccErrorKind::UnexpectedStr)?;
+            }
+            let string = self.lexer.identifier().parse::<LiteralAST>()?;
+            self.lexer.consume(Token::Literal)?;
+            Ok(Expr::Literal(string.into()).into())
+        } else if self.lexer.is_token(Token::OBracket) {
+            self.parse_tensor()
         } else if self.lexer.is_token(Token::OParenth) {
             // This will be a binary expression surrounded by parentheses.
             self.lexer.consume(Token::OParenth)?;
@@ -484,12 +1421,7 @@ impl Parser {
             self.lexer.consume(Token::CParenth)?;
 
             if lhs.is_some() {
-                let lhs = lhs.unwrap();
-                if self.lexer.is_any_token(Token::all_binops()) {
-                    return self.parse_binary_expr_with_lhs(lhs);
-                } else {
-                    return Ok(lhs);
-                }
+                return Ok(lhs.unwrap());
             } else {
                 return Err(QccErrorKind::ExpectedExpr)?;
             }
@@ -498,24 +1430,98 @@ impl Parser {
         }
     }
 
+    /// Parses a tensor literal `[e0, e1, ..]`.
+    fn parse_tensor(&mut self) -> Result<QccCell<Expr>> {
+        let location = self.lexer.location.clone();
+        self.lexer.consume(Token::OBracket)?;
+
+        let mut elems: Vec<QccCell<Expr>> = vec![];
+        while !self.lexer.is_token(Token::CBracket) {
+            elems.push(self.parse_expr()?);
+
+            if !self.lexer.is_any_token(&[Token::Comma, Token::CBracket]) {
+                return Err(QccErrorKind::ExpectedComma)?;
+            }
+            if self.lexer.is_token(Token::Comma) {
+                self.lexer.consume(Token::Comma)?;
+            }
+        }
+        self.lexer.consume(Token::CBracket)?;
+
+        Ok(Expr::Literal(LiteralAST::Lit_Tensor(elems, location).into()).into())
+    }
+
     /// Parses binary expression but the left-most expression is already parsed.
     fn parse_binary_expr_with_lhs(&mut self, lhs: QccCell<Expr>) -> Result<QccCell<Expr>> {
-        if self.lexer.is_none_token(Token::all_binops()) {
+        if !self.is_binop() {
             return Err(QccErrorKind::ExpectedOpcode)?;
         }
 
-        let mut expr = lhs;
+        self.parse_binary_rhs(lhs, 0)
+    }
 
-        while self.lexer.is_any_token(Token::all_binops()) {
-            let op = self.lexer.identifier().parse::<Opcode>()?;
+    /// Folds operators binding at least as tight as `min_precedence` into
+    /// `lhs`, grouping those of the same precedence from the left.
+    fn parse_binary_rhs(
+        &mut self,
+        mut lhs: QccCell<Expr>,
+        min_precedence: u8,
+    ) -> Result<QccCell<Expr>> {
+        while let Some(op) = self.binop()? {
+            if op.precedence() < min_precedence {
+                break;
+            }
             self.lexer.consume(self.lexer.token.unwrap())?;
 
-            let rhs = self.parse_expr()?;
+            let mut rhs = self.parse_operand()?;
+            while let Some(next) = self.binop()? {
+                if next.precedence() <= op.precedence() {
+                    break;
+                }
+                rhs = self.parse_binary_rhs(rhs, next.precedence())?;
+            }
 
-            expr = Expr::BinaryExpr(expr, op, rhs).into();
+            lhs = match op {
+                BinOp::Builtin(op) => Expr::BinaryExpr(lhs, op, rhs).into(),
+                BinOp::Defined(name, location) => {
+                    let function = FunctionAST::new(
+                        name,
+                        location,
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                    );
+                    Expr::FnCall(function, vec![lhs, rhs]).into()
+                }
+            };
         }
 
-        Ok(expr.into())
+        Ok(lhs)
+    }
+
+    /// Checks if the current token is a binary operator.
+    fn is_binop(&self) -> bool {
+        self.lexer.is_any_token(Token::all_binops()) || self.lexer.is_token(Token::Operator)
+    }
+
+    /// Returns the binary operator at the current token, if any.
+    fn binop(&self) -> Result<Option<BinOp>> {
+        if self.lexer.is_any_token(Token::all_binops()) {
+            Ok(Some(BinOp::Builtin(
+                self.lexer.identifier().parse::<Opcode>()?,
+            )))
+        } else if self.lexer.is_token(Token::Operator) {
+            let function = self.operators.get(&self.lexer.identifier());
+            let function = function.ok_or(QccErrorKind::UnknownOpcode)?;
+            Ok(Some(BinOp::Defined(
+                function.clone(),
+                self.lexer.location.clone(),
+            )))
+        } else {
+            Ok(None)
+        }
     }
 
     /// Parse a binary expression.
@@ -551,9 +1557,8 @@ impl Parser {
             if !self.lexer.is_token(Token::Identifier) {
                 return Err(QccErrorKind::ExpectedType)?;
             }
-            let type_ = self.lexer.identifier().parse::<Type>()?;
+            let type_ = self.parse_type()?;
             var.set_type(type_);
-            self.lexer.consume(Token::Identifier)?;
         }
 
         if !self.lexer.is_token(Token::Assign) {
@@ -566,7 +1571,10 @@ impl Parser {
         Ok(Expr::Let(var, val).into())
     }
 
-    fn parse_module(&mut self) -> Result<ModuleAST> {
+    /// Parses a module along with the modules nested in it. Nested modules are
+    /// flattened into modules of their own which follow the enclosing one,
+    /// named after the modules they are in, e.g. `b` in `a` is `a_b`.
+    fn parse_module(&mut self, outer: Option<&Ident>) -> Result<Vec<ModuleAST>> {
         if !self.lexer.is_token(Token::Module) {
             return Err(QccErrorKind::ExpectedMod)?;
         }
@@ -579,6 +1587,9 @@ impl Parser {
             name = sanitize(self.lexer.identifier());
             self.lexer.consume(Token::Identifier)?;
         }
+        if let Some(outer) = outer {
+            name = format!("{}_{}", outer, name);
+        }
 
         if !self.lexer.is_token(Token::OCurly) {
             return Err(QccErrorKind::ExpectedMod)?;
@@ -586,14 +1597,32 @@ impl Parser {
         self.lexer.consume(Token::OCurly)?;
 
         let mut functions: Vec<QccCell<FunctionAST>> = Default::default();
+        let mut gates = vec![];
+        let mut nested = vec![];
         while !self.lexer.is_token(Token::CCurly) {
-            let function = self.parse_function()?;
-            functions.push(std::rc::Rc::new(function.into()));
+            if self.lexer.is_token(Token::Gate) {
+                gates.push(self.parse_gate()?);
+            } else if self.lexer.is_token(Token::Module) {
+                nested.extend(self.parse_module(Some(&name))?);
+            } else {
+                let function = self.parse_function()?;
+                functions.push(std::rc::Rc::new(function.into()));
+            }
         }
 
         self.lexer.consume(Token::CCurly)?;
 
-        Ok(ModuleAST::new(name, location, functions))
+        let mut module = ModuleAST::new(name, location, functions);
+        for gate in gates {
+            module.append_gate(gate);
+        }
+        nested.insert(0, module);
+        Ok(nested)
+    }
+
+    /// Takes the errors found in the source so far, leaving none behind.
+    pub(crate) fn take_diagnostics(&mut self) -> Diagnostics {
+        std::mem::take(&mut self.diagnostics)
     }
 
     /* TODO: If we have more than one quale file in a parsing session
@@ -615,29 +1644,42 @@ impl Parser {
             (_, module_name) = module_basename.unwrap();
         }
         // TODO: We need a mangler for sanitizing module name.
-        let module_name: Ident = module_name.trim_end_matches(".ql").into();
+        let module_name = module_name.trim_end_matches(".ql");
+        // a prelude, e.g. `std@1.ql`, defines the module without its version
+        let module_name: Ident = match module_name.split_once('@') {
+            Some((name, _)) => name.into(),
+            None => module_name.into(),
+        };
         let module_location = Location::new(src, 1, 1);
         // qast.add_module_info(module_name.clone(), module_location.clone());
         // representation for this module
         let mut this = ModuleAST::new(sanitize(module_name), module_location, Default::default());
         let mut imports = Vec::new();
 
-        // TODO: Move this entirely in parse_module, parse_module should return
-        // a Qast and it can recursively call itself when `module` is seen
-        // inside the file.
-        self.lexer.next_token()?;
+        if let Err(e) = self.lexer.next_token() {
+            let err: QccErrorLoc = (e, self.lexer.location.clone()).into();
+            self.diagnostics.error_on_line(err, self.lexer.line());
+            Err(QccErrorKind::ParseError)?
+        }
         loop {
             if self.lexer.token.is_none() {
                 break;
             }
             if self.lexer.is_token(Token::Module) {
-                match self.parse_module() {
-                    Ok(module) => qast.append_module(module),
+                match self.parse_module(None) {
+                    Ok(modules) => modules
+                        .into_iter()
+                        .for_each(|module| qast.append_module(module)),
                     Err(e) => {
                         seen_errors = true;
 
+                        // the lexer is stuck at a character it can't tokenize
+                        let stuck = e.is(QccErrorKind::LexerError);
                         let err: QccErrorLoc = (e, self.lexer.location.clone()).into();
-                        err.report(self.lexer.line());
+                        self.diagnostics.error_on_line(err, self.lexer.line());
+                        if stuck {
+                            break;
+                        }
                     }
                 }
             } else if self.lexer.is_token(Token::Hash) || self.lexer.is_token(Token::Function) {
@@ -646,24 +1688,110 @@ impl Parser {
                     Err(e) => {
                         seen_errors = true;
 
+                        // the lexer is stuck at a character it can't tokenize
+                        let stuck = e.is(QccErrorKind::LexerError);
+                        let err: QccErrorLoc = (e, self.lexer.location.clone()).into();
+                        self.diagnostics.error_on_line(err, self.lexer.line());
+                        if stuck {
+                            break;
+                        }
+                    }
+                }
+            } else if self.lexer.is_token(Token::Gate) {
+                match self.parse_gate() {
+                    Ok(gate) => this.append_gate(gate),
+                    Err(e) => {
+                        seen_errors = true;
+
+                        let stuck = e.is(QccErrorKind::LexerError);
                         let err: QccErrorLoc = (e, self.lexer.location.clone()).into();
-                        err.report(self.lexer.line());
+                        self.diagnostics.error_on_line(err, self.lexer.line());
+                        if stuck {
+                            break;
+                        }
                     }
                 }
             } else {
-                if self.lexer.is_token(Token::Import) {
+                if self.lexer.is_token(Token::Pub) {
+                    // only imports can be re-exported
                     let line = self.lexer.line();
-                    match self.parse_import(&qast) {
+                    let location = self.lexer.location.clone();
+                    let parsed = match self.lexer.consume(Token::Pub) {
+                        Ok(()) if self.lexer.is_token(Token::Import) => {
+                            self.parse_import(&mut qast)
+                        }
+                        Ok(()) => Err((QccErrorKind::ParseError, location).into()),
+                        Err(e) => Err((e, location).into()),
+                    };
+                    match parsed {
                         Ok((mod_name, fn_name)) => {
+                            this.add_reexport(fn_name.clone(), mod_name.clone());
                             imports.push((mod_name, fn_name));
                         }
-                        Err(err) => {
+                        Err(mut err) => {
+                            seen_errors = true;
+
+                            let stuck = err.get_error().is(QccErrorKind::LexerError);
+                            if stuck {
+                                err.set_loc(self.lexer.location.clone());
+                            }
+                            self.diagnostics.error_on_line(err, line);
+                            if stuck {
+                                break;
+                            }
+                        }
+                    }
+                } else if self.lexer.is_token(Token::Import) {
+                    let line = self.lexer.line();
+                    match self.parse_import(&mut qast) {
+                        Ok((mod_name, fn_name)) => {
+                            imports.push((mod_name, fn_name));
+                        }
+                        Err(mut err) => {
                             seen_errors = true;
-                            err.report(line);
+
+                            let stuck = err.get_error().is(QccErrorKind::LexerError);
+                            if stuck {
+                                err.set_loc(self.lexer.location.clone());
+                            }
+                            self.diagnostics.error_on_line(err, line);
+                            if stuck {
+                                break;
+                            }
                         }
                     }
-                } else {
-                    self.lexer.consume(self.lexer.token.unwrap())?;
+                } else if self.lexer.is_token(Token::Alias) {
+                    let line = self.lexer.line();
+                    if let Err(mut err) = self.parse_alias(&qast) {
+                        seen_errors = true;
+
+                        let stuck = err.get_error().is(QccErrorKind::LexerError);
+                        if stuck {
+                            err.set_loc(self.lexer.location.clone());
+                        }
+                        self.diagnostics.error_on_line(err, line);
+                        if stuck {
+                            break;
+                        }
+                    }
+                } else if self.lexer.is_token(Token::Use) {
+                    let line = self.lexer.line();
+                    if let Err(mut err) = self.parse_use(&mut qast) {
+                        seen_errors = true;
+
+                        let stuck = err.get_error().is(QccErrorKind::LexerError);
+                        if stuck {
+                            err.set_loc(self.lexer.location.clone());
+                        }
+                        self.diagnostics.error_on_line(err, line);
+                        if stuck {
+                            break;
+                        }
+                    }
+                } else if let Err(e) = self.lexer.consume(self.lexer.token.unwrap()) {
+                    let err: QccErrorLoc = (e, self.lexer.location.clone()).into();
+                    self.diagnostics.error_on_line(err, self.lexer.line());
+                    Err(QccErrorKind::ParseError)?
                 }
             }
         }
@@ -672,7 +1800,8 @@ impl Parser {
         for (mod_name, fn_name) in imports {
             mangle_module(&mut this, mod_name, fn_name);
         }
-        qast.append_module(this);
+        // modules must be unique across every file parsed, imported ones too
+        qast = qast.merge(Qast::new(vec![std::rc::Rc::new(this.into())]))?;
 
         if seen_errors {
             Err(QccErrorKind::ParseError)?
@@ -681,3 +1810,800 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::infer;
+    use crate::optimizer::passes::gather_callees;
+
+    #[test]
+    fn check_print_config() -> Result<()> {
+        let config = Parser::parse_cmdline(vec!["tests/test1.ql", "-O2"])?.unwrap();
+        let printed = config.to_string();
+        assert!(printed.contains("Stage: O2"));
+        assert!(
+            printed.contains("Passes: constfold, angle-reduce, dse, dce, gate-cancel, schedule")
+        );
+        assert!(printed.contains("Emit: qasm"));
+
+        // nothing is left to do after printing
+        let args = vec!["--print-config", "tests/test1.ql", "-O2"];
+        assert!(Parser::parse_cmdline(args)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_cmdline_flags() -> Result<()> {
+        let parse = |flag: &str| Parser::parse_cmdline(vec!["tests/test1.ql", flag]);
+        let flags: [(&str, fn(&Config) -> bool); 11] = [
+            ("--dump-ast", |config| config.dump_ast),
+            ("--dump-ast-only", |config| config.dump_ast_only),
+            ("--dump-qasm", |config| config.dump_qasm),
+            ("--dump-symbols", |config| config.dump_symbols),
+            ("--ast-locations=off", |config| !config.ast_locations),
+            ("--analyze", |config| config.analyzer.status),
+            ("--strict", |config| config.strict),
+            ("--fail-on-warning", |config| config.fail_on_warning),
+            ("-Werror", |config| config.deny_warnings),
+            ("-d", |config| config.debug),
+            ("--debug", |config| config.debug),
+        ];
+        let default = parse("--ast-locations=on")?.unwrap();
+        for (flag, is_set) in flags {
+            assert!(!is_set(&default), "{}", flag);
+            assert!(is_set(&parse(flag)?.unwrap()), "{}", flag);
+        }
+
+        // dumps are only named `--dump-*`, `--print-config` is the odd one out
+        for flag in ["--print-ast", "--print-ast-only", "--print-qasm"] {
+            assert!(parse(flag).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_version() -> Result<()> {
+        // like `--help`, printing the version doesn't need a source file
+        assert!(Parser::parse_cmdline(vec!["--version"])?.is_none());
+        assert!(Parser::parse_cmdline(vec!["-v"])?.is_none());
+        assert!(Config::version().starts_with(env!("CARGO_PKG_VERSION")));
+        Ok(())
+    }
+
+    #[test]
+    fn check_help_without_source() -> Result<()> {
+        // foo.ql doesn't exist, which isn't checked when help is asked for
+        for args in [
+            vec!["foo.ql", "--help"],
+            vec!["--help", "foo.ql"],
+            vec!["-h"],
+            vec!["--no-such-flag", "-h"],
+            vec!["--tol", "--help"],
+            vec!["foo.ql", "--version"],
+        ] {
+            assert!(Parser::parse_cmdline(args)?.is_none());
+        }
+        assert!(Parser::parse_cmdline(vec!["-d"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_opt_levels() -> Result<()> {
+        let levels = [
+            ("-O0", OptLevel::None),
+            ("-O1", OptLevel::Less),
+            ("-O2", OptLevel::Default),
+            ("-O3", OptLevel::Aggressive),
+            ("-Og", OptLevel::Debug),
+            ("-Oz", OptLevel::Size),
+        ];
+        for (flag, level) in levels {
+            let config = Parser::parse_cmdline(vec!["tests/test1.ql", flag])?.unwrap();
+            assert_eq!(config.optimizer.level, level);
+        }
+
+        let config = Parser::parse_cmdline(vec!["tests/test1.ql", "-Oz"])?.unwrap();
+        let printed = config.to_string();
+        assert!(printed.contains("Stage: Oz (size)"));
+        assert!(printed.contains("Passes: constfold, angle-reduce, dse, dce, gate-cancel\n"));
+
+        // -O3 is documented as an alias of -O2
+        let passes = |flag| -> Result<String> {
+            let config = Parser::parse_cmdline(vec!["tests/test1.ql", flag])?.unwrap();
+            let printed = config.to_string();
+            Ok(printed
+                .lines()
+                .find(|l| l.starts_with("Passes:"))
+                .unwrap()
+                .to_string())
+        };
+        assert_eq!(passes("-O3")?, passes("-O2")?);
+
+        let err = Parser::parse_cmdline(vec!["tests/test1.ql", "-O7"]).err();
+        assert_eq!(err, Some(QccErrorKind::CmdlineErr.into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_output_dir() -> Result<()> {
+        use crate::codegen::{circuit, qasm::QasmModule, statevector, Translator};
+
+        // lib and bin tests run at the same time, so each gets its own
+        let dir = std::env::temp_dir().join(format!("qcc-output-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let mut parser = Parser::new(vec!["tests/hadamard.ql", "--output-dir", dir])?.unwrap();
+        let config = parser.get_config();
+        assert_eq!(
+            Path::new(&config.optimizer.asm),
+            Path::new(dir).join("hadamard.s")
+        );
+
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        std::fs::create_dir_all(dir)?;
+        let circuits = circuit::lower(ast)?;
+        statevector::generate(&circuits, &config.optimizer.asm)?;
+        QasmModule::translate(circuits)?.generate(&config.optimizer.asm)?;
+        assert!(Path::new(dir).join("hadamard.s").is_file());
+        assert!(Path::new(dir).join("hadamard.json").is_file());
+        std::fs::remove_dir_all(dir)?;
+
+        // a relative output is inside the directory
+        let args = vec![
+            "tests/hadamard.ql",
+            "-o",
+            "out/h.s",
+            "--output-dir",
+            "build",
+        ];
+        let config = Parser::parse_cmdline(args)?.unwrap();
+        assert_eq!(Path::new(&config.optimizer.asm), Path::new("build/out/h.s"));
+
+        // stdout isn't a path, so it isn't moved into the directory
+        let args = vec!["tests/hadamard.ql", "-o", "-", "--output-dir", "build"];
+        let config = Parser::parse_cmdline(args)?.unwrap();
+        assert_eq!(config.optimizer.asm, STDOUT);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_register_size() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/register-size.ql"])?.unwrap();
+        let config = parser.get_config();
+        let ast = parser.parse(&config.analyzer.src)?;
+
+        for module in &ast {
+            for function in &*module {
+                assert_eq!(
+                    *function.get_input_type(),
+                    vec![Type::Qreg(4), Type::Creg(4)]
+                );
+            }
+        }
+
+        let mut parser = Parser::new(vec!["tests/register-size-invalid.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        // `3 / 2` isn't truncated to a size of 1
+        assert_eq!(parser.take_diagnostics().len(), 2);
+
+        let mut parser = Parser::new(vec!["tests/register-size-overflow.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        let errors = parser.take_diagnostics();
+        assert_eq!(errors.len(), 1);
+        assert!(errors
+            .iter()
+            .next()
+            .unwrap()
+            .is(QccErrorKind::IntegerOverflow));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_raw_identifier() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/raw-ident.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        for module in &ast {
+            let names = module
+                .into_iter()
+                .map(|f| f.get_name().clone())
+                .collect::<Vec<Ident>>();
+            assert_eq!(names, ["module", "main"]);
+
+            let main = module.into_iter().last().unwrap();
+            let call = main.into_iter().next().unwrap();
+            let Expr::Let(ref var, ref val) = *call.as_ref().borrow() else {
+                panic!("expected a let binding");
+            };
+            assert_eq!(var.get_type(), Type::F64);
+            let Expr::FnCall(ref f, _) = *val.as_ref().borrow() else {
+                panic!("expected a call");
+            };
+            assert_eq!(f.get_name(), "module");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_nested_modules() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/nested-module.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+
+        let names = ast
+            .into_iter()
+            .map(|module| module.get_name())
+            .collect::<Vec<Ident>>();
+        assert_eq!(names, ["a", "a_b", "nested_module"]);
+
+        // `import a::b::f` resolves calls to `f` inside the nested module
+        let mut callees = vec![];
+        for module in &ast {
+            for function in &*module {
+                if function.get_name() == "main" {
+                    gather_callees(&function, &mut callees);
+                }
+            }
+        }
+        assert_eq!(callees, ["a_b::f"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_nested_tensor() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/nested-tensor.ql"])?.unwrap();
+        let config = parser.get_config();
+        let ast = parser.parse(&config.analyzer.src)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let vals = main
+            .into_iter()
+            .map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(_, ref val) => val.as_ref().borrow().to_string(),
+                _ => panic!("expected a let binding"),
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(vals, ["[x, [y, z]]", "[]", "[[[], []], [[]]]"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_string_literals() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/strings.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let strings = main
+            .into_iter()
+            .map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(ref var, ref val) => {
+                    assert_eq!(var.get_type(), Type::Str);
+                    match *val.as_ref().borrow() {
+                        Expr::Literal(ref lit) => match *lit.as_ref().borrow() {
+                            LiteralAST::Lit_Str(ref bytes) => bytes.clone(),
+                            _ => panic!("expected a string"),
+                        },
+                        _ => panic!("expected a literal"),
+                    }
+                }
+                _ => panic!("expected a let binding"),
+            })
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(strings, [&b"hello"[..], b"say \"hi\"\tthen\nleave"]);
+
+        // escapes are written back as in source
+        let quoted = LiteralAST::Lit_Str(strings[1].clone());
+        assert_eq!(quoted.to_string(), r#""say \"hi\"\tthen\nleave""#);
+
+        for invalid in [r#""a\q""#, r#""a"b""#, "\"a"] {
+            assert!(invalid.parse::<LiteralAST>().is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_while_loops() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/while.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let repeat = module.into_iter().next().unwrap();
+        let body = repeat.into_iter().collect::<Vec<_>>();
+        assert_eq!(body.len(), 2);
+        let Expr::Loop(ref cond, ref loop_body) = *body[0].as_ref().borrow() else {
+            panic!("expected a loop");
+        };
+        assert_eq!(cond.as_ref().borrow().to_string(), "(n: float64 > 0)");
+        let bound = loop_body
+            .iter()
+            .map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(ref var, _) => (var.name().clone(), var.get_type()),
+                _ => panic!("expected a let binding"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bound,
+            [("q".to_string(), Type::Qbit), ("n".to_string(), Type::F64)]
+        );
+        assert_eq!(body[0].as_ref().borrow().get_type(), Type::Unit);
+        assert_eq!(*repeat.get_output_type(), Type::Qbit);
+
+        assert!(format!("{:#}", *repeat)
+            .contains("    while (n: float64 > 0) {\n        q: qubit = h: qubit (q: qubit)\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_retained_comments() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/comments.ql"])?.unwrap();
+        let config = parser.get_config();
+        parser.parse(&config.analyzer.src)?;
+        assert!(parser.comments().is_empty());
+
+        let mut parser = Parser::new(vec!["tests/comments.ql"])?.unwrap();
+        parser.retain_comments();
+        parser.parse(&config.analyzer.src)?;
+        let comments = parser
+            .comments()
+            .iter()
+            .map(|(location, text)| (location.row(), location.col(), text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            [
+                (1, 1, "// flips a qubit"),
+                (3, 5, "// `x` is its own inverse"),
+                (4, 25, "// applied once")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_package() -> Result<()> {
+        let mut parser = Parser::new(vec!["--package", "tests/package"])?.unwrap();
+        let config = parser.get_config();
+        assert_eq!(
+            Path::new(&config.analyzer.src),
+            Path::new("tests/package/main.ql")
+        );
+
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        // sibling files are parsed as they are imported
+        let modules = (&ast)
+            .into_iter()
+            .map(|module| module.get_name())
+            .collect::<Vec<Ident>>();
+        assert_eq!(modules, ["coin", "toss", "main"]);
+
+        let mut parser = Parser::new(vec!["--package", "tests/duplicate-package"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+
+        // `other.ql` imports `main.ql` while being imported by it
+        let mut parser = Parser::new(vec!["--package", "tests/cyclic-package"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        let diagnostics = parser.take_diagnostics();
+        let cyclic = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.is(QccErrorKind::CyclicImport))
+            .unwrap();
+        assert_eq!(
+            cyclic.error().get_loc().borrow().to_string(),
+            "@other.ql:1:8"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_sibling_import() -> Result<()> {
+        // `toss.ql` and `coin.ql` are found next to `main.ql` without
+        // compiling it as a package
+        let mut parser = Parser::new(vec!["tests/package/main.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(config.package.is_none());
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        let modules = (&ast)
+            .into_iter()
+            .map(|module| module.get_name())
+            .collect::<Vec<Ident>>();
+        assert_eq!(modules, ["coin", "toss", "main"]);
+
+        // there is no `nolib.ql` next to `test10.ql`
+        let mut parser = Parser::new(vec!["tests/test10.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        let diagnostics = parser.take_diagnostics();
+        let missing = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.is(QccErrorKind::NoFile))
+            .unwrap();
+        assert_eq!(
+            missing.error().get_loc().borrow().to_string(),
+            "@test10.ql:11:8"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_diamond_import() -> Result<()> {
+        // `lib.ql` is parsed for both `left.ql` and `right.ql`
+        let mut parser = Parser::new(vec!["tests/diamond/main.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let functions = (&ast)
+            .into_iter()
+            .map(|module| {
+                let functions = module
+                    .into_iter()
+                    .map(|function| function.get_name().clone())
+                    .collect::<Vec<Ident>>();
+                (module.get_name(), functions)
+            })
+            .collect::<Vec<(Ident, Vec<Ident>)>>();
+        let module = |name: &str, functions: &[&str]| {
+            let functions = functions.iter().map(|f| f.to_string()).collect();
+            (name.to_string(), functions)
+        };
+        assert_eq!(
+            functions,
+            [
+                module("lib", &["flip", "mix"]),
+                module("left", &["left"]),
+                module("right", &["right"]),
+                module("main", &["main"]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_prelude() -> Result<()> {
+        let mut parser = Parser::new(vec!["-I", "tests/prelude", "tests/use.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert_eq!(config.include_paths, ["tests/prelude"]);
+
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        let std = (&ast)
+            .into_iter()
+            .find(|module| module.get_name() == "std")
+            .unwrap();
+        let functions = std
+            .into_iter()
+            .map(|function| function.get_name().clone())
+            .collect::<Vec<Ident>>();
+        assert_eq!(functions, ["flip"]);
+
+        // calls are mangled wherever they are, even inside nested functions
+        // and tensors
+        let mut parser = Parser::new(vec!["-I", "tests/prelude", "tests/use-nested.ql"])?.unwrap();
+        let config = parser.get_config();
+        let ast = parser.parse(&config.analyzer.src)?;
+        let module = (&ast)
+            .into_iter()
+            .find(|module| module.get_name() != "std")
+            .unwrap();
+        let main = module.into_iter().next().unwrap();
+        let mut callees = vec![];
+        gather_callees(&main, &mut callees);
+        assert_eq!(callees, ["std::flip", "std::flip", "inner"]);
+
+        let mut parser =
+            Parser::new(vec!["-I", "tests/prelude", "tests/use-mismatch.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        let errors = parser.take_diagnostics();
+        assert_eq!(errors.len(), 2);
+        let mut errors = errors.iter();
+        assert!(errors
+            .next()
+            .unwrap()
+            .is(QccErrorKind::PreludeVersionMismatch));
+        assert!(errors.next().unwrap().is(QccErrorKind::UnknownPrelude));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_module_alias() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/alias.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        // calls through the alias resolve to functions of the aliased module
+        infer(&mut ast)?;
+
+        let mut callees = vec![];
+        for module in &ast {
+            for function in &*module {
+                for expr in &*function {
+                    if let Expr::Let(_, ref val) = *expr.as_ref().borrow() {
+                        if let Expr::FnCall(ref f, _) = *val.as_ref().borrow() {
+                            callees.push(f.get_name().clone());
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            callees,
+            ["long_module_name::square", "long_module_name::cube"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_else_if() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/else-if.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let body = main.into_iter().collect::<Vec<_>>();
+        let Expr::Conditional(ref cond, ref truth, ref otherwise) = *body[0].as_ref().borrow()
+        else {
+            panic!("expected a conditional");
+        };
+        assert_eq!(cond.as_ref().borrow().to_string(), "a: bit");
+        assert_eq!(truth.len(), 1);
+        // `else if` is the only instruction of the else block
+        let [ref nested] = otherwise[..] else {
+            panic!("expected a single conditional");
+        };
+        let Expr::Conditional(ref cond, ref truth, ref otherwise) = *nested.as_ref().borrow()
+        else {
+            panic!("expected a nested conditional");
+        };
+        assert_eq!(cond.as_ref().borrow().to_string(), "b: bit");
+        assert_eq!((truth.len(), otherwise.len()), (1, 1));
+
+        assert!(main.to_string().contains(
+            "    } else {
+        if b: bit {
+            q: qubit = y: qubit (q: qubit)
+        } else {
+            q: qubit = z: qubit (q: qubit)
+        }
+    }
+    q: qubit
+"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_operator_precedence() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/precedence.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let vals = main
+            .into_iter()
+            .filter_map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(_, ref val) => Some(val.as_ref().borrow().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vals,
+            [
+                "((1 - 2) - 3)",
+                "(1 + (2 * 3))",
+                "((x: float64 / 2) * 3)",
+                "((x: float64 + 1) < (x: float64 * 2))"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_bitwise_precedence() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/bits.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let parity = module.into_iter().next().unwrap();
+        let lets = parity
+            .into_iter()
+            .filter_map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(ref var, ref val) => {
+                    Some((var.get_type(), val.as_ref().borrow().to_string()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lets,
+            [
+                (Type::Bit, "(a: bit ^ b: bit)".into()),
+                // `&` binds tighter than `|`
+                (Type::Bit, "(~: bit (r: bit) | (a: bit & c: bit))".into())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_attribute_args() -> Result<()> {
+        use crate::attributes::Attribute::*;
+
+        let mut parser = Parser::new(vec!["tests/controlled.ql"])?.unwrap();
+        let config = parser.get_config();
+        let ast = parser.parse(&config.analyzer.src)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let toffoli = module.into_iter().next().unwrap();
+        assert_eq!(toffoli.get_attrs(), &Attributes(vec![Deter, Controlled(2)]));
+        assert!(format!("{toffoli:#}").starts_with("fn [[deter, controlled(2)]] toffoli"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_user_operator() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/operator.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // `**` is desugared to calls to `pow`, typed by its signature
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().nth(1).unwrap();
+        let vals = main
+            .into_iter()
+            .filter_map(|expr| match *expr.as_ref().borrow() {
+                Expr::Let(_, ref val) => Some(val.as_ref().borrow().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vals,
+            [
+                "pow: float64 (theta: float64, 2)",
+                "(1 + (pow: float64 (a: float64, 2) * pow: float64 (pow: float64 (3, 2), 1)))"
+            ]
+        );
+
+        // an operator can't be used before the function defining it
+        let src = "fn main() : f64 {\n    return 2 ** 3;\n}\n";
+        let mut parser = Parser::from_source(src, config.clone());
+        assert!(parser.parse(&config.analyzer.src).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_conditionals() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/if-else.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let body = main.into_iter().collect::<Vec<_>>();
+        let Expr::Conditional(ref cond, ref truth, ref otherwise) = *body[2].as_ref().borrow()
+        else {
+            panic!("expected a conditional");
+        };
+        assert_eq!(cond.as_ref().borrow().to_string(), "(n: float64 > 2)");
+        assert_eq!(truth.len(), 1);
+        // `else if` nests a conditional in the else block
+        let [ref nested] = otherwise[..] else {
+            panic!("expected a single conditional");
+        };
+        assert!(
+            matches!(*nested.as_ref().borrow(), Expr::Conditional(_, _, ref otherwise) if otherwise.len() == 1)
+        );
+
+        assert!(format!("{:#}", *main).contains(
+            "    if b: bit {\n        q: qubit = y: qubit (q: qubit)\n    }\n    q: qubit\n"
+        ));
+        assert!(format!("{:#}", *main).contains(
+            "    } else {\n        if (n: float64 > 1) {\n            q: qubit = h: qubit (q: qubit)\n"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_syntax_errors() -> Result<()> {
+        // parsing goes on with the next function after a broken one
+        let mut parser = Parser::new(vec!["tests/syntax-errors.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+        let errors = parser.take_diagnostics();
+        let locations = errors
+            .iter()
+            .map(|error| {
+                assert!(error.is(QccErrorKind::ExpectedExpr));
+                error.error().get_loc().borrow().to_string()
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            locations,
+            ["@syntax-errors.ql:3:13", "@syntax-errors.ql:8:18"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_stray_character_after_import() -> Result<()> {
+        // the lexer can't step past `%`, so parsing stops instead of retrying
+        let mut parser = Parser::from_source("import %;\n", Config::default());
+        assert!(parser.parse(&"stray.ql".into()).is_err());
+        let errors = parser.take_diagnostics();
+        assert_eq!(errors.iter().count(), 1);
+        assert!(errors
+            .iter()
+            .all(|error| error.is(QccErrorKind::LexerError)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_reexport() -> Result<()> {
+        let mut parser = Parser::new(vec!["--package", "tests/reexport"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        // calls to a re-exported function are resolved to the module defining it
+        let main = (&ast)
+            .into_iter()
+            .find(|module| module.get_name() == "main")
+            .unwrap();
+        let mut callees = vec![];
+        gather_callees(&main.into_iter().next().unwrap(), &mut callees);
+        assert_eq!(callees, ["b::twice", "a::flip"]);
+
+        // `pub` only re-exports imports
+        let mut parser = Parser::new(vec!["tests/reexport/pub-fn.ql"])?.unwrap();
+        let config = parser.get_config();
+        assert!(parser.parse(&config.analyzer.src).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+//! The compilation pipeline, shared by the command line driver and by library
+//! functions compiling sources which don't come from the command line.
+use crate::analyzer::report::AnalysisReport;
+use crate::ast::Qast;
+use crate::codegen::circuit::{self, Circuit};
+use crate::codegen::qasm::{self, QasmGate, QasmModule};
+use crate::codegen::Translator;
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::error::{QccErrorKind, Result};
+use crate::inference::{check_ast, infer_ast};
+use crate::parser::Parser;
+
+/// Parses the source and infers its types, collecting errors of each stage
+/// into `diagnostics`.
+pub(crate) fn check_source(
+    parser: &mut Parser,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Result<Qast> {
+    let qast = parser.parse(&config.analyzer.src);
+    diagnostics.append(parser.take_diagnostics());
+    let mut qast = qast?;
+
+    infer_ast(&mut qast, diagnostics)?;
+    if config.strict {
+        check_ast(&qast, diagnostics)?;
+    }
+    if config.deny_warnings && diagnostics.warning_count() > 0 {
+        Err(QccErrorKind::DeniedWarning)?
+    }
+    Ok(qast)
+}
+
+/// A source compiled down to circuits, which are either emitted as they are
+/// or translated to OpenQASM.
+pub(crate) struct Compiled {
+    /// optimized circuits of quantum functions
+    pub(crate) circuits: Vec<Circuit>,
+    /// gates declared in the source, which are emitted along with circuits
+    pub(crate) gates: Vec<QasmGate>,
+    /// analysis of the optimized source, if `--analyze` asks for it
+    pub(crate) report: Option<AnalysisReport>,
+}
+
+impl Compiled {
+    /// Rounds parameters of circuits as `--float-precision` asks for. States
+    /// are simulated with parameters as computed, so only what is printed is
+    /// rounded.
+    pub(crate) fn round(&mut self, config: &Config) {
+        if let Some(digits) = config.float_precision {
+            self.circuits
+                .iter_mut()
+                .for_each(|c| c.round_params(digits));
+        }
+    }
+
+    /// Translates circuits to OpenQASM of the version `config` asks for.
+    pub(crate) fn translate(self, config: &Config) -> Result<QasmModule> {
+        QasmModule::translate_to(self.circuits, self.gates, config.qasm_version)
+    }
+}
+
+/// Optimizes an inferred source and lowers it into circuits, collecting
+/// warnings and errors into `diagnostics`.
+pub(crate) fn compile(
+    mut qast: Qast,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Result<Compiled> {
+    config.optimizer.optimize(&mut qast, diagnostics);
+    let report = if config.analyzer.status {
+        Some(config.analyzer.analyze(&qast)?)
+    } else {
+        None
+    };
+
+    let gates = qasm::declared_gates(&qast);
+    let mut circuits = match circuit::lower(qast) {
+        Ok(circuits) => circuits,
+        // e.g. at the condition of a loop which can't be unrolled
+        Err(err) => {
+            let kind = *err.get_error().kind();
+            diagnostics.error(err, "");
+            Err(kind)?
+        }
+    };
+    config.optimizer.optimize_circuits(&mut circuits);
+
+    Ok(Compiled {
+        circuits,
+        gates,
+        report,
+    })
+}
+
+/// Parses `src` as if it were read from `path` and infers its types. Errors
+/// are printed before returning.
+pub fn parse_and_infer(src: &str, path: &str, config: &Config) -> Result<Qast> {
+    let mut config = config.clone();
+    config.analyzer.src = path.into();
+    let mut parser = Parser::from_source(src, config.clone());
+
+    let mut diagnostics = Diagnostics::default();
+    let qast = check_source(&mut parser, &config, &mut diagnostics);
+    diagnostics.report();
+    qast
+}
+
+/// Compiles `src` as if it were read from `path` into OpenQASM, optimizing
+/// it as `config` asks for.
+pub fn compile_source(src: &str, path: &str, config: &Config) -> Result<QasmModule> {
+    let mut config = config.clone();
+    config.analyzer.src = path.into();
+    let mut parser = Parser::from_source(src, config.clone());
+
+    let mut diagnostics = Diagnostics::default();
+    let compiled = check_source(&mut parser, &config, &mut diagnostics)
+        .and_then(|qast| compile(qast, &config, &mut diagnostics));
+    diagnostics.report();
+
+    let mut compiled = compiled?;
+    compiled.round(&config);
+    compiled.translate(&config)
+}
@@ -0,0 +1,443 @@
+//! State vector simulation of circuits.
+//!
+//! Qubit `i` is the `i`-th least significant bit of a basis state index, so
+//! amplitudes of `q[1] q[0]` are ordered as |00〉, |01〉, |10〉, |11〉.
+use crate::codegen::circuit::{Circuit, Op, Param};
+use crate::complex::Complex;
+use crate::config::Tolerance;
+use crate::error::QccErrorKind;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+/// A 2x2 unitary in row-major order.
+type Matrix = [[Complex; 2]; 2];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StateVector {
+    amplitudes: Vec<Complex>,
+}
+
+impl StateVector {
+    /// Returns the state |0..0〉 of `qubits` qubits.
+    pub(crate) fn new(qubits: usize) -> Self {
+        let mut amplitudes = vec![Complex::ZERO; 1 << qubits];
+        amplitudes[0] = Complex::ONE;
+        Self { amplitudes }
+    }
+
+    /// Returns the basis state whose index is `index`, e.g. |01〉 for 1.
+    pub(crate) fn basis(qubits: usize, index: usize) -> Self {
+        let mut amplitudes = vec![Complex::ZERO; 1 << qubits];
+        amplitudes[index] = Complex::ONE;
+        Self { amplitudes }
+    }
+
+    pub(crate) fn amplitudes(&self) -> &[Complex] {
+        &self.amplitudes
+    }
+
+    /// Checks if both states are the same up to a global phase, which can't
+    /// be observed. Amplitudes may differ within `tol` due to rounding.
+    pub(crate) fn equals_up_to_phase(&self, other: &Self, tol: Tolerance) -> bool {
+        if self.amplitudes.len() != other.amplitudes.len() {
+            return false;
+        }
+        // relative phase taken from the first amplitude which isn't zero
+        let phase = self
+            .amplitudes
+            .iter()
+            .zip(&other.amplitudes)
+            .find(|(&lhs, _)| !tol.close(lhs, Complex::ZERO))
+            .map(|(&lhs, &rhs)| rhs / lhs)
+            .unwrap_or(Complex::ONE);
+
+        tol.close(phase.abs().into(), Complex::ONE)
+            && self
+                .amplitudes
+                .iter()
+                .zip(&other.amplitudes)
+                .all(|(&lhs, &rhs)| tol.close(lhs * phase, rhs))
+    }
+
+    /// Applies a standard gate or `U` with constant parameters.
+    pub(crate) fn apply_gate(
+        &mut self,
+        name: &str,
+        params: &[f64],
+        qubits: &[usize],
+    ) -> Result<(), QccErrorKind> {
+        // single-qubit gates as defined in `qelib1.inc`, i.e. in terms of U
+        let u = match (name, params) {
+            ("U" | "u3", &[theta, phi, lambda]) => (theta, phi, lambda),
+            ("u2", &[phi, lambda]) => (FRAC_PI_2, phi, lambda),
+            ("u1" | "rz", &[lambda]) => (0.0, 0.0, lambda),
+            ("rx", &[theta]) => (theta, -FRAC_PI_2, FRAC_PI_2),
+            ("ry", &[theta]) => (theta, 0.0, 0.0),
+            ("h", []) => (FRAC_PI_2, 0.0, PI),
+            ("x", []) => (PI, 0.0, PI),
+            ("y", []) => (PI, FRAC_PI_2, FRAC_PI_2),
+            ("z", []) => (0.0, 0.0, PI),
+            ("s", []) => (0.0, 0.0, FRAC_PI_2),
+            ("t", []) => (0.0, 0.0, FRAC_PI_4),
+            ("cx" | "CX", []) => {
+                let &[control, target] = qubits else {
+                    Err(QccErrorKind::TranslationError)?
+                };
+                self.apply_cx(control, target);
+                return Ok(());
+            }
+            ("swap", []) => {
+                let &[a, b] = qubits else {
+                    Err(QccErrorKind::TranslationError)?
+                };
+                self.apply_swap(a, b);
+                return Ok(());
+            }
+            _ => Err(QccErrorKind::TranslationError)?,
+        };
+
+        let &[qubit] = qubits else {
+            Err(QccErrorKind::TranslationError)?
+        };
+        self.apply(unitary(u), qubit);
+        Ok(())
+    }
+
+    fn apply(&mut self, matrix: Matrix, qubit: usize) {
+        let mask = 1 << qubit;
+        for i in (0..self.amplitudes.len()).filter(|i| i & mask == 0) {
+            let (a0, a1) = (self.amplitudes[i], self.amplitudes[i | mask]);
+            self.amplitudes[i] = matrix[0][0] * a0 + matrix[0][1] * a1;
+            self.amplitudes[i | mask] = matrix[1][0] * a0 + matrix[1][1] * a1;
+        }
+    }
+
+    fn apply_cx(&mut self, control: usize, target: usize) {
+        let (control, target) = (1 << control, 1 << target);
+        for i in (0..self.amplitudes.len()).filter(|i| i & control != 0 && i & target == 0) {
+            self.amplitudes.swap(i, i | target);
+        }
+    }
+
+    fn apply_swap(&mut self, a: usize, b: usize) {
+        let (a, b) = (1 << a, 1 << b);
+        for i in (0..self.amplitudes.len()).filter(|i| i & a != 0 && i & b == 0) {
+            self.amplitudes.swap(i, i ^ a ^ b);
+        }
+    }
+
+    /// Probability of measuring `qubit` as 1.
+    pub(crate) fn probability(&self, qubit: usize) -> f64 {
+        let mask = 1 << qubit;
+        (0..self.amplitudes.len())
+            .filter(|i| i & mask != 0)
+            .map(|i| self.amplitudes[i].norm_sqr())
+            .sum()
+    }
+
+    /// Resets a qubit to |0〉 without measuring it. This is exact as long as
+    /// the qubit isn't entangled with the rest, which holds for freshly
+    /// allocated and freed qubits.
+    pub(crate) fn reset(&mut self, qubit: usize) {
+        let mask = 1 << qubit;
+        for i in (0..self.amplitudes.len()).filter(|i| i & mask == 0) {
+            let (a0, a1) = (self.amplitudes[i], self.amplitudes[i | mask]);
+            let phase = if a0.abs() == 0.0 { a1 } else { a0 };
+            let norm = (a0.norm_sqr() + a1.norm_sqr()).sqrt();
+            self.amplitudes[i] = if phase.abs() == 0.0 {
+                Complex::ZERO
+            } else {
+                phase * (norm / phase.abs()).into()
+            };
+            self.amplitudes[i | mask] = Complex::ZERO;
+        }
+    }
+}
+
+/// Returns `U(theta, phi, lambda)`.
+fn unitary((theta, phi, lambda): (f64, f64, f64)) -> Matrix {
+    let (cos, sin) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    [
+        [cos.into(), -Complex::from_phase(lambda) * sin.into()],
+        [
+            Complex::from_phase(phi) * sin.into(),
+            Complex::from_phase(phi + lambda) * cos.into(),
+        ],
+    ]
+}
+
+/// Runs a circuit from |0..0〉 skipping its measurements, so the returned
+/// state is the one right before any collapse. Operations conditioned on a
+/// measurement with a certain outcome are followed, but gates whose
+/// parameters or conditions are only known at runtime can't be simulated.
+pub(crate) fn simulate_unmeasured(circuit: &Circuit) -> Result<StateVector, QccErrorKind> {
+    Ok(simulate_measured(circuit)?.0)
+}
+
+/// Runs a circuit from each basis state, which gives the columns of its
+/// unitary matrix. Measurements, resets and conditionals aren't unitary.
+pub(crate) fn simulate_unitary(circuit: &Circuit) -> Result<Vec<StateVector>, QccErrorKind> {
+    if !circuit.ops.iter().all(|op| matches!(op, Op::Gate { .. })) {
+        Err(QccErrorKind::NonUnitary)?
+    }
+    (0..1 << circuit.qubits)
+        .map(|index| {
+            simulate(
+                circuit,
+                StateVector::basis(circuit.qubits, index),
+                |_, _, _| None,
+            )
+        })
+        .collect()
+}
+
+/// Runs a circuit from |0..0〉 and returns its state along with the value of
+/// each classical bit whose measurement has a certain outcome. A random
+/// outcome collapses the state in a way that isn't tracked, so bits measured
+/// after it are unknown. Operations conditioned on a known bit are run if it
+/// has the value they expect.
+fn simulate_measured(circuit: &Circuit) -> Result<(StateVector, Vec<Option<bool>>), QccErrorKind> {
+    let tol = Tolerance::default();
+    let mut bits = vec![None; circuit.clbits];
+    let mut collapsed = false;
+    let state = simulate(
+        circuit,
+        StateVector::new(circuit.qubits),
+        |state, qubit, clbit| {
+            let one = state.probability(qubit);
+            let outcome = match one {
+                _ if collapsed => None,
+                _ if tol.close(one.into(), Complex::ZERO) => Some(false),
+                _ if tol.close(one.into(), Complex::ONE) => Some(true),
+                _ => None,
+            };
+            collapsed |= outcome.is_none();
+            bits[clbit] = outcome;
+            outcome
+        },
+    )?;
+    Ok((state, bits))
+}
+
+/// Runs a circuit from `state`, leaving the state as is on measurements, which
+/// are handed to `measure` as the state, qubit and classical bit. It returns
+/// the outcome of the measurement if it is known.
+fn simulate(
+    circuit: &Circuit,
+    mut state: StateVector,
+    mut measure: impl FnMut(&StateVector, usize, usize) -> Option<bool>,
+) -> Result<StateVector, QccErrorKind> {
+    let mut bits = vec![None; circuit.clbits];
+    for op in &circuit.ops {
+        apply(op, &mut state, &mut bits, &mut measure)?;
+    }
+    Ok(state)
+}
+
+fn apply(
+    op: &Op,
+    state: &mut StateVector,
+    bits: &mut [Option<bool>],
+    measure: &mut impl FnMut(&StateVector, usize, usize) -> Option<bool>,
+) -> Result<(), QccErrorKind> {
+    match op {
+        Op::Reset(qubit) => state.reset(*qubit),
+        Op::Measure { qubit, clbit } => bits[*clbit] = measure(state, *qubit, *clbit),
+        // classical bits which aren't measured yet are only known when
+        // running the circuit
+        Op::Conditional { clbit, value, op } => match bits[*clbit] {
+            Some(bit) if bit == *value => apply(op, state, bits, measure)?,
+            Some(_) => {}
+            None => Err(QccErrorKind::TranslationError)?,
+        },
+        Op::Gate {
+            name,
+            params,
+            qubits,
+        } => {
+            let params = params
+                .iter()
+                .map(Param::value)
+                .collect::<Option<Vec<f64>>>()
+                .ok_or(QccErrorKind::TranslationError)?;
+            state.apply_gate(name, &params, qubits)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_state_vector() {
+        let close = |lhs: Complex, rhs: Complex| (lhs - rhs).abs() < 1e-9;
+        let half = Complex::from(std::f64::consts::FRAC_1_SQRT_2);
+
+        // bell state
+        let mut state = StateVector::new(2);
+        assert_eq!(state.apply_gate("h", &[], &[0]), Ok(()));
+        assert_eq!(state.apply_gate("cx", &[], &[0, 1]), Ok(()));
+        let expected = [half, Complex::ZERO, Complex::ZERO, half];
+        assert!(state
+            .amplitudes()
+            .iter()
+            .zip(expected)
+            .all(|(&a, b)| close(a, b)));
+
+        // |10〉 and |01〉 are exchanged, leaving |00〉 and |11〉 in place
+        let mut state = StateVector::new(2);
+        assert_eq!(state.apply_gate("x", &[], &[0]), Ok(()));
+        assert_eq!(state.apply_gate("swap", &[], &[0, 1]), Ok(()));
+        assert!(close(state.amplitudes()[2], Complex::ONE));
+        assert_eq!(state.probability(0), 0.0);
+        assert_eq!(
+            state.apply_gate("swap", &[], &[0]),
+            Err(QccErrorKind::TranslationError)
+        );
+
+        // |1〉 becomes |0〉 again
+        let mut state = StateVector::new(1);
+        assert_eq!(state.apply_gate("x", &[], &[0]), Ok(()));
+        state.reset(0);
+        assert!(close(state.amplitudes()[0], Complex::ONE));
+
+        assert_eq!(
+            state.apply_gate("rz", &[], &[0]),
+            Err(QccErrorKind::TranslationError)
+        );
+    }
+
+    #[test]
+    fn check_tolerance() {
+        let mut state = StateVector::new(1);
+        assert_eq!(state.apply_gate("h", &[], &[0]), Ok(()));
+        let mut near = StateVector::new(1);
+        assert_eq!(near.apply_gate("h", &[], &[0]), Ok(()));
+        near.amplitudes[1] = near.amplitudes[1] + Complex::from(1e-11);
+
+        assert!(state.equals_up_to_phase(&near, Tolerance::default()));
+        assert!(!state.equals_up_to_phase(&near, Tolerance::new(1e-13)));
+        // a global phase is never told apart
+        let mut phased = StateVector::new(1);
+        assert_eq!(phased.apply_gate("x", &[], &[0]), Ok(()));
+        let mut flipped = StateVector::new(1);
+        assert_eq!(flipped.apply_gate("y", &[], &[0]), Ok(()));
+        assert!(phased.equals_up_to_phase(&flipped, Tolerance::new(1e-13)));
+    }
+
+    #[test]
+    fn check_measurement_basis() -> crate::error::Result<()> {
+        use crate::codegen::circuit::lower;
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/measure-basis.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let circuits = lower(ast)?;
+        let outcomes = |name: &str| {
+            let circuit = circuits.iter().find(|c| c.name == name).unwrap();
+            simulate_measured(circuit).map(|(_, bits)| bits)
+        };
+        // |+〉 and |+i〉 are rotated onto |0〉 before measuring
+        assert_eq!(outcomes("x_basis"), Ok(vec![Some(false)]));
+        assert_eq!(outcomes("y_basis"), Ok(vec![Some(false)]));
+        assert_eq!(outcomes("z_basis"), Ok(vec![None]));
+
+        let x_basis = circuits.iter().find(|c| c.name == "x_basis").unwrap();
+        assert_eq!(
+            x_basis.ops[1].to_string(),
+            format!("ry({}) q[0];", -FRAC_PI_2)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_if_let_measure() -> crate::error::Result<()> {
+        use crate::codegen::circuit::lower;
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/if-let.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        for module in &ast {
+            for function in &*module {
+                assert!(format!("{:#}", *function)
+                    .contains("    if let c: bit = measure: bit (q: qubit) {\n"));
+            }
+        }
+
+        let circuits = lower(ast)?;
+        let taken = circuits.iter().find(|c| c.name == "taken").unwrap();
+        let ops = taken.ops.iter().map(Op::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            ops[3..],
+            [
+                "measure q[0] -> c[2];",
+                "if(c[2]==1) x q[1];",
+                "reset q[2];",
+                "if(c[2]==1) x q[2];",
+                "if(c[2]==1) measure q[2] -> c[1];",
+                "measure q[1] -> c[0];",
+            ]
+        );
+        // bits of `out`, the copy of `c` and `c` itself
+        assert_eq!(
+            simulate_measured(taken).map(|(_, bits)| bits),
+            Ok(vec![Some(true), Some(true), Some(true)])
+        );
+
+        let skipped = circuits.iter().find(|c| c.name == "skipped").unwrap();
+        assert_eq!(
+            simulate_measured(skipped).map(|(_, bits)| bits),
+            Ok(vec![Some(false), Some(false)])
+        );
+        // the branch is known without measuring when the outcome is certain
+        assert_eq!(simulate_unmeasured(skipped), Ok(StateVector::new(2)));
+
+        // but not when it is random
+        let src = "#[entry]
+fn coin(out: bit) {
+    let q = 0q(0.7071067811865476, 0.7071067811865476);
+    let r = 0q(1, 0);
+    if let c = measure(q) {
+        let r = x(r);
+    }
+    measure r -> out;
+}
+";
+        let ast = crate::session::parse_and_infer(src, "coin.ql", &Default::default())?;
+        assert_eq!(
+            simulate_unmeasured(&lower(ast)?[0]).err(),
+            Some(QccErrorKind::TranslationError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_swap() -> crate::error::Result<()> {
+        use crate::codegen::circuit::lower;
+        use crate::inference::infer;
+        use crate::parser::Parser;
+
+        let mut parser = Parser::new(vec!["tests/swap.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+
+        let circuits = lower(ast)?;
+        assert_eq!(
+            simulate_measured(&circuits[0]).map(|(_, bits)| bits),
+            Ok(vec![Some(true), Some(false)])
+        );
+
+        Ok(())
+    }
+}
@@ -2,42 +2,359 @@
 //!
 //! Read more on quantum language type systems.
 
+use crate::ast::Ident;
 use crate::error::QccErrorKind;
+use std::collections::HashMap;
 
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
 pub(crate) enum Type {
+    /// not yet inferred, or failed to be
     #[default]
     Bottom,
+    /// no value, as returned by functions which return nothing
+    Unit,
     Rad,
     Qbit,
     Bit,
     F64,
+    I32,
+    I64,
+    /// complex number, written with an imaginary literal like `2i`
+    Complex,
+    /// string, only known at compile time as nothing runs classically
+    Str,
+    /// register of `n` qubits, written as `qbit[n]`
+    Qreg(usize),
+    /// register of `n` classical bits, written as `bit[n]`
+    Creg(usize),
+    /// tensor of elements of the given type, with the length of each of its
+    /// dimensions, outermost first
+    Tensor(Box<Type>, Vec<usize>),
+    /// register of qubits or bits whose size is a compile-time parameter of
+    /// a generic function, e.g. `qbit[n]`
+    GenericRegister(Box<Type>, Ident),
+}
+
+impl Type {
+    /// Checks if values of this type carry qubits, and hence are subject to
+    /// the no-cloning rules and must be lowered onto physical qubits.
+    pub(crate) fn is_quantum(&self) -> bool {
+        match self {
+            Self::Qbit | Self::Qreg(_) => true,
+            Self::Tensor(elem, _) | Self::GenericRegister(elem, _) => elem.is_quantum(),
+            Self::Bottom
+            | Self::Unit
+            | Self::Rad
+            | Self::Bit
+            | Self::F64
+            | Self::I32
+            | Self::I64
+            | Self::Complex
+            | Self::Str
+            | Self::Creg(_) => false,
+        }
+    }
+
+    pub(crate) fn is_integer(&self) -> bool {
+        matches!(self, Self::I32 | Self::I64)
+    }
+
+    /// Checks if `value` is an integer in the range of this integer type.
+    pub(crate) fn represents(&self, value: f64) -> bool {
+        let (min, max) = match self {
+            Self::I32 => (i32::MIN as f64, i32::MAX as f64),
+            Self::I64 => (i64::MIN as f64, i64::MAX as f64),
+            _ => return false,
+        };
+        value.fract() == 0.0 && (min..=max).contains(&value)
+    }
+
+    /// Returns the type operands of both types are promoted to in arithmetic.
+    /// Integers widen to the larger integer, or to `float64` when mixed with
+    /// one, and real numbers to `complex` when mixed with one. Other types
+    /// only go with themselves.
+    pub(crate) fn bigtype(&self, other: &Self) -> Option<Self> {
+        match (self, other) {
+            _ if self == other => Some(self.clone()),
+            (Self::I32, Self::I64) | (Self::I64, Self::I32) => Some(Self::I64),
+            (Self::F64, ty) | (ty, Self::F64) if ty.is_integer() => Some(Self::F64),
+            (Self::Complex, ty) | (ty, Self::Complex) if ty.is_integer() || *ty == Self::F64 => {
+                Some(Self::Complex)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the type of a matrix, `self`, applied to `qubits`, or `None` if
+    /// they aren't a matrix and qubits. The state of `n` qubits has `2^n`
+    /// amplitudes, so only a `2^n×2^n` matrix transforms them.
+    pub(crate) fn transform(&self, qubits: &Self) -> Option<Result<Self, QccErrorKind>> {
+        let Self::Tensor(elem, dims) = self else {
+            return None;
+        };
+        let len = match qubits {
+            Self::Qbit => 1,
+            Self::Qreg(len) => *len,
+            _ => return None,
+        };
+        if elem.is_quantum() {
+            return None;
+        }
+
+        let amplitudes = 1usize.checked_shl(len as u32);
+        Some(match (dims.as_slice(), amplitudes) {
+            ([rows, cols], Some(amplitudes)) if *rows == amplitudes && *cols == amplitudes => {
+                Ok(qubits.clone())
+            }
+            _ => Err(QccErrorKind::ShapeMismatch),
+        })
+    }
+
+    /// Returns a register of `len` elements of the given scalar type, as in
+    /// `qbit[3]` or `bit[3]`.
+    pub(crate) fn register(elem: Type, len: usize) -> core::result::Result<Self, QccErrorKind> {
+        match elem {
+            Self::Qbit => Ok(Self::Qreg(len)),
+            Self::Bit => Ok(Self::Creg(len)),
+            _ => Err(QccErrorKind::UnexpectedType),
+        }
+    }
+
+    /// Replaces registers sized by a generic with registers of the size the
+    /// generic is given in `sizes`.
+    pub(crate) fn substitute(&self, sizes: &HashMap<Ident, usize>) -> Self {
+        match self {
+            Self::GenericRegister(elem, size) => match sizes.get(size) {
+                Some(&len) => Self::register(*elem.clone(), len).unwrap_or(self.clone()),
+                None => self.clone(),
+            },
+            Self::Tensor(elem, dims) => {
+                Self::Tensor(Box::new(elem.substitute(sizes)), dims.clone())
+            }
+            ty => ty.clone(),
+        }
+    }
+
+    /// Returns the type of a tensor literal with elements of the given types.
+    /// A tensor of qubits is a qubit register, and an empty tensor has elements
+    /// of no type in particular. Tensors whose elements differ in type are
+    /// `Bottom`, those whose elements differ in shape are ragged.
+    pub(crate) fn tensor(elems: &[Type]) -> Result<Self, QccErrorKind> {
+        let shapes = elems.iter().map(Self::shape).collect::<Vec<_>>();
+        // an empty tensor has no elements to type, but its shape is known
+        let Some((elem, dims)) = shapes.first() else {
+            return Ok(Self::Tensor(Box::new(Self::Bottom), vec![0]));
+        };
+        let untyped = |(elem, dims): &(Self, &[usize])| *elem == Self::Bottom && dims.is_empty();
+        if shapes.iter().any(untyped) {
+            return Ok(Self::Bottom);
+        }
+        if shapes.iter().any(|(_, other)| other != dims) {
+            Err(QccErrorKind::ShapeMismatch)?
+        }
+        if shapes.iter().any(|(other, _)| other != elem) {
+            return Ok(Self::Bottom);
+        }
+
+        Ok(match (elem, *dims) {
+            (Self::Qbit, []) => Self::Qreg(elems.len()),
+            _ => {
+                let dims = std::iter::once(elems.len()).chain(dims.iter().copied());
+                Self::Tensor(Box::new(elem.clone()), dims.collect())
+            }
+        })
+    }
+
+    /// Splits a type into the type of its elements and the length of each of
+    /// its dimensions, which are none for scalars.
+    fn shape(&self) -> (Self, &[usize]) {
+        match self {
+            Self::Tensor(elem, dims) => (*elem.clone(), dims),
+            Self::Qreg(len) => (Self::Qbit, std::slice::from_ref(len)),
+            ty => (ty.clone(), &[]),
+        }
+    }
 }
 
 impl std::fmt::Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Bottom => write!(f, "<bottom>"),
+            Self::Unit => write!(f, "()"),
             Self::Rad => write!(f, "radians"),
             Self::Qbit => write!(f, "qubit"),
             Self::Bit => write!(f, "bit"),
             Self::F64 => write!(f, "float64"),
+            Self::I32 => write!(f, "int32"),
+            Self::I64 => write!(f, "int64"),
+            Self::Complex => write!(f, "complex"),
+            Self::Str => write!(f, "str"),
+            Self::Qreg(n) => write!(f, "qubit[{}]", n),
+            Self::Creg(n) => write!(f, "bit[{}]", n),
+            Self::Tensor(elem, dims) => {
+                write!(f, "{}", elem)?;
+                dims.iter().try_for_each(|len| write!(f, "[{}]", len))
+            }
+            Self::GenericRegister(elem, size) => write!(f, "{}[{}]", elem, size),
         }
     }
 }
 
+/// Parameter and return types of a function.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub(crate) params: Vec<Type>,
+    pub(crate) ret: Type,
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(|param| param.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "({}) -> {}", params.join(", "), self.ret)
+    }
+}
+
 impl std::str::FromStr for Type {
     type Err = QccErrorKind; // at this point, we can only infer the kind of
                              // error, location cannot be determined here, but
                              // can be tagged along down the call stack.
 
+    /// Scalar types are written in short, e.g. `f64`, or as they are displayed,
+    /// e.g. `float64`.
     fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         Ok(match s {
-            "rad" => Self::Rad,
-            "qbit" => Self::Qbit,
+            "rad" | "radians" => Self::Rad,
+            "qbit" | "qubit" => Self::Qbit,
             "bit" => Self::Bit,
-            "f64" => Self::F64,
+            "f64" | "float64" => Self::F64,
+            "i32" | "int32" => Self::I32,
+            "i64" | "int64" => Self::I64,
+            "complex" => Self::Complex,
+            "str" => Self::Str,
             _ => Err(QccErrorKind::UnexpectedType)?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_is_quantum() {
+        assert!(Type::Qbit.is_quantum());
+        assert!(Type::Qreg(4).is_quantum());
+        assert!(Type::Tensor(Box::new(Type::Qbit), vec![2, 2]).is_quantum());
+
+        assert!(!Type::F64.is_quantum());
+        assert!(!Type::Bit.is_quantum());
+        assert!(!Type::Creg(4).is_quantum());
+        assert!(!Type::Tensor(Box::new(Type::F64), vec![2]).is_quantum());
+        assert!(!Type::Bottom.is_quantum());
+        assert!(!Type::Unit.is_quantum());
+    }
+
+    #[test]
+    fn check_type_names() -> Result<(), QccErrorKind> {
+        assert_eq!("float64".parse::<Type>()?, Type::F64);
+        assert_eq!("f64".parse::<Type>()?, Type::F64);
+        assert_eq!("qubit".parse::<Type>()?, Type::Qbit);
+
+        // displayed scalar types parse back
+        let scalars = [
+            Type::Rad,
+            Type::Qbit,
+            Type::Bit,
+            Type::F64,
+            Type::I32,
+            Type::I64,
+            Type::Str,
+        ];
+        for ty in scalars {
+            assert_eq!(ty.to_string().parse::<Type>()?, ty);
+        }
+        assert_eq!("float".parse::<Type>(), Err(QccErrorKind::UnexpectedType));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_tensor_type() {
+        let float64 = |dims: &[usize]| Type::Tensor(Box::new(Type::F64), dims.to_vec());
+        assert_eq!(Type::tensor(&[Type::Qbit, Type::Qbit]), Ok(Type::Qreg(2)));
+        assert_eq!(Type::tensor(&[Type::F64, Type::F64]), Ok(float64(&[2])));
+        assert_eq!(Type::tensor(&[Type::Qbit, Type::F64]), Ok(Type::Bottom));
+
+        // empty tensors have a shape, but no type of elements
+        let empty = Type::Tensor(Box::new(Type::Bottom), vec![0]);
+        assert_eq!(Type::tensor(&[]), Ok(empty.clone()));
+        let empties = Type::Tensor(Box::new(Type::Bottom), vec![2, 0]);
+        assert_eq!(Type::tensor(&[empty.clone(), empty]), Ok(empties.clone()));
+        let single = Type::Tensor(Box::new(Type::Bottom), vec![1, 0]);
+        assert_eq!(
+            Type::tensor(&[empties, single]),
+            Err(QccErrorKind::ShapeMismatch)
+        );
+
+        // a 2x1 tensor of rows, and a 2x2 one
+        assert_eq!(
+            Type::tensor(&[float64(&[1]), float64(&[1])]),
+            Ok(float64(&[2, 1]))
+        );
+        let square = Type::tensor(&[float64(&[2]), float64(&[2])]);
+        assert_eq!(square, Ok(float64(&[2, 2])));
+        assert_eq!(square.unwrap().to_string(), "float64[2][2]");
+        assert_eq!(
+            Type::tensor(&[Type::Qreg(2), Type::Qreg(2)]),
+            Ok(Type::Tensor(Box::new(Type::Qbit), vec![2, 2]))
+        );
+
+        // ragged tensors have no shape
+        assert_eq!(
+            Type::tensor(&[float64(&[2]), float64(&[1])]),
+            Err(QccErrorKind::ShapeMismatch)
+        );
+        assert_eq!(
+            Type::tensor(&[Type::F64, float64(&[2])]),
+            Err(QccErrorKind::ShapeMismatch)
+        );
+    }
+
+    #[test]
+    fn check_transform() {
+        let matrix = |n: usize| Type::Tensor(Box::new(Type::F64), vec![n, n]);
+        assert_eq!(matrix(2).transform(&Type::Qbit), Some(Ok(Type::Qbit)));
+        assert_eq!(matrix(4).transform(&Type::Qreg(2)), Some(Ok(Type::Qreg(2))));
+        assert_eq!(
+            matrix(3).transform(&Type::Qbit),
+            Some(Err(QccErrorKind::ShapeMismatch))
+        );
+        assert_eq!(
+            matrix(2).transform(&Type::Qreg(2)),
+            Some(Err(QccErrorKind::ShapeMismatch))
+        );
+        assert_eq!(matrix(2).transform(&Type::F64), None);
+        assert_eq!(Type::F64.transform(&Type::Qbit), None);
+    }
+
+    #[test]
+    fn check_bigtype() {
+        assert_eq!(Type::I32.bigtype(&Type::I64), Some(Type::I64));
+        assert_eq!(Type::I64.bigtype(&Type::F64), Some(Type::F64));
+        assert_eq!(Type::F64.bigtype(&Type::I32), Some(Type::F64));
+        assert_eq!(Type::Bit.bigtype(&Type::Bit), Some(Type::Bit));
+        assert_eq!(Type::Bit.bigtype(&Type::I32), None);
+
+        assert!(Type::I32.represents(-5.0));
+        assert!(!Type::I32.represents(2.5));
+        assert!(!Type::I32.represents(1e10));
+        assert!(Type::I64.represents(1e10));
+        assert!(!Type::F64.represents(1.0));
+        assert_eq!("i64".parse::<Type>(), Ok(Type::I64));
+        assert_eq!(Type::I32.to_string(), "int32");
+    }
+}
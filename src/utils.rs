@@ -1,6 +1,7 @@
 //! Utils module contains help documentation.
-use crate::ast::{Expr, FunctionAST, Ident, ModuleAST, Qast, QccCell};
-use crate::error::Result;
+use crate::ast::{Expr, FunctionAST, Ident, LiteralAST, ModuleAST, Qast, QccCell, VarAST};
+use crate::builtins::Builtin;
+use crate::error::{QccErrorKind, QccErrorLoc, Result};
 
 /// It takes an expression and a slice of expressions, and validates if atleast
 /// one of the predicates match to the given expression.
@@ -41,73 +42,118 @@ pub(crate) fn usage() {
     {:14}\t{:<20}
     {:14}\t{:<20}
     {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
+    {:14}\t{:<20}
 ",
-        "--help",
+        "-h,--help",
         "show this page",
+        "-v,--version",
+        "print version and the commit qcc is built from",
         "--dump-ast",
         "print AST",
         "--dump-ast-only",
         "print AST without translating to assemmbly",
+        "--ast-locations=<on|off>",
+        "print locations in AST dumps, on by default",
         "--dump-qasm",
         "print OpenQASM IR",
+        "--dump-symbols",
+        "print functions and their locals after inference",
         "--analyze",
         "run static analyzer",
+        "--strict",
+        "reject types left uninferred",
+        "--fail-on-warning",
+        "exit with failure after warnings",
+        "-Werror",
+        "treat warnings as errors",
+        "--emit=metrics",
+        "print circuit metrics as JSON",
+        "--emit=statevector",
+        "write final state vectors to a .json file",
+        "--emit=circuit",
+        "print circuit operations as a table",
+        "--emit=unitary",
+        "write unitary matrices to a .json file",
+        "--explain <code>",
+        "explain an error code",
+        "--print-config",
+        "print resolved configuration",
+        "--list-passes",
+        "list optimization passes",
+        "--enable-pass=<pass>",
+        "run a pass regardless of -O",
+        "--disable-pass=<pass>",
+        "skip a pass regardless of -O",
         "-O0",
-        "disable optimizations (NA)",
+        "disable optimizations",
         "-O1",
-        "enable first-level optimizations (NA)",
+        "enable first-level optimizations",
+        "-O2",
+        "enable second-level optimizations",
+        "-O3",
+        "same as -O2",
         "-Og",
-        "enable all optimizations (NA)",
+        "optimize without removing code",
+        "-Oz",
+        "optimize for circuit size",
         "-d,--debug",
         "run compiler in debug-mode",
         "-o",
-        "compiled output"
+        "compiled output, - for stdout",
+        "--output-dir <dir>",
+        "write artifacts into a directory",
+        "--package <dir>",
+        "compile a directory from its main.ql",
+        "-I <dir>",
+        "search a directory for preludes of `use`",
+        "--verify <a.ql> <b.ql>",
+        "check both programs prepare the same states",
+        "--tol <eps>",
+        "tolerance of amplitude comparisons",
+        "--float-precision <n>",
+        "round emitted parameters to n decimals",
+        "--qasm-version <v>",
+        "emit OpenQASM 2.0 (default) or 3.0"
     );
 }
 
-/// Simple Name Mangler
-///
-/// This simple mangler uses module name as prefix and underscored with function
-/// names.
-pub(crate) fn mangle(ast: &mut Qast) -> Result<()> {
-    for mut module in ast {
-        let mod_name = module.get_name();
-        for mut function in &mut *module {
-            let fn_name = function.get_name().clone();
-            function.set_name(format!("{}_{}", mod_name.clone(), fn_name).into());
+/// Separates a module from a function in a mangled name. It can't appear in
+/// identifiers, so names mangled from different modules and functions, e.g.
+/// `a_b::f` and `a::b_f`, never collide.
+pub(crate) const SEPARATOR: &str = "::";
 
-            for instruction in &mut *function {
-                mangle_expr(instruction, mod_name.clone() + "_");
-            }
-        }
-    }
-
-    Ok(())
+/// Mangles the name of a function as it is called from other modules, e.g.
+/// `flip` of `std` into `std::flip`.
+pub(crate) fn mangle(module: &str, function: &str) -> Ident {
+    format!("{module}{SEPARATOR}{function}")
 }
 
-fn mangle_expr(expr: &mut QccCell<Expr>, prefix: Ident) {
-    // TODO: prefix: &str
-    match *expr.as_ref().borrow_mut() {
-        Expr::BinaryExpr(ref mut lhs, _, ref mut rhs) => {
-            mangle_expr(lhs, prefix.clone());
-            mangle_expr(rhs, prefix);
-        }
-        Expr::Let(_, ref mut val) => {
-            mangle_expr(val, prefix);
-        }
-        Expr::FnCall(ref mut f, ref mut args) => {
-            for arg in args {
-                mangle_expr(arg, prefix.clone());
-            }
-
-            f.set_name(prefix + f.get_name());
-        }
-        _ => {}
-    }
-}
-
-/// Replaces all occurences of `fn_name` in instructions with
-/// (`mod_name + `_` + `fn_name`).
+/// Replaces all occurences of `fn_name` in instructions with its name mangled
+/// with `mod_name`, see `mangle`.
 fn mangle_expr_check(expr: &mut QccCell<Expr>, mod_name: &Ident, fn_name: &Ident) {
     match *expr.as_ref().borrow_mut() {
         Expr::BinaryExpr(ref mut lhs, _, ref mut rhs) => {
@@ -123,10 +169,29 @@ fn mangle_expr_check(expr: &mut QccCell<Expr>, mod_name: &Ident, fn_name: &Ident
             }
 
             if *f.get_name() == *fn_name {
-                f.set_name(mod_name.to_owned() + "_" + f.get_name());
+                f.set_name(mangle(mod_name, f.get_name()));
             }
         }
-        _ => {}
+        Expr::Conditional(ref mut cond, ref mut truth, ref mut otherwise) => {
+            mangle_expr_check(cond, mod_name, fn_name);
+            for expr in truth.iter_mut().chain(otherwise) {
+                mangle_expr_check(expr, mod_name, fn_name);
+            }
+        }
+        Expr::Loop(ref mut cond, ref mut body) => {
+            mangle_expr_check(cond, mod_name, fn_name);
+            for expr in body {
+                mangle_expr_check(expr, mod_name, fn_name);
+            }
+        }
+        Expr::Literal(ref lit) => {
+            if let LiteralAST::Lit_Tensor(ref mut elems, _) = *lit.as_ref().borrow_mut() {
+                for elem in elems {
+                    mangle_expr_check(elem, mod_name, fn_name);
+                }
+            }
+        }
+        Expr::Var(_) => {}
     }
 }
 
@@ -135,14 +200,209 @@ fn mangle_expr_check(expr: &mut QccCell<Expr>, mod_name: &Ident, fn_name: &Ident
 /// a function name.
 pub(crate) fn mangle_module(module: &mut ModuleAST, mod_name: Ident, fn_name: Ident) -> Result<()> {
     for mut function in module {
-        for instruction in &mut *function {
-            mangle_expr_check(instruction, &mod_name, &fn_name);
-        }
+        mangle_function(&mut function, &mod_name, &fn_name);
+    }
+
+    Ok(())
+}
+
+/// Mangles calls to `fn_name` in a function, including its nested functions.
+fn mangle_function(function: &mut FunctionAST, mod_name: &Ident, fn_name: &Ident) {
+    for nested in function.iter_nested_mut() {
+        mangle_function(nested, mod_name, fn_name);
+    }
+    for instruction in &mut *function {
+        mangle_expr_check(instruction, mod_name, fn_name);
+    }
+}
+
+/// Splits a mangled name into the module and the function as declared, e.g.
+/// `std::flip` into `std` and `flip`. Other names are returned as is.
+pub(crate) fn demangle(name: &str) -> (Option<Ident>, Ident) {
+    match name.split_once(SEPARATOR) {
+        Some((module, function)) => (Some(module.into()), function.into()),
+        None => (None, name.into()),
     }
+}
+
+/// Writes an expression for a diagnostic, naming each call to an imported
+/// function as written, e.g. `flip (in std)` rather than `std::flip`.
+pub(crate) fn demangle_expr(expr: &QccCell<Expr>) -> String {
+    let callee = |name: &Ident| match demangle(name) {
+        (Some(module), function) => format!("{function} (in {module})"),
+        (None, function) => function,
+    };
+    expr.as_ref().borrow().display_with(&callee).to_string()
+}
 
+/// Lifts nested functions out of their enclosing functions into the module.
+///
+/// A lifted function is renamed as (`outer` + `_` + `inner`), after the whole
+/// path of functions enclosing it, and every parameter of an enclosing
+/// function it refers to is appended to its own parameters. Calls to it are
+/// updated to pass these captured values along, so a function in between
+/// captures them as well.
+///
+/// The name of a lifted function may already be taken in the module, e.g. by
+/// a function `outer_inner`. Calls to either can't be told apart then, so this
+/// is an error at the nested function.
+pub(crate) fn lift_nested(ast: &mut Qast) -> core::result::Result<(), QccErrorLoc> {
+    for mut module in ast {
+        let mut lifted = vec![];
+        for mut function in &mut *module {
+            lifted.extend(lift_function(&mut function, &[]));
+        }
+
+        let mut names = (&*module)
+            .into_iter()
+            .map(|function| function.get_name().clone())
+            .collect::<Vec<Ident>>();
+        for function in lifted {
+            if names.contains(function.get_name()) {
+                Err((QccErrorKind::DuplicateFunction, function.get_loc().clone()))?
+            }
+            names.push(function.get_name().clone());
+            module.append_function(function);
+        }
+    }
     Ok(())
 }
 
+/// Lifts functions nested in `function`, which may capture its parameters
+/// and those of functions `enclosing` it.
+fn lift_function(function: &mut FunctionAST, enclosing: &[VarAST]) -> Vec<FunctionAST> {
+    let mut lifted = vec![];
+    let outer_name = function.get_name().clone();
+    // parameters of inner functions shadow those of outer ones
+    let mut outer_params = enclosing
+        .iter()
+        .filter(|p| function.iter_params().all(|q| q.name() != p.name()))
+        .cloned()
+        .collect::<Vec<VarAST>>();
+    outer_params.extend(function.iter_params().cloned());
+
+    let mut pending = function.take_nested();
+    while !pending.is_empty() {
+        let mut nested = pending.remove(0);
+        let inner_name = nested.get_name().clone();
+        // functions nested in it are named after the whole path
+        nested.set_name(format!("{}_{}", outer_name, inner_name));
+        lifted.extend(lift_function(&mut nested, &outer_params));
+
+        // parameters of enclosing functions which are used but not shadowed,
+        // including those passed along to functions nested in it
+        let mut used = vec![];
+        for expr in &nested {
+            gather_vars(expr, &mut used);
+        }
+        let captured = outer_params
+            .iter()
+            .filter(|p| used.contains(p.name()))
+            .filter(|p| nested.iter_params().all(|q| q.name() != p.name()))
+            .cloned()
+            .collect::<Vec<VarAST>>();
+
+        for param in &captured {
+            nested.append_param(param.clone());
+        }
+
+        // siblings which aren't lifted yet may call it too
+        let callers = std::iter::once(&mut *function)
+            .chain(lifted.iter_mut())
+            .chain(pending.iter_mut());
+        for caller in callers {
+            for instruction in &mut *caller {
+                rename_call(instruction, &inner_name, nested.get_name(), &captured);
+            }
+        }
+        lifted.push(nested);
+    }
+
+    lifted
+}
+
+/// Collects names of all variables referred in an expression.
+fn gather_vars(expr: &QccCell<Expr>, names: &mut Vec<Ident>) {
+    match *expr.as_ref().borrow() {
+        Expr::Var(ref var) => names.push(var.name().clone()),
+        Expr::BinaryExpr(ref lhs, _, ref rhs) => {
+            gather_vars(lhs, names);
+            gather_vars(rhs, names);
+        }
+        Expr::Let(_, ref val) => gather_vars(val, names),
+        Expr::FnCall(_, ref args) => {
+            for arg in args {
+                gather_vars(arg, names);
+            }
+        }
+        Expr::Literal(ref lit) => {
+            if let LiteralAST::Lit_Tensor(ref elems, _) = *lit.as_ref().borrow() {
+                for elem in elems {
+                    gather_vars(elem, names);
+                }
+            }
+        }
+        Expr::Conditional(ref cond, ref truth, ref otherwise) => {
+            gather_vars(cond, names);
+            for expr in truth.iter().chain(otherwise) {
+                gather_vars(expr, names);
+            }
+        }
+        Expr::Loop(ref cond, ref body) => {
+            gather_vars(cond, names);
+            for expr in body {
+                gather_vars(expr, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Renames calls of `from` to `to`, passing `captured` as extra arguments.
+fn rename_call(expr: &mut QccCell<Expr>, from: &Ident, to: &Ident, captured: &[VarAST]) {
+    match *expr.as_ref().borrow_mut() {
+        Expr::BinaryExpr(ref mut lhs, _, ref mut rhs) => {
+            rename_call(lhs, from, to, captured);
+            rename_call(rhs, from, to, captured);
+        }
+        Expr::Let(_, ref mut val) => {
+            rename_call(val, from, to, captured);
+        }
+        Expr::FnCall(ref mut f, ref mut args) => {
+            for arg in &mut *args {
+                rename_call(arg, from, to, captured);
+            }
+
+            if *f.get_name() == *from {
+                f.set_name(to.clone());
+                for param in captured {
+                    args.push(param.clone().into());
+                }
+            }
+        }
+        Expr::Literal(ref lit) => {
+            if let LiteralAST::Lit_Tensor(ref mut elems, _) = *lit.as_ref().borrow_mut() {
+                for elem in elems {
+                    rename_call(elem, from, to, captured);
+                }
+            }
+        }
+        Expr::Conditional(ref mut cond, ref mut truth, ref mut otherwise) => {
+            rename_call(cond, from, to, captured);
+            for expr in truth.iter_mut().chain(otherwise) {
+                rename_call(expr, from, to, captured);
+            }
+        }
+        Expr::Loop(ref mut cond, ref mut body) => {
+            rename_call(cond, from, to, captured);
+            for expr in body {
+                rename_call(expr, from, to, captured);
+            }
+        }
+        _ => {}
+    }
+}
+
 pub(crate) fn sanitize(identifier: Ident) -> Ident {
     let mut sanitized = String::new();
     for c in identifier.bytes() {
@@ -160,6 +420,177 @@ pub(crate) fn sanitize(identifier: Ident) -> Ident {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::error::Result;
+    use crate::inference::infer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn check_lift_nested() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/nested-fn.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        lift_nested(&mut ast)?;
+
+        let mut names = vec![];
+        for module in &ast {
+            for function in &*module {
+                assert_eq!(function.iter_nested().count(), 0);
+                names.push(function.get_name().clone());
+
+                if function.get_name() == "rotate_angle" {
+                    let params = function
+                        .iter_params()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<String>>();
+                    assert_eq!(params, ["scale: float64", "theta: float64"]);
+                }
+                if function.get_name() == "rotate" {
+                    assert_eq!(
+                        function.into_iter().next().unwrap().borrow().to_string(),
+                        "a: float64 = rotate_angle: float64 (2, theta: float64)"
+                    );
+                }
+            }
+        }
+        assert_eq!(names, ["rotate", "rotate_angle"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_lift_nested_twice() -> Result<()> {
+        let mut parser = Parser::new(vec!["tests/nested-fn-deep.ql"])?.unwrap();
+        let config = parser.get_config();
+        let mut ast = parser.parse(&config.analyzer.src)?;
+        infer(&mut ast)?;
+        lift_nested(&mut ast)?;
+
+        // `theta` is captured by `angle`, and so by `twice` which calls it
+        let mut lifted = vec![];
+        for module in &ast {
+            for function in &*module {
+                let params = function
+                    .iter_params()
+                    .map(|p| p.name().clone())
+                    .collect::<Vec<Ident>>();
+                lifted.push((function.get_name().clone(), params, function.to_string()));
+            }
+        }
+        assert_eq!(lifted[0].0, "rotate");
+        assert!(lifted[0]
+            .2
+            .contains("rotate_twice: float64 (2, theta: float64)"));
+        assert_eq!(lifted[1].0, "rotate_twice_angle");
+        assert_eq!(lifted[1].1, ["factor", "theta"]);
+        assert_eq!(lifted[2].0, "rotate_twice");
+        assert_eq!(lifted[2].1, ["scale", "theta"]);
+        assert!(lifted[2].2.contains("rotate_twice_angle: float64 ("));
+        assert!(lifted[2].2.contains(", theta: float64)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_mangle_module() -> Result<()> {
+        // `f` of `a_b` and `b_f` of `a` would both be `a_b_f` if `_` separated
+        // the module from the function
+        let src = "module a_b {
+    fn f(q: qbit) : qbit {
+        return h(q);
+    }
+}
+
+module a {
+    fn b_f(q: qbit) : qbit {
+        return x(q);
+    }
+}
+
+import a_b::f;
+import a::b_f;
+
+fn main(q: qbit) : qbit {
+    let q = f(q);
+    return b_f(q);
+}
+";
+        let ast = crate::session::parse_and_infer(src, "mangle.ql", &Default::default())?;
+        let mut callees = vec![];
+        for module in &ast {
+            for function in &*module {
+                if function.get_name() == "main" {
+                    crate::optimizer::passes::gather_callees(&function, &mut callees);
+                }
+            }
+        }
+        assert_eq!(callees, ["a_b::f", "a::b_f"]);
+        assert_eq!(demangle(&callees[0]), (Some("a_b".into()), "f".into()));
+        assert_eq!(demangle(&callees[1]), (Some("a".into()), "b_f".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_lift_nested_collision() -> Result<()> {
+        let src = "fn rotate_angle(scale: f64) : f64 {
+    return scale;
+}
+
+fn rotate(q: qbit, theta: f64) : qbit {
+    fn angle(scale: f64) : f64 {
+        return theta * scale;
+    }
+
+    let a = angle(2.0);
+    return q;
+}
+";
+        let mut ast = crate::session::parse_and_infer(src, "nested-fn.ql", &Default::default())?;
+        // the lifted `angle` would be called like the function of the module
+        let err = lift_nested(&mut ast).err().unwrap();
+        assert!(err.get_error().is(QccErrorKind::DuplicateFunction));
+        assert_eq!(err.get_loc().borrow().row(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_demangle() {
+        assert_eq!(demangle("std::flip"), (Some("std".into()), "flip".into()));
+        assert_eq!(demangle("flip"), (None, "flip".into()));
+        assert_eq!(demangle("std_flip"), (None, "std_flip".into()));
+
+        // both module and function may contain `_`, which doesn't separate them
+        assert_eq!(
+            demangle("std::flip_twice"),
+            (Some("std".into()), "flip_twice".into())
+        );
+        assert_eq!(
+            demangle("use_nested::flip"),
+            (Some("use_nested".into()), "flip".into())
+        );
+        assert_eq!(mangle("a_b", "f"), "a_b::f");
+        assert_ne!(mangle("a_b", "f"), mangle("a", "b_f"));
+    }
+
+    #[test]
+    fn check_demangle_expr() -> Result<()> {
+        // only callees are demangled, not variables named alike
+        let src = "fn main(q: qbit) : qbit {\n    let std_flip: qbit = std::flip(q);\n    return std_flip;\n}\n";
+        let ast = Parser::from_source(src, Default::default()).parse(&"x.ql".into())?;
+        let module = ast.into_iter().next().unwrap();
+        let main = module.into_iter().next().unwrap();
+        let binding = main.into_iter().next().unwrap();
+        assert_eq!(
+            demangle_expr(&binding),
+            "std_flip: qubit = flip (in std)(q)"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn check_assert_eq_any() {
         assert_eq_any!(true, [false, true]);
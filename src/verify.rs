@@ -0,0 +1,102 @@
+//! Equivalence checking of two programs, see `--verify`.
+//!
+//! Functions of the same name in both programs are simulated without their
+//! measurements from |0..0〉. Programs are equivalent if each pair of final
+//! states only differs by a global phase. This is meant for checking that
+//! optimizations keep the meaning of a program.
+use crate::codegen::circuit::Circuit;
+use crate::config::{Config, Tolerance};
+use crate::diagnostics::Diagnostics;
+use crate::error::{QccErrorKind, Result};
+use crate::parser::Parser;
+use crate::session::{self, check_source};
+use crate::simulator::simulate_unmeasured;
+
+/// Compiles the source of `config` down to optimized circuits.
+fn compile(config: &Config) -> Result<Vec<Circuit>> {
+    let mut parser = Parser::from_config(config.clone())?;
+    let mut diagnostics = Diagnostics::default();
+    let compiled = check_source(&mut parser, config, &mut diagnostics)
+        .and_then(|qast| session::compile(qast, config, &mut diagnostics));
+    diagnostics.report();
+    Ok(compiled?.circuits)
+}
+
+/// Compares circuits of functions found in both programs, reporting each
+/// pair of states which differ. Functions found in only one of them, e.g.
+/// removed as dead code, are skipped.
+pub(crate) fn equivalent(lhs: &[Circuit], rhs: &[Circuit], tol: Tolerance) -> Result<bool> {
+    let mut equivalent = true;
+    for lhs in lhs {
+        let Some(rhs) = rhs.iter().find(|rhs| rhs.name == lhs.name) else {
+            continue;
+        };
+
+        let (lhs_state, rhs_state) = (simulate_unmeasured(lhs)?, simulate_unmeasured(rhs)?);
+        if lhs_state.equals_up_to_phase(&rhs_state, tol) {
+            continue;
+        }
+
+        equivalent = false;
+        eprintln!("`{}` prepares different states:", lhs.name);
+        let amplitudes = lhs_state.amplitudes().iter().zip(rhs_state.amplitudes());
+        for (basis, (lhs, rhs)) in amplitudes.enumerate() {
+            if !tol.close(*lhs, *rhs) {
+                eprintln!("\t|{:b}〉: {} != {}", basis, lhs, rhs);
+            }
+        }
+        if lhs.qubits != rhs.qubits {
+            eprintln!("\t{} qubits != {} qubits", lhs.qubits, rhs.qubits);
+        }
+    }
+    Ok(equivalent)
+}
+
+/// Checks the source of `config` is equivalent to the one given to
+/// `--verify`, both being compiled with the same options.
+pub(crate) fn verify(config: &Config) -> Result<()> {
+    let Some(other) = &config.verify else {
+        return Ok(());
+    };
+    let mut other_config = config.clone();
+    other_config.analyzer.src = other.clone();
+
+    if equivalent(
+        &compile(config)?,
+        &compile(&other_config)?,
+        config.tolerance,
+    )? {
+        Ok(())
+    } else {
+        Err(QccErrorKind::NotEquivalent)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::circuit::Op;
+
+    #[test]
+    fn check_optimized_equivalence() -> Result<()> {
+        let unoptimized = Parser::parse_cmdline(vec!["tests/verify.ql", "-O0"])?.unwrap();
+        let optimized = Parser::parse_cmdline(vec!["tests/verify.ql", "-O2"])?.unwrap();
+        let (lhs, mut rhs) = (compile(&unoptimized)?, compile(&optimized)?);
+        // only the hadamard and one rotation are left
+        assert_eq!((lhs[0].ops.len(), rhs[0].ops.len()), (6, 3));
+        // rotations by angles below 1e-6 are dropped, so states only agree
+        // that closely
+        let tol = Tolerance::new(1e-6);
+        assert!(equivalent(&lhs, &rhs, tol)?);
+        assert!(!equivalent(&lhs, &rhs, Tolerance::default())?);
+
+        rhs[0].ops.push(Op::Gate {
+            name: "x".into(),
+            params: vec![],
+            qubits: vec![0],
+        });
+        assert!(!equivalent(&lhs, &rhs, tol)?);
+
+        Ok(())
+    }
+}
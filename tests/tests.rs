@@ -3,6 +3,7 @@ use qcc::codegen::{qasm, Translator};
 use qcc::error::QccErrorKind;
 use qcc::inference::infer;
 use qcc::parser::Parser;
+use qcc::{compile_source, parse_and_infer, Config};
 
 #[test]
 fn compile() -> Result<(), Box<dyn std::error::Error>> {
@@ -27,18 +28,21 @@ fn compile() -> Result<(), Box<dyn std::error::Error>> {
                 match infer(&mut ast) {
                     Ok(_) => {}
                     Err(err) => {
-                        assert_eq_any!(err, [QccErrorKind::TypeError]);
+                        assert_eq!(err.kind(), &QccErrorKind::TypeError);
                         continue;
                     }
                 }
 
                 match qasm::QasmModule::translate(ast) {
                     Ok(_) => {}
-                    Err(err) => assert_eq_any!(err, [QccErrorKind::TranslationError]),
+                    Err(err) => assert_eq!(err.kind(), &QccErrorKind::TranslationError),
                 }
             }
 
-            Err(err) => assert_eq_any!(err, [QccErrorKind::LexerError, QccErrorKind::ParseError]),
+            Err(err) => assert!(matches!(
+                err.kind(),
+                QccErrorKind::LexerError | QccErrorKind::ParseError
+            )),
         }
     }
 
@@ -61,6 +65,61 @@ fn cmdline() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn explain() -> Result<(), Box<dyn std::error::Error>> {
+    // no source file is needed to explain an error code
+    assert!(Parser::parse_cmdline(vec!["--explain", "E0012"])?.is_none());
+
+    assert_eq!(
+        Parser::parse_cmdline(vec!["--explain", "E9999"]).err(),
+        Some(QccErrorKind::CmdlineErr.into())
+    );
+    assert_eq!(
+        Parser::parse_cmdline(vec!["--explain"]).err(),
+        Some(QccErrorKind::CmdlineErr.into())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn parse_failure_kind() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = Parser::new(vec!["./tests/register-size-invalid.ql"])?.unwrap();
+    let config = parser.get_config();
+
+    match parser.parse(&config.analyzer.src) {
+        Ok(_) => unreachable!(),
+        Err(err) => match err.kind() {
+            QccErrorKind::ParseError => {}
+            kind => panic!("expected a parse error, found {:?}", kind),
+        },
+    }
+    Ok(())
+}
+
+#[test]
+fn compile_string() -> Result<(), Box<dyn std::error::Error>> {
+    let src = "fn main() : qbit {\n    let q = alloc();\n    return h(q);\n}\n";
+    // the source isn't read from `path`, which only names it
+    let asm = compile_source(src, "inline.ql", &Config::default())?.to_string();
+    assert!(asm.contains("\n// main\nreset q[0];\nh q[0];\n"));
+
+    match compile_source("fn main( {}\n", "inline.ql", &Config::default()) {
+        Ok(_) => unreachable!(),
+        Err(err) => assert_eq!(err.kind(), &QccErrorKind::ParseError),
+    }
+    Ok(())
+}
+
+#[test]
+fn warning_only() -> Result<(), Box<dyn std::error::Error>> {
+    // `tmp` is never read, which is only warned about
+    let src = "fn main() : f64 {\n    let tmp = 1 + 2;\n    0.5\n}\n";
+    let ast = parse_and_infer(src, "warning.ql", &Config::default())?;
+    assert_eq!(ast.into_iter().count(), 1);
+    Ok(())
+}
+
 #[test]
 fn non_existing_src() -> Result<(), Box<dyn std::error::Error>> {
     let path = "./tests/test-non-existent.ql";
@@ -101,6 +160,9 @@ fn check_output_directives() -> Result<(), Box<dyn std::error::Error>> {
     let paths = std::fs::read_dir("./tests")?;
     for p in paths {
         let path = p.unwrap().path().into_os_string().into_string().unwrap();
+        if !path.ends_with(".ql") {
+            continue;
+        }
         let path = path.as_str();
         let temp = "temp.s";
 
@@ -148,3 +210,229 @@ fn check_output_directives() -> Result<(), Box<dyn std::error::Error>> {
     }
     Ok(())
 }
+
+#[test]
+fn fail_on_warning() -> Result<(), Box<dyn std::error::Error>> {
+    let asm = std::env::temp_dir().join("qcc-fail-on-warning.s");
+    let asm = asm.to_str().unwrap();
+    let qcc = |flags: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+            .args(["tests/fold.ql", "-O1", "-o", asm])
+            .args(flags)
+            .output()
+    };
+
+    // dividing by zero is only warned about
+    let output = qcc(&[])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("warning"));
+
+    std::fs::remove_file(asm)?;
+    let output = qcc(&["--fail-on-warning"])?;
+    assert!(!output.status.success());
+    assert!(std::path::Path::new(asm).exists());
+    std::fs::remove_file(asm)?;
+
+    Ok(())
+}
+
+#[test]
+fn dump_ast() -> Result<(), Box<dyn std::error::Error>> {
+    let asm = std::env::temp_dir().join("qcc-dump-ast.s");
+    let asm = asm.to_str().unwrap();
+    let qcc = |src: &str, flag: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+            .args([src, flag, "-o", asm])
+            .output()
+    };
+
+    // the AST is printed after inference, without translating it
+    let output = qcc("tests/bell.ql", "--dump-ast-only")?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("fn main"));
+    assert!(!std::path::Path::new(asm).exists());
+
+    let output = qcc("tests/bell.ql", "--dump-ast")?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("fn main"));
+    assert!(std::path::Path::new(asm).exists());
+    std::fs::remove_file(asm)?;
+
+    // the AST is printed before it is optimized
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+        .args(["tests/fold.ql", "-O1", "--dump-ast-only"])
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("((2 * 3) + 1)"));
+
+    // nothing is printed for a source with type errors
+    let output = qcc("tests/test1.ql", "--dump-ast-only")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn classical_loops() -> Result<(), Box<dyn std::error::Error>> {
+    let asm = std::env::temp_dir().join("qcc-classical-loops.s");
+    let asm = asm.to_str().unwrap();
+
+    // `pow` loops for as long as its argument says, it isn't a circuit so the
+    // loop is left alone
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+        .args(["tests/operator.ql", "-o", asm])
+        .output()?;
+    assert!(output.status.success());
+    assert!(std::fs::read_to_string(asm)?.starts_with("OPENQASM 2.0;"));
+    std::fs::remove_file(asm)?;
+
+    Ok(())
+}
+
+#[test]
+fn output_in_new_directory() -> Result<(), Box<dyn std::error::Error>> {
+    // directories of `-o` are created like `--output-dir`
+    let dir = std::env::temp_dir().join("qcc-output-in-new-directory");
+    let asm = dir.join("sub/dir/out.qasm");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+        .args(["tests/hadamard.ql", "-o", asm.to_str().unwrap()])
+        .output()?;
+    assert!(output.status.success());
+    assert!(std::fs::read_to_string(&asm)?.starts_with("OPENQASM 2.0;"));
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn json_to_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    // `-o -` prints exports like assembly, it doesn't write a `-.json`
+    let dir = std::env::temp_dir().join("qcc-json-to-stdout");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("plus.ql"),
+        "fn main(q: qbit) : qbit {\n    return h(q);\n}\n",
+    )?;
+    for (emit, expected) in [
+        ("--emit=statevector", "\"amplitudes\""),
+        ("--emit=unitary", "\"unitary\""),
+    ] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+            .current_dir(&dir)
+            .args(["plus.ql", emit, "-o", "-"])
+            .output()?;
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stdout)?.contains(expected));
+        assert!(!dir.join("-.json").exists());
+    }
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+/// Returns gates applied in OpenQASM 2.0 output without being defined first.
+fn undeclared_gates(qasm: &str) -> Vec<String> {
+    let mut declared = vec!["U".to_string(), "CX".to_string()];
+    let mut undeclared = vec![];
+    for line in qasm.lines() {
+        // definitions of standard gates apply others in braces on one line
+        let body = match line.trim().strip_prefix("gate ") {
+            Some(gate) => {
+                let name = gate.split(['(', ' ']).next().unwrap();
+                declared.push(name.into());
+                gate.split_once('{').map_or("", |(_, body)| body)
+            }
+            None => line,
+        };
+        for statement in body.split(';') {
+            let statement = statement.trim().trim_matches(['{', '}']).trim();
+            let statement = match statement.strip_prefix("if(") {
+                Some(conditioned) => conditioned.split_once(')').unwrap().1.trim(),
+                None => statement,
+            };
+            let name = statement.split(['(', ' ']).next().unwrap();
+            let keywords = ["OPENQASM", "include", "qreg", "creg", "reset", "measure"];
+            if name.is_empty() || name.starts_with("//") || keywords.contains(&name) {
+                continue;
+            }
+            if !declared.iter().any(|gate| gate == name) {
+                undeclared.push(name.into());
+            }
+        }
+    }
+    undeclared
+}
+
+#[test]
+fn imported_gates_are_declared() -> Result<(), Box<dyn std::error::Error>> {
+    let sources: &[&[&str]] = &[
+        &["-I", "tests/prelude", "tests/use.ql"],
+        &["-I", "tests/prelude", "tests/use-nested.ql"],
+        &["tests/nested-module.ql"],
+        &["tests/diamond/main.ql"],
+        &["tests/reexport/main.ql"],
+        &["--package", "tests/package"],
+    ];
+    for args in sources {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+            .args(*args)
+            .args(["-o", "-"])
+            .output()?;
+        assert!(output.status.success(), "{:?}", args);
+        let qasm = String::from_utf8(output.stdout)?;
+        assert_eq!(undeclared_gates(&qasm), Vec::<String>::new(), "{}", qasm);
+    }
+
+    // functions of different modules with the same name are told apart
+    let qasm = compile_source(
+        "module a { fn flip(q: qbit) : qbit { return x(q); } }
+module b { fn flip(q: qbit) : qbit { return h(q); } }
+import a::flip;
+
+fn main(q: qbit) : qbit {
+    let q = flip(q);
+    return b::flip(q);
+}
+",
+        "flips.ql",
+        &Config::default(),
+    )?
+    .to_string();
+    assert_eq!(undeclared_gates(&qasm), Vec::<String>::new(), "{}", qasm);
+    assert!(qasm.contains("gate a_flip q"));
+    assert!(qasm.contains("gate b_flip q"));
+    assert!(qasm.contains("a_flip q[0];\nb_flip q[0];"));
+
+    Ok(())
+}
+
+#[test]
+fn imported_gate_names() -> Result<(), Box<dyn std::error::Error>> {
+    let qcc = |args: &[&str]| -> Result<String, Box<dyn std::error::Error>> {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_qcc"))
+            .args(args)
+            .args(["-o", "-"])
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    // a function of a prelude is defined and applied by its own name
+    let qasm = qcc(&["-I", "tests/prelude", "tests/use.ql"])?;
+    assert!(qasm.contains("gate flip q\n{\n    h q;\n}\n"));
+    assert!(qasm.ends_with("// main\nreset q[0];\nflip q[0];\n"));
+
+    // `lib` is defined once, though both `left` and `right` import it
+    let qasm = qcc(&["tests/diamond/main.ql"])?;
+    assert_eq!(qasm.matches("gate flip q").count(), 1);
+    assert!(qasm.contains("gate left q\n{\n    flip q;\n}\n"));
+    assert!(qasm.contains("gate right q\n{\n    mix q;\n}\n"));
+    assert!(qasm.ends_with("// main\nleft q[0];\nright q[0];\n"));
+
+    // `toss` is applied on no qubit, so its body is inlined into `main`
+    let qasm = qcc(&["--package", "tests/package"])?;
+    assert!(qasm.ends_with("// main\nreset q[0];\nflip q[0];\n"));
+
+    Ok(())
+}